@@ -0,0 +1,95 @@
+//! Server configuration: `api-server.toml`, then `BITNET_API_*` environment
+//! variables, then command-line flags.
+
+use bitnet_common::config::{
+    AuthConfig, CacheConfig, LimitsConfig, ReceiptsConfig, StoreConfig,
+};
+use bitnet_common::models::ModelEntry;
+use bitnet_common::policy::FailurePolicy;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+pub const DEFAULT_CONFIG_PATH: &str = "./api-server.toml";
+pub const ENV_PREFIX: &str = "BITNET_API";
+
+pub const DEFAULT_MODEL_ID: &str = "bitnet-b1.58-2b";
+pub const DEFAULT_WEIGHTS_PATH: &str = "./BitNet/models/BitNet-b1.58-2B-4T/ggml-model-i2_s.gguf";
+pub const DEFAULT_TOKENIZER_PATH: &str = "./tokenizer";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Directory scanned for `*.gguf` models in addition to `[[models]]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub models_dir: Option<PathBuf>,
+    pub server: ServerConfig,
+    pub proving: ProvingConfig,
+    pub auth: AuthConfig,
+    pub limits: LimitsConfig,
+    pub store: StoreConfig,
+    pub receipts: ReceiptsConfig,
+    pub cache: CacheConfig,
+    pub models: Vec<ModelEntry>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            models_dir: None,
+            server: ServerConfig::default(),
+            proving: ProvingConfig::default(),
+            auth: AuthConfig::default(),
+            limits: LimitsConfig::default(),
+            store: StoreConfig::default(),
+            receipts: ReceiptsConfig::default(),
+            cache: CacheConfig::default(),
+            models: vec![ModelEntry::new(DEFAULT_MODEL_ID, DEFAULT_WEIGHTS_PATH)
+                .with_tokenizer(DEFAULT_TOKENIZER_PATH)],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// Allowed CORS origins; `*` allows any.
+    pub cors_origins: Vec<String>,
+    pub on_failure: FailurePolicy,
+    /// Seconds requests in flight get to finish after SIGTERM.
+    pub shutdown_grace_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8936,
+            cors_origins: Vec::new(),
+            on_failure: FailurePolicy::Error,
+            shutdown_grace_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProvingConfig {
+    /// Prover binary for models without their own `prover.host_binary`.
+    pub host_binary: PathBuf,
+    /// Directory `bitnet-host` writes receipts into.
+    pub proofs_dir: String,
+    /// Seconds one proof may run before the host is killed; 0 disables.
+    pub timeout_secs: u64,
+}
+
+impl Default for ProvingConfig {
+    fn default() -> Self {
+        Self {
+            host_binary: PathBuf::from("./target/release/bitnet-host"),
+            proofs_dir: "./proofs".to_string(),
+            timeout_secs: 1800,
+        }
+    }
+}
@@ -1,177 +1,449 @@
+mod config;
+mod verify;
+
 use axum::{
-    extract::{Query, State},
+    extract::State,
     http::{HeaderMap, StatusCode},
-    response::Json,
+    middleware,
+    response::{IntoResponse, Json},
     routing::{get, post},
-    Router,
+    Extension, Router,
+};
+use bitnet_common::attestation::{self, Verifier};
+use bitnet_common::auth::{self, AuthContext, Authenticator, KeysCommand, Scope};
+use bitnet_common::cache::{self, CacheControl, ResponseCache};
+use bitnet_common::config::{self as layered, ConfigLoader, SharedConfig};
+use bitnet_common::cors::cors_layer;
+use bitnet_common::error::{api_error, invalid_request, ApiError};
+use bitnet_common::health::{self, Probes, Readiness};
+use bitnet_common::logprobs::{self, chat_logprobs, completion_logprobs, Logprob, SampledLogprob};
+use bitnet_common::metrics::{self, Metrics};
+use bitnet_common::models::{self, ModelEntry, ModelRegistry};
+use bitnet_common::policy::{verified_header, FailurePolicy};
+use bitnet_common::process::{output_with_timeout, ProcessError};
+use bitnet_common::rate_limit::{self, ProofGate, ProofQuota, RateLimiter};
+use bitnet_common::receipts::{self, ReceiptStore};
+use bitnet_common::shutdown;
+use bitnet_common::store::{self, CompletionRecord, CompletionStore, ProofState};
+use bitnet_common::tokenize::{
+    self, context_length_exceeded, tokenizer_unavailable, TokenizerCache,
+};
+use bitnet_openai::chat::{
+    stop_position, ChatChoice, ChatCompletionRequest, ChatCompletionResponse, ChatMessage,
+    FinishReason, Role, Usage,
 };
-use clap::Parser;
+use bitnet_openai::completions::{CompletionChoice, CompletionRequest, CompletionResponse};
+use clap::{Parser, Subcommand};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::process::Command;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tower_http::cors::CorsLayer;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
 use tower_http::trace::TraceLayer;
 use tracing::{info, warn, error};
 use uuid::Uuid;
 
+use crate::config::Config;
+
+/// Command-line flags. Unset flags fall back to the config file, then to
+/// `BITNET_API_*` environment variables, then to built-in defaults.
 #[derive(Parser, Debug)]
 #[command(name = "bitnet-api-server")]
 #[command(about = "BitNet zkML OpenAI-Compatible API Server")]
 struct Args {
-    #[arg(short, long, default_value = "127.0.0.1")]
-    host: String,
+    /// TOML config file (default: ./api-server.toml if present)
+    #[arg(long)]
+    config: Option<String>,
+
+    /// Print the effective configuration and exit
+    #[arg(long)]
+    print_config: bool,
+
+    #[arg(long)]
+    host: Option<String>,
     
-    #[arg(short, long, default_value = "8936")]
-    port: u16,
+    #[arg(short, long)]
+    port: Option<u16>,
     
-    #[arg(short, long, default_value = "./BitNet/models/BitNet-b1.58-2B-4T/ggml-model-i2_s.gguf")]
-    weights_path: String,
+    /// Serve a single model from these weights instead of `[[models]]`
+    #[arg(short, long)]
+    weights_path: Option<String>,
     
-    #[arg(short, long, default_value = "./tokenizer")]
-    tokenizer_path: String,
+    /// Tokenizer for the model given with --weights-path
+    #[arg(short, long)]
+    tokenizer_path: Option<String>,
     
-    #[arg(short, long, default_value = "./target/release/bitnet-host")]
-    host_binary: String,
-}
+    #[arg(long)]
+    host_binary: Option<String>,
 
-// OpenAI API Types
-#[derive(Debug, Deserialize)]
-struct ChatCompletionRequest {
-    model: String,
-    messages: Vec<ChatMessage>,
-    #[serde(default = "default_max_tokens")]
-    max_tokens: Option<u32>,
-    #[serde(default = "default_temperature")]
-    temperature: Option<f32>,
-    #[serde(default)]
-    stream: bool,
+    /// Seconds a single proof may run before the host is killed (0 disables)
+    #[arg(long)]
+    proof_timeout_secs: Option<u64>,
+
+    /// JSON file holding hashed API keys
+    #[arg(long)]
+    api_keys: Option<String>,
+
+    /// SQLite database holding hashed API keys (overrides --api-keys)
+    #[arg(long)]
+    api_keys_db: Option<String>,
+
+    /// SQLite database recording completions and their proofs
+    #[arg(long)]
+    completions_db: Option<String>,
+
+    /// Directory of the `local` receipt store
+    #[arg(long)]
+    receipts_dir: Option<String>,
+
+    /// Directory of the on-disk response cache
+    #[arg(long)]
+    cache_dir: Option<String>,
+
+    /// Accept requests without an API key (local development only)
+    #[arg(long)]
+    no_auth: bool,
+
+    /// Allowed CORS origin; repeat for several, `*` allows any
+    #[arg(long = "cors-origin")]
+    cors_origins: Vec<String>,
+
+    /// Requests per minute allowed per API key or IP (0 disables)
+    #[arg(long)]
+    rate_limit_per_minute: Option<u32>,
+
+    /// Requests a client may burst above the per-minute rate
+    #[arg(long)]
+    rate_limit_burst: Option<u32>,
+
+    /// Proofs a single client may run at once (0 disables)
+    #[arg(long)]
+    proof_concurrency_per_client: Option<u32>,
+
+    /// Proofs that may run at once across all clients (0 disables)
+    #[arg(long)]
+    proof_concurrency: Option<u32>,
+
+    /// Proofs a single client may request per UTC day (0 disables)
+    #[arg(long)]
+    proofs_per_day: Option<u32>,
+
+    /// What to do when the zkVM host fails: return an error, or an
+    /// unverified fallback answer
+    #[arg(long, value_enum)]
+    on_failure: Option<FailurePolicy>,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
 }
 
-fn default_max_tokens() -> Option<u32> { Some(150) }
-fn default_temperature() -> Option<f32> { Some(0.7) }
+impl Args {
+    fn config_loader(&self) -> ConfigLoader {
+        let mut loader = ConfigLoader::new(
+            self.config.as_deref(),
+            config::DEFAULT_CONFIG_PATH,
+            config::ENV_PREFIX,
+        );
+        loader
+            .set("server.host", self.host.clone())
+            .set("server.port", self.port.map(i64::from))
+            .set(
+                "server.cors_origins",
+                Some(self.cors_origins.clone()).filter(|origins| !origins.is_empty()),
+            )
+            .set(
+                "server.on_failure",
+                self.on_failure.map(|policy| format!("{:?}", policy).to_lowercase()),
+            )
+            .set("models", self.single_model())
+            .set("proving.host_binary", self.host_binary.clone())
+            .set(
+                "proving.timeout_secs",
+                self.proof_timeout_secs.and_then(|secs| i64::try_from(secs).ok()),
+            )
+            .set("auth.enabled", Some(false).filter(|_| self.no_auth))
+            .set("auth.key_file", self.api_keys.clone())
+            .set("auth.key_db", self.api_keys_db.clone())
+            .set("store.path", self.completions_db.clone())
+            .set("receipts.dir", self.receipts_dir.clone())
+            .set("cache.dir", self.cache_dir.clone())
+            .set("limits.rate_limit_per_minute", self.rate_limit_per_minute.map(i64::from))
+            .set("limits.rate_limit_burst", self.rate_limit_burst.map(i64::from))
+            .set(
+                "limits.proof_concurrency_per_client",
+                self.proof_concurrency_per_client.map(i64::from),
+            )
+            .set("limits.proof_concurrency", self.proof_concurrency.map(i64::from))
+            .set("limits.proofs_per_day", self.proofs_per_day.map(i64::from));
+        loader
+    }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct ChatMessage {
-    role: String,
-    content: String,
+    /// `-w`/`-t` replace the configured models with one default-named model.
+    fn single_model(&self) -> Option<toml::Value> {
+        if self.weights_path.is_none() && self.tokenizer_path.is_none() {
+            return None;
+        }
+        let entry = ModelEntry::new(
+            config::DEFAULT_MODEL_ID,
+            self.weights_path.as_deref().unwrap_or(config::DEFAULT_WEIGHTS_PATH),
+        )
+        .with_tokenizer(
+            self.tokenizer_path.as_deref().unwrap_or(config::DEFAULT_TOKENIZER_PATH),
+        );
+        let entry = toml::Value::try_from(entry).expect("model entry serializes to TOML");
+        Some(toml::Value::Array(vec![entry]))
+    }
 }
 
-#[derive(Debug, Serialize)]
-struct ChatCompletionResponse {
-    id: String,
-    object: String,
-    created: i64,
-    model: String,
-    choices: Vec<ChatChoice>,
-    usage: Usage,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    zk_proof: Option<String>,
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Manage API keys
+    #[command(subcommand)]
+    Keys(KeysCommand),
 }
 
-#[derive(Debug, Serialize)]
-struct ChatChoice {
-    index: u32,
-    message: ChatMessage,
-    finish_reason: String,
+/// Completion length when a request doesn't set `max_tokens`.
+const DEFAULT_MAX_TOKENS: u32 = 150;
+
+/// Sampling temperature when a request doesn't set one: the host decodes
+/// greedily, as it always did before requests could choose.
+const DEFAULT_TEMPERATURE: f32 = 0.0;
+
+// Application State
+#[derive(Clone)]
+struct AppState {
+    config: SharedConfig<Config>,
+    models: Arc<ModelRegistry>,
+    tokenizers: Arc<TokenizerCache>,
+    /// `None` when `[store]` is disabled.
+    store: Option<Arc<dyn CompletionStore>>,
+    /// `None` when `[receipts]` has no backend.
+    receipts: Option<Arc<ReceiptStore>>,
+    /// `None` when `[cache]` is disabled.
+    cache: Option<Arc<ResponseCache>>,
+    proof_quota: Arc<ProofQuota>,
+    probes: Arc<Probes>,
+    /// Decides who sees `/readyz` details.
+    authenticator: Authenticator,
+    metrics: Arc<Metrics>,
 }
 
-#[derive(Debug, Serialize)]
-struct Usage {
-    prompt_tokens: u32,
-    completion_tokens: u32,
-    total_tokens: u32,
+/// Proving statistics written by `bitnet-host` next to the receipt.
+#[derive(Debug, Deserialize)]
+struct HostProvingStats {
+    total_cycles: u64,
+    segments: usize,
+    proving_time_ms: u64,
 }
 
-#[derive(Debug, Serialize)]
-struct ModelInfo {
-    id: String,
-    object: String,
-    created: i64,
-    owned_by: String,
+/// The JSON file `bitnet-host` writes with `--output`.
+#[derive(Debug, Deserialize)]
+struct HostOutput {
+    response: String,
+    receipt: String,
+    /// Generated token IDs, from hosts that report them.
+    #[serde(default)]
+    tokens: Option<Vec<u32>>,
+    /// Decoded from the journal, with `--logprobs`.
+    #[serde(default)]
+    logprobs: Option<Vec<SampledLogprob>>,
+    stats: Option<HostProvingStats>,
 }
 
-#[derive(Debug, Serialize)]
-struct ModelsResponse {
-    object: String,
-    data: Vec<ModelInfo>,
+/// Texts returned by the host. One choice is covered by `zkml_proof`; several
+/// carry a receipt each.
+#[derive(Serialize, Deserialize)]
+struct GeneratedResponse {
+    choices: Vec<GeneratedChoice>,
+    usage: Usage,
+    zkml_proof: Option<String>,
+    verified: bool,
+    /// Host output holding the receipt.
+    receipt_path: Option<String>,
+    /// Receipt's digest in the receipt store.
+    receipt_digest: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
-struct ApiError {
-    error: ErrorDetails,
+#[derive(Serialize, Deserialize)]
+struct GeneratedChoice {
+    text: String,
+    finish_reason: FinishReason,
+    logprobs: Option<Vec<Logprob>>,
+    /// This choice's own receipt, when each of several choices was proven in
+    /// its own host run.
+    #[serde(default)]
+    receipt: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
-struct ErrorDetails {
-    message: String,
-    #[serde(rename = "type")]
-    error_type: String,
-    code: Option<String>,
+impl GeneratedResponse {
+    fn apply_stop(&mut self, stop: &[String]) {
+        for choice in &mut self.choices {
+            choice.apply_stop(stop);
+        }
+    }
 }
 
-// Application State
-#[derive(Clone)]
-struct AppState {
-    weights_path: String,
-    tokenizer_path: String,
-    host_binary: String,
-    request_count: Arc<Mutex<u64>>,
-}
-
-impl AppState {
-    fn new(weights_path: String, tokenizer_path: String, host_binary: String) -> Self {
-        Self {
-            weights_path,
-            tokenizer_path,
-            host_binary,
-            request_count: Arc::new(Mutex::new(0)),
+impl GeneratedChoice {
+    /// Cuts the text before the first stop sequence. The host has no notion of
+    /// stop sequences, so the receipt still covers the full generated text.
+    fn apply_stop(&mut self, stop: &[String]) {
+        if let Some(end) = stop_position(&self.text, stop) {
+            self.text.truncate(end);
+            self.finish_reason = FinishReason::Stop;
+            if let Some(logprobs) = &mut self.logprobs {
+                logprobs::truncate(logprobs, end);
+            }
         }
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
-    
     // Initialize tracing
     tracing_subscriber::fmt()
-        .with_env_filter("api_server=info,tower_http=debug")
+        .with_env_filter("api_server=info,bitnet_common=info,tower_http=debug")
         .init();
 
+    let args = Args::parse();
+    let loader = args.config_loader();
+    let config: Config = loader.load()?;
+
+    if args.print_config {
+        layered::print_config(&config)?;
+        return Ok(());
+    }
+
+    if let Some(Commands::Keys(command)) = args.command {
+        let store = auth::open_key_store(&config.auth)?;
+        command.run(store.as_ref())?;
+        return Ok(());
+    }
+    
     info!("Starting BitNet zkML API Server");
-    info!("Host: {}:{}", args.host, args.port);
-    info!("Weights: {}", args.weights_path);
-    info!("Tokenizer: {}", args.tokenizer_path);
-    info!("Host binary: {}", args.host_binary);
-
-    let state = AppState::new(
-        args.weights_path,
-        args.tokenizer_path,
-        args.host_binary,
+    match loader.path() {
+        Some(path) => info!("Config file: {:?}", path),
+        None => info!("No config file; using flags, environment and defaults"),
+    }
+    info!("Host: {}:{}", config.server.host, config.server.port);
+    info!("Host binary: {:?}", config.proving.host_binary);
+    info!("On failure: {:?}", config.server.on_failure);
+
+    let model_registry = ModelRegistry::new(&config.models, config.models_dir.as_deref())?;
+    let authenticator = Authenticator::from_config(&config.auth)?;
+    let cors = cors_layer(&config.server.cors_origins)?;
+    let rate_limiter = RateLimiter::new(
+        config.limits.rate_limit_per_minute,
+        config.limits.rate_limit_burst,
+    );
+    let proof_quota = ProofQuota::new(
+        config.limits.proof_concurrency_per_client,
+        config.limits.proof_concurrency,
+        config.limits.proofs_per_day,
     );
+    let addr = format!("{}:{}", config.server.host, config.server.port);
+    let grace = Duration::from_secs(config.server.shutdown_grace_secs);
+    let store = store::open_completion_store(&config.store)?;
+    let receipt_store = ReceiptStore::from_config(&config.receipts)?;
+    if let Some(receipt_store) = &receipt_store {
+        receipts::spawn_gc(receipt_store.clone(), &config.receipts);
+    }
 
-    let app = Router::new()
+    let metrics = Metrics::new();
+    let response_cache = ResponseCache::from_config(&config.cache, metrics.clone())?;
+    let tokenizers = TokenizerCache::new();
+    let shared_config = SharedConfig::new(config);
+
+    let state = AppState {
+        config: shared_config.clone(),
+        models: model_registry.clone(),
+        tokenizers: tokenizers.clone(),
+        store: store.clone(),
+        receipts: receipt_store.clone(),
+        cache: response_cache,
+        proof_quota: proof_quota.clone(),
+        probes: Probes::new(),
+        authenticator: authenticator.clone(),
+        metrics: metrics.clone(),
+    };
+
+    spawn_config_reload(
+        loader,
+        shared_config,
+        model_registry.clone(),
+        authenticator.clone(),
+        rate_limiter.clone(),
+        proof_quota.clone(),
+    );
+
+    // Every completion runs the prover, so it needs the `prove` scope
+    let proving_routes = Router::new()
         .route("/v1/chat/completions", post(chat_completions))
-        .route("/v1/models", get(list_models))
+        .route("/v1/completions", post(completions))
+        .route_layer(middleware::from_fn_with_state(proof_quota, rate_limit::proof_quota))
+        .route_layer(middleware::from_fn_with_state(
+            rate_limiter.clone(),
+            rate_limit::rate_limit,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            authenticator.scoped(Scope::Prove),
+            auth::require_scope,
+        ));
+
+    let mut chat_routes = Router::new()
+        .merge(models::models_router(model_registry.clone()))
+        .merge(tokenize::tokenize_router(model_registry.clone(), tokenizers))
+        // Receipts only: this server does not sign attestations
+        .merge(attestation::attestations_router(Verifier::new(None, model_registry)))
+        .route("/v1/verify", post(verify::verify));
+    if let Some(store) = store {
+        chat_routes = chat_routes.merge(store::completions_router(store));
+    }
+    if let Some(receipt_store) = receipt_store {
+        chat_routes = chat_routes.merge(receipts::receipts_router(receipt_store));
+    }
+    let chat_routes = chat_routes
+        .route_layer(middleware::from_fn_with_state(rate_limiter, rate_limit::rate_limit))
+        .route_layer(middleware::from_fn_with_state(
+            authenticator.scoped(Scope::Chat),
+            auth::require_scope,
+        ));
+
+    let app = Router::new()
         .route("/health", get(health_check))
+        .route("/livez", get(health::livez))
+        .route("/readyz", get(readyz))
         .route("/", get(root))
-        .layer(CorsLayer::permissive())
+        .merge(proving_routes)
+        .merge(chat_routes)
+        .merge(auth::admin_router(authenticator))
+        .merge(metrics::metrics_router(metrics.clone()))
+        .layer(middleware::from_fn_with_state(metrics, metrics::track_requests))
+        .layer(cors)
         .layer(TraceLayer::new_for_http())
         .with_state(state);
 
-    let addr = format!("{}:{}", args.host, args.port);
     let listener = tokio::net::TcpListener::bind(&addr).await?;
     
     info!("🚀 BitNet zkML API Server listening on http://{}", addr);
     info!("📖 OpenAI-compatible endpoints:");
     info!("   POST /v1/chat/completions");
+    info!("   GET  /v1/chat/completions/{{id}}");
+    info!("   GET  /v1/receipts/{{digest}}");
+    info!("   POST /v1/completions");
     info!("   GET  /v1/models");
+    info!("   GET  /v1/models/{{id}}");
+    info!("   POST /v1/attestations/verify");
+    info!("   POST /v1/verify");
+    info!("   POST /v1/tokenize");
+    info!("   POST /v1/detokenize");
     info!("   GET  /health");
+    info!("   GET  /livez");
+    info!("   GET  /readyz");
+    info!("   GET  /metrics");
+    info!("   GET  /v1/admin/keys (admin)");
 
-    axum::serve(listener, app).await?;
+    shutdown::serve(listener, app, grace).await?;
 
+    info!("Shutdown complete");
     Ok(())
 }
 
@@ -182,14 +454,17 @@ async fn root() -> Json<serde_json::Value> {
         "description": "OpenAI-compatible API for BitNet Zero-Knowledge Machine Learning",
         "endpoints": {
             "chat": "/v1/chat/completions",
+            "completions": "/v1/completions",
             "models": "/v1/models",
-            "health": "/health"
+            "verify": "/v1/verify",
+            "health": "/health",
+            "metrics": "/metrics"
         }
     }))
 }
 
 async fn health_check(State(state): State<AppState>) -> Json<serde_json::Value> {
-    let count = *state.request_count.lock().await;
+    let count = state.metrics.requests_served("/v1/chat/completions");
     Json(serde_json::json!({
         "status": "healthy",
         "service": "BitNet zkML API",
@@ -198,76 +473,300 @@ async fn health_check(State(state): State<AppState>) -> Json<serde_json::Value>
     }))
 }
 
-async fn list_models() -> Json<ModelsResponse> {
-    Json(ModelsResponse {
-        object: "list".to_string(),
-        data: vec![
-            ModelInfo {
-                id: "bitnet-b1.58-2b".to_string(),
-                object: "model".to_string(),
-                created: 1699000000, // Timestamp for when BitNet was released
-                owned_by: "microsoft".to_string(),
-            }
-        ],
-    })
+/// `GET /readyz`: every model's GGUF header parses, its tokenizer parses and
+/// its prover proves the configured guest, and proving has a free slot.
+/// Details are for admin keys only.
+async fn readyz(State(state): State<AppState>, headers: HeaderMap) -> Readiness {
+    let config = state.config.get();
+    let models = state.models.list();
+    let mut checks = Vec::new();
+    if models.is_empty() {
+        checks.push(health::Check::new("models", Err("no models configured".to_string())));
+    }
+    for model in &models {
+        checks.push(state.probes.model(model).await);
+        checks.push(health::tokenizer(&state.tokenizers, model).await);
+        let host_binary = model
+            .prover
+            .host_binary
+            .as_ref()
+            .unwrap_or(&config.proving.host_binary);
+        checks.push(state.probes.prover(model, host_binary).await);
+    }
+    let (running, limit) = state.proof_quota.concurrency();
+    checks.push(health::queue("proof_queue", running, limit));
+    Readiness::new(checks).for_caller(&state.authenticator, &headers)
 }
 
 async fn chat_completions(
     State(state): State<AppState>,
+    Extension(caller): Extension<AuthContext>,
+    Extension(gate): Extension<ProofGate>,
     headers: HeaderMap,
     Json(request): Json<ChatCompletionRequest>,
-) -> Result<Json<ChatCompletionResponse>, (StatusCode, Json<ApiError>)> {
-    // Increment request counter
-    {
-        let mut count = state.request_count.lock().await;
-        *count += 1;
-    }
-
-    info!("Received chat completion request for model: {}", request.model);
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiError>)> {
+    info!(
+        "Received chat completion request for model: {} (key: {})",
+        request.model,
+        caller.key_id.as_deref().unwrap_or("anonymous")
+    );
+    request.validate().map_err(invalid_request)?;
+    let model = state.models.resolve(&request.model)?;
     
     // Extract the user's prompt from messages
     let prompt = extract_prompt_from_messages(&request.messages)?;
     info!("Extracted prompt: '{}'", prompt);
 
+    let response_id = format!("chatcmpl-{}", Uuid::new_v4());
+    let timestamp = chrono::Utc::now().timestamp() as u64;
+
+    let max_tokens = request.max_tokens().unwrap_or(DEFAULT_MAX_TOKENS);
+    let temperature = request.temperature.unwrap_or(DEFAULT_TEMPERATURE);
+    let logprobs = request.logprobs.then(|| request.top_logprobs.unwrap_or(0) as usize);
+
     // Generate response using BitNet zkVM host
-    let (response_text, zk_proof) = generate_bitnet_response(
-        &state,
-        &prompt,
-        request.max_tokens.unwrap_or(50),
+    let host = HostRequest {
+        prompt: &prompt,
+        max_tokens,
+        temperature,
+        logprobs,
+        seeds: sample_seeds(request.seed, request.n()),
+    };
+    let key = cache_key(&state, &model, &host, request.seed).await;
+    let (mut generated, cache_status) = cache::cached(
+        state.cache.as_ref(),
+        key,
+        CacheControl::from_headers(&headers),
+        generate_bitnet_response(&state, &model, &gate, &response_id, host),
+        |generated| generated.verified,
     ).await?;
+    generated.apply_stop(&request.stop_sequences());
+    let receipt_path = generated.receipt_path.take();
+    let receipt_digest = generated.receipt_digest.take();
 
-    let response_id = format!("chatcmpl-{}", Uuid::new_v4());
-    let timestamp = chrono::Utc::now().timestamp();
+    let response = ChatCompletionResponse {
+        id: response_id,
+        object: "chat.completion",
+        created: timestamp,
+        model: request.model.clone(),
+        choices: generated
+            .choices
+            .into_iter()
+            .enumerate()
+            .map(|(index, choice)| ChatChoice {
+                index: index as u32,
+                logprobs: choice.logprobs.as_deref().map(chat_logprobs),
+                message: ChatMessage::assistant(choice.text),
+                finish_reason: choice.finish_reason,
+                zkml_proof: choice.receipt,
+            })
+            .collect(),
+        usage: generated.usage,
+        zkml_proof: generated.zkml_proof,
+        verified: generated.verified,
+        proof_status: None,
+    };
 
-    // Estimate token counts (simplified)
-    let prompt_tokens = estimate_token_count(&prompt);
-    let completion_tokens = estimate_token_count(&response_text);
+    match &response.zkml_proof {
+        Some(proof) => info!("Generated response with proof length: {} chars", proof.len()),
+        None => warn!("Returning unverified response {}", response.id),
+    }
 
-    let response = ChatCompletionResponse {
+    let params = serde_json::json!({
+        "max_tokens": max_tokens,
+        "temperature": temperature,
+        "logprobs": logprobs,
+        "n": request.n(),
+        "seed": request.seed,
+        "stop": request.stop_sequences(),
+    });
+    let record = CompletionRecord::new(&request, &response, &params);
+    store_completion(&state, &model, &caller, record, receipt_path, receipt_digest).await;
+
+    let mut headers = verify::proof_headers(&state, &model, response.zkml_proof.as_deref()).await;
+    headers.extend(cache_status.headers());
+    Ok(([verified_header(response.verified)], headers, Json(response)))
+}
+
+/// Raw text completion: the prompt goes to the host as is.
+async fn completions(
+    State(state): State<AppState>,
+    Extension(caller): Extension<AuthContext>,
+    Extension(gate): Extension<ProofGate>,
+    headers: HeaderMap,
+    Json(request): Json<CompletionRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiError>)> {
+    info!(
+        "Received completion request for model: {} (key: {})",
+        request.model,
+        caller.key_id.as_deref().unwrap_or("anonymous")
+    );
+    request.validate().map_err(invalid_request)?;
+    let model = state.models.resolve(&request.model)?;
+    let prompt = request.prompt.text();
+
+    let response_id = format!("cmpl-{}", Uuid::new_v4());
+    let timestamp = chrono::Utc::now().timestamp() as u64;
+
+    let max_tokens = request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS);
+    let temperature = request.temperature.unwrap_or(DEFAULT_TEMPERATURE);
+    let logprobs = request.logprobs.map(|top| top as usize);
+
+    let host = HostRequest {
+        prompt,
+        max_tokens,
+        temperature,
+        logprobs,
+        seeds: sample_seeds(request.seed, request.n.unwrap_or(1)),
+    };
+    let key = cache_key(&state, &model, &host, request.seed).await;
+    let (mut generated, cache_status) = cache::cached(
+        state.cache.as_ref(),
+        key,
+        CacheControl::from_headers(&headers),
+        generate_bitnet_response(&state, &model, &gate, &response_id, host),
+        |generated| generated.verified,
+    ).await?;
+    generated.apply_stop(&request.stop_sequences());
+    let receipt_path = generated.receipt_path.take();
+    let receipt_digest = generated.receipt_digest.take();
+    // Log-probabilities cover the completion tokens only, even with `echo`
+    let offset = if request.echo { prompt.chars().count() } else { 0 };
+
+    let response = CompletionResponse {
         id: response_id,
-        object: "chat.completion".to_string(),
+        object: "text_completion",
         created: timestamp,
-        model: request.model,
-        choices: vec![ChatChoice {
-            index: 0,
-            message: ChatMessage {
-                role: "assistant".to_string(),
-                content: response_text,
-            },
-            finish_reason: "stop".to_string(),
-        }],
-        usage: Usage {
-            prompt_tokens,
-            completion_tokens,
-            total_tokens: prompt_tokens + completion_tokens,
-        },
-        zk_proof: Some(zk_proof),
+        model: request.model.clone(),
+        choices: generated
+            .choices
+            .into_iter()
+            .enumerate()
+            .map(|(index, choice)| CompletionChoice {
+                logprobs: choice
+                    .logprobs
+                    .as_deref()
+                    .map(|logprobs| completion_logprobs(logprobs, offset)),
+                text: if request.echo {
+                    format!("{}{}", prompt, choice.text)
+                } else {
+                    choice.text
+                },
+                index: index as u32,
+                finish_reason: choice.finish_reason,
+                zkml_proof: choice.receipt,
+            })
+            .collect(),
+        usage: generated.usage,
+        zkml_proof: generated.zkml_proof,
+        verified: generated.verified,
+        proof_status: None,
     };
 
-    info!("Generated response with proof length: {} chars", 
-          response.zk_proof.as_ref().unwrap().len());
+    match &response.zkml_proof {
+        Some(proof) => info!("Generated completion with proof length: {} chars", proof.len()),
+        None => warn!("Returning unverified completion {}", response.id),
+    }
+
+    let params = serde_json::json!({
+        "max_tokens": max_tokens,
+        "temperature": temperature,
+        "logprobs": logprobs,
+        "n": request.n.unwrap_or(1),
+        "seed": request.seed,
+        "stop": request.stop_sequences(),
+    });
+    let record = CompletionRecord::new(&request, &response, &params);
+    store_completion(&state, &model, &caller, record, receipt_path, receipt_digest).await;
+
+    let mut headers = verify::proof_headers(&state, &model, response.zkml_proof.as_deref()).await;
+    headers.extend(cache_status.headers());
+    Ok(([verified_header(response.verified)], headers, Json(response)))
+}
+
+/// Everything that decides the host's answers to a greedy or seeded request.
+#[derive(Serialize)]
+struct CacheKey<'a> {
+    model_digest: String,
+    image_id: Option<&'a str>,
+    prompt_tokens: Vec<u32>,
+    max_tokens: u32,
+    temperature: f32,
+    logprobs: Option<usize>,
+    n: usize,
+    /// `None` when decoding greedily, which no seed changes.
+    seed: Option<u64>,
+}
 
-    Ok(Json(response))
+/// The response cache key of a request, when the cache is enabled and the
+/// request decodes greedily or fixes its `seed`; otherwise the host's answers
+/// vary. `None` if the model can't be hashed or the prompt tokenized.
+async fn cache_key(
+    state: &AppState,
+    model: &ModelEntry,
+    host: &HostRequest<'_>,
+    seed: Option<u64>,
+) -> Option<String> {
+    let seed = match seed {
+        _ if host.temperature <= 0.0 => None,
+        Some(seed) => Some(seed),
+        None => return None,
+    };
+    state.cache.as_ref()?;
+    let parts = async {
+        let tokenizer = state.tokenizers.get(model).await?;
+        anyhow::Ok(CacheKey {
+            model_digest: state.models.digest(model).await?,
+            image_id: model.image_id.as_deref(),
+            prompt_tokens: tokenizer.tokenizer.encode(host.prompt, true)?,
+            max_tokens: host.max_tokens,
+            temperature: host.temperature,
+            logprobs: host.logprobs,
+            n: host.seeds.len(),
+            seed,
+        })
+    };
+    match parts.await {
+        Ok(parts) => Some(ResponseCache::key(&parts)),
+        Err(e) => {
+            warn!("Not caching the response for {}: {:#}", model.id, e);
+            None
+        }
+    }
+}
+
+/// Records a served completion with its receipt, when the store is enabled.
+async fn store_completion(
+    state: &AppState,
+    model: &ModelEntry,
+    caller: &AuthContext,
+    record: anyhow::Result<CompletionRecord>,
+    receipt_path: Option<String>,
+    receipt_digest: Option<String>,
+) {
+    if state.store.is_none() {
+        return;
+    }
+    let model_digest = state.models.digest(model).await.ok();
+    let record = record.map(|mut record| {
+        record.model_digest = model_digest;
+        record.key_id = caller.key_id.clone();
+        if receipt_path.is_some() {
+            record.proof_state = ProofState::Verified;
+        }
+        record.receipt_path = receipt_path;
+        record.receipt_digest = receipt_digest;
+        record
+    });
+    store::save(state.store.as_ref(), record);
+}
+
+/// One seed per choice: consecutive from the request's `seed`, so a repeated
+/// request gets the same choices, or random.
+fn sample_seeds(seed: Option<u64>, n: u32) -> Vec<u64> {
+    (0..u64::from(n))
+        .map(|i| seed.map_or_else(rand::random, |seed| seed.wrapping_add(i)))
+        .collect()
 }
 
 fn extract_prompt_from_messages(messages: &[ChatMessage]) -> Result<String, (StatusCode, Json<ApiError>)> {
@@ -275,81 +774,463 @@ fn extract_prompt_from_messages(messages: &[ChatMessage]) -> Result<String, (Sta
     let user_message = messages
         .iter()
         .rev()
-        .find(|msg| msg.role == "user")
+        .find(|msg| msg.role == Role::User)
         .ok_or_else(|| {
-            (
+            api_error(
                 StatusCode::BAD_REQUEST,
-                Json(ApiError {
-                    error: ErrorDetails {
-                        message: "No user message found in conversation".to_string(),
-                        error_type: "invalid_request_error".to_string(),
-                        code: Some("missing_user_message".to_string()),
-                    },
-                }),
+                "No user message found in conversation",
+                "invalid_request_error",
+                "missing_user_message",
             )
         })?;
 
-    Ok(user_message.content.clone())
+    Ok(user_message.text())
 }
 
+/// Runs on a cache miss, so only requests that really need a proof take one
+/// from the caller's quota, and only once the context length is checked. A
+/// host run samples a single completion, so each choice is proven in a run of
+/// its own, with its own seed, one after another.
 async fn generate_bitnet_response(
     state: &AppState,
-    prompt: &str,
-    max_tokens: u32,
-) -> Result<(String, String), (StatusCode, Json<ApiError>)> {
+    model: &ModelEntry,
+    gate: &ProofGate,
+    request_id: &str,
+    mut host: HostRequest<'_>,
+) -> Result<GeneratedResponse, (StatusCode, Json<ApiError>)> {
     info!("Executing BitNet zkVM host for prompt generation");
 
+    let config = state.config.get();
+    host.max_tokens = model
+        .prover
+        .max_tokens
+        .map_or(host.max_tokens, |cap| host.max_tokens.min(cap));
+    check_context_length(state, model, host.prompt, host.max_tokens).await?;
+    let _permit = gate.acquire(host.seeds.len() as u32)?;
+
+    let mut proven = Vec::with_capacity(host.seeds.len());
+    let mut failure = None;
+    for (index, &seed) in host.seeds.iter().enumerate() {
+        let output_path = if host.seeds.len() == 1 {
+            format!("{}/{}.json", config.proving.proofs_dir, request_id)
+        } else {
+            format!("{}/{}-{}.json", config.proving.proofs_dir, request_id, index)
+        };
+        match prove_choice(state, model, &host, seed, output_path).await {
+            Ok(choice) => proven.push(choice),
+            Err(e) => {
+                failure = Some(e);
+                break;
+            }
+        }
+    }
+
+    let Some(failure) = failure else {
+        let usage = Usage::new(
+            proven.first().map_or(0, |choice| choice.usage.prompt_tokens),
+            proven.iter().map(|choice| choice.usage.completion_tokens).sum(),
+        );
+        if proven.len() == 1 {
+            let choice = proven.remove(0);
+            return Ok(GeneratedResponse {
+                choices: vec![choice.generated],
+                usage,
+                zkml_proof: Some(choice.receipt),
+                verified: true,
+                receipt_path: Some(choice.receipt_path),
+                receipt_digest: choice.receipt_digest,
+            });
+        }
+        // No one receipt covers every choice, so each carries its own
+        return Ok(GeneratedResponse {
+            choices: proven
+                .into_iter()
+                .map(|choice| GeneratedChoice {
+                    receipt: Some(choice.receipt),
+                    ..choice.generated
+                })
+                .collect(),
+            usage,
+            zkml_proof: None,
+            verified: true,
+            receipt_path: None,
+            receipt_digest: None,
+        });
+    };
+
+    match config.server.on_failure {
+        FailurePolicy::Error => Err(failure.into_api_error()),
+        FailurePolicy::Fallback => {
+            warn!("Serving unverified fallback response ({})", failure.reason());
+            state
+                .metrics
+                .fallback_responses
+                .with_label_values(&[failure.reason()])
+                .inc();
+            let fallback = "The verifiable BitNet backend could not answer this request. \
+                            This response is unverified and carries no proof.";
+            Ok(GeneratedResponse {
+                choices: host
+                    .seeds
+                    .iter()
+                    .map(|_| GeneratedChoice {
+                        text: fallback.to_string(),
+                        finish_reason: FinishReason::Stop,
+                        logprobs: None,
+                        receipt: None,
+                    })
+                    .collect(),
+                usage: Usage::default(),
+                zkml_proof: None,
+                verified: false,
+                receipt_path: None,
+                receipt_digest: None,
+            })
+        }
+    }
+}
+
+/// What the host samples: one choice per seed.
+struct HostRequest<'a> {
+    prompt: &'a str,
+    max_tokens: u32,
+    /// 0 decodes greedily, ignoring the seeds.
+    temperature: f32,
+    logprobs: Option<usize>,
+    seeds: Vec<u64>,
+}
+
+/// One choice and the receipt that proves it.
+struct ProvenChoice {
+    generated: GeneratedChoice,
+    usage: Usage,
+    receipt: String,
+    /// Host output holding the receipt.
+    receipt_path: String,
+    /// Receipt's digest in the receipt store.
+    receipt_digest: Option<String>,
+}
+
+async fn prove_choice(
+    state: &AppState,
+    model: &ModelEntry,
+    host: &HostRequest<'_>,
+    seed: u64,
+    output_path: String,
+) -> Result<ProvenChoice, HostFailure> {
+    let config = state.config.get();
+    let _queued = state.metrics.track_queue("proof");
+    let started = Instant::now();
+    let host_binary = model
+        .prover
+        .host_binary
+        .as_ref()
+        .unwrap_or(&config.proving.host_binary);
+
     // Execute the BitNet host binary
-    let output = Command::new(&state.host_binary)
-        .arg("--weights")
-        .arg(&state.weights_path)
-        .arg("--tokenizer")
-        .arg(&state.tokenizer_path)
+    let mut command = Command::new(host_binary);
+    command.arg("--weights").arg(&model.weights_path);
+    command
         .arg("--prompt")
-        .arg(prompt)
+        .arg(host.prompt)
         .arg("--max-tokens")
-        .arg(max_tokens.to_string())
+        .arg(host.max_tokens.to_string())
+        .arg("--temperature")
+        .arg(host.temperature.to_string())
+        .arg("--seed")
+        .arg(seed.to_string())
         .arg("--output")
-        .arg("./proofs/api_request.json")
-        .output();
+        .arg(&output_path);
+    if let Some(top) = host.logprobs {
+        command.arg("--logprobs").arg(top.to_string());
+    }
+    // Dropped with the request if the client disconnects, which kills the host
+    let output =
+        output_with_timeout(&mut command, Duration::from_secs(config.proving.timeout_secs)).await;
 
     match output {
-        Ok(result) => {
-            if result.status.success() {
-                let stdout = String::from_utf8_lossy(&result.stdout);
-                info!("BitNet host executed successfully");
-                info!("Host output: {}", stdout);
-
-                // Parse the output to extract response and proof
-                // For now, create a mock response since RISC0 compilation failed
-                let response_text = format!("Hello! I am a verifiable BitNet agent responding to: '{}'. This is a zero-knowledge verified response.", prompt);
-                let mock_proof = base64::encode(format!("zkproof_for_prompt_{}_tokens_{}", prompt.len(), max_tokens));
-
-                Ok((response_text, mock_proof))
-            } else {
-                let stderr = String::from_utf8_lossy(&result.stderr);
-                warn!("BitNet host execution failed: {}", stderr);
-                
-                // Return a fallback response with a mock proof
-                let fallback_response = format!("I am BitNet, a zero-knowledge verifiable AI assistant. You said: '{}'. [Note: This is a fallback response as zkVM compilation needs to be completed]", prompt);
-                let mock_proof = base64::encode("fallback_proof_placeholder");
-                
-                Ok((fallback_response, mock_proof))
+        Ok(result) if result.status.success() => {
+            info!("BitNet host executed successfully");
+            state
+                .metrics
+                .inference_duration
+                .with_label_values(&["bitnet-host"])
+                .observe(started.elapsed().as_secs_f64());
+
+            match read_host_output(&output_path).await {
+                Ok(host_output) => {
+                    record_proving_stats(state, host_output.stats.as_ref());
+                    let usage = count_usage(
+                        state,
+                        model,
+                        host.prompt,
+                        &host_output.response,
+                        host_output.tokens.as_deref(),
+                    )
+                    .await;
+                    let finish_reason = if usage.completion_tokens >= host.max_tokens {
+                        FinishReason::Length
+                    } else {
+                        FinishReason::Stop
+                    };
+                    let logprobs = match host.logprobs {
+                        Some(_) => journal_logprobs(state, model, host_output.logprobs).await,
+                        None => None,
+                    };
+                    let receipt_digest =
+                        receipts::save(state.receipts.as_ref(), &host_output.receipt).await;
+                    // The host verifies the receipt before writing it out
+                    Ok(ProvenChoice {
+                        generated: GeneratedChoice {
+                            text: host_output.response,
+                            finish_reason,
+                            logprobs,
+                            receipt: None,
+                        },
+                        usage,
+                        receipt: host_output.receipt,
+                        receipt_path: output_path,
+                        receipt_digest,
+                    })
+                }
+                Err(e) => {
+                    error!("Unreadable BitNet host output {}: {}", output_path, e);
+                    state
+                        .metrics
+                        .host_process_failures
+                        .with_label_values(&["bitnet-host", "output"])
+                        .inc();
+                    Err(HostFailure::BadOutput)
+                }
             }
         }
+        Ok(result) => {
+            let stderr = String::from_utf8_lossy(&result.stderr);
+            warn!("BitNet host execution failed: {}", stderr);
+            state
+                .metrics
+                .host_process_failures
+                .with_label_values(&["bitnet-host", "exit_status"])
+                .inc();
+            Err(HostFailure::Failed)
+        }
+        Err(ProcessError::TimedOut(timeout)) => {
+            warn!("BitNet host killed after {:?}", timeout);
+            state
+                .metrics
+                .host_process_failures
+                .with_label_values(&["bitnet-host", "timeout"])
+                .inc();
+            Err(HostFailure::TimedOut)
+        }
         Err(e) => {
             error!("Failed to execute BitNet host: {}", e);
-            
-            // Return a fallback response
-            let fallback_response = format!("Hello! I'm BitNet zkML agent. Your message: '{}'. [Fallback mode - zkVM host needs compilation]", prompt);
-            let mock_proof = base64::encode("development_proof_placeholder");
-            
-            Ok((fallback_response, mock_proof))
+            state
+                .metrics
+                .host_process_failures
+                .with_label_values(&["bitnet-host", "spawn"])
+                .inc();
+            Err(HostFailure::Unavailable)
         }
     }
 }
 
-fn estimate_token_count(text: &str) -> u32 {
-    // Simple token estimation: ~4 characters per token
-    (text.len() as f32 / 4.0).ceil() as u32
+#[derive(Debug, Clone, Copy)]
+enum HostFailure {
+    /// The host binary could not be started.
+    Unavailable,
+    /// The host exited with an error.
+    Failed,
+    /// The host ran past `proving.timeout_secs` and was killed.
+    TimedOut,
+    /// The host succeeded but its output file was missing or malformed.
+    BadOutput,
+}
+
+impl HostFailure {
+    fn reason(self) -> &'static str {
+        match self {
+            HostFailure::Unavailable => "host_unavailable",
+            HostFailure::Failed => "host_failed",
+            HostFailure::TimedOut => "host_timeout",
+            HostFailure::BadOutput => "host_bad_output",
+        }
+    }
+
+    fn into_api_error(self) -> (StatusCode, Json<ApiError>) {
+        let (status, message) = match self {
+            HostFailure::Unavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "The zkVM prover is unavailable",
+            ),
+            HostFailure::Failed => (
+                StatusCode::BAD_GATEWAY,
+                "The zkVM prover failed to generate a proof",
+            ),
+            HostFailure::TimedOut => (
+                StatusCode::GATEWAY_TIMEOUT,
+                "The zkVM prover did not finish in time",
+            ),
+            HostFailure::BadOutput => (
+                StatusCode::BAD_GATEWAY,
+                "The zkVM prover returned an unreadable result",
+            ),
+        };
+        api_error(status, message, "server_error", self.reason())
+    }
+}
+
+/// The file is left as the host wrote it; `/v1/verify` reads a completion's
+/// receipt back from the receipt store, not from here.
+async fn read_host_output(path: &str) -> anyhow::Result<HostOutput> {
+    Ok(serde_json::from_slice(&tokio::fs::read(path).await?)?)
+}
+
+fn record_proving_stats(state: &AppState, stats: Option<&HostProvingStats>) {
+    match stats {
+        Some(stats) => {
+            state
+                .metrics
+                .proving_duration
+                .observe(stats.proving_time_ms as f64 / 1000.0);
+            state.metrics.proving_cycles.observe(stats.total_cycles as f64);
+            state.metrics.proving_segments.observe(stats.segments as f64);
+        }
+        None => warn!("No proving stats in BitNet host output"),
+    }
+}
+
+/// Rejects prompts that leave no room for `max_tokens` in the model's context
+/// window, before the host spends time on them. The host tokenizes with the
+/// same GGUF tokenizer. Unlike bitnet-zkml there is no `on_overflow` setting:
+/// prompts are never truncated here. Nothing else bounds the sequence, so a
+/// tokenizer that can't be loaded turns the request away.
+async fn check_context_length(
+    state: &AppState,
+    model: &ModelEntry,
+    prompt: &str,
+    max_tokens: u32,
+) -> Result<(), (StatusCode, Json<ApiError>)> {
+    let tokenizer = state
+        .tokenizers
+        .get(model)
+        .await
+        .map_err(|e| tokenizer_unavailable(model, e))?;
+    let window = match model.context_size {
+        Some(size) => tokenizer.context_window(size),
+        None => match tokenizer.context_length {
+            Some(length) => length,
+            None => return Ok(()),
+        },
+    };
+    let prompt_tokens = tokenizer.count_prompt(prompt).map_err(|e| {
+        api_error(
+            StatusCode::BAD_REQUEST,
+            format!("Cannot tokenize the prompt: {}", e),
+            "invalid_request_error",
+            "invalid_prompt",
+        )
+    })?;
+    if prompt_tokens.saturating_add(max_tokens) > window {
+        return Err(context_length_exceeded(window, prompt_tokens, max_tokens));
+    }
+    Ok(())
+}
+
+/// Token usage from the model's own tokenizer, which the host uses too,
+/// preferring the generated IDs when the host reports them. Zero if the
+/// tokenizer can't be loaded.
+async fn count_usage(
+    state: &AppState,
+    model: &ModelEntry,
+    prompt: &str,
+    text: &str,
+    tokens: Option<&[u32]>,
+) -> Usage {
+    let counts = async {
+        let tokenizer = state.tokenizers.get(model).await?;
+        let completion_tokens = match tokens {
+            Some(tokens) => tokens.len() as u32,
+            None => tokenizer.count_completion(text)?,
+        };
+        anyhow::Ok((tokenizer.count_prompt(prompt)?, completion_tokens))
+    };
+    let (prompt_tokens, completion_tokens) = counts.await.unwrap_or_else(|e| {
+        warn!("Cannot count tokens for {}: {}", model.id, e);
+        (0, 0)
+    });
+    Usage::new(prompt_tokens, completion_tokens)
+}
+
+async fn journal_logprobs(
+    state: &AppState,
+    model: &ModelEntry,
+    sampled: Option<Vec<SampledLogprob>>,
+) -> Option<Vec<Logprob>> {
+    let resolved = async {
+        let sampled = sampled.ok_or_else(|| anyhow::anyhow!("the host does not report them"))?;
+        let tokenizer = state.tokenizers.get(model).await?;
+        logprobs::resolve(&sampled, |token| tokenizer.tokenizer.decode_bytes(&[token], false))
+    };
+    resolved
+        .await
+        .map_err(|e| warn!("No log-probabilities from bitnet-host: {:#}", e))
+        .ok()
+}
+
+/// Re-reads the configuration on SIGHUP. Models, prover paths, the failure
+/// policy, limits and API keys apply immediately; the listen address, CORS
+/// origins and auth backend need a restart.
+fn spawn_config_reload(
+    loader: ConfigLoader,
+    shared_config: SharedConfig<Config>,
+    model_registry: Arc<ModelRegistry>,
+    authenticator: Authenticator,
+    rate_limiter: Arc<RateLimiter>,
+    proof_quota: Arc<ProofQuota>,
+) {
+    layered::reload_on_sighup(move || {
+        let new_config: Config = match loader.load() {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Config reload failed, keeping the current settings: {}", e);
+                return;
+            }
+        };
+        let old_config = shared_config.get();
+
+        layered::warn_if_changed("server.host", &old_config.server.host, &new_config.server.host);
+        layered::warn_if_changed("server.port", &old_config.server.port, &new_config.server.port);
+        layered::warn_if_changed(
+            "server.shutdown_grace_secs",
+            &old_config.server.shutdown_grace_secs,
+            &new_config.server.shutdown_grace_secs,
+        );
+        layered::warn_if_changed(
+            "server.cors_origins",
+            &old_config.server.cors_origins,
+            &new_config.server.cors_origins,
+        );
+        layered::warn_if_changed("auth", &old_config.auth, &new_config.auth);
+        layered::warn_if_changed("store", &old_config.store, &new_config.store);
+        layered::warn_if_changed("receipts", &old_config.receipts, &new_config.receipts);
+        layered::warn_if_changed("cache", &old_config.cache, &new_config.cache);
+
+        let limits = &new_config.limits;
+        rate_limiter.reconfigure(limits.rate_limit_per_minute, limits.rate_limit_burst);
+        proof_quota.reconfigure(
+            limits.proof_concurrency_per_client,
+            limits.proof_concurrency,
+            limits.proofs_per_day,
+        );
+        if let Err(e) = model_registry.reload(&new_config.models, new_config.models_dir.as_deref()) {
+            error!("Failed to reload models, keeping the current list: {}", e);
+        }
+        if let Err(e) = authenticator.reload() {
+            error!("Failed to reload API keys: {}", e);
+        }
+
+        shared_config.replace(new_config);
+        info!("Configuration reloaded");
+    });
 }
@@ -0,0 +1,354 @@
+//! `POST /v1/verify`: checks a receipt against the model's image ID and
+//! decodes what the guest committed to its journal.
+//!
+//! The receipt comes either from the request, as the base64 `zkml_proof` of a
+//! completion, or from the receipt store, by the digest the completion store
+//! kept for a completion ID. Completion IDs are looked up in the completion
+//! store, so, as at `GET /v1/chat/completions/{id}`, other keys' completions
+//! look absent except to admins.
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::Json,
+    Extension,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bitnet_common::attestation;
+use bitnet_common::auth::AuthContext;
+use bitnet_common::error::{api_error, ApiError};
+use bitnet_common::models::ModelEntry;
+use bitnet_common::policy::{ZK_IMAGE_ID_HEADER, ZK_JOURNAL_DIGEST_HEADER};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::AppState;
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyRequest {
+    /// The base64 `zkml_proof` of a completion.
+    pub proof: Option<String>,
+    /// A completion served by this server, instead of `proof`.
+    pub completion_id: Option<String>,
+    /// Model the receipt is checked against; a completion's own by default.
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerifyResponse {
+    pub verified: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completion_id: Option<String>,
+    pub model: String,
+    pub image_id: Option<String>,
+    /// `sha256:<hex>` of the weights file, when it could be read.
+    pub model_digest: Option<String>,
+    pub journal_digest: String,
+    /// `sha256:<hex>` of the weights the guest ran, from the journal.
+    pub journal_model_digest: String,
+    /// Generated tokens, as committed to the journal.
+    pub tokens: Vec<u32>,
+    /// `tokens`, detokenized with the model's tokenizer.
+    pub text: String,
+}
+
+/// `X-ZK-Image-Id` and `X-ZK-Journal-Digest` for a completion, when its proof
+/// is a receipt.
+///
+/// The image ID is the one the model's host binary reports with `--image-id`,
+/// the guest it actually proved; the header is left out when the binary can't
+/// be asked, rather than echoing a configured `image_id` it may not match.
+pub async fn proof_headers(state: &AppState, model: &ModelEntry, proof: Option<&str>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let Some(receipt) = proof.and_then(attestation::decode_receipt) else {
+        return headers;
+    };
+    let config = state.config.get();
+    let host_binary = model
+        .prover
+        .host_binary
+        .as_ref()
+        .unwrap_or(&config.proving.host_binary);
+    match state.probes.image_id(host_binary).await {
+        Ok(image_id) => {
+            if let Ok(image_id) = HeaderValue::from_str(&image_id) {
+                headers.insert(ZK_IMAGE_ID_HEADER, image_id);
+            }
+        }
+        Err(e) => warn!("Leaving out {}: {}", ZK_IMAGE_ID_HEADER, e),
+    }
+    let digest = attestation::journal_digest(&receipt);
+    headers.insert(
+        ZK_JOURNAL_DIGEST_HEADER,
+        HeaderValue::from_str(&digest).expect("hex is a valid header value"),
+    );
+    headers
+}
+
+pub async fn verify(
+    State(state): State<AppState>,
+    Extension(caller): Extension<AuthContext>,
+    Json(request): Json<VerifyRequest>,
+) -> Result<Json<VerifyResponse>, (StatusCode, Json<ApiError>)> {
+    let (proof, stored_model) = match (request.proof, &request.completion_id) {
+        (Some(proof), None) => (proof, None),
+        (None, Some(completion_id)) => {
+            let (receipt, model) = stored_proof(&state, &caller, completion_id).await?;
+            (receipt, Some(model))
+        }
+        _ => {
+            return Err(api_error(
+                StatusCode::BAD_REQUEST,
+                "Pass exactly one of `proof` and `completion_id`",
+                "invalid_request_error",
+                "invalid_value",
+            ))
+        }
+    };
+    let Some(model_id) = request.model.or(stored_model) else {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "Verifying a proof needs `model`",
+            "invalid_request_error",
+            "missing_model",
+        ));
+    };
+    let model = state.models.resolve(&model_id)?;
+
+    let Some(receipt) = attestation::decode_receipt(&proof) else {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "`proof` is not a base64 RISC Zero receipt",
+            "invalid_request_error",
+            "invalid_proof",
+        ));
+    };
+    let output = attestation::journal_output(&receipt).map_err(|e| {
+        api_error(
+            StatusCode::BAD_REQUEST,
+            format!("Cannot decode the receipt: {}", e),
+            "invalid_request_error",
+            "invalid_journal",
+        )
+    })?;
+    let journal_digest = attestation::journal_digest(&receipt);
+
+    let image_id = model.image_id.clone();
+    let checked = match image_id.as_deref().map(attestation::parse_image_id) {
+        None => Err(format!("model `{}` has no image_id configured", model.id)),
+        Some(None) => Err("the model's image_id is not a 32-byte hex digest".to_string()),
+        // Receipt verification is CPU-bound
+        Some(Some(digest)) => tokio::task::spawn_blocking(move || receipt.verify(digest))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|verified| verified.map_err(|e| e.to_string())),
+    };
+
+    let model_digest = match state.models.digest(&model).await {
+        Ok(digest) => Some(digest),
+        Err(e) => {
+            warn!("Cannot hash weights for {}: {}", model.id, e);
+            None
+        }
+    };
+    // A valid receipt for other weights doesn't prove this model's answer
+    let checked = checked.and_then(|()| match &model_digest {
+        Some(digest) if *digest != output.model_digest => Err(format!(
+            "the receipt ran weights {}, not the model's {}",
+            output.model_digest, digest
+        )),
+        _ => Ok(()),
+    });
+    let text = detokenize(&state, &model, output.tokens()).await;
+
+    Ok(Json(VerifyResponse {
+        verified: checked.is_ok(),
+        reason: checked.err(),
+        completion_id: request.completion_id,
+        model: model.id,
+        image_id,
+        model_digest,
+        journal_digest,
+        tokens: output.tokens().to_vec(),
+        journal_model_digest: output.model_digest,
+        text,
+    }))
+}
+
+/// The receipt and model of a completion `caller` may see.
+async fn stored_proof(
+    state: &AppState,
+    caller: &AuthContext,
+    completion_id: &str,
+) -> Result<(String, String), (StatusCode, Json<ApiError>)> {
+    let not_found = || {
+        api_error(
+            StatusCode::NOT_FOUND,
+            format!("No receipt for completion `{}`", completion_id),
+            "invalid_request_error",
+            "completion_not_found",
+        )
+    };
+    let Some(store) = &state.store else {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "Completions are not stored on this server; pass `proof` instead",
+            "invalid_request_error",
+            "store_disabled",
+        ));
+    };
+    let Some(receipts) = &state.receipts else {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "Receipts are not stored on this server; pass `proof` instead",
+            "invalid_request_error",
+            "receipts_disabled",
+        ));
+    };
+    let record = store.get(completion_id).map_err(|e| {
+        api_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Cannot read completion store: {}", e),
+            "server_error",
+            "store_error",
+        )
+    })?;
+    let Some(record) = record.filter(|record| record.visible_to(caller)) else {
+        return Err(not_found());
+    };
+    let Some(receipt_digest) = &record.receipt_digest else {
+        return Err(not_found());
+    };
+    let receipt = receipts.get(receipt_digest).await.map_err(|e| {
+        api_error(
+            StatusCode::BAD_GATEWAY,
+            format!("Cannot read receipt store: {}", e),
+            "server_error",
+            "store_error",
+        )
+    })?;
+    let Some(receipt) = receipt else {
+        return Err(not_found());
+    };
+    Ok((BASE64.encode(receipt), record.model))
+}
+
+/// Decodes the journal's tokens as the host does; empty if the tokenizer
+/// can't be loaded.
+async fn detokenize(state: &AppState, model: &ModelEntry, tokens: &[u32]) -> String {
+    let decoded = async {
+        let tokenizer = state.tokenizers.get(model).await?;
+        tokenizer.tokenizer.decode(tokens, true)
+    };
+    decoded.await.unwrap_or_else(|e| {
+        warn!("Cannot detokenize for {}: {}", model.id, e);
+        String::new()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitnet_common::auth::{Authenticator, Scope};
+    use bitnet_common::config::SharedConfig;
+    use bitnet_common::health::Probes;
+    use bitnet_common::metrics::Metrics;
+    use bitnet_common::models::ModelRegistry;
+    use bitnet_common::rate_limit::ProofQuota;
+    use bitnet_common::receipts::{LocalBackend, ReceiptStore};
+    use bitnet_common::store::{CompletionRecord, SqliteCompletionStore};
+    use bitnet_common::tokenize::TokenizerCache;
+    use std::sync::Arc;
+
+    use crate::config::Config;
+
+    fn state(name: &str) -> AppState {
+        let dir =
+            std::env::temp_dir().join(format!("bitnet-verify-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        AppState {
+            config: SharedConfig::new(Config::default()),
+            models: ModelRegistry::new(&[ModelEntry::new("tiny", dir.join("tiny.gguf"))], None)
+                .unwrap(),
+            tokenizers: TokenizerCache::new(),
+            store: Some(Arc::new(SqliteCompletionStore::open(":memory:").unwrap())),
+            receipts: Some(ReceiptStore::new(Box::new(LocalBackend::new(&dir)))),
+            cache: None,
+            proof_quota: ProofQuota::new(0, 0, 0),
+            probes: Probes::new(),
+            authenticator: Authenticator::disabled(),
+            metrics: Metrics::new(),
+        }
+    }
+
+    fn caller(key_id: &str, scopes: Vec<Scope>) -> AuthContext {
+        AuthContext {
+            key_id: Some(key_id.to_string()),
+            scopes,
+        }
+    }
+
+    async fn code(state: &AppState, request: serde_json::Value) -> String {
+        let request = serde_json::from_value(request).unwrap();
+        let result = verify(
+            State(state.clone()),
+            Extension(caller("a", vec![])),
+            Json(request),
+        );
+        let (status, Json(error)) = result.await.expect_err("the request is rejected");
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        error.error.code.unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_requests() {
+        let state = state("requests");
+        let both = serde_json::json!({"proof": "AAAA", "completion_id": "c", "model": "tiny"});
+        assert_eq!(code(&state, both).await, "invalid_value");
+        assert_eq!(
+            code(&state, serde_json::json!({"model": "tiny"})).await,
+            "invalid_value"
+        );
+        assert_eq!(
+            code(&state, serde_json::json!({"proof": "AAAA"})).await,
+            "missing_model"
+        );
+        let garbage = serde_json::json!({"proof": "not a receipt!", "model": "tiny"});
+        assert_eq!(code(&state, garbage).await, "invalid_proof");
+    }
+
+    #[tokio::test]
+    async fn stored_receipts_are_only_visible_to_their_owner() {
+        let state = state("owner");
+        let stored = state
+            .receipts
+            .as_ref()
+            .unwrap()
+            .put(b"receipt")
+            .await
+            .unwrap();
+        let response = serde_json::json!({"id": "c", "object": "text_completion", "model": "tiny"});
+        let mut record = CompletionRecord::new(&(), &response, &()).unwrap();
+        record.key_id = Some("a".to_string());
+        record.receipt_digest = Some(stored.digest);
+        state.store.as_ref().unwrap().insert(&record).unwrap();
+
+        let (status, _) = stored_proof(&state, &caller("b", vec![]), "c")
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let expected = (BASE64.encode(b"receipt"), "tiny".to_string());
+        assert_eq!(
+            stored_proof(&state, &caller("a", vec![]), "c")
+                .await
+                .unwrap(),
+            expected
+        );
+        let admin = caller("b", vec![Scope::Admin]);
+        assert_eq!(stored_proof(&state, &admin, "c").await.unwrap(), expected);
+        let (status, _) = stored_proof(&state, &admin, "missing").await.unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
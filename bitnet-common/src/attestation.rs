@@ -0,0 +1,589 @@
+//! Proofs attached to completions, and `POST /v1/attestations/verify`.
+//!
+//! A completion carries one of two proofs, base64-encoded:
+//!
+//! - a RISC Zero receipt (bincode), produced by `bitnet-host` and checked
+//!   against the model's zkVM image ID;
+//! - a signed attestation: a JSON [`Statement`] of model, prompt, sampling
+//!   parameters and response, its SHA-256 commitment, and an ed25519
+//!   signature over that commitment by the server key.
+//!
+//! An attestation proves which server produced an answer, not that the
+//! computation was correct; only a receipt does that.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::Json,
+    routing::{get, post},
+    Router,
+};
+use base64::Engine;
+use bitnet_core::guest::GuestOutput;
+use ed25519_dalek::{Signature, Signer as _, SigningKey, Verifier as _, VerifyingKey};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::info;
+
+use crate::error::{api_error, ApiErrorResponse};
+use crate::models::ModelRegistry;
+
+/// `type` of a signed attestation.
+pub const ATTESTATION_TYPE: &str = "ed25519-sha256";
+
+const BASE64: base64::engine::GeneralPurpose = base64::engine::general_purpose::STANDARD;
+
+/// Sampling parameters covered by a [`Statement`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SamplingParams {
+    pub max_tokens: u32,
+    pub temperature: f32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    /// Number of choices, when more than one; the attested response is then
+    /// the JSON array of their texts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+}
+
+/// What a server attests to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Statement {
+    pub model: String,
+    /// `sha256:<hex>` of the weights file, when it could be read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_digest: Option<String>,
+    pub prompt_sha256: String,
+    pub params: SamplingParams,
+    pub response_sha256: String,
+    pub created: u64,
+}
+
+impl Statement {
+    pub fn new(
+        model: &str,
+        model_digest: Option<String>,
+        prompt: &str,
+        params: SamplingParams,
+        response: &str,
+    ) -> Self {
+        Self {
+            model: model.to_string(),
+            model_digest,
+            prompt_sha256: sha256_hex(prompt.as_bytes()),
+            params,
+            response_sha256: sha256_hex(response.as_bytes()),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        }
+    }
+
+    /// SHA-256 of the statement's JSON encoding.
+    fn commitment(&self) -> [u8; 32] {
+        Sha256::digest(serde_json::to_vec(self).expect("statement serializes")).into()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attestation {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub statement: Statement,
+    /// Hex SHA-256 commitment to `statement`.
+    pub commitment: String,
+    /// Hex ed25519 public key of the signing server.
+    pub public_key: String,
+    /// Hex ed25519 signature over the commitment bytes.
+    pub signature: String,
+}
+
+impl Attestation {
+    /// The base64 form returned as `zkml_proof`.
+    pub fn encode(&self) -> String {
+        BASE64.encode(serde_json::to_vec(self).expect("attestation serializes"))
+    }
+}
+
+/// The server's attestation key.
+pub struct AttestationSigner {
+    key: SigningKey,
+}
+
+impl AttestationSigner {
+    /// Reads the hex-encoded key seed at `path`, generating one on first use.
+    pub fn load_or_create(path: &Path) -> anyhow::Result<Self> {
+        let key = if path.exists() {
+            let seed = hex::decode(std::fs::read_to_string(path)?.trim())?;
+            let seed: [u8; 32] = seed
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("{:?} does not hold a 32-byte ed25519 seed", path))?;
+            SigningKey::from_bytes(&seed)
+        } else {
+            let key = SigningKey::generate(&mut OsRng);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            write_private(path, &hex::encode(key.to_bytes()))?;
+            info!("Generated attestation key {:?}", path);
+            key
+        };
+        Ok(Self { key })
+    }
+
+    pub fn public_key(&self) -> String {
+        hex::encode(self.key.verifying_key().to_bytes())
+    }
+
+    pub fn attest(&self, statement: Statement) -> Attestation {
+        let commitment = statement.commitment();
+        Attestation {
+            kind: ATTESTATION_TYPE.to_string(),
+            statement,
+            commitment: hex::encode(commitment),
+            public_key: self.public_key(),
+            signature: hex::encode(self.key.sign(&commitment).to_bytes()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, content: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(content.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, content: &str) -> std::io::Result<()> {
+    std::fs::write(path, content)
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Decodes a base64 `zkml_proof` holding a bincode RISC Zero receipt.
+pub fn decode_receipt(proof: &str) -> Option<risc0_zkvm::Receipt> {
+    let bytes = BASE64.decode(proof.trim()).ok()?;
+    bincode::deserialize(&bytes).ok()
+}
+
+/// Hex SHA-256 of a receipt's journal.
+pub fn journal_digest(receipt: &risc0_zkvm::Receipt) -> String {
+    sha256_hex(&receipt.journal.bytes)
+}
+
+/// Parses a hex image ID, with or without `0x`.
+pub fn parse_image_id(image_id: &str) -> Option<risc0_zkvm::sha::Digest> {
+    let bytes = hex::decode(image_id.trim_start_matches("0x")).ok()?;
+    risc0_zkvm::sha::Digest::try_from(bytes.as_slice()).ok()
+}
+
+/// What the BitNet guest committed to a receipt's journal.
+pub fn journal_output(receipt: &risc0_zkvm::Receipt) -> anyhow::Result<GuestOutput> {
+    receipt
+        .journal
+        .decode::<GuestOutput>()
+        .map_err(|e| anyhow::anyhow!("journal is not BitNet output: {}", e))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyRequest {
+    /// The base64 `zkml_proof` from a completion.
+    pub proof: String,
+    /// Checked against the attestation's prompt hash when given.
+    pub prompt: Option<String>,
+    /// Checked against the attestation's response hash when given.
+    pub response: Option<String>,
+    /// Public key to trust instead of this server's own.
+    pub public_key: Option<String>,
+    /// zkVM image ID for a receipt; defaults to the model's `image_id`.
+    pub image_id: Option<String>,
+    /// Model whose `image_id` a receipt is checked against.
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerifyResponse {
+    pub valid: bool,
+    /// `ed25519-sha256` or `risc0-receipt`.
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub statement: Option<Statement>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_id: Option<String>,
+    /// Hex SHA-256 of a receipt's journal.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub journal_digest: Option<String>,
+}
+
+impl VerifyResponse {
+    fn invalid(kind: &'static str, reason: impl Into<String>) -> Self {
+        Self {
+            valid: false,
+            kind,
+            reason: Some(reason.into()),
+            statement: None,
+            public_key: None,
+            image_id: None,
+            journal_digest: None,
+        }
+    }
+}
+
+/// Checks attestations and receipts.
+pub struct Verifier {
+    /// This server's key, trusted by default. `None` on servers that don't sign.
+    public_key: Option<String>,
+    models: Arc<ModelRegistry>,
+}
+
+impl Verifier {
+    pub fn new(signer: Option<&AttestationSigner>, models: Arc<ModelRegistry>) -> Arc<Self> {
+        Arc::new(Self {
+            public_key: signer.map(AttestationSigner::public_key),
+            models,
+        })
+    }
+
+    pub fn verify(&self, request: &VerifyRequest) -> Result<VerifyResponse, ApiErrorResponse> {
+        let bytes = BASE64.decode(request.proof.trim()).map_err(|_| {
+            api_error(
+                StatusCode::BAD_REQUEST,
+                "`proof` is not valid base64",
+                "invalid_request_error",
+                "invalid_proof",
+            )
+        })?;
+        match serde_json::from_slice::<Attestation>(&bytes) {
+            Ok(attestation) => Ok(self.verify_attestation(request, attestation)),
+            Err(_) => self.verify_receipt(request, &bytes),
+        }
+    }
+
+    fn verify_attestation(
+        &self,
+        request: &VerifyRequest,
+        attestation: Attestation,
+    ) -> VerifyResponse {
+        let kind = ATTESTATION_TYPE;
+        if attestation.kind != ATTESTATION_TYPE {
+            return VerifyResponse::invalid(
+                kind,
+                format!("unknown attestation type `{}`", attestation.kind),
+            );
+        }
+        let Some(trusted_key) = request.public_key.as_ref().or(self.public_key.as_ref()) else {
+            return VerifyResponse::invalid(
+                kind,
+                "no public key to verify against; pass `public_key`",
+            );
+        };
+        if !attestation.public_key.eq_ignore_ascii_case(trusted_key) {
+            return VerifyResponse::invalid(kind, "signed by an untrusted key");
+        }
+
+        let commitment = attestation.statement.commitment();
+        if hex::encode(commitment) != attestation.commitment.to_lowercase() {
+            return VerifyResponse::invalid(kind, "commitment does not match the statement");
+        }
+        let key = hex::decode(&attestation.public_key)
+            .ok()
+            .and_then(|key| <[u8; 32]>::try_from(key).ok())
+            .and_then(|key| VerifyingKey::from_bytes(&key).ok());
+        let signature = hex::decode(&attestation.signature)
+            .ok()
+            .and_then(|signature| Signature::from_slice(&signature).ok());
+        let (Some(key), Some(signature)) = (key, signature) else {
+            return VerifyResponse::invalid(kind, "malformed public key or signature");
+        };
+        if key.verify(&commitment, &signature).is_err() {
+            return VerifyResponse::invalid(kind, "signature does not match the commitment");
+        }
+
+        let statement = attestation.statement;
+        if let Some(prompt) = &request.prompt {
+            if sha256_hex(prompt.as_bytes()) != statement.prompt_sha256 {
+                return VerifyResponse::invalid(kind, "prompt does not match the attestation");
+            }
+        }
+        if let Some(response) = &request.response {
+            if sha256_hex(response.as_bytes()) != statement.response_sha256 {
+                return VerifyResponse::invalid(kind, "response does not match the attestation");
+            }
+        }
+
+        VerifyResponse {
+            valid: true,
+            kind,
+            reason: None,
+            statement: Some(statement),
+            public_key: Some(attestation.public_key),
+            image_id: None,
+            journal_digest: None,
+        }
+    }
+
+    fn verify_receipt(
+        &self,
+        request: &VerifyRequest,
+        bytes: &[u8],
+    ) -> Result<VerifyResponse, ApiErrorResponse> {
+        let kind = "risc0-receipt";
+        let Ok(receipt) = bincode::deserialize::<risc0_zkvm::Receipt>(bytes) else {
+            return Ok(VerifyResponse::invalid(
+                kind,
+                "proof is neither an attestation nor a receipt",
+            ));
+        };
+
+        let image_id = match (&request.image_id, &request.model) {
+            (Some(image_id), _) => image_id.clone(),
+            (None, Some(model)) => self.models.resolve(model)?.image_id.ok_or_else(|| {
+                api_error(
+                    StatusCode::BAD_REQUEST,
+                    format!("Model `{}` has no image_id configured", model),
+                    "invalid_request_error",
+                    "missing_image_id",
+                )
+            })?,
+            (None, None) => {
+                return Err(api_error(
+                    StatusCode::BAD_REQUEST,
+                    "Verifying a receipt needs `image_id` or `model`",
+                    "invalid_request_error",
+                    "missing_image_id",
+                ))
+            }
+        };
+        let Some(digest) = parse_image_id(&image_id) else {
+            return Ok(VerifyResponse::invalid(
+                kind,
+                "`image_id` is not a 32-byte hex digest",
+            ));
+        };
+
+        let (valid, reason) = match receipt.verify(digest) {
+            Ok(()) => (true, None),
+            Err(e) => (false, Some(e.to_string())),
+        };
+        Ok(VerifyResponse {
+            valid,
+            kind,
+            reason,
+            statement: None,
+            public_key: None,
+            image_id: Some(image_id),
+            journal_digest: Some(journal_digest(&receipt)),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct PublicKeyResponse {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    public_key: Option<String>,
+}
+
+async fn verify(
+    State(verifier): State<Arc<Verifier>>,
+    Json(request): Json<VerifyRequest>,
+) -> Result<Json<VerifyResponse>, ApiErrorResponse> {
+    let verifier = Arc::clone(&verifier);
+    // Receipt verification is CPU-bound
+    let response = tokio::task::spawn_blocking(move || verifier.verify(&request))
+        .await
+        .map_err(|e| {
+            api_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Verification failed: {}", e),
+                "server_error",
+                "internal_error",
+            )
+        })??;
+    Ok(Json(response))
+}
+
+async fn public_key(State(verifier): State<Arc<Verifier>>) -> Json<PublicKeyResponse> {
+    Json(PublicKeyResponse {
+        kind: ATTESTATION_TYPE,
+        public_key: verifier.public_key.clone(),
+    })
+}
+
+/// `POST /v1/attestations/verify` and `GET /v1/attestations/key`. Callers add
+/// their own authentication layers.
+pub fn attestations_router<S>(verifier: Arc<Verifier>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/v1/attestations/verify", post(verify))
+        .route("/v1/attestations/key", get(public_key))
+        .with_state(verifier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "bitnet-attestation-{}-{}/key",
+            name,
+            std::process::id()
+        ))
+    }
+
+    /// A freshly generated key.
+    fn signer(name: &str) -> AttestationSigner {
+        let path = key_path(name);
+        let _ = std::fs::remove_file(&path);
+        AttestationSigner::load_or_create(&path).unwrap()
+    }
+
+    fn verifier(signer: &AttestationSigner) -> Arc<Verifier> {
+        Verifier::new(Some(signer), ModelRegistry::new(&[], None).unwrap())
+    }
+
+    fn statement() -> Statement {
+        let params = SamplingParams {
+            max_tokens: 8,
+            temperature: 0.0,
+            stop: Vec::new(),
+            n: None,
+        };
+        Statement::new("bitnet", None, "Hello", params, " world")
+    }
+
+    fn request(attestation: &Attestation) -> VerifyRequest {
+        VerifyRequest {
+            proof: attestation.encode(),
+            prompt: Some("Hello".to_string()),
+            response: Some(" world".to_string()),
+            public_key: None,
+            image_id: None,
+            model: None,
+        }
+    }
+
+    #[test]
+    fn signed_statements_verify_and_keys_persist() {
+        let signer = signer("round-trip");
+        let attestation = signer.attest(statement());
+        let response = verifier(&signer).verify(&request(&attestation)).unwrap();
+        assert!(response.valid, "{:?}", response.reason);
+        assert_eq!(response.statement, Some(attestation.statement));
+        assert_eq!(response.public_key, Some(signer.public_key()));
+
+        // A restart signs with the same key
+        let path = key_path("round-trip");
+        assert_eq!(
+            AttestationSigner::load_or_create(&path)
+                .unwrap()
+                .public_key(),
+            signer.public_key()
+        );
+    }
+
+    #[test]
+    fn tampered_statements_are_rejected() {
+        let signer = signer("tampered");
+        let verifier = verifier(&signer);
+
+        let mut attestation = signer.attest(statement());
+        attestation.statement.model = "other".to_string();
+        let response = verifier.verify(&request(&attestation)).unwrap();
+        assert!(!response.valid);
+        assert_eq!(
+            response.reason.as_deref(),
+            Some("commitment does not match the statement")
+        );
+
+        // Recomputing the commitment doesn't help without the key
+        attestation.commitment = hex::encode(attestation.statement.commitment());
+        let response = verifier.verify(&request(&attestation)).unwrap();
+        assert_eq!(
+            response.reason.as_deref(),
+            Some("signature does not match the commitment")
+        );
+
+        let mut request = request(&signer.attest(statement()));
+        request.response = Some(" there".to_string());
+        let response = verifier.verify(&request).unwrap();
+        assert_eq!(
+            response.reason.as_deref(),
+            Some("response does not match the attestation")
+        );
+    }
+
+    #[test]
+    fn other_keys_are_untrusted_unless_named() {
+        let ours = signer("ours");
+        let theirs = signer("theirs");
+        let attestation = theirs.attest(statement());
+
+        let response = verifier(&ours).verify(&request(&attestation)).unwrap();
+        assert!(!response.valid);
+        assert_eq!(
+            response.reason.as_deref(),
+            Some("signed by an untrusted key")
+        );
+
+        let mut request = request(&attestation);
+        request.public_key = Some(theirs.public_key().to_uppercase());
+        assert!(verifier(&ours).verify(&request).unwrap().valid);
+
+        // A statement re-signed by another key doesn't pass as ours
+        let mut forged = ours.attest(statement());
+        forged.signature = theirs.attest(statement()).signature;
+        let response = verifier(&ours).verify(&VerifyRequest {
+            proof: forged.encode(),
+            ..request
+        });
+        assert!(!response.unwrap().valid);
+    }
+
+    #[test]
+    fn malformed_proofs_and_image_ids_are_rejected() {
+        let signer = signer("malformed");
+        let mut request = request(&signer.attest(statement()));
+        request.proof = "not base64!".to_string();
+        let (status, body) = verifier(&signer).verify(&request).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body.error.code.as_deref(), Some("invalid_proof"));
+
+        request.proof = BASE64.encode(b"neither");
+        let response = verifier(&signer).verify(&request).unwrap();
+        assert!(!response.valid);
+        assert_eq!(response.kind, "risc0-receipt");
+
+        let digest = "ab".repeat(32);
+        assert!(parse_image_id(&digest).is_some());
+        assert_eq!(
+            parse_image_id(&format!("0x{}", digest)),
+            parse_image_id(&digest)
+        );
+        assert!(parse_image_id("zz").is_none());
+        assert!(parse_image_id(&"ab".repeat(31)).is_none());
+    }
+}
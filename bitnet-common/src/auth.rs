@@ -0,0 +1,731 @@
+//! API key authentication for the inference servers.
+//!
+//! Clients send `Authorization: Bearer <key>`. Only the SHA-256 hash of each
+//! key is stored, either in a JSON key file or in a SQLite database. Every key
+//! carries a set of scopes and can be revoked without being deleted.
+
+use axum::{
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get},
+    Router,
+};
+use clap::{Subcommand, ValueEnum};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    path::{Path as FsPath, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+};
+use tracing::{info, warn};
+
+use crate::config::AuthConfig;
+use crate::error::{api_error, ApiErrorResponse};
+
+const KEY_PREFIX: &str = "bnk_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Chat and model listing endpoints.
+    Chat,
+    /// Endpoints that run the zkVM prover.
+    Prove,
+    /// Key management; implies every other scope.
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Chat => "chat",
+            Scope::Prove => "prove",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "chat" => Ok(Scope::Chat),
+            "prove" => Ok(Scope::Prove),
+            "admin" => Ok(Scope::Admin),
+            other => Err(anyhow::anyhow!("Unknown scope: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyRecord {
+    pub id: String,
+    pub name: String,
+    /// Hex-encoded SHA-256 of the full key.
+    pub key_hash: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<i64>,
+}
+
+impl ApiKeyRecord {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_at.is_some()
+    }
+}
+
+fn join_scopes(scopes: &[Scope]) -> String {
+    scopes
+        .iter()
+        .map(Scope::as_str)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Creates a new random key. The plaintext is returned once and never stored.
+pub fn generate_key(name: &str, scopes: Vec<Scope>) -> (String, ApiKeyRecord) {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let key = format!("{}{}", KEY_PREFIX, hex::encode(secret));
+
+    let mut id = [0u8; 6];
+    rand::thread_rng().fill_bytes(&mut id);
+
+    let record = ApiKeyRecord {
+        id: format!("key_{}", hex::encode(id)),
+        name: name.to_string(),
+        key_hash: hash_key(&key),
+        scopes,
+        created_at: chrono::Utc::now().timestamp(),
+        revoked_at: None,
+    };
+
+    (key, record)
+}
+
+/// Persistent storage for API key records.
+pub trait KeyStore: Send + Sync {
+    fn find_by_hash(&self, key_hash: &str) -> anyhow::Result<Option<ApiKeyRecord>>;
+    fn list(&self) -> anyhow::Result<Vec<ApiKeyRecord>>;
+    fn insert(&self, record: ApiKeyRecord) -> anyhow::Result<()>;
+    /// Marks a key as revoked. Returns `false` if no such key exists.
+    fn revoke(&self, id: &str) -> anyhow::Result<bool>;
+    /// Picks up changes made outside the server, e.g. an edited key file.
+    fn reload(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct KeyFile {
+    keys: Vec<ApiKeyRecord>,
+}
+
+/// Keys kept in a JSON file, loaded into memory and rewritten on change.
+pub struct FileKeyStore {
+    path: PathBuf,
+    keys: RwLock<Vec<ApiKeyRecord>>,
+}
+
+impl FileKeyStore {
+    pub fn open(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let keys = Self::read(&path)?;
+        Ok(Self {
+            path,
+            keys: RwLock::new(keys),
+        })
+    }
+
+    fn read(path: &FsPath) -> anyhow::Result<Vec<ApiKeyRecord>> {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let content = std::fs::read_to_string(path)?;
+        let file: KeyFile = serde_json::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Invalid key file {:?}: {}", path, e))?;
+        Ok(file.keys)
+    }
+
+    fn write(&self, keys: &[ApiKeyRecord]) -> anyhow::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let content = serde_json::to_string_pretty(&KeyFile {
+            keys: keys.to_vec(),
+        })?;
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, content)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+impl KeyStore for FileKeyStore {
+    fn find_by_hash(&self, key_hash: &str) -> anyhow::Result<Option<ApiKeyRecord>> {
+        let keys = self.keys.read().unwrap();
+        Ok(keys.iter().find(|k| k.key_hash == key_hash).cloned())
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<ApiKeyRecord>> {
+        Ok(self.keys.read().unwrap().clone())
+    }
+
+    fn insert(&self, record: ApiKeyRecord) -> anyhow::Result<()> {
+        let mut keys = self.keys.write().unwrap();
+        keys.push(record);
+        self.write(&keys)
+    }
+
+    fn revoke(&self, id: &str) -> anyhow::Result<bool> {
+        let mut keys = self.keys.write().unwrap();
+        let Some(record) = keys.iter_mut().find(|k| k.id == id) else {
+            return Ok(false);
+        };
+        if record.revoked_at.is_none() {
+            record.revoked_at = Some(chrono::Utc::now().timestamp());
+        }
+        self.write(&keys)?;
+        Ok(true)
+    }
+
+    fn reload(&self) -> anyhow::Result<()> {
+        let keys = Self::read(&self.path)?;
+        info!("Reloaded {} API keys from {:?}", keys.len(), self.path);
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+}
+
+/// Keys kept in a SQLite database.
+pub struct SqliteKeyStore {
+    conn: Mutex<rusqlite::Connection>,
+}
+
+impl SqliteKeyStore {
+    pub fn open(path: impl AsRef<FsPath>) -> anyhow::Result<Self> {
+        let conn = rusqlite::Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS api_keys (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                key_hash TEXT NOT NULL UNIQUE,
+                scopes TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                revoked_at INTEGER
+            );",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn row_to_record(row: &rusqlite::Row<'_>) -> rusqlite::Result<ApiKeyRecord> {
+        let scopes: String = row.get(3)?;
+        Ok(ApiKeyRecord {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            key_hash: row.get(2)?,
+            scopes: scopes.split(',').filter_map(|s| s.parse().ok()).collect(),
+            created_at: row.get(4)?,
+            revoked_at: row.get(5)?,
+        })
+    }
+}
+
+const SELECT_KEYS: &str =
+    "SELECT id, name, key_hash, scopes, created_at, revoked_at FROM api_keys";
+
+impl KeyStore for SqliteKeyStore {
+    fn find_by_hash(&self, key_hash: &str) -> anyhow::Result<Option<ApiKeyRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("{} WHERE key_hash = ?1", SELECT_KEYS))?;
+        let mut rows = stmt.query_map([key_hash], Self::row_to_record)?;
+        Ok(rows.next().transpose()?)
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<ApiKeyRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("{} ORDER BY created_at", SELECT_KEYS))?;
+        let rows = stmt.query_map([], Self::row_to_record)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn insert(&self, record: ApiKeyRecord) -> anyhow::Result<()> {
+        let scopes = join_scopes(&record.scopes);
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO api_keys (id, name, key_hash, scopes, created_at, revoked_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            rusqlite::params![
+                record.id,
+                record.name,
+                record.key_hash,
+                scopes,
+                record.created_at,
+                record.revoked_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn revoke(&self, id: &str) -> anyhow::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let exists = conn.query_row(
+            "SELECT COUNT(*) FROM api_keys WHERE id = ?1",
+            [id],
+            |row| row.get::<_, i64>(0),
+        )? > 0;
+        conn.execute(
+            "UPDATE api_keys SET revoked_at = ?2 WHERE id = ?1 AND revoked_at IS NULL",
+            rusqlite::params![id, chrono::Utc::now().timestamp()],
+        )?;
+        Ok(exists)
+    }
+}
+
+/// Opens the SQLite store if a database path is given, the key file otherwise.
+pub fn open_key_store(config: &AuthConfig) -> anyhow::Result<Arc<dyn KeyStore>> {
+    let key_file = &config.key_file;
+    match &config.key_db {
+        Some(db) => {
+            info!("Using API key database: {}", db);
+            Ok(Arc::new(SqliteKeyStore::open(db)?))
+        }
+        None => {
+            info!("Using API key file: {}", key_file);
+            Ok(Arc::new(FileKeyStore::open(key_file)?))
+        }
+    }
+}
+
+/// Identity of the caller, attached to authenticated requests as an extension.
+#[derive(Debug, Clone)]
+pub struct AuthContext {
+    /// `None` when authentication is disabled.
+    pub key_id: Option<String>,
+    pub scopes: Vec<Scope>,
+}
+
+impl AuthContext {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+}
+
+/// The 403 for a key without `scope`.
+pub fn missing_scope(scope: Scope) -> ApiErrorResponse {
+    api_error(
+        StatusCode::FORBIDDEN,
+        format!("API key lacks the '{}' scope", scope),
+        "permission_error",
+        "insufficient_scope",
+    )
+}
+
+#[derive(Clone)]
+pub struct Authenticator {
+    store: Option<Arc<dyn KeyStore>>,
+}
+
+impl Authenticator {
+    pub fn new(store: Arc<dyn KeyStore>) -> Self {
+        match store.list() {
+            Ok(keys) if keys.iter().all(ApiKeyRecord::is_revoked) => {
+                warn!("No active API keys configured; every authenticated request will be rejected");
+                warn!("Issue one with: keys issue --name <name> --scopes chat,prove");
+            }
+            Ok(keys) => info!("Loaded {} API keys", keys.len()),
+            Err(e) => warn!("Failed to list API keys: {}", e),
+        }
+        Self { store: Some(store) }
+    }
+
+    /// Accepts every request. Only meant for local development.
+    pub fn disabled() -> Self {
+        warn!("API key authentication is DISABLED");
+        Self { store: None }
+    }
+
+    pub fn from_config(config: &AuthConfig) -> anyhow::Result<Self> {
+        if config.enabled {
+            Ok(Self::new(open_key_store(config)?))
+        } else {
+            Ok(Self::disabled())
+        }
+    }
+
+    /// Re-reads the key store, e.g. after the key file was edited.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        match &self.store {
+            Some(store) => store.reload(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn store(&self) -> Option<&Arc<dyn KeyStore>> {
+        self.store.as_ref()
+    }
+
+    /// State for [`require_scope`], guarding a group of routes.
+    pub fn scoped(&self, scope: Scope) -> RequireScope {
+        RequireScope {
+            auth: self.clone(),
+            scope,
+        }
+    }
+
+    pub fn authenticate(
+        &self,
+        headers: &HeaderMap,
+        scope: Scope,
+    ) -> Result<AuthContext, ApiErrorResponse> {
+        let Some(store) = &self.store else {
+            return Ok(AuthContext {
+                key_id: None,
+                scopes: vec![Scope::Admin],
+            });
+        };
+
+        let key = bearer_token(headers).ok_or_else(|| {
+            api_error(
+                StatusCode::UNAUTHORIZED,
+                "Missing API key. Send it as 'Authorization: Bearer <key>'",
+                "invalid_request_error",
+                "invalid_api_key",
+            )
+        })?;
+
+        let record = store
+            .find_by_hash(&hash_key(key))
+            .map_err(|e| {
+                api_error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Key store error: {}", e),
+                    "server_error",
+                    "key_store_error",
+                )
+            })?
+            .filter(|record| !record.is_revoked())
+            .ok_or_else(|| {
+                api_error(
+                    StatusCode::UNAUTHORIZED,
+                    "Invalid or revoked API key",
+                    "invalid_request_error",
+                    "invalid_api_key",
+                )
+            })?;
+
+        if !record.allows(scope) {
+            return Err(missing_scope(scope));
+        }
+
+        Ok(AuthContext {
+            key_id: Some(record.id),
+            scopes: record.scopes,
+        })
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+#[derive(Clone)]
+pub struct RequireScope {
+    auth: Authenticator,
+    scope: Scope,
+}
+
+/// Middleware rejecting requests without a valid key for the given scope.
+///
+/// ```ignore
+/// .route_layer(middleware::from_fn_with_state(auth.scoped(Scope::Chat), require_scope))
+/// ```
+pub async fn require_scope(
+    State(guard): State<RequireScope>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    match guard.auth.authenticate(request.headers(), guard.scope) {
+        Ok(context) => {
+            request.extensions_mut().insert(context);
+            next.run(request).await
+        }
+        Err((status, body)) => {
+            let mut response = (status, body).into_response();
+            if status == StatusCode::UNAUTHORIZED {
+                response.headers_mut().insert(
+                    header::WWW_AUTHENTICATE,
+                    header::HeaderValue::from_static("Bearer"),
+                );
+            }
+            response
+        }
+    }
+}
+
+// Key management endpoints
+
+#[derive(Serialize)]
+struct KeyView {
+    id: String,
+    name: String,
+    scopes: Vec<Scope>,
+    created_at: i64,
+    revoked_at: Option<i64>,
+}
+
+impl From<ApiKeyRecord> for KeyView {
+    fn from(record: ApiKeyRecord) -> Self {
+        Self {
+            id: record.id,
+            name: record.name,
+            scopes: record.scopes,
+            created_at: record.created_at,
+            revoked_at: record.revoked_at,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct IssueKeyRequest {
+    name: String,
+    scopes: Vec<Scope>,
+}
+
+/// `/v1/admin/keys` routes, guarded by the `admin` scope.
+pub fn admin_router<S>(auth: Authenticator) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/v1/admin/keys", get(list_keys).post(issue_key))
+        .route("/v1/admin/keys/:id", delete(revoke_key))
+        .route_layer(middleware::from_fn_with_state(
+            auth.scoped(Scope::Admin),
+            require_scope,
+        ))
+        .with_state(auth)
+}
+
+fn store_or_error(auth: &Authenticator) -> Result<&Arc<dyn KeyStore>, ApiErrorResponse> {
+    auth.store().ok_or_else(|| {
+        api_error(
+            StatusCode::NOT_FOUND,
+            "Authentication is disabled on this server",
+            "invalid_request_error",
+            "auth_disabled",
+        )
+    })
+}
+
+fn store_failure(e: anyhow::Error) -> ApiErrorResponse {
+    api_error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Key store error: {}", e),
+        "server_error",
+        "key_store_error",
+    )
+}
+
+async fn list_keys(
+    State(auth): State<Authenticator>,
+) -> Result<Json<serde_json::Value>, ApiErrorResponse> {
+    let keys = store_or_error(&auth)?.list().map_err(store_failure)?;
+    let data: Vec<KeyView> = keys.into_iter().map(KeyView::from).collect();
+    Ok(Json(serde_json::json!({ "object": "list", "data": data })))
+}
+
+async fn issue_key(
+    State(auth): State<Authenticator>,
+    Json(request): Json<IssueKeyRequest>,
+) -> Result<Json<serde_json::Value>, ApiErrorResponse> {
+    let store = store_or_error(&auth)?;
+    let (key, record) = generate_key(&request.name, request.scopes);
+    store.insert(record.clone()).map_err(store_failure)?;
+    info!("Issued API key {} ({})", record.id, record.name);
+
+    Ok(Json(serde_json::json!({
+        "id": record.id,
+        "name": record.name,
+        "scopes": record.scopes,
+        "key": key,
+    })))
+}
+
+async fn revoke_key(
+    State(auth): State<Authenticator>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, ApiErrorResponse> {
+    let store = store_or_error(&auth)?;
+    if !store.revoke(&id).map_err(store_failure)? {
+        return Err(api_error(
+            StatusCode::NOT_FOUND,
+            format!("No API key with id '{}'", id),
+            "invalid_request_error",
+            "key_not_found",
+        ));
+    }
+    info!("Revoked API key {}", id);
+    Ok(Json(serde_json::json!({ "id": id, "revoked": true })))
+}
+
+/// `keys` subcommand shared by both server binaries.
+#[derive(Subcommand, Debug)]
+pub enum KeysCommand {
+    /// Create a new key and print it once
+    Issue {
+        #[arg(long)]
+        name: String,
+        #[arg(long, value_delimiter = ',', default_value = "chat")]
+        scopes: Vec<Scope>,
+    },
+    /// Revoke a key by id
+    Revoke { id: String },
+    /// List all keys
+    List,
+}
+
+impl KeysCommand {
+    pub fn run(self, store: &dyn KeyStore) -> anyhow::Result<()> {
+        match self {
+            KeysCommand::Issue { name, scopes } => {
+                let (key, record) = generate_key(&name, scopes);
+                store.insert(record.clone())?;
+                println!("id:     {}", record.id);
+                println!("scopes: {}", join_scopes(&record.scopes));
+                println!("key:    {}", key);
+                println!("Store this key now; it cannot be shown again.");
+            }
+            KeysCommand::Revoke { id } => {
+                if store.revoke(&id)? {
+                    println!("Revoked {}", id);
+                } else {
+                    anyhow::bail!("No API key with id '{}'", id);
+                }
+            }
+            KeysCommand::List => {
+                for record in store.list()? {
+                    let status = if record.is_revoked() { "revoked" } else { "active" };
+                    println!(
+                        "{}\t{}\t{}\t{}",
+                        record.id,
+                        record.name,
+                        join_scopes(&record.scopes),
+                        status
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authenticator() -> Authenticator {
+        Authenticator::new(Arc::new(SqliteKeyStore::open(":memory:").unwrap()))
+    }
+
+    fn issue(auth: &Authenticator, scopes: Vec<Scope>) -> (String, ApiKeyRecord) {
+        let (key, record) = generate_key("test", scopes);
+        auth.store().unwrap().insert(record.clone()).unwrap();
+        (key, record)
+    }
+
+    fn bearer(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, value.parse().unwrap());
+        headers
+    }
+
+    fn rejection(result: Result<AuthContext, ApiErrorResponse>) -> (StatusCode, Option<String>) {
+        let (status, Json(error)) = result.unwrap_err();
+        (status, error.error.code)
+    }
+
+    #[test]
+    fn missing_and_malformed_keys_are_unauthorized() {
+        let auth = authenticator();
+        let (key, _) = issue(&auth, vec![Scope::Chat]);
+        let unauthorized = (StatusCode::UNAUTHORIZED, Some("invalid_api_key".to_string()));
+
+        for headers in [
+            HeaderMap::new(),
+            bearer(&format!("Basic {}", key)),
+            bearer("Bearer "),
+            bearer("Bearer bnk_not-a-key"),
+        ] {
+            assert_eq!(rejection(auth.authenticate(&headers, Scope::Chat)), unauthorized);
+        }
+        let caller = auth.authenticate(&bearer(&format!("Bearer {}", key)), Scope::Chat);
+        assert!(caller.is_ok());
+    }
+
+    #[test]
+    fn revoked_keys_are_unauthorized() {
+        let auth = authenticator();
+        let (key, record) = issue(&auth, vec![Scope::Chat]);
+        assert!(auth.store().unwrap().revoke(&record.id).unwrap());
+
+        let headers = bearer(&format!("Bearer {}", key));
+        let (status, code) = rejection(auth.authenticate(&headers, Scope::Chat));
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(code.as_deref(), Some("invalid_api_key"));
+    }
+
+    #[test]
+    fn keys_need_the_route_scope() {
+        let auth = authenticator();
+        let (key, record) = issue(&auth, vec![Scope::Chat]);
+        let headers = bearer(&format!("Bearer {}", key));
+
+        let (status, code) = rejection(auth.authenticate(&headers, Scope::Prove));
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(code.as_deref(), Some("insufficient_scope"));
+        let caller = auth.authenticate(&headers, Scope::Chat).unwrap();
+        assert_eq!(caller.key_id, Some(record.id));
+        assert!(!caller.allows(Scope::Prove));
+    }
+
+    #[test]
+    fn admin_keys_pass_every_scope() {
+        let auth = authenticator();
+        let (key, _) = issue(&auth, vec![Scope::Admin]);
+        let headers = bearer(&format!("Bearer {}", key));
+
+        for scope in [Scope::Chat, Scope::Prove, Scope::Admin] {
+            let caller = auth.authenticate(&headers, scope).unwrap();
+            assert!(caller.allows(Scope::Chat) && caller.allows(Scope::Prove));
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Answers to deterministic requests, served again without re-running
+//! inference or proving.
+//!
+//! A request is deterministic when it samples greedily or with a fixed seed.
+//! Its key is the SHA-256 of whatever decides the answer: the model digest,
+//! the prompt tokens, the sampling parameters and the seeds. The cached value
+//! holds the answer and its proof, so a hit returns the existing receipt.
+//!
+//! Entries live in an LRU in memory and, with `dir` set, as files that
+//! survive restarts. `Cache-Control: no-cache` skips the lookup and
+//! `no-store` skips storing the answer.
+
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use lru::LruCache;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    future::Future,
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+
+use crate::config::CacheConfig;
+use crate::metrics::Metrics;
+
+/// Response header saying whether the answer came from the cache.
+pub const CACHE_HEADER: HeaderName = HeaderName::from_static("x-cache");
+
+/// What a request allows of the cache, from its `Cache-Control` header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    /// Compute a fresh answer even if one is cached.
+    pub no_cache: bool,
+    /// Don't cache the answer.
+    pub no_store: bool,
+}
+
+impl CacheControl {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut control = Self::default();
+        let directives = headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+        for directive in directives {
+            let directive = directive.trim();
+            if directive.eq_ignore_ascii_case("no-cache") {
+                control.no_cache = true;
+            } else if directive.eq_ignore_ascii_case("no-store") {
+                control.no_store = true;
+            }
+        }
+        control
+    }
+}
+
+/// How a response relates to the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    Hit,
+    /// Computed, and cached unless the request said `no-store`.
+    Miss,
+    /// Not cacheable: the cache is disabled or the request isn't
+    /// deterministic.
+    Skipped,
+}
+
+impl CacheStatus {
+    /// `X-Cache: hit` or `miss`; nothing for uncacheable requests.
+    pub fn headers(self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let value = match self {
+            CacheStatus::Hit => "hit",
+            CacheStatus::Miss => "miss",
+            CacheStatus::Skipped => return headers,
+        };
+        headers.insert(CACHE_HEADER, HeaderValue::from_static(value));
+        headers
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    stored: u64,
+    value: serde_json::Value,
+}
+
+pub struct ResponseCache {
+    memory: Mutex<LruCache<String, Entry>>,
+    dir: Option<PathBuf>,
+    ttl: Option<Duration>,
+    metrics: Arc<Metrics>,
+}
+
+impl ResponseCache {
+    /// Opens the configured cache, or `None` when `[cache]` is disabled.
+    pub fn from_config(
+        config: &CacheConfig,
+        metrics: Arc<Metrics>,
+    ) -> anyhow::Result<Option<Arc<Self>>> {
+        if !config.enabled {
+            info!("Response cache disabled");
+            return Ok(None);
+        }
+        let capacity = NonZeroUsize::new(config.memory_entries).unwrap_or(NonZeroUsize::MIN);
+        let dir = config.dir.as_ref().map(PathBuf::from);
+        match &dir {
+            Some(dir) => {
+                std::fs::create_dir_all(dir)?;
+                info!(
+                    "Caching up to {} responses in memory and in {:?}",
+                    capacity, dir
+                );
+            }
+            None => info!("Caching up to {} responses in memory", capacity),
+        }
+        Ok(Some(Arc::new(Self {
+            memory: Mutex::new(LruCache::new(capacity)),
+            dir,
+            ttl: (config.ttl_secs > 0).then(|| Duration::from_secs(config.ttl_secs)),
+            metrics,
+        })))
+    }
+
+    /// The cache key of everything that decides an answer.
+    pub fn key(parts: &impl Serialize) -> String {
+        let json = serde_json::to_vec(parts).expect("cache key serializes");
+        hex::encode(Sha256::digest(json))
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let in_memory = self.memory.lock().unwrap().get(key).cloned();
+        let (entry, tier) = match in_memory {
+            Some(entry) => (Some(entry), "memory"),
+            None => (self.read_disk(key).await, "disk"),
+        };
+        let value = match entry.filter(|entry| !self.expired(entry)) {
+            Some(entry) => {
+                let value = serde_json::from_value(entry.value.clone()).ok();
+                if value.is_some() && tier == "disk" {
+                    self.memory.lock().unwrap().put(key.to_string(), entry);
+                }
+                value
+            }
+            None => {
+                self.memory.lock().unwrap().pop(key);
+                None
+            }
+        };
+        let result = if value.is_some() { tier } else { "miss" };
+        self.metrics
+            .cache_lookups
+            .with_label_values(&[result])
+            .inc();
+        value
+    }
+
+    pub async fn put<T: Serialize>(&self, key: &str, value: &T) {
+        let entry = match serde_json::to_value(value) {
+            Ok(value) => Entry {
+                stored: now(),
+                value,
+            },
+            Err(e) => {
+                warn!("Cannot cache response: {}", e);
+                return;
+            }
+        };
+        if let Some(dir) = &self.dir {
+            let path = dir.join(format!("{}.json", key));
+            // Readers never see a partly written entry
+            let tmp_path = path.with_extension("tmp");
+            let written = async {
+                tokio::fs::write(&tmp_path, serde_json::to_vec(&entry)?).await?;
+                tokio::fs::rename(&tmp_path, &path).await?;
+                anyhow::Ok(())
+            };
+            if let Err(e) = written.await {
+                warn!("Cannot write cache entry {:?}: {}", path, e);
+            }
+        }
+        self.memory.lock().unwrap().put(key.to_string(), entry);
+    }
+
+    async fn read_disk(&self, key: &str) -> Option<Entry> {
+        let path = self.dir.as_ref()?.join(format!("{}.json", key));
+        let content = tokio::fs::read(&path).await.ok()?;
+        match serde_json::from_slice::<Entry>(&content) {
+            Ok(entry) if self.expired(&entry) => {
+                let _ = tokio::fs::remove_file(&path).await;
+                None
+            }
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                warn!("Unreadable cache entry {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    fn expired(&self, entry: &Entry) -> bool {
+        self.ttl
+            .is_some_and(|ttl| now().saturating_sub(entry.stored) >= ttl.as_secs())
+    }
+}
+
+/// Answers from `cache` under `key`, or runs `compute` and caches its answer
+/// if `cacheable` says so. Without a cache or a key, only runs `compute`.
+pub async fn cached<T, E>(
+    cache: Option<&Arc<ResponseCache>>,
+    key: Option<String>,
+    control: CacheControl,
+    compute: impl Future<Output = Result<T, E>>,
+    cacheable: impl FnOnce(&T) -> bool,
+) -> Result<(T, CacheStatus), E>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let (Some(cache), Some(key)) = (cache, key) else {
+        return compute.await.map(|value| (value, CacheStatus::Skipped));
+    };
+    if !control.no_cache {
+        if let Some(value) = cache.get(&key).await {
+            info!("Answering from the response cache");
+            return Ok((value, CacheStatus::Hit));
+        }
+    }
+    let value = compute.await?;
+    if !control.no_store && cacheable(&value) {
+        cache.put(&key, &value).await;
+    }
+    Ok((value, CacheStatus::Miss))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
@@ -0,0 +1,442 @@
+//! Layered server configuration.
+//!
+//! Settings are resolved in order: built-in defaults, the TOML file,
+//! environment variables, then command-line flags. Environment variables are
+//! named `<PREFIX>_<SECTION>__<KEY>`, e.g. `BITNET_ZKML_INFERENCE__THREADS=8`.
+//! A value is read as the type of the setting it names: verbatim for strings,
+//! as TOML otherwise (so arrays are written `["a", "b"]`). Variables that name
+//! no setting, such as `BITNET_API_KEY`, are skipped with a warning.
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use tracing::{info, warn};
+
+/// `[auth]` section shared by both servers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Set to false to accept anonymous requests (local development only).
+    pub enabled: bool,
+    /// JSON file holding hashed API keys.
+    pub key_file: String,
+    /// SQLite database holding hashed API keys; overrides `key_file`.
+    pub key_db: Option<String>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            key_file: "./api_keys.json".to_string(),
+            key_db: None,
+        }
+    }
+}
+
+/// `[limits]` section shared by both servers. Zero disables a limit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Requests per minute allowed per API key or IP.
+    pub rate_limit_per_minute: u32,
+    /// Requests a client may burst above the per-minute rate.
+    pub rate_limit_burst: u32,
+    /// Proofs a single client may run at once.
+    pub proof_concurrency_per_client: usize,
+    /// Proofs that may run at once across all clients.
+    pub proof_concurrency: usize,
+    /// Proofs a single client may request per UTC day.
+    pub proofs_per_day: u32,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            rate_limit_per_minute: 60,
+            rate_limit_burst: 10,
+            proof_concurrency_per_client: 1,
+            proof_concurrency: 2,
+            proofs_per_day: 100,
+        }
+    }
+}
+
+/// `[store]` section shared by both servers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreConfig {
+    /// Record completions and their proofs.
+    pub enabled: bool,
+    /// SQLite database of completions.
+    pub path: String,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: "./completions.db".to_string(),
+        }
+    }
+}
+
+/// `[cache]` section shared by both servers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Answer repeated deterministic requests from the cache.
+    pub enabled: bool,
+    /// Responses kept in memory; the least recently used goes first.
+    pub memory_entries: usize,
+    /// Directory of the on-disk tier, which survives restarts; unset keeps
+    /// responses in memory only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+    /// Seconds a cached response is served for; 0 serves it until evicted.
+    pub ttl_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            memory_entries: 256,
+            dir: None,
+            ttl_secs: 0,
+        }
+    }
+}
+
+/// `[receipts]` section shared by both servers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReceiptsConfig {
+    pub backend: ReceiptBackendKind,
+    /// Directory of the `local` backend.
+    pub dir: String,
+    pub s3: S3Config,
+    /// Days a receipt is kept after it was last stored; 0 keeps receipts
+    /// forever.
+    pub retention_days: u64,
+    /// Seconds between garbage collection runs.
+    pub gc_interval_secs: u64,
+}
+
+impl Default for ReceiptsConfig {
+    fn default() -> Self {
+        Self {
+            backend: ReceiptBackendKind::Local,
+            dir: "./receipts".to_string(),
+            s3: S3Config::default(),
+            retention_days: 0,
+            gc_interval_secs: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReceiptBackendKind {
+    /// Receipts are not kept.
+    None,
+    Local,
+    S3,
+}
+
+/// An S3-compatible bucket. Credentials default to `AWS_ACCESS_KEY_ID` and
+/// `AWS_SECRET_ACCESS_KEY`.
+///
+/// The secret key is masked wherever the config is shown: `--print-config`
+/// and the restart warnings of a reload.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+    /// e.g. `https://s3.us-east-1.amazonaws.com` or `http://127.0.0.1:9000`.
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    /// Prepended to every key, e.g. `bitnet/`.
+    pub prefix: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_key_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "redact")]
+    pub secret_access_key: Option<String>,
+}
+
+/// Stands in for a secret in printed configuration.
+const REDACTED: &str = "<redacted>";
+
+fn redact<S: serde::Serializer>(secret: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    secret.as_ref().map(|_| REDACTED).serialize(serializer)
+}
+
+impl std::fmt::Debug for S3Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Config")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("prefix", &self.prefix)
+            .field("access_key_id", &self.access_key_id)
+            .field(
+                "secret_access_key",
+                &self.secret_access_key.as_ref().map(|_| REDACTED),
+            )
+            .finish()
+    }
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            endpoint: "http://127.0.0.1:9000".to_string(),
+            bucket: "receipts".to_string(),
+            region: "us-east-1".to_string(),
+            prefix: String::new(),
+            access_key_id: None,
+            secret_access_key: None,
+        }
+    }
+}
+
+/// Resolves a configuration type from its layers. Keeps the command-line
+/// overrides so that a reload re-applies them on top of the edited file.
+pub struct ConfigLoader {
+    path: Option<PathBuf>,
+    env_prefix: String,
+    overrides: toml::Table,
+}
+
+impl ConfigLoader {
+    /// `path` is used if given; otherwise `default_path` is read when it exists.
+    pub fn new(path: Option<&str>, default_path: &str, env_prefix: &str) -> Self {
+        let path = match path {
+            Some(path) => Some(PathBuf::from(path)),
+            None => Some(PathBuf::from(default_path)).filter(|p| p.exists()),
+        };
+        Self {
+            path,
+            env_prefix: env_prefix.to_string(),
+            overrides: toml::Table::new(),
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Records a command-line value for a dotted key such as `inference.threads`.
+    /// `None` leaves the lower layers untouched.
+    pub fn set<V: Into<toml::Value>>(&mut self, key: &str, value: Option<V>) -> &mut Self {
+        if let Some(value) = value {
+            insert_dotted(&mut self.overrides, key, value.into());
+        }
+        self
+    }
+
+    pub fn load<T>(&self) -> anyhow::Result<T>
+    where
+        T: Default + Serialize + DeserializeOwned,
+    {
+        let mut merged = toml::Table::try_from(T::default())?;
+
+        if let Some(path) = &self.path {
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read config {:?}: {}", path, e))?;
+            let file: toml::Table = toml::from_str(&content)
+                .map_err(|e| anyhow::anyhow!("Invalid config {:?}: {}", path, e))?;
+            merge(&mut merged, file);
+        }
+
+        merge(&mut merged, self.env_layer::<T>()?);
+        merge(&mut merged, self.overrides.clone());
+
+        toml::Value::Table(merged)
+            .try_into()
+            .map_err(|e| anyhow::anyhow!("Invalid configuration: {}", e))
+    }
+
+    fn env_layer<T>(&self) -> anyhow::Result<toml::Table>
+    where
+        T: Default + Serialize + DeserializeOwned,
+    {
+        let defaults = toml::Table::try_from(T::default())?;
+        let prefix = format!("{}_", self.env_prefix);
+        let mut table = toml::Table::new();
+        let mut vars: Vec<(String, String)> = std::env::vars().collect();
+        vars.sort();
+        for (name, raw) in vars {
+            let Some(rest) = name.strip_prefix(&prefix) else {
+                continue;
+            };
+            let key = rest.to_lowercase().replace("__", ".");
+            match env_value::<T>(&defaults, &key, &raw) {
+                Some(value) => insert_dotted(&mut table, &key, value),
+                None if lookup_dotted(&defaults, &key).is_some() => {
+                    anyhow::bail!("Invalid value for {}: {:?}", name, raw);
+                }
+                None => warn!("Ignoring {}: it names no configuration setting", name),
+            }
+        }
+        Ok(table)
+    }
+}
+
+/// Reads `raw` as the setting at `key`: verbatim where the setting is a
+/// string, otherwise as TOML, falling back to a string for optional string
+/// settings that have no default. `None` when no reading fits the setting,
+/// or there is no such setting.
+fn env_value<T>(defaults: &toml::Table, key: &str, raw: &str) -> Option<toml::Value>
+where
+    T: DeserializeOwned,
+{
+    let string = toml::Value::String(raw.to_string());
+    let candidates = match lookup_dotted(defaults, key) {
+        Some(toml::Value::String(_)) => vec![string],
+        _ => parse_env_value(raw).into_iter().chain([string]).collect(),
+    };
+    candidates.into_iter().find(|value| {
+        let mut trial = defaults.clone();
+        insert_dotted(&mut trial, key, value.clone());
+        toml::Value::Table(trial).try_into::<T>().is_ok()
+    })
+}
+
+fn parse_env_value(raw: &str) -> Option<toml::Value> {
+    toml::from_str::<toml::Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+}
+
+fn lookup_dotted<'a>(table: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+    let mut parts = key.split('.');
+    let mut value = table.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_table()?.get(part)?;
+    }
+    Some(value)
+}
+
+fn insert_dotted(table: &mut toml::Table, key: &str, value: toml::Value) {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().unwrap_or_default();
+    let mut current = table;
+    for part in parts {
+        let entry = current
+            .entry(part.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        if !entry.is_table() {
+            *entry = toml::Value::Table(toml::Table::new());
+        }
+        current = entry.as_table_mut().unwrap();
+    }
+    current.insert(last.to_string(), value);
+}
+
+fn merge(base: &mut toml::Table, layer: toml::Table) {
+    for (key, value) in layer {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(layer_table)) => {
+                merge(base_table, layer_table);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// The effective configuration, swapped atomically on reload.
+pub struct SharedConfig<T> {
+    current: Arc<RwLock<Arc<T>>>,
+}
+
+impl<T> Clone for SharedConfig<T> {
+    fn clone(&self) -> Self {
+        Self {
+            current: Arc::clone(&self.current),
+        }
+    }
+}
+
+impl<T> SharedConfig<T> {
+    pub fn new(config: T) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(config))),
+        }
+    }
+
+    /// A snapshot that stays consistent for the whole request.
+    pub fn get(&self) -> Arc<T> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    pub fn replace(&self, config: T) {
+        *self.current.write().unwrap() = Arc::new(config);
+    }
+}
+
+/// Prints the effective configuration as TOML, for `--print-config`.
+pub fn print_config<T: Serialize>(config: &T) -> anyhow::Result<()> {
+    print!("{}", toml::to_string_pretty(config)?);
+    Ok(())
+}
+
+/// Calls `reload` every time the process receives SIGHUP.
+pub fn reload_on_sighup<F>(mut reload: F)
+where
+    F: FnMut() + Send + 'static,
+{
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Cannot listen for SIGHUP, hot reload disabled: {}", e);
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            info!("SIGHUP received, reloading configuration");
+            reload();
+        }
+    });
+
+    #[cfg(not(unix))]
+    {
+        let _ = &mut reload;
+        warn!("Configuration hot reload is only supported on Unix");
+    }
+}
+
+/// Warns about a changed setting that only takes effect after a restart.
+pub fn warn_if_changed<V: PartialEq + std::fmt::Debug>(name: &str, old: &V, new: &V) {
+    if old != new {
+        warn!("{} changed from {:?} to {:?}; restart to apply", name, old, new);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn s3_secret_is_never_shown() {
+        let config = S3Config {
+            access_key_id: Some("AKIDEXAMPLE".to_string()),
+            secret_access_key: Some("wJalrXUtnFEMI".to_string()),
+            ..S3Config::default()
+        };
+        let printed = toml::to_string_pretty(&config).unwrap();
+        assert!(printed.contains("AKIDEXAMPLE"));
+        assert!(printed.contains("secret_access_key = \"<redacted>\""));
+        assert!(!format!("{:?}", config).contains("wJalrXUtnFEMI"));
+        let unset = toml::to_string_pretty(&S3Config::default()).unwrap();
+        assert!(!unset.contains("secret_access_key"));
+    }
+}
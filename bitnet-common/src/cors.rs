@@ -0,0 +1,44 @@
+use axum::http::{header, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::warn;
+
+use crate::cache::CACHE_HEADER;
+use crate::policy::{ZK_IMAGE_ID_HEADER, ZK_JOURNAL_DIGEST_HEADER, ZK_VERIFIED_HEADER};
+
+/// Builds the CORS layer from the configured origins.
+///
+/// An empty list allows no cross-origin requests at all. `*` has to be
+/// listed explicitly to get the old permissive behaviour. Browsers may send
+/// `Cache-Control` to steer the response cache, and read the proof, cache
+/// and `Retry-After` headers of a response.
+pub fn cors_layer(origins: &[String]) -> anyhow::Result<CorsLayer> {
+    let layer = CorsLayer::new()
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::CACHE_CONTROL,
+        ])
+        .expose_headers([
+            ZK_VERIFIED_HEADER,
+            ZK_IMAGE_ID_HEADER,
+            ZK_JOURNAL_DIGEST_HEADER,
+            CACHE_HEADER,
+            header::RETRY_AFTER,
+        ]);
+
+    if origins.iter().any(|origin| origin == "*") {
+        warn!("CORS allows any origin; do not expose this server to the internet");
+        return Ok(layer.allow_origin(AllowOrigin::any()));
+    }
+
+    let origins = origins
+        .iter()
+        .map(|origin| {
+            HeaderValue::from_str(origin)
+                .map_err(|_| anyhow::anyhow!("Invalid CORS origin: {}", origin))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(layer.allow_origin(AllowOrigin::list(origins)))
+}
@@ -0,0 +1,20 @@
+use axum::{http::StatusCode, response::Json};
+
+pub use bitnet_openai::error::{ApiError, ErrorDetails, ValidationError};
+
+/// Error half of a handler result, as returned by the chat handlers.
+pub type ApiErrorResponse = (StatusCode, Json<ApiError>);
+
+pub fn api_error(
+    status: StatusCode,
+    message: impl Into<String>,
+    error_type: &str,
+    code: &str,
+) -> ApiErrorResponse {
+    (status, Json(ApiError::new(message, error_type, code)))
+}
+
+/// 400 for a request that fails `validate()`, naming the field at fault.
+pub fn invalid_request(error: ValidationError) -> ApiErrorResponse {
+    (StatusCode::BAD_REQUEST, Json(error.into()))
+}
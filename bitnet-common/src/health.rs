@@ -0,0 +1,305 @@
+//! Liveness and readiness probes: `GET /livez` and `GET /readyz`.
+//!
+//! Liveness only says the process is serving requests. Readiness runs each
+//! server's checks (the model loads, its tokenizer parses, the prover is
+//! there and proves the configured guest, the queues have room) and answers
+//! 503 unless all pass, with every check's outcome in the body. Only admin
+//! callers see each check's detail, which names files and binaries; anyone
+//! else gets the check names and whether they passed.
+//!
+//! Checks that only depend on a file are cached until the file changes, so
+//! probing often stays cheap. A prover that fails to report its image ID is
+//! not asked again for [`IMAGE_ID_RETRY`].
+
+use axum::{
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use bitnet_core::{GgufFile, ModelConfig};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::process::Command;
+
+use crate::attestation::parse_image_id;
+use crate::auth::{Authenticator, Scope};
+use crate::models::ModelEntry;
+use crate::process::output_with_timeout;
+use crate::tokenize::TokenizerCache;
+
+/// How long a prover gets to report its image ID.
+const IMAGE_ID_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a failed `--image-id` probe is reported without rerunning it, so
+/// frequent probes can't keep a broken prover busy.
+pub const IMAGE_ID_RETRY: Duration = Duration::from_secs(30);
+
+/// The outcome of one readiness check.
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub ok: bool,
+    /// Omitted from [`Readiness::redacted`] bodies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    pub fn new(name: &'static str, result: Result<String, String>) -> Self {
+        let (ok, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        Self {
+            name,
+            model: None,
+            ok,
+            detail: Some(detail),
+        }
+    }
+
+    pub fn for_model(mut self, model: &str) -> Self {
+        self.model = Some(model.to_string());
+        self
+    }
+}
+
+/// The `/readyz` body: 200 when every check passed, 503 otherwise.
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub status: &'static str,
+    pub checks: Vec<Check>,
+}
+
+impl Readiness {
+    pub fn new(checks: Vec<Check>) -> Self {
+        let ready = checks.iter().all(|check| check.ok);
+        Self {
+            status: if ready { "ready" } else { "not_ready" },
+            checks,
+        }
+    }
+
+    /// Drops every check's detail, for callers without an admin key.
+    pub fn redacted(mut self) -> Self {
+        for check in &mut self.checks {
+            check.detail = None;
+        }
+        self
+    }
+
+    /// Keeps the details only when `headers` carry an admin key, or
+    /// authentication is off.
+    pub fn for_caller(self, authenticator: &Authenticator, headers: &HeaderMap) -> Self {
+        match authenticator.authenticate(headers, Scope::Admin) {
+            Ok(_) => self,
+            Err(_) => self.redacted(),
+        }
+    }
+}
+
+impl IntoResponse for Readiness {
+    fn into_response(self) -> Response {
+        let status = if self.status == "ready" {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        (status, Json(self)).into_response()
+    }
+}
+
+/// `GET /livez`: answers as long as the server is running.
+pub async fn livez() -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "status": "alive" }))
+}
+
+/// A file, as of its last modification.
+type FileKey = (PathBuf, u64, Option<SystemTime>);
+
+fn file_key(path: &Path) -> Result<FileKey, String> {
+    let metadata = std::fs::metadata(path).map_err(|e| format!("{:?}: {}", path, e))?;
+    Ok((path.to_path_buf(), metadata.len(), metadata.modified().ok()))
+}
+
+/// Readiness checks whose results are cached per file.
+#[derive(Default)]
+pub struct Probes {
+    models: Mutex<HashMap<FileKey, Result<String, String>>>,
+    image_ids: Mutex<HashMap<FileKey, String>>,
+    /// Failed `--image-id` probes, and when they ran.
+    failed_image_ids: Mutex<HashMap<FileKey, (Instant, String)>>,
+}
+
+impl Probes {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// `model`: the weights' GGUF header parses into a [`ModelConfig`].
+    /// Only the header is read; loading the weights is the backend's job.
+    pub async fn model(&self, entry: &ModelEntry) -> Check {
+        let result = match file_key(&entry.weights_path) {
+            Ok(key) => {
+                let cached = self.models.lock().unwrap().get(&key).cloned();
+                match cached {
+                    Some(result) => result,
+                    None => {
+                        let path = entry.weights_path.clone();
+                        let result = tokio::task::spawn_blocking(move || read_header(&path))
+                            .await
+                            .map_err(|e| e.to_string())
+                            .and_then(|result| result);
+                        self.models.lock().unwrap().insert(key, result.clone());
+                        result
+                    }
+                }
+            }
+            Err(e) => Err(e),
+        };
+        Check::new("model", result).for_model(&entry.id)
+    }
+
+    /// `prover`: `binary` runs, and proves the guest the model's `image_id`
+    /// names. The binary reports its guest with `--image-id`.
+    pub async fn prover(&self, entry: &ModelEntry, binary: &Path) -> Check {
+        let result = match self.image_id(binary).await {
+            Ok(reported) => match entry.image_id.as_deref() {
+                None => Ok(format!(
+                    "{:?} proves guest {}; no image_id configured to check it against",
+                    binary, reported
+                )),
+                Some(expected) => match (parse_image_id(expected), parse_image_id(&reported)) {
+                    (Some(a), Some(b)) if a == b => {
+                        Ok(format!("{:?} proves guest {}", binary, reported))
+                    }
+                    (None, _) => Err(format!(
+                        "configured image_id {} is not a 32-byte hex digest",
+                        expected
+                    )),
+                    _ => Err(format!(
+                        "{:?} proves guest {}, but the configured image_id is {}",
+                        binary, reported, expected
+                    )),
+                },
+            },
+            Err(e) => Err(e),
+        };
+        Check::new("prover", result).for_model(&entry.id)
+    }
+
+    /// The image ID `binary` reports with `--image-id`.
+    pub async fn image_id(&self, binary: &Path) -> Result<String, String> {
+        let key = file_key(binary)?;
+        if let Some(reported) = self.image_ids.lock().unwrap().get(&key) {
+            return Ok(reported.clone());
+        }
+        if let Some((probed, error)) = self.failed_image_ids.lock().unwrap().get(&key) {
+            if probed.elapsed() < IMAGE_ID_RETRY {
+                return Err(error.clone());
+            }
+        }
+        let output = output_with_timeout(Command::new(binary).arg("--image-id"), IMAGE_ID_TIMEOUT)
+            .await
+            .map_err(|e| format!("{:?} --image-id: {}", binary, e));
+        let result = output.and_then(|output| {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let reported = stdout.trim();
+            if output.status.success() && parse_image_id(reported).is_some() {
+                Ok(reported.to_string())
+            } else {
+                Err(format!(
+                    "{:?} --image-id did not print an image ID ({})",
+                    binary, output.status
+                ))
+            }
+        });
+        // A failure may be transient, so it is only remembered briefly
+        match &result {
+            Ok(reported) => {
+                self.failed_image_ids.lock().unwrap().remove(&key);
+                self.image_ids.lock().unwrap().insert(key, reported.clone());
+            }
+            Err(e) => {
+                let failed = (Instant::now(), e.clone());
+                self.failed_image_ids.lock().unwrap().insert(key, failed);
+            }
+        }
+        result
+    }
+}
+
+fn read_header(path: &Path) -> Result<String, String> {
+    let config = GgufFile::open(path).and_then(|gguf| ModelConfig::from_gguf(&gguf));
+    match config {
+        Ok(config) => Ok(format!(
+            "{} with {} layers, context {}",
+            config.architecture, config.block_count, config.context_length
+        )),
+        Err(e) => Err(format!("{:?}: {:#}", path, e)),
+    }
+}
+
+/// `tokenizer`: the model's tokenizer parses.
+pub async fn tokenizer(tokenizers: &TokenizerCache, entry: &ModelEntry) -> Check {
+    let result = tokenizers
+        .get(entry)
+        .await
+        .map(|tokenizer| format!("{} tokens", tokenizer.tokenizer.vocab_size()))
+        .map_err(|e| format!("{:#}", e));
+    Check::new("tokenizer", result).for_model(&entry.id)
+}
+
+/// Fails once `used` reaches `limit`; a zero limit never fails.
+pub fn queue(name: &'static str, used: usize, limit: usize) -> Check {
+    let result = if limit == 0 {
+        Ok(format!("{} in use, no limit", used))
+    } else if used < limit {
+        Ok(format!("{} of {} in use", used, limit))
+    } else {
+        Err(format!("saturated: {} of {} in use", used, limit))
+    };
+    Check::new(name, result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn failed_image_id_probes_are_not_rerun() {
+        let dir = std::env::temp_dir().join(format!("bitnet-health-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let runs = dir.join("runs");
+        let prover = dir.join("prover");
+        let script = format!("#!/bin/sh\necho run >> {:?}\nexit 1\n", runs);
+        std::fs::write(&prover, script).unwrap();
+        std::fs::set_permissions(&prover, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let entry: ModelEntry =
+            serde_json::from_value(serde_json::json!({"id": "m", "weights_path": "m.gguf"}))
+                .unwrap();
+
+        let probes = Probes::new();
+        for _ in 0..3 {
+            assert!(!probes.prover(&entry, &prover).await.ok);
+        }
+        assert_eq!(std::fs::read_to_string(&runs).unwrap().lines().count(), 1);
+
+        let readiness = Readiness::new(vec![probes.prover(&entry, &prover).await]).redacted();
+        assert_eq!(
+            serde_json::to_value(&readiness).unwrap(),
+            serde_json::json!({
+                "status": "not_ready",
+                "checks": [{"name": "prover", "model": "m", "ok": false}],
+            })
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
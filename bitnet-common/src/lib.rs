@@ -0,0 +1,20 @@
+//! Shared server infrastructure for the BitNet zkML inference servers
+//! (`api-server` and `bitnet-zkml`).
+
+pub mod attestation;
+pub mod auth;
+pub mod cache;
+pub mod config;
+pub mod cors;
+pub mod error;
+pub mod health;
+pub mod logprobs;
+pub mod metrics;
+pub mod models;
+pub mod policy;
+pub mod process;
+pub mod rate_limit;
+pub mod receipts;
+pub mod shutdown;
+pub mod store;
+pub mod tokenize;
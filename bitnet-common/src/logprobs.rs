@@ -0,0 +1,122 @@
+//! Token log-probabilities, from the native backend or a zkVM journal, in the
+//! chat and legacy completion response formats.
+
+use bitnet_core::TokenLogprobs;
+use bitnet_openai::chat::{ChoiceLogprobs, TokenLogprob, TopLogprob};
+use bitnet_openai::completions::CompletionLogprobs;
+use serde::{Deserialize, Serialize};
+
+/// A sampled token's log-probability and the most likely alternatives, by
+/// token ID. `bitnet-host --logprobs` writes these, decoded from the journal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SampledLogprob {
+    pub token: u32,
+    pub logprob: f32,
+    #[serde(default)]
+    pub top_logprobs: Vec<(u32, f32)>,
+}
+
+impl From<TokenLogprobs> for SampledLogprob {
+    fn from(logprobs: TokenLogprobs) -> Self {
+        Self {
+            token: logprobs.token,
+            logprob: logprobs.logprob,
+            top_logprobs: logprobs.top,
+        }
+    }
+}
+
+/// A sampled token's log-probability, with the bytes of it and of its
+/// alternatives.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Logprob {
+    pub bytes: Vec<u8>,
+    pub logprob: f32,
+    pub top: Vec<(Vec<u8>, f32)>,
+}
+
+/// Looks up the bytes of every token with `token_bytes`, from the tokenizer
+/// that produced the token IDs.
+pub fn resolve(
+    sampled: &[SampledLogprob],
+    token_bytes: impl Fn(u32) -> anyhow::Result<Vec<u8>>,
+) -> anyhow::Result<Vec<Logprob>> {
+    sampled
+        .iter()
+        .map(|sampled| {
+            Ok(Logprob {
+                bytes: token_bytes(sampled.token)?,
+                logprob: sampled.logprob,
+                top: sampled
+                    .top_logprobs
+                    .iter()
+                    .map(|&(token, logprob)| Ok((token_bytes(token)?, logprob)))
+                    .collect::<anyhow::Result<_>>()?,
+            })
+        })
+        .collect()
+}
+
+/// Drops the tokens that start at or after byte `len` of the generated text,
+/// once it has been cut at a stop sequence.
+pub fn truncate(logprobs: &mut Vec<Logprob>, len: usize) {
+    let mut start = 0;
+    logprobs.retain(|logprob| {
+        let keep = start < len;
+        start += logprob.bytes.len();
+        keep
+    });
+}
+
+pub fn chat_logprobs(logprobs: &[Logprob]) -> ChoiceLogprobs {
+    ChoiceLogprobs {
+        content: logprobs
+            .iter()
+            .map(|logprob| TokenLogprob {
+                token: text(&logprob.bytes),
+                logprob: logprob.logprob,
+                bytes: Some(logprob.bytes.clone()),
+                top_logprobs: logprob
+                    .top
+                    .iter()
+                    .map(|(bytes, logprob)| TopLogprob {
+                        token: text(bytes),
+                        logprob: *logprob,
+                        bytes: Some(bytes.clone()),
+                    })
+                    .collect(),
+            })
+            .collect(),
+    }
+}
+
+/// `offset` is the length in characters of any echoed prompt in front of the
+/// completion; only completion tokens are reported.
+pub fn completion_logprobs(logprobs: &[Logprob], offset: usize) -> CompletionLogprobs {
+    let mut result = CompletionLogprobs {
+        tokens: Vec::new(),
+        token_logprobs: Vec::new(),
+        top_logprobs: Vec::new(),
+        text_offset: Vec::new(),
+    };
+    let mut offset = offset;
+    for logprob in logprobs {
+        let token = text(&logprob.bytes);
+        result.text_offset.push(offset as u32);
+        offset += token.chars().count();
+        result.tokens.push(token);
+        result.token_logprobs.push(Some(logprob.logprob));
+        result.top_logprobs.push(Some(
+            logprob
+                .top
+                .iter()
+                .map(|(bytes, logprob)| (text(bytes), *logprob))
+                .collect(),
+        ));
+    }
+    result
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
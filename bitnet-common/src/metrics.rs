@@ -0,0 +1,224 @@
+//! Prometheus metrics exposed on `/metrics`.
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::{sync::Arc, time::Instant};
+
+pub struct Metrics {
+    registry: Registry,
+    /// Requests by route, method and status code.
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    /// Wall-clock time of one inference, by backend.
+    pub inference_duration: HistogramVec,
+    pub proving_duration: Histogram,
+    pub proving_cycles: Histogram,
+    pub proving_segments: Histogram,
+    /// Jobs currently waiting or running, by queue.
+    pub queue_depth: IntGaugeVec,
+    /// Responses served without a real proof, by reason.
+    pub fallback_responses: IntCounterVec,
+    /// Failed subprocess runs (`bitnet-host`, `llama-cli`), by process and reason.
+    pub host_process_failures: IntCounterVec,
+    /// Response cache lookups, by the tier that answered or `miss`.
+    pub cache_lookups: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("bitnet_http_requests_total", "HTTP requests by route and status"),
+            &["route", "method", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "bitnet_http_request_duration_seconds",
+                "HTTP request latency by route",
+            )
+            .buckets(exponential_buckets(0.005, 2.0, 18).unwrap()),
+            &["route"],
+        )
+        .unwrap();
+        let inference_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "bitnet_inference_duration_seconds",
+                "Time spent generating one completion",
+            )
+            .buckets(exponential_buckets(0.05, 2.0, 14).unwrap()),
+            &["backend"],
+        )
+        .unwrap();
+        let proving_duration = Histogram::with_opts(
+            HistogramOpts::new("bitnet_proving_duration_seconds", "Time spent proving")
+                .buckets(exponential_buckets(1.0, 2.0, 14).unwrap()),
+        )
+        .unwrap();
+        let proving_cycles = Histogram::with_opts(
+            HistogramOpts::new("bitnet_proving_cycles", "Total zkVM cycles per proof")
+                .buckets(exponential_buckets(1_048_576.0, 2.0, 16).unwrap()),
+        )
+        .unwrap();
+        let proving_segments = Histogram::with_opts(
+            HistogramOpts::new("bitnet_proving_segments", "zkVM segments per proof")
+                .buckets(exponential_buckets(1.0, 2.0, 14).unwrap()),
+        )
+        .unwrap();
+        let queue_depth = IntGaugeVec::new(
+            Opts::new("bitnet_queue_depth", "Jobs waiting or running"),
+            &["queue"],
+        )
+        .unwrap();
+        let fallback_responses = IntCounterVec::new(
+            Opts::new(
+                "bitnet_fallback_responses_total",
+                "Responses served without a verified proof",
+            ),
+            &["reason"],
+        )
+        .unwrap();
+        let host_process_failures = IntCounterVec::new(
+            Opts::new(
+                "bitnet_host_process_failures_total",
+                "Failed inference or prover subprocess runs",
+            ),
+            &["process", "reason"],
+        )
+        .unwrap();
+        let cache_lookups = IntCounterVec::new(
+            Opts::new("bitnet_cache_lookups_total", "Response cache lookups by result"),
+            &["result"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(http_request_duration.clone())).unwrap();
+        registry.register(Box::new(inference_duration.clone())).unwrap();
+        registry.register(Box::new(proving_duration.clone())).unwrap();
+        registry.register(Box::new(proving_cycles.clone())).unwrap();
+        registry.register(Box::new(proving_segments.clone())).unwrap();
+        registry.register(Box::new(queue_depth.clone())).unwrap();
+        registry.register(Box::new(fallback_responses.clone())).unwrap();
+        registry.register(Box::new(host_process_failures.clone())).unwrap();
+        registry.register(Box::new(cache_lookups.clone())).unwrap();
+
+        Arc::new(Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            inference_duration,
+            proving_duration,
+            proving_cycles,
+            proving_segments,
+            queue_depth,
+            fallback_responses,
+            host_process_failures,
+            cache_lookups,
+        })
+    }
+
+    /// Total requests served on `route`, across methods and status codes.
+    pub fn requests_served(&self, route: &str) -> u64 {
+        self.registry
+            .gather()
+            .iter()
+            .filter(|family| family.get_name() == "bitnet_http_requests_total")
+            .flat_map(|family| family.get_metric())
+            .filter(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|label| label.get_name() == "route" && label.get_value() == route)
+            })
+            .map(|metric| metric.get_counter().get_value() as u64)
+            .sum()
+    }
+
+    /// Increments `queue` until the returned guard is dropped.
+    pub fn track_queue(self: &Arc<Self>, queue: &str) -> QueueGuard {
+        self.queue_depth.with_label_values(&[queue]).inc();
+        QueueGuard {
+            metrics: Arc::clone(self),
+            queue: queue.to_string(),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+pub struct QueueGuard {
+    metrics: Arc<Metrics>,
+    queue: String,
+}
+
+impl Drop for QueueGuard {
+    fn drop(&mut self) {
+        self.metrics
+            .queue_depth
+            .with_label_values(&[&self.queue])
+            .dec();
+    }
+}
+
+/// Middleware recording request counts and latency by matched route.
+pub async fn track_requests(
+    State(metrics): State<Arc<Metrics>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+    let start = Instant::now();
+
+    let response = next.run(request).await;
+
+    metrics
+        .http_request_duration
+        .with_label_values(&[&route])
+        .observe(start.elapsed().as_secs_f64());
+    metrics
+        .http_requests
+        .with_label_values(&[&route, &method, response.status().as_str()])
+        .inc();
+
+    response
+}
+
+async fn render_metrics(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, TextEncoder::new().format_type().to_string())],
+        metrics.render(),
+    )
+}
+
+/// The `/metrics` route. Left unauthenticated so Prometheus can scrape it.
+pub fn metrics_router<S>(metrics: Arc<Metrics>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/metrics", get(render_metrics))
+        .with_state(metrics)
+}
@@ -0,0 +1,416 @@
+//! Model registry behind `/v1/models`.
+//!
+//! Models come from `[[models]]` entries in the config file and, optionally,
+//! from a scan of `models_dir` for `*.gguf` files. Explicit entries win over
+//! scanned ones with the same id.
+
+use crate::error::{api_error, ApiErrorResponse};
+pub use bitnet_openai::models::{ModelList, ModelObject};
+use axum::{
+    extract::{Path as UrlPath, State},
+    http::StatusCode,
+    response::Json,
+    routing::get,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::OnceCell;
+use tracing::{info, warn};
+
+/// One `[[models]]` entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelEntry {
+    /// Name clients pass as `model`.
+    pub id: String,
+    /// GGUF weights file.
+    pub weights_path: PathBuf,
+    /// Tokenizer file or directory, if it is not embedded in the GGUF. The
+    /// servers and `bitnet-host` currently tokenize with the GGUF's own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokenizer_path: Option<PathBuf>,
+    /// RISC Zero image ID of the guest that proves this model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_id: Option<String>,
+    /// Context window in tokens; the server default applies when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_size: Option<u32>,
+    #[serde(default = "default_owned_by")]
+    pub owned_by: String,
+    #[serde(default, skip_serializing_if = "ProverSettings::is_empty")]
+    pub prover: ProverSettings,
+}
+
+/// Per-model overrides for the zkVM prover.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProverSettings {
+    /// Prover binary built for this model's guest, instead of the server default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_binary: Option<PathBuf>,
+    /// Upper bound on tokens generated inside the zkVM.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+}
+
+impl ProverSettings {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+fn default_owned_by() -> String {
+    "microsoft".to_string()
+}
+
+impl ModelEntry {
+    pub fn new(id: impl Into<String>, weights_path: impl Into<PathBuf>) -> Self {
+        Self {
+            id: id.into(),
+            weights_path: weights_path.into(),
+            tokenizer_path: None,
+            image_id: None,
+            context_size: None,
+            owned_by: default_owned_by(),
+            prover: ProverSettings::default(),
+        }
+    }
+
+    pub fn with_tokenizer(mut self, tokenizer_path: impl Into<PathBuf>) -> Self {
+        self.tokenizer_path = Some(tokenizer_path.into());
+        self
+    }
+}
+
+/// Cache key for a digest: the file is re-hashed if its size or mtime change.
+type DigestKey = (PathBuf, u64, Option<SystemTime>);
+
+pub struct ModelRegistry {
+    models: RwLock<Arc<BTreeMap<String, ModelEntry>>>,
+    /// Concurrent requests for the same file wait on one hash.
+    digests: Mutex<HashMap<DigestKey, Arc<OnceCell<String>>>>,
+}
+
+impl ModelRegistry {
+    pub fn new(entries: &[ModelEntry], models_dir: Option<&Path>) -> anyhow::Result<Arc<Self>> {
+        let registry = Arc::new(Self {
+            models: RwLock::new(Arc::new(BTreeMap::new())),
+            digests: Mutex::new(HashMap::new()),
+        });
+        registry.reload(entries, models_dir)?;
+        Ok(registry)
+    }
+
+    /// Rebuilds the model list, e.g. after a config reload. On error the
+    /// current list is kept.
+    pub fn reload(&self, entries: &[ModelEntry], models_dir: Option<&Path>) -> anyhow::Result<()> {
+        let mut models: BTreeMap<String, ModelEntry> = BTreeMap::new();
+        if let Some(dir) = models_dir {
+            for entry in scan_models_dir(dir)? {
+                if let Some(previous) = models.get(&entry.id) {
+                    warn!(
+                        "Model `{}`: {:?} is also named so; serving {:?}",
+                        entry.id, previous.weights_path, entry.weights_path
+                    );
+                }
+                models.insert(entry.id.clone(), entry);
+            }
+        }
+
+        let mut explicit = BTreeMap::new();
+        for entry in entries {
+            if explicit.insert(entry.id.clone(), entry.clone()).is_some() {
+                anyhow::bail!("Model `{}` is configured more than once", entry.id);
+            }
+        }
+        models.extend(explicit);
+
+        if models.is_empty() {
+            warn!("No models configured; every completion request will be rejected");
+        }
+        for entry in models.values() {
+            if !entry.weights_path.exists() {
+                warn!("Model `{}`: weights not found at {:?}", entry.id, entry.weights_path);
+            }
+        }
+        info!(
+            "Model registry: {}",
+            models.keys().cloned().collect::<Vec<_>>().join(", ")
+        );
+
+        *self.models.write().unwrap() = Arc::new(models);
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Option<ModelEntry> {
+        self.models.read().unwrap().get(id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<ModelEntry> {
+        self.models.read().unwrap().values().cloned().collect()
+    }
+
+    /// Looks up the model a request names, or the OpenAI-style 404.
+    pub fn resolve(&self, id: &str) -> Result<ModelEntry, ApiErrorResponse> {
+        self.get(id).ok_or_else(|| model_not_found(id))
+    }
+
+    /// SHA-256 of the model's weights, cached until the file changes.
+    pub async fn digest(&self, entry: &ModelEntry) -> anyhow::Result<String> {
+        let metadata = tokio::fs::metadata(&entry.weights_path).await?;
+        let key = (
+            entry.weights_path.clone(),
+            metadata.len(),
+            metadata.modified().ok(),
+        );
+        let cell = self.digests.lock().unwrap().entry(key).or_default().clone();
+        let digest = cell
+            .get_or_try_init(|| {
+                let path = entry.weights_path.clone();
+                async move { tokio::task::spawn_blocking(move || hash_file(&path)).await? }
+            })
+            .await?;
+        Ok(digest.clone())
+    }
+
+    fn describe(&self, entry: &ModelEntry, digest: Option<String>) -> ModelObject {
+        let created = std::fs::metadata(&entry.weights_path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|age| age.as_secs())
+            .unwrap_or(0);
+        ModelObject {
+            id: entry.id.clone(),
+            object: "model",
+            created,
+            owned_by: entry.owned_by.clone(),
+            context_length: entry.context_size,
+            image_id: entry.image_id.clone(),
+            digest,
+        }
+    }
+}
+
+pub fn model_not_found(id: &str) -> ApiErrorResponse {
+    api_error(
+        StatusCode::NOT_FOUND,
+        format!("The model `{}` does not exist", id),
+        "invalid_request_error",
+        "model_not_found",
+    )
+}
+
+fn hash_file(path: &Path) -> anyhow::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("sha256:{}", hex::encode(hasher.finalize())))
+}
+
+/// Finds `*.gguf` files in `dir` and its immediate subdirectories. A model in
+/// its own subdirectory (the BitNet layout, `models/<name>/ggml-model-i2_s.gguf`)
+/// is named after the directory, otherwise after the file. A `tokenizer.json`
+/// next to the weights is picked up. Unreadable subdirectories are skipped.
+fn scan_models_dir(dir: &Path) -> anyhow::Result<Vec<ModelEntry>> {
+    let mut found = Vec::new();
+    let read_dir = std::fs::read_dir(dir)
+        .map_err(|e| anyhow::anyhow!("Cannot scan models directory {:?}: {}", dir, e))?;
+
+    let mut paths: Vec<PathBuf> = read_dir.flatten().map(|item| item.path()).collect();
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let files = match std::fs::read_dir(&path) {
+                Ok(files) => files,
+                Err(e) => {
+                    warn!("Skipping unreadable models subdirectory {:?}: {}", path, e);
+                    continue;
+                }
+            };
+            let mut ggufs: Vec<PathBuf> = files
+                .flatten()
+                .map(|file| file.path())
+                .filter(|file| is_gguf(file))
+                .collect();
+            ggufs.sort();
+            if ggufs.len() == 1 {
+                found.push(scanned_entry(name.to_lowercase(), ggufs.remove(0)));
+            } else {
+                for gguf in ggufs {
+                    let stem = file_stem(&gguf);
+                    found.push(scanned_entry(format!("{}/{}", name.to_lowercase(), stem), gguf));
+                }
+            }
+        } else if is_gguf(&path) {
+            found.push(scanned_entry(file_stem(&path), path));
+        }
+    }
+
+    Ok(found)
+}
+
+fn scanned_entry(id: String, weights_path: PathBuf) -> ModelEntry {
+    let tokenizer = weights_path.with_file_name("tokenizer.json");
+    let entry = ModelEntry::new(id, weights_path);
+    if tokenizer.exists() {
+        entry.with_tokenizer(tokenizer)
+    } else {
+        entry
+    }
+}
+
+fn is_gguf(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|ext| ext == "gguf")
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+async fn list_models(State(registry): State<Arc<ModelRegistry>>) -> Json<ModelList> {
+    let data = registry
+        .list()
+        .iter()
+        .map(|entry| registry.describe(entry, None))
+        .collect();
+    Json(ModelList {
+        object: "list",
+        data,
+    })
+}
+
+async fn retrieve_model(
+    State(registry): State<Arc<ModelRegistry>>,
+    UrlPath(id): UrlPath<String>,
+) -> Result<Json<ModelObject>, ApiErrorResponse> {
+    let entry = registry.resolve(&id)?;
+    let digest = match registry.digest(&entry).await {
+        Ok(digest) => Some(digest),
+        Err(e) => {
+            warn!("Cannot hash weights for `{}`: {}", entry.id, e);
+            None
+        }
+    };
+    Ok(Json(registry.describe(&entry, digest)))
+}
+
+/// `GET /v1/models` and `GET /v1/models/{id}`. Callers add their own
+/// authentication layers.
+pub fn models_router<S>(registry: Arc<ModelRegistry>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/v1/models", get(list_models))
+        .route("/v1/models/*id", get(retrieve_model))
+        .with_state(registry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct ModelsDir(PathBuf);
+
+    impl ModelsDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir()
+                .join(format!("bitnet-models-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn add(&self, file: &str, content: &[u8]) -> PathBuf {
+            let path = self.0.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, content).unwrap();
+            path
+        }
+    }
+
+    impl Drop for ModelsDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn ids(registry: &ModelRegistry) -> Vec<String> {
+        registry.list().into_iter().map(|entry| entry.id).collect()
+    }
+
+    #[test]
+    fn scanned_models_are_named_after_their_directory_or_file() {
+        let dir = ModelsDir::new("naming");
+        let single = dir.add("BitNet-2B/ggml-model-i2_s.gguf", b"a");
+        dir.add("BitNet-2B/tokenizer.json", b"{}");
+        dir.add("pair/q4.gguf", b"b");
+        dir.add("pair/Q8.gguf", b"c");
+        dir.add("Loose.gguf", b"d");
+        dir.add("notes.txt", b"e");
+
+        let registry = ModelRegistry::new(&[], Some(&dir.0)).unwrap();
+        assert_eq!(ids(&registry), ["bitnet-2b", "loose", "pair/q4", "pair/q8"]);
+        let entry = registry.get("bitnet-2b").unwrap();
+        assert_eq!(entry.weights_path, single);
+        assert_eq!(entry.tokenizer_path, Some(dir.0.join("BitNet-2B/tokenizer.json")));
+        assert_eq!(registry.get("loose").unwrap().tokenizer_path, None);
+    }
+
+    #[test]
+    fn configured_models_override_scanned_ones_but_not_each_other() {
+        let dir = ModelsDir::new("duplicates");
+        dir.add("loose.gguf", b"a");
+        let configured = ModelEntry::new("loose", "/elsewhere.gguf");
+
+        let registry = ModelRegistry::new(std::slice::from_ref(&configured), Some(&dir.0)).unwrap();
+        assert_eq!(registry.get("loose"), Some(configured.clone()));
+
+        let twice = [configured.clone(), configured];
+        let error = ModelRegistry::new(&twice, None).err().unwrap();
+        assert!(error.to_string().contains("configured more than once"));
+        // A failed reload keeps the current list
+        assert!(registry.reload(&twice, None).is_err());
+        assert_eq!(ids(&registry), ["loose"]);
+    }
+
+    #[tokio::test]
+    async fn digests_are_hashed_once_per_file_version() {
+        let dir = ModelsDir::new("digest");
+        let path = dir.add("model.gguf", b"weights");
+        let registry = ModelRegistry::new(&[], None).unwrap();
+        let entry = ModelEntry::new("model", &path);
+
+        let (first, second) = tokio::join!(registry.digest(&entry), registry.digest(&entry));
+        let expected = format!("sha256:{}", hex::encode(Sha256::digest(b"weights")));
+        assert_eq!(first.unwrap(), expected);
+        assert_eq!(second.unwrap(), expected);
+        assert_eq!(registry.digests.lock().unwrap().len(), 1);
+
+        std::fs::write(&path, b"new weights!").unwrap();
+        let changed = registry.digest(&entry).await.unwrap();
+        assert_eq!(changed, format!("sha256:{}", hex::encode(Sha256::digest(b"new weights!"))));
+    }
+}
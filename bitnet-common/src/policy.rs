@@ -0,0 +1,30 @@
+use axum::http::{HeaderName, HeaderValue};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Response header telling clients whether the answer carries a verified proof.
+pub const ZK_VERIFIED_HEADER: HeaderName = HeaderName::from_static("x-zk-verified");
+
+/// Response header with the zkVM image ID a receipt is checked against.
+pub const ZK_IMAGE_ID_HEADER: HeaderName = HeaderName::from_static("x-zk-image-id");
+
+/// Response header with the hex SHA-256 of a receipt's journal.
+pub const ZK_JOURNAL_DIGEST_HEADER: HeaderName = HeaderName::from_static("x-zk-journal-digest");
+
+/// What to do when inference or proving fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    /// Return an error to the client.
+    #[default]
+    Error,
+    /// Return a degraded answer, marked `verified: false`.
+    Fallback,
+}
+
+pub fn verified_header(verified: bool) -> (HeaderName, HeaderValue) {
+    (
+        ZK_VERIFIED_HEADER,
+        HeaderValue::from_static(if verified { "true" } else { "false" }),
+    )
+}
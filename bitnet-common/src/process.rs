@@ -0,0 +1,46 @@
+//! Inference and prover subprocesses with deadlines.
+//!
+//! Children are spawned with `kill_on_drop`, so when a request is cancelled,
+//! because the client disconnected or its deadline passed, the process is
+//! killed instead of running on unobserved.
+
+use std::{fmt, process::Output, time::Duration};
+use tokio::process::Command;
+
+#[derive(Debug)]
+pub enum ProcessError {
+    /// The process could not be started.
+    Spawn(std::io::Error),
+    /// The process was killed after running for this long.
+    TimedOut(Duration),
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessError::Spawn(e) => write!(f, "failed to start: {}", e),
+            ProcessError::TimedOut(timeout) => {
+                write!(f, "timed out after {}s", timeout.as_secs())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProcessError {}
+
+/// Runs `command` to completion and collects its output, killing it after
+/// `timeout` (zero disables the deadline) or when the returned future is
+/// dropped.
+pub async fn output_with_timeout(
+    command: &mut Command,
+    timeout: Duration,
+) -> Result<Output, ProcessError> {
+    let output = command.kill_on_drop(true).output();
+    if timeout.is_zero() {
+        return output.await.map_err(ProcessError::Spawn);
+    }
+    match tokio::time::timeout(timeout, output).await {
+        Ok(output) => output.map_err(ProcessError::Spawn),
+        Err(_) => Err(ProcessError::TimedOut(timeout)),
+    }
+}
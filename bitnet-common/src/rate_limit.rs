@@ -0,0 +1,522 @@
+//! Per-client request rate limits and proving quotas.
+//!
+//! Clients are identified by their API key id, or by their IP address when
+//! authentication is disabled. Rejections are `429 Too Many Requests` in the
+//! OpenAI error format with a `Retry-After` header.
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::auth::AuthContext;
+use crate::error::{api_error, ApiErrorResponse};
+
+/// Hint sent when a client hits a concurrency limit; proofs take minutes.
+const CONCURRENCY_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Buckets are pruned once the map grows past this many clients.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Identifies the caller for rate limiting purposes.
+pub fn client_id(auth: Option<&AuthContext>, addr: Option<SocketAddr>) -> String {
+    match (auth.and_then(|a| a.key_id.as_deref()), addr) {
+        (Some(key_id), _) => format!("key:{}", key_id),
+        (None, Some(addr)) => format!("ip:{}", addr.ip()),
+        (None, None) => "anonymous".to_string(),
+    }
+}
+
+/// [`client_id`] of an authenticated request.
+pub fn request_client_id(request: &Request) -> String {
+    client_id(
+        request.extensions().get::<AuthContext>(),
+        request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0),
+    )
+}
+
+#[derive(Debug)]
+pub struct RateLimited {
+    message: String,
+    error_type: &'static str,
+    code: &'static str,
+    retry_after: Duration,
+}
+
+impl RateLimited {
+    /// The 429 without its `Retry-After` header.
+    fn error(self) -> ApiErrorResponse {
+        api_error(
+            StatusCode::TOO_MANY_REQUESTS,
+            self.message,
+            self.error_type,
+            self.code,
+        )
+    }
+}
+
+impl IntoResponse for RateLimited {
+    fn into_response(self) -> Response {
+        let retry_after = self.retry_after;
+        let mut response = self.error().into_response();
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, retry_after_header(retry_after));
+        response
+    }
+}
+
+/// Whole seconds, rounded up so clients never retry a fraction of a second
+/// too early, and at least one.
+fn retry_after_header(retry_after: Duration) -> HeaderValue {
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    HeaderValue::from(seconds.max(1))
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+struct LimiterState {
+    capacity: f64,
+    refill_per_sec: f64,
+    buckets: HashMap<String, TokenBucket>,
+}
+
+/// Token bucket limiter, one bucket per client.
+pub struct RateLimiter {
+    state: Mutex<LimiterState>,
+}
+
+impl RateLimiter {
+    /// Allows `per_minute` requests on average with bursts of up to `burst`.
+    /// A `per_minute` of zero disables limiting.
+    pub fn new(per_minute: u32, burst: u32) -> Arc<Self> {
+        let limiter = Arc::new(Self {
+            state: Mutex::new(LimiterState {
+                capacity: 0.0,
+                refill_per_sec: 0.0,
+                buckets: HashMap::new(),
+            }),
+        });
+        limiter.reconfigure(per_minute, burst);
+        limiter
+    }
+
+    /// Applies new limits; existing buckets keep their current level.
+    pub fn reconfigure(&self, per_minute: u32, burst: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.capacity = f64::from(burst.max(1));
+        state.refill_per_sec = f64::from(per_minute) / 60.0;
+    }
+
+    pub fn check(&self, client: &str) -> Result<(), RateLimited> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: &str, now: Instant) -> Result<(), RateLimited> {
+        let mut state = self.state.lock().unwrap();
+        let capacity = state.capacity;
+        let refill_per_sec = state.refill_per_sec;
+
+        if refill_per_sec == 0.0 {
+            return Ok(());
+        }
+
+        if state.buckets.len() >= MAX_TRACKED_CLIENTS {
+            state.buckets.retain(|_, bucket| {
+                let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                bucket.tokens + elapsed * refill_per_sec < capacity
+            });
+        }
+
+        let bucket = state.buckets.entry(client.to_string()).or_insert(TokenBucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * refill_per_sec).min(capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let wait = (1.0 - bucket.tokens) / refill_per_sec;
+        Err(RateLimited {
+            message: "Rate limit reached for requests. Please slow down.".to_string(),
+            error_type: "requests",
+            code: "rate_limit_exceeded",
+            retry_after: Duration::from_secs_f64(wait),
+        })
+    }
+}
+
+/// Middleware applying a [`RateLimiter`]. Must run after authentication so
+/// that requests are counted against their API key.
+pub async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Err(limited) = limiter.check(&request_client_id(&request)) {
+        return limited.into_response();
+    }
+    next.run(request).await
+}
+
+#[derive(Default)]
+struct ClientProofUsage {
+    running: usize,
+    day: Option<chrono::NaiveDate>,
+    used_today: u32,
+}
+
+#[derive(Default)]
+struct QuotaState {
+    max_concurrent_per_client: usize,
+    max_concurrent_total: usize,
+    daily_limit: u32,
+    running_total: usize,
+    clients: HashMap<String, ClientProofUsage>,
+}
+
+impl QuotaState {
+    /// `client`'s usage, with today's count reset on a new day.
+    fn usage(&mut self, client: &str, today: chrono::NaiveDate) -> &mut ClientProofUsage {
+        if self.clients.len() >= MAX_TRACKED_CLIENTS {
+            self.clients.retain(|_, usage| usage.running > 0 || usage.day == Some(today));
+        }
+        let usage = self.clients.entry(client.to_string()).or_default();
+        if usage.day != Some(today) {
+            usage.day = Some(today);
+            usage.used_today = 0;
+        }
+        usage
+    }
+}
+
+/// Rejects `count` more proofs beyond the daily limit, until midnight UTC.
+fn check_daily_quota(
+    usage: &ClientProofUsage,
+    daily_limit: u32,
+    count: u32,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<(), RateLimited> {
+    if daily_limit == 0 || usage.used_today.saturating_add(count) <= daily_limit {
+        return Ok(());
+    }
+    let midnight = now
+        .date_naive()
+        .succ_opt()
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .map(|t| t.and_utc())
+        .unwrap_or(now);
+    Err(RateLimited {
+        message: format!(
+            "Daily proof quota of {} exhausted; it resets at 00:00 UTC",
+            daily_limit
+        ),
+        error_type: "insufficient_quota",
+        code: "insufficient_quota",
+        retry_after: (midnight - now).to_std().unwrap_or_default(),
+    })
+}
+
+/// Concurrency and daily limits for proof generation.
+pub struct ProofQuota {
+    state: Mutex<QuotaState>,
+}
+
+impl ProofQuota {
+    /// A limit of zero disables that particular check.
+    pub fn new(
+        max_concurrent_per_client: usize,
+        max_concurrent_total: usize,
+        daily_limit: u32,
+    ) -> Arc<Self> {
+        let quota = Arc::new(Self {
+            state: Mutex::new(QuotaState::default()),
+        });
+        quota.reconfigure(max_concurrent_per_client, max_concurrent_total, daily_limit);
+        quota
+    }
+
+    /// Applies new limits. Running proofs and today's usage are kept.
+    pub fn reconfigure(
+        &self,
+        max_concurrent_per_client: usize,
+        max_concurrent_total: usize,
+        daily_limit: u32,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        state.max_concurrent_per_client = max_concurrent_per_client;
+        state.max_concurrent_total = max_concurrent_total;
+        state.daily_limit = daily_limit;
+    }
+
+    /// Proofs running now, and the total limit (zero when unlimited).
+    pub fn concurrency(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.running_total, state.max_concurrent_total)
+    }
+
+    /// Reserves a proving slot for `client`, released when the permit drops,
+    /// to run `proofs` proofs one after another. All of them count against
+    /// the daily quota, or none do.
+    pub fn try_acquire(
+        self: &Arc<Self>,
+        client: &str,
+        proofs: u32,
+    ) -> Result<ProofPermit, RateLimited> {
+        self.try_acquire_at(client, proofs, chrono::Utc::now())
+    }
+
+    fn try_acquire_at(
+        self: &Arc<Self>,
+        client: &str,
+        proofs: u32,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<ProofPermit, RateLimited> {
+        let mut state = self.state.lock().unwrap();
+        let max_concurrent_per_client = state.max_concurrent_per_client;
+        let max_concurrent_total = state.max_concurrent_total;
+        let daily_limit = state.daily_limit;
+
+        if max_concurrent_total > 0 && state.running_total >= max_concurrent_total {
+            return Err(RateLimited {
+                message: "The prover is at capacity. Please retry later.".to_string(),
+                error_type: "requests",
+                code: "rate_limit_exceeded",
+                retry_after: CONCURRENCY_RETRY_AFTER,
+            });
+        }
+
+        let usage = state.usage(client, now.date_naive());
+        if max_concurrent_per_client > 0 && usage.running >= max_concurrent_per_client {
+            return Err(RateLimited {
+                message: format!(
+                    "Too many concurrent proofs; at most {} may run at once per client",
+                    max_concurrent_per_client
+                ),
+                error_type: "requests",
+                code: "rate_limit_exceeded",
+                retry_after: CONCURRENCY_RETRY_AFTER,
+            });
+        }
+
+        check_daily_quota(usage, daily_limit, proofs, now)?;
+
+        usage.running += 1;
+        usage.used_today += proofs;
+        state.running_total += 1;
+
+        Ok(ProofPermit {
+            quota: Arc::clone(self),
+            client: client.to_string(),
+        })
+    }
+
+    /// Counts `count` proofs against `client`'s daily quota, all or none,
+    /// without taking a concurrency slot: for proofs that a queue runs later.
+    pub fn try_charge(&self, client: &str, count: u32) -> Result<(), RateLimited> {
+        self.try_charge_at(client, count, chrono::Utc::now())
+    }
+
+    fn try_charge_at(
+        &self,
+        client: &str,
+        count: u32,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<(), RateLimited> {
+        let mut state = self.state.lock().unwrap();
+        let daily_limit = state.daily_limit;
+        let usage = state.usage(client, now.date_naive());
+        check_daily_quota(usage, daily_limit, count, now)?;
+        usage.used_today += count;
+        Ok(())
+    }
+}
+
+/// Held for the duration of one proof.
+pub struct ProofPermit {
+    quota: Arc<ProofQuota>,
+    client: String,
+}
+
+impl Drop for ProofPermit {
+    fn drop(&mut self) {
+        let mut state = self.quota.state.lock().unwrap();
+        state.running_total = state.running_total.saturating_sub(1);
+        if let Some(usage) = state.clients.get_mut(&self.client) {
+            usage.running = usage.running.saturating_sub(1);
+        }
+    }
+}
+
+/// A request's access to the [`ProofQuota`], attached as an extension by
+/// [`proof_quota`]. Handlers take a slot only once they know a proof will
+/// run: after validating the request and missing the response cache.
+#[derive(Clone)]
+pub struct ProofGate {
+    quota: Arc<ProofQuota>,
+    client: String,
+    /// Set by a rejected [`ProofGate::acquire`], for the middleware to send.
+    retry_after: Arc<Mutex<Option<Duration>>>,
+}
+
+impl ProofGate {
+    pub fn new(quota: Arc<ProofQuota>, client: String) -> Self {
+        Self {
+            quota,
+            client,
+            retry_after: Arc::default(),
+        }
+    }
+
+    /// Reserves a proving slot for the caller, released when the permit drops,
+    /// for `proofs` proofs run one after another.
+    pub fn acquire(&self, proofs: u32) -> Result<ProofPermit, ApiErrorResponse> {
+        self.quota.try_acquire(&self.client, proofs).map_err(|limited| {
+            *self.retry_after.lock().unwrap() = Some(limited.retry_after);
+            limited.error()
+        })
+    }
+
+    /// Counts `count` queued proofs against the caller's daily quota.
+    pub fn charge(&self, count: u32) -> Result<(), ApiErrorResponse> {
+        self.quota.try_charge(&self.client, count).map_err(|limited| {
+            *self.retry_after.lock().unwrap() = Some(limited.retry_after);
+            limited.error()
+        })
+    }
+
+    /// Adds the `Retry-After` of a rejected [`ProofGate::acquire`] or
+    /// [`ProofGate::charge`].
+    pub fn finish(&self, mut response: Response) -> Response {
+        if let Some(retry_after) = self.retry_after.lock().unwrap().take() {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_after_header(retry_after));
+        }
+        response
+    }
+}
+
+/// Middleware giving the handler a [`ProofGate`]. Must run after
+/// authentication so that quotas apply per API key.
+pub async fn proof_quota(
+    State(quota): State<Arc<ProofQuota>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let gate = ProofGate::new(quota, request_client_id(&request));
+    request.extensions_mut().insert(gate.clone());
+    gate.finish(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn buckets_refill_at_the_per_minute_rate() {
+        let limiter = RateLimiter::new(60, 2);
+        let start = Instant::now();
+        assert!(limiter.check_at("a", start).is_ok());
+        assert!(limiter.check_at("a", start).is_ok());
+        let limited = limiter.check_at("a", start).unwrap_err();
+        assert_eq!(limited.retry_after, Duration::from_secs(1));
+        // Other clients have their own bucket
+        assert!(limiter.check_at("b", start).is_ok());
+
+        let later = start + Duration::from_millis(1500);
+        assert!(limiter.check_at("a", later).is_ok());
+        let limited = limiter.check_at("a", later).unwrap_err();
+        assert_eq!(limited.retry_after, Duration::from_millis(500));
+        // Never refills past the burst
+        let much_later = later + Duration::from_secs(600);
+        for _ in 0..2 {
+            assert!(limiter.check_at("a", much_later).is_ok());
+        }
+        assert!(limiter.check_at("a", much_later).is_err());
+    }
+
+    #[test]
+    fn daily_proof_quota_resets_at_midnight() {
+        let quota = ProofQuota::new(0, 0, 2);
+        let evening = Utc.with_ymd_and_hms(2026, 3, 1, 23, 59, 30).unwrap();
+        drop(quota.try_acquire_at("a", 1, evening).unwrap());
+        drop(quota.try_acquire_at("a", 1, evening).unwrap());
+        let limited = quota.try_acquire_at("a", 1, evening).err().unwrap();
+        assert_eq!(limited.code, "insufficient_quota");
+        assert_eq!(limited.retry_after, Duration::from_secs(30));
+
+        let next_day = Utc.with_ymd_and_hms(2026, 3, 2, 0, 0, 1).unwrap();
+        assert!(quota.try_acquire_at("a", 1, next_day).is_ok());
+    }
+
+    #[test]
+    fn queued_proofs_count_against_the_daily_quota() {
+        let quota = ProofQuota::new(1, 1, 3);
+        let morning = Utc.with_ymd_and_hms(2026, 3, 1, 9, 0, 0).unwrap();
+        quota.try_charge_at("a", 2, morning).unwrap();
+        // All or none, and no concurrency slot is held
+        assert!(quota.try_charge_at("a", 2, morning).is_err());
+        assert_eq!(quota.concurrency(), (0, 1));
+        let permit = quota.try_acquire_at("a", 1, morning).unwrap();
+        drop(permit);
+        let limited = quota.try_acquire_at("a", 1, morning).err().unwrap();
+        assert_eq!(limited.code, "insufficient_quota");
+    }
+
+    #[test]
+    fn one_slot_can_run_several_proofs() {
+        let quota = ProofQuota::new(1, 1, 3);
+        let morning = Utc.with_ymd_and_hms(2026, 3, 1, 9, 0, 0).unwrap();
+        // All or none
+        assert!(quota.try_acquire_at("a", 4, morning).is_err());
+        assert_eq!(quota.concurrency(), (0, 1));
+        let permit = quota.try_acquire_at("a", 3, morning).unwrap();
+        assert_eq!(quota.concurrency(), (1, 1));
+        drop(permit);
+        let limited = quota.try_acquire_at("a", 1, morning).err().unwrap();
+        assert_eq!(limited.code, "insufficient_quota");
+    }
+
+    #[test]
+    fn permits_hold_concurrency_until_dropped() {
+        let quota = ProofQuota::new(1, 0, 0);
+        let permit = quota.try_acquire("a", 1).unwrap();
+        assert!(quota.try_acquire("a", 1).is_err());
+        assert!(quota.try_acquire("b", 1).is_ok());
+        drop(permit);
+        assert!(quota.try_acquire("a", 1).is_ok());
+        assert_eq!(quota.concurrency(), (0, 0));
+    }
+
+    #[test]
+    fn retry_after_rounds_up_to_whole_seconds() {
+        let header = |millis| retry_after_header(Duration::from_millis(millis));
+        assert_eq!(header(0), "1");
+        assert_eq!(header(1), "1");
+        assert_eq!(header(1000), "1");
+        assert_eq!(header(1001), "2");
+        assert_eq!(header(29_999), "30");
+    }
+}
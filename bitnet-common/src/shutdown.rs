@@ -0,0 +1,75 @@
+//! Graceful shutdown on SIGTERM or Ctrl-C.
+//!
+//! The listener closes as soon as the signal arrives. Requests already in
+//! flight, streams included, get a grace period to finish; whatever is still
+//! running after it is dropped, which kills any child process it started.
+
+use axum::Router;
+use std::{future::IntoFuture, net::SocketAddr, time::Duration};
+use tokio::{net::TcpListener, sync::watch};
+use tracing::{error, info, warn};
+
+/// Resolves on the first SIGTERM or Ctrl-C.
+pub async fn signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Cannot listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                error!("Cannot listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => info!("Ctrl-C received"),
+        _ = terminate => info!("SIGTERM received"),
+    }
+}
+
+/// Serves `app` until [`signal`], then waits up to `grace` for in-flight
+/// requests before returning.
+pub async fn serve(listener: TcpListener, app: Router, grace: Duration) -> std::io::Result<()> {
+    let (stopping_tx, mut stopping) = watch::channel(false);
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async move {
+        signal().await;
+        info!(
+            "Shutting down: no new connections, waiting up to {:?} for requests in flight",
+            grace
+        );
+        let _ = stopping_tx.send(true);
+    });
+    let deadline = async {
+        if stopping.wait_for(|stopping| *stopping).await.is_ok() {
+            tokio::time::sleep(grace).await;
+        } else {
+            std::future::pending::<()>().await;
+        }
+    };
+
+    tokio::select! {
+        served = server.into_future() => served,
+        _ = deadline => {
+            warn!("Grace period elapsed; dropping requests still in flight");
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,487 @@
+//! Completions and their proofs, kept across restarts, and
+//! `GET /v1/chat/completions/{id}`.
+//!
+//! Each served completion is recorded with its request, response, sampling
+//! parameters, model digest and where its proof stands. Deferred proofs of
+//! multi-choice completions are tracked per choice at
+//! `GET /v1/proofs/{id}-{index}`, so their record stays `queued`.
+
+use axum::{
+    extract::{Path as UrlPath, State},
+    http::StatusCode,
+    response::Json,
+    routing::get,
+    Extension, Router,
+};
+use bitnet_openai::chat::ProofStatus;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+
+use crate::auth::{AuthContext, Scope};
+use crate::config::StoreConfig;
+use crate::error::{api_error, ApiErrorResponse};
+
+/// Where a completion's proof stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProofState {
+    /// No proof: a fallback answer.
+    Unverified,
+    /// A signed attestation.
+    Attested,
+    /// A zkVM receipt, served with the completion.
+    Verified,
+    /// A deferred receipt, waiting for a prover.
+    Queued,
+    Proving,
+    /// A deferred receipt, ready at `GET /v1/proofs/{id}`.
+    Ready,
+    Failed,
+}
+
+impl ProofState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ProofState::Unverified => "unverified",
+            ProofState::Attested => "attested",
+            ProofState::Verified => "verified",
+            ProofState::Queued => "queued",
+            ProofState::Proving => "proving",
+            ProofState::Ready => "ready",
+            ProofState::Failed => "failed",
+        }
+    }
+
+    /// `proof_status` of a completion response, for deferred proofs.
+    pub fn deferred_status(self) -> Option<ProofStatus> {
+        match self {
+            ProofState::Queued => Some(ProofStatus::Queued),
+            ProofState::Proving => Some(ProofStatus::Proving),
+            ProofState::Ready => Some(ProofStatus::Ready),
+            ProofState::Failed => Some(ProofStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+impl From<ProofStatus> for ProofState {
+    fn from(status: ProofStatus) -> Self {
+        match status {
+            ProofStatus::Queued => ProofState::Queued,
+            ProofStatus::Proving => ProofState::Proving,
+            ProofStatus::Ready => ProofState::Ready,
+            ProofStatus::Failed => ProofState::Failed,
+        }
+    }
+}
+
+impl FromStr for ProofState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+            .map_err(|_| anyhow::anyhow!("unknown proof state `{}`", s))
+    }
+}
+
+/// One served completion.
+#[derive(Debug, Clone, Serialize)]
+pub struct CompletionRecord {
+    pub id: String,
+    /// `chat.completion` or `text_completion`.
+    pub object: String,
+    pub model: String,
+    /// `sha256:<hex>` of the weights file, when it could be read.
+    pub model_digest: Option<String>,
+    pub created: u64,
+    /// API key that requested the completion; `None` without authentication.
+    pub key_id: Option<String>,
+    pub params: serde_json::Value,
+    pub request: serde_json::Value,
+    pub response: serde_json::Value,
+    pub proof_state: ProofState,
+    /// Host output holding the receipt, on the server's disk.
+    pub receipt_path: Option<String>,
+    /// Receipt's digest at `GET /v1/receipts/{digest}`.
+    pub receipt_digest: Option<String>,
+}
+
+impl CompletionRecord {
+    /// A record of `response` to `request`, with no proof. The response must
+    /// carry `id`, `object` and `model`.
+    pub fn new(
+        request: &impl Serialize,
+        response: &impl Serialize,
+        params: &impl Serialize,
+    ) -> anyhow::Result<Self> {
+        let response = serde_json::to_value(response)?;
+        let field = |name: &str| {
+            response[name]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow::anyhow!("response has no `{}`", name))
+        };
+        Ok(Self {
+            id: field("id")?,
+            object: field("object")?,
+            model: field("model")?,
+            model_digest: None,
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            key_id: None,
+            params: serde_json::to_value(params)?,
+            request: serde_json::to_value(request)?,
+            response,
+            proof_state: ProofState::Unverified,
+            receipt_path: None,
+            receipt_digest: None,
+        })
+    }
+
+    /// Other keys' completions look absent, except to admins.
+    pub fn visible_to(&self, caller: &AuthContext) -> bool {
+        self.key_id.is_none()
+            || self.key_id == caller.key_id
+            || caller.scopes.contains(&Scope::Admin)
+    }
+}
+
+/// Persistent storage for completion records.
+pub trait CompletionStore: Send + Sync {
+    fn insert(&self, record: &CompletionRecord) -> anyhow::Result<()>;
+    fn get(&self, id: &str) -> anyhow::Result<Option<CompletionRecord>>;
+    /// Records a proof's progress. Returns `false` if no such completion exists.
+    fn update_proof(
+        &self,
+        id: &str,
+        state: ProofState,
+        receipt_path: Option<&str>,
+    ) -> anyhow::Result<bool>;
+    /// Links a completion to its receipt in the receipt store.
+    fn set_receipt_digest(&self, id: &str, digest: &str) -> anyhow::Result<bool>;
+}
+
+/// Completions kept in a SQLite database.
+pub struct SqliteCompletionStore {
+    conn: Mutex<rusqlite::Connection>,
+}
+
+impl SqliteCompletionStore {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let conn = rusqlite::Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS completions (
+                id TEXT PRIMARY KEY,
+                object TEXT NOT NULL,
+                model TEXT NOT NULL,
+                model_digest TEXT,
+                created INTEGER NOT NULL,
+                key_id TEXT,
+                params TEXT NOT NULL,
+                request TEXT NOT NULL,
+                response TEXT NOT NULL,
+                proof_state TEXT NOT NULL,
+                receipt_path TEXT,
+                receipt_digest TEXT
+            );
+            CREATE INDEX IF NOT EXISTS completions_created ON completions (created);",
+        )?;
+        // Databases created before receipts were content-addressed
+        let has_digest = conn
+            .prepare(
+                "SELECT 1 FROM pragma_table_info('completions') WHERE name = 'receipt_digest'",
+            )?
+            .exists([])?;
+        if !has_digest {
+            conn.execute("ALTER TABLE completions ADD COLUMN receipt_digest TEXT", [])?;
+        }
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn row_to_record(row: &rusqlite::Row<'_>) -> rusqlite::Result<CompletionRecord> {
+        let json = |index: usize| -> rusqlite::Result<serde_json::Value> {
+            let text: String = row.get(index)?;
+            serde_json::from_str(&text).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    index,
+                    rusqlite::types::Type::Text,
+                    e.into(),
+                )
+            })
+        };
+        let proof_state: String = row.get(9)?;
+        Ok(CompletionRecord {
+            id: row.get(0)?,
+            object: row.get(1)?,
+            model: row.get(2)?,
+            model_digest: row.get(3)?,
+            created: row.get::<_, i64>(4)? as u64,
+            key_id: row.get(5)?,
+            params: json(6)?,
+            request: json(7)?,
+            response: json(8)?,
+            proof_state: proof_state.parse().map_err(|e: anyhow::Error| {
+                rusqlite::Error::FromSqlConversionFailure(9, rusqlite::types::Type::Text, e.into())
+            })?,
+            receipt_path: row.get(10)?,
+            receipt_digest: row.get(11)?,
+        })
+    }
+}
+
+impl CompletionStore for SqliteCompletionStore {
+    fn insert(&self, record: &CompletionRecord) -> anyhow::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO completions (id, object, model, model_digest, created,
+                 key_id, params, request, response, proof_state, receipt_path, receipt_digest)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            rusqlite::params![
+                record.id,
+                record.object,
+                record.model,
+                record.model_digest,
+                record.created as i64,
+                record.key_id,
+                record.params.to_string(),
+                record.request.to_string(),
+                record.response.to_string(),
+                record.proof_state.as_str(),
+                record.receipt_path,
+                record.receipt_digest
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get(&self, id: &str) -> anyhow::Result<Option<CompletionRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, object, model, model_digest, created, key_id, params, request,
+                 response, proof_state, receipt_path, receipt_digest
+             FROM completions WHERE id = ?1",
+        )?;
+        let mut rows = stmt.query_map([id], Self::row_to_record)?;
+        Ok(rows.next().transpose()?)
+    }
+
+    fn update_proof(
+        &self,
+        id: &str,
+        state: ProofState,
+        receipt_path: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        let updated = self.conn.lock().unwrap().execute(
+            "UPDATE completions SET proof_state = ?2, receipt_path = COALESCE(?3, receipt_path)
+             WHERE id = ?1",
+            rusqlite::params![id, state.as_str(), receipt_path],
+        )?;
+        Ok(updated > 0)
+    }
+
+    fn set_receipt_digest(&self, id: &str, digest: &str) -> anyhow::Result<bool> {
+        let updated = self.conn.lock().unwrap().execute(
+            "UPDATE completions SET receipt_digest = ?2 WHERE id = ?1",
+            rusqlite::params![id, digest],
+        )?;
+        Ok(updated > 0)
+    }
+}
+
+/// Opens the configured store, or `None` when `[store]` is disabled.
+pub fn open_completion_store(
+    config: &StoreConfig,
+) -> anyhow::Result<Option<Arc<dyn CompletionStore>>> {
+    if !config.enabled {
+        info!("Completion store disabled");
+        return Ok(None);
+    }
+    info!("Using completion database: {}", config.path);
+    Ok(Some(Arc::new(SqliteCompletionStore::open(&config.path)?)))
+}
+
+/// Records a completion. A failure is logged, not returned: the client still
+/// gets its answer.
+pub fn save(store: Option<&Arc<dyn CompletionStore>>, record: anyhow::Result<CompletionRecord>) {
+    let Some(store) = store else {
+        return;
+    };
+    if let Err(e) = record.and_then(|record| store.insert(&record)) {
+        warn!("Cannot record completion: {:#}", e);
+    }
+}
+
+/// A stored completion: the response as served, with its proof's progress.
+#[derive(Debug, Serialize)]
+pub struct StoredCompletion {
+    #[serde(flatten)]
+    pub response: serde_json::Value,
+    pub model_digest: Option<String>,
+    pub params: serde_json::Value,
+    pub proof_state: ProofState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receipt_digest: Option<String>,
+}
+
+async fn retrieve_completion(
+    State(store): State<Arc<dyn CompletionStore>>,
+    Extension(caller): Extension<AuthContext>,
+    UrlPath(id): UrlPath<String>,
+) -> Result<Json<StoredCompletion>, ApiErrorResponse> {
+    let record = store.get(&id).map_err(|e| {
+        api_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Cannot read completion store: {}", e),
+            "server_error",
+            "store_error",
+        )
+    })?;
+    let visible = |record: &CompletionRecord| {
+        record.object == "chat.completion" && record.visible_to(&caller)
+    };
+    let Some(record) = record.filter(visible) else {
+        return Err(api_error(
+            StatusCode::NOT_FOUND,
+            format!("No chat completion `{}`", id),
+            "invalid_request_error",
+            "completion_not_found",
+        ));
+    };
+
+    let mut response = record.response;
+    if let Some(status) = record.proof_state.deferred_status() {
+        response["proof_status"] = serde_json::json!(status);
+    }
+    Ok(Json(StoredCompletion {
+        response,
+        model_digest: record.model_digest,
+        params: record.params,
+        proof_state: record.proof_state,
+        receipt_digest: record.receipt_digest,
+    }))
+}
+
+/// `GET /v1/chat/completions/{id}`. Callers add their own authentication
+/// layers.
+pub fn completions_router<S>(store: Arc<dyn CompletionStore>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/v1/chat/completions/:id", get(retrieve_completion))
+        .with_state(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: &str, key_id: Option<&str>) -> CompletionRecord {
+        let request = serde_json::json!({"prompt": "Hi"});
+        let response = serde_json::json!({"id": id, "object": "text_completion", "model": "tiny"});
+        let mut record =
+            CompletionRecord::new(&request, &response, &serde_json::json!({})).unwrap();
+        record.key_id = key_id.map(str::to_string);
+        record
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let store = SqliteCompletionStore::open(":memory:").unwrap();
+        let mut stored = record("cmpl-1", Some("a"));
+        stored.model_digest = Some("sha256:00".to_string());
+        stored.proof_state = ProofState::Verified;
+        stored.receipt_path = Some("/proofs/cmpl-1.json".to_string());
+        stored.receipt_digest = Some("ab".repeat(32));
+        store.insert(&stored).unwrap();
+
+        let read = store.get("cmpl-1").unwrap().unwrap();
+        assert_eq!(
+            serde_json::to_value(&read).unwrap(),
+            serde_json::to_value(&stored).unwrap()
+        );
+        assert!(store.get("cmpl-2").unwrap().is_none());
+
+        assert!(store
+            .update_proof("cmpl-1", ProofState::Failed, None)
+            .unwrap());
+        assert!(store
+            .set_receipt_digest("cmpl-1", &"cd".repeat(32))
+            .unwrap());
+        let read = store.get("cmpl-1").unwrap().unwrap();
+        assert_eq!(read.proof_state, ProofState::Failed);
+        assert_eq!(read.receipt_path, stored.receipt_path);
+        assert_eq!(read.receipt_digest, Some("cd".repeat(32)));
+        assert!(!store
+            .update_proof("cmpl-2", ProofState::Failed, None)
+            .unwrap());
+    }
+
+    #[test]
+    fn adds_receipt_digest_to_old_databases() {
+        let path = std::env::temp_dir().join(format!("bitnet-store-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE completions (
+                    id TEXT PRIMARY KEY,
+                    object TEXT NOT NULL,
+                    model TEXT NOT NULL,
+                    model_digest TEXT,
+                    created INTEGER NOT NULL,
+                    key_id TEXT,
+                    params TEXT NOT NULL,
+                    request TEXT NOT NULL,
+                    response TEXT NOT NULL,
+                    proof_state TEXT NOT NULL,
+                    receipt_path TEXT
+                );
+                INSERT INTO completions VALUES ('cmpl-old', 'text_completion', 'tiny', NULL, 0,
+                    NULL, '{}', '{}', '{}', 'verified', '/proofs/cmpl-old.json');",
+            )
+            .unwrap();
+
+        let store = SqliteCompletionStore::open(&path).unwrap();
+        let old = store.get("cmpl-old").unwrap().unwrap();
+        assert_eq!(old.receipt_path.as_deref(), Some("/proofs/cmpl-old.json"));
+        assert_eq!(old.receipt_digest, None);
+        assert!(store
+            .set_receipt_digest("cmpl-old", &"ab".repeat(32))
+            .unwrap());
+        drop(store);
+
+        // Opening a migrated database again leaves it as it is
+        let store = SqliteCompletionStore::open(&path).unwrap();
+        let old = store.get("cmpl-old").unwrap().unwrap();
+        assert_eq!(old.receipt_digest, Some("ab".repeat(32)));
+        drop(store);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn records_are_visible_to_their_key_and_admins() {
+        let caller = |key_id: Option<&str>, scopes: Vec<Scope>| AuthContext {
+            key_id: key_id.map(str::to_string),
+            scopes,
+        };
+        let owned = record("cmpl-1", Some("a"));
+        assert!(owned.visible_to(&caller(Some("a"), vec![Scope::Chat])));
+        assert!(!owned.visible_to(&caller(Some("b"), vec![Scope::Chat, Scope::Prove])));
+        assert!(!owned.visible_to(&caller(None, vec![])));
+        assert!(owned.visible_to(&caller(Some("b"), vec![Scope::Admin])));
+
+        // Completions served without authentication belong to no one
+        let unowned = record("cmpl-2", None);
+        assert!(unowned.visible_to(&caller(Some("b"), vec![Scope::Chat])));
+    }
+}
@@ -0,0 +1,219 @@
+//! Tokenizers read from each model's GGUF, for usage accounting and for
+//! `POST /v1/tokenize` and `POST /v1/detokenize`. `bitnet-host` tokenizes
+//! with the same GGUF tokenizer, so proven completions are counted and
+//! decoded with it too.
+
+use axum::{extract::State, http::StatusCode, response::Json, routing::post, Router};
+use bitnet_core::{GgufFile, Tokenizer};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use crate::error::{api_error, ApiErrorResponse};
+use crate::models::{ModelEntry, ModelRegistry};
+
+/// A model's tokenizer and the context length from its metadata.
+pub struct ModelTokenizer {
+    pub tokenizer: Tokenizer,
+    pub context_length: Option<u32>,
+}
+
+impl ModelTokenizer {
+    /// Token count of a prompt as the model sees it, BOS included.
+    pub fn count_prompt(&self, prompt: &str) -> anyhow::Result<u32> {
+        Ok(self.tokenizer.encode(prompt, true)?.len() as u32)
+    }
+
+    /// Token count of generated text.
+    pub fn count_completion(&self, text: &str) -> anyhow::Result<u32> {
+        Ok(self.tokenizer.encode(text, false)?.len() as u32)
+    }
+
+    /// The window a model runs with: the configured size, capped by the
+    /// context length the model was trained for.
+    pub fn context_window(&self, configured: u32) -> u32 {
+        self.context_length
+            .map_or(configured, |length| length.min(configured))
+    }
+}
+
+/// The 503 for a model whose tokenizer can't be loaded.
+pub fn tokenizer_unavailable(model: &ModelEntry, error: anyhow::Error) -> ApiErrorResponse {
+    api_error(
+        StatusCode::SERVICE_UNAVAILABLE,
+        format!("Cannot load the tokenizer for `{}`: {}", model.id, error),
+        "server_error",
+        "tokenizer_unavailable",
+    )
+}
+
+/// OpenAI's error for a prompt that leaves no room for `max_tokens`.
+pub fn context_length_exceeded(
+    window: u32,
+    prompt_tokens: u32,
+    max_tokens: u32,
+) -> ApiErrorResponse {
+    api_error(
+        StatusCode::BAD_REQUEST,
+        format!(
+            "This model's maximum context length is {} tokens. However, you requested {} tokens \
+             ({} in the messages, {} in the completion). Please reduce the length of the \
+             messages or completion.",
+            window,
+            u64::from(prompt_tokens) + u64::from(max_tokens),
+            prompt_tokens,
+            max_tokens
+        ),
+        "invalid_request_error",
+        "context_length_exceeded",
+    )
+}
+
+/// Tokenizers by model id, loaded on first use and reloaded if the model's
+/// weights path changes.
+#[derive(Default)]
+pub struct TokenizerCache {
+    loaded: Mutex<HashMap<String, (PathBuf, Arc<ModelTokenizer>)>>,
+}
+
+impl TokenizerCache {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub async fn get(&self, entry: &ModelEntry) -> anyhow::Result<Arc<ModelTokenizer>> {
+        if let Some((path, tokenizer)) = self.loaded.lock().unwrap().get(&entry.id) {
+            if path == &entry.weights_path {
+                return Ok(Arc::clone(tokenizer));
+            }
+        }
+
+        let path = entry.weights_path.clone();
+        let tokenizer = tokio::task::spawn_blocking(move || -> anyhow::Result<ModelTokenizer> {
+            let gguf = GgufFile::open(&path)?;
+            let context_length = gguf
+                .architecture()
+                .and_then(|arch| gguf.get_u64(&format!("{}.context_length", arch)))
+                .ok()
+                .map(|length| length as u32);
+            Ok(ModelTokenizer {
+                tokenizer: Tokenizer::from_gguf(&gguf)?,
+                context_length,
+            })
+        })
+        .await??;
+
+        let tokenizer = Arc::new(tokenizer);
+        self.loaded.lock().unwrap().insert(
+            entry.id.clone(),
+            (entry.weights_path.clone(), Arc::clone(&tokenizer)),
+        );
+        Ok(tokenizer)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenizeRequest {
+    pub model: String,
+    pub prompt: String,
+    /// Prepend BOS the way completions do.
+    #[serde(default = "default_true")]
+    pub add_special_tokens: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenizeResponse {
+    pub count: usize,
+    /// The model's context window, where known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_model_len: Option<u32>,
+    pub tokens: Vec<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DetokenizeRequest {
+    pub model: String,
+    pub tokens: Vec<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DetokenizeResponse {
+    pub prompt: String,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Clone)]
+struct TokenizeState {
+    models: Arc<ModelRegistry>,
+    tokenizers: Arc<TokenizerCache>,
+}
+
+impl TokenizeState {
+    async fn tokenizer(
+        &self,
+        model: &str,
+    ) -> Result<(ModelEntry, Arc<ModelTokenizer>), ApiErrorResponse> {
+        let entry = self.models.resolve(model)?;
+        let tokenizer = self
+            .tokenizers
+            .get(&entry)
+            .await
+            .map_err(|e| tokenizer_unavailable(&entry, e))?;
+        Ok((entry, tokenizer))
+    }
+}
+
+fn invalid_input(e: anyhow::Error) -> ApiErrorResponse {
+    api_error(
+        StatusCode::BAD_REQUEST,
+        e.to_string(),
+        "invalid_request_error",
+        "invalid_tokens",
+    )
+}
+
+async fn tokenize(
+    State(state): State<TokenizeState>,
+    Json(request): Json<TokenizeRequest>,
+) -> Result<Json<TokenizeResponse>, ApiErrorResponse> {
+    let (entry, tokenizer) = state.tokenizer(&request.model).await?;
+    let tokens = tokenizer
+        .tokenizer
+        .encode(&request.prompt, request.add_special_tokens)
+        .map_err(invalid_input)?;
+    Ok(Json(TokenizeResponse {
+        count: tokens.len(),
+        max_model_len: entry.context_size.or(tokenizer.context_length),
+        tokens,
+    }))
+}
+
+async fn detokenize(
+    State(state): State<TokenizeState>,
+    Json(request): Json<DetokenizeRequest>,
+) -> Result<Json<DetokenizeResponse>, ApiErrorResponse> {
+    let (_, tokenizer) = state.tokenizer(&request.model).await?;
+    let prompt = tokenizer
+        .tokenizer
+        .decode(&request.tokens, false)
+        .map_err(invalid_input)?;
+    Ok(Json(DetokenizeResponse { prompt }))
+}
+
+/// `POST /v1/tokenize` and `POST /v1/detokenize`. Callers add their own
+/// authentication layers.
+pub fn tokenize_router<S>(models: Arc<ModelRegistry>, tokenizers: Arc<TokenizerCache>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/v1/tokenize", post(tokenize))
+        .route("/v1/detokenize", post(detokenize))
+        .with_state(TokenizeState { models, tokenizers })
+}
@@ -0,0 +1,391 @@
+//! GGUF container parsing (versions 2 and 3).
+//!
+//! Only the header, metadata and tensor table are decoded eagerly; tensor
+//! data stays in the mapped or loaded file and is sliced on demand.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::{collections::BTreeMap, ops::Deref, path::Path, sync::Arc};
+
+use crate::tensor::{GgmlType, Tensor};
+
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+const DEFAULT_ALIGNMENT: u64 = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataValue {
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    String(String),
+    Array(Vec<MetadataValue>),
+}
+
+impl MetadataValue {
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Self::U8(v) => Some(v.into()),
+            Self::U16(v) => Some(v.into()),
+            Self::U32(v) => Some(v.into()),
+            Self::U64(v) => Some(v),
+            Self::I8(v) => u64::try_from(v).ok(),
+            Self::I16(v) => u64::try_from(v).ok(),
+            Self::I32(v) => u64::try_from(v).ok(),
+            Self::I64(v) => u64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Self::I8(v) => Some(v.into()),
+            Self::I16(v) => Some(v.into()),
+            Self::I32(v) => Some(v.into()),
+            Self::I64(v) => Some(v),
+            _ => self.as_u64().and_then(|v| i64::try_from(v).ok()),
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        match *self {
+            Self::F32(v) => Some(v),
+            Self::F64(v) => Some(v as f32),
+            _ => self.as_i64().map(|v| v as f32),
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Self::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[MetadataValue]> {
+        match self {
+            Self::Array(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TensorInfo {
+    pub name: String,
+    /// Dimensions in GGML order: `dims[0]` is the contiguous one.
+    pub dims: Vec<u64>,
+    pub ggml_type: u32,
+    /// Offset from the start of the tensor data section.
+    pub offset: u64,
+}
+
+impl TensorInfo {
+    pub fn elements(&self) -> u64 {
+        self.dims.iter().product()
+    }
+}
+
+/// The raw file contents, memory-mapped where available.
+pub enum Storage {
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+    Owned(Vec<u8>),
+}
+
+impl Deref for Storage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            #[cfg(feature = "mmap")]
+            Self::Mapped(map) => map,
+            Self::Owned(bytes) => bytes,
+        }
+    }
+}
+
+pub struct GgufFile {
+    pub version: u32,
+    pub metadata: BTreeMap<String, MetadataValue>,
+    pub tensors: Vec<TensorInfo>,
+    data_offset: usize,
+    storage: Arc<Storage>,
+}
+
+impl GgufFile {
+    /// Opens a GGUF file, mapping it into memory when the `mmap` feature is on.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path).with_context(|| format!("opening {:?}", path))?;
+
+        #[cfg(feature = "mmap")]
+        // SAFETY: the model file is treated as read-only for the life of the
+        // process; truncating it underneath a running server is unsupported.
+        let storage = Storage::Mapped(unsafe { memmap2::Mmap::map(&file)? });
+        #[cfg(not(feature = "mmap"))]
+        let storage = {
+            use std::io::Read;
+            let mut bytes = Vec::new();
+            let mut file = file;
+            file.read_to_end(&mut bytes)?;
+            Storage::Owned(bytes)
+        };
+
+        Self::parse(storage).with_context(|| format!("parsing GGUF {:?}", path))
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        Self::parse(Storage::Owned(bytes))
+    }
+
+    fn parse(storage: Storage) -> Result<Self> {
+        let mut reader = Reader {
+            bytes: &storage,
+            pos: 0,
+        };
+
+        if reader.take(4)? != GGUF_MAGIC {
+            bail!("not a GGUF file");
+        }
+        let version = reader.u32()?;
+        if !(2..=3).contains(&version) {
+            bail!("unsupported GGUF version {}", version);
+        }
+        let tensor_count = reader.u64()?;
+        let metadata_count = reader.u64()?;
+
+        let mut metadata = BTreeMap::new();
+        for _ in 0..metadata_count {
+            let key = reader.string()?;
+            let value_type = reader.u32()?;
+            let value = reader.value(value_type)?;
+            metadata.insert(key, value);
+        }
+
+        let mut tensors = Vec::with_capacity(tensor_count as usize);
+        for _ in 0..tensor_count {
+            let name = reader.string()?;
+            let n_dims = reader.u32()?;
+            let dims = (0..n_dims)
+                .map(|_| reader.u64())
+                .collect::<Result<Vec<_>>>()?;
+            let ggml_type = reader.u32()?;
+            let offset = reader.u64()?;
+            tensors.push(TensorInfo {
+                name,
+                dims,
+                ggml_type,
+                offset,
+            });
+        }
+
+        let alignment = metadata
+            .get("general.alignment")
+            .and_then(MetadataValue::as_u64)
+            .unwrap_or(DEFAULT_ALIGNMENT);
+        let data_offset = (reader.pos as u64).div_ceil(alignment) * alignment;
+
+        Ok(Self {
+            version,
+            metadata,
+            tensors,
+            data_offset: data_offset as usize,
+            storage: Arc::new(storage),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&MetadataValue> {
+        self.metadata.get(key)
+    }
+
+    pub fn get_u64(&self, key: &str) -> Result<u64> {
+        self.get(key)
+            .and_then(MetadataValue::as_u64)
+            .ok_or_else(|| anyhow!("missing or invalid metadata `{}`", key))
+    }
+
+    pub fn get_str(&self, key: &str) -> Result<&str> {
+        self.get(key)
+            .and_then(MetadataValue::as_str)
+            .ok_or_else(|| anyhow!("missing or invalid metadata `{}`", key))
+    }
+
+    pub fn architecture(&self) -> Result<&str> {
+        self.get_str("general.architecture")
+    }
+
+    pub fn tensor_info(&self, name: &str) -> Option<&TensorInfo> {
+        self.tensors.iter().find(|tensor| tensor.name == name)
+    }
+
+    /// Loads a tensor by name, keeping a reference to the file's storage.
+    pub fn tensor(&self, name: &str) -> Result<Tensor> {
+        let info = self
+            .tensor_info(name)
+            .ok_or_else(|| anyhow!("tensor `{}` not found", name))?;
+        let dtype = GgmlType::from_id(info.ggml_type)
+            .with_context(|| format!("tensor `{}`", name))?;
+        let (cols, rows) = match info.dims.as_slice() {
+            [cols] => (*cols as usize, 1),
+            [cols, rows] => (*cols as usize, *rows as usize),
+            dims => bail!("tensor `{}` has unsupported shape {:?}", name, dims),
+        };
+
+        let start = self.data_offset + info.offset as usize;
+        let len = dtype.tensor_bytes(rows, cols)?;
+        if start + len > self.storage.len() {
+            bail!("tensor `{}` extends past the end of the file", name);
+        }
+        Tensor::new(dtype, rows, cols, Arc::clone(&self.storage), start, len)
+            .with_context(|| format!("tensor `{}`", name))
+    }
+
+    pub fn has_tensor(&self, name: &str) -> bool {
+        self.tensor_info(name).is_some()
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| anyhow!("unexpected end of file at byte {}", self.pos))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u64()? as usize;
+        let bytes = self.take(len)?;
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    fn value(&mut self, value_type: u32) -> Result<MetadataValue> {
+        Ok(match value_type {
+            0 => MetadataValue::U8(self.array::<1>()?[0]),
+            1 => MetadataValue::I8(self.array::<1>()?[0] as i8),
+            2 => MetadataValue::U16(u16::from_le_bytes(self.array()?)),
+            3 => MetadataValue::I16(i16::from_le_bytes(self.array()?)),
+            4 => MetadataValue::U32(self.u32()?),
+            5 => MetadataValue::I32(i32::from_le_bytes(self.array()?)),
+            6 => MetadataValue::F32(f32::from_le_bytes(self.array()?)),
+            7 => MetadataValue::Bool(self.array::<1>()?[0] != 0),
+            8 => MetadataValue::String(self.string()?),
+            9 => {
+                let element_type = self.u32()?;
+                let len = self.u64()?;
+                let values = (0..len)
+                    .map(|_| self.value(element_type))
+                    .collect::<Result<Vec<_>>>()?;
+                MetadataValue::Array(values)
+            }
+            10 => MetadataValue::U64(self.u64()?),
+            11 => MetadataValue::I64(i64::from_le_bytes(self.array()?)),
+            12 => MetadataValue::F64(f64::from_le_bytes(self.array()?)),
+            other => bail!("unknown metadata value type {}", other),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::GgufBuilder;
+
+    #[test]
+    fn parses_metadata_and_tensors() {
+        let bytes = GgufBuilder::new()
+            .string("general.architecture", "bitnet-b1.58")
+            .u32("bitnet-b1.58.block_count", 2)
+            .f32("bitnet-b1.58.rope.freq_base", 500000.0)
+            .bool("tokenizer.ggml.add_bos_token", false)
+            .strings("tokenizer.ggml.tokens", &["a", "b"])
+            .i32s("tokenizer.ggml.token_type", &[1, 3])
+            .f32_tensor("norm.weight", &[1.0, 2.0, 3.0])
+            .f32_matrix("proj.weight", 2, 2, &[1.0, 2.0, 3.0, 4.0])
+            .build();
+        let gguf = GgufFile::from_bytes(bytes).unwrap();
+
+        assert_eq!(gguf.version, 3);
+        assert_eq!(gguf.architecture().unwrap(), "bitnet-b1.58");
+        assert_eq!(gguf.get_u64("bitnet-b1.58.block_count").unwrap(), 2);
+        let freq_base = gguf.get("bitnet-b1.58.rope.freq_base").unwrap();
+        assert_eq!(freq_base.as_f32(), Some(500000.0));
+        let add_bos = gguf.get("tokenizer.ggml.add_bos_token").unwrap();
+        assert_eq!(add_bos.as_bool(), Some(false));
+        let types = gguf.get("tokenizer.ggml.token_type").unwrap().as_array().unwrap();
+        assert_eq!(types, [MetadataValue::I32(1), MetadataValue::I32(3)]);
+        assert!(gguf.get_u64("general.architecture").is_err());
+
+        assert_eq!(gguf.tensors.len(), 2);
+        assert_eq!(gguf.tensor_info("proj.weight").unwrap().dims, [2, 2]);
+        assert_eq!(gguf.tensor("norm.weight").unwrap().to_vec(), [1.0, 2.0, 3.0]);
+        let proj = gguf.tensor("proj.weight").unwrap();
+        assert_eq!((proj.rows, proj.cols), (2, 2));
+        assert_eq!(proj.row(1), [3.0, 4.0]);
+        assert!(gguf.tensor("missing.weight").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let valid = GgufBuilder::new()
+            .string("general.architecture", "llama")
+            .build();
+
+        let mut bad_magic = valid.clone();
+        bad_magic[0] = b'X';
+        assert!(GgufFile::from_bytes(bad_magic).is_err());
+
+        let mut bad_version = valid.clone();
+        bad_version[4..8].copy_from_slice(&1u32.to_le_bytes());
+        assert!(GgufFile::from_bytes(bad_version).is_err());
+
+        // Cut off inside the first metadata key
+        let truncated = valid[..30].to_vec();
+        assert!(GgufFile::from_bytes(truncated).is_err());
+    }
+
+    #[test]
+    fn rejects_tensor_past_end_of_file() {
+        let mut bytes = GgufBuilder::new()
+            .f32_matrix("proj.weight", 2, 4, &[0.0; 8])
+            .build();
+        bytes.truncate(bytes.len() - 32);
+        let gguf = GgufFile::from_bytes(bytes).unwrap();
+        assert!(gguf.tensor("proj.weight").is_err());
+    }
+}
@@ -0,0 +1,319 @@
+//! The computation the RISC Zero guest proves.
+//!
+//! `methods/guest` reads a [`GuestInput`] and then the model's GGUF, as a
+//! `u32` length and the raw bytes, calls [`run`] and commits the
+//! [`GuestOutput`] to the journal. A guest that fails, such as a replay of a
+//! token the sampler could not have produced, commits nothing and yields no
+//! receipt.
+//! `bitnet-host` and the servers decode journals with these same types. The
+//! fields are fixed-width, as the zkVM's serde has no `usize`.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::gguf::GgufFile;
+use crate::model::{FinishReason, GenerateParams, Model, Replay};
+use crate::sampler::TokenLogprobs;
+
+/// [`GenerateParams`] as the guest reads them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GuestParams {
+    pub max_tokens: u32,
+    /// 0 selects greedy decoding.
+    pub temperature: f32,
+    pub top_p: f32,
+    pub seed: u64,
+    pub stop_tokens: Vec<u32>,
+    /// Alternatives to commit with each token's log-probability; `None`
+    /// commits none.
+    pub logprobs: Option<u32>,
+}
+
+impl From<&GuestParams> for GenerateParams {
+    fn from(params: &GuestParams) -> Self {
+        Self {
+            max_tokens: params.max_tokens as usize,
+            temperature: params.temperature,
+            top_p: params.top_p,
+            seed: params.seed,
+            stop_tokens: params.stop_tokens.clone(),
+            logprobs: params.logprobs.map(|top| top as usize),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GuestInput {
+    /// Samples a completion of `prompt`.
+    Generate {
+        prompt: Vec<u32>,
+        params: GuestParams,
+    },
+    /// Checks a recorded completion of `prompt` ([`Model::replay`]).
+    Replay {
+        prompt: Vec<u32>,
+        completion: Vec<u32>,
+        params: GuestParams,
+    },
+    /// Embeds each input ([`Model::embed`]).
+    Embed {
+        inputs: Vec<Vec<u32>>,
+        commit: EmbeddingCommit,
+    },
+}
+
+/// What an embedding receipt commits for each vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingCommit {
+    /// Its [`embedding_digest`]; a small journal.
+    Hash,
+    /// The vector itself.
+    Vector,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CommittedEmbedding {
+    Hash(String),
+    Vector(Vec<f32>),
+}
+
+impl CommittedEmbedding {
+    pub fn new(vector: Vec<f32>, commit: EmbeddingCommit) -> Self {
+        match commit {
+            EmbeddingCommit::Hash => Self::Hash(embedding_digest(&vector)),
+            EmbeddingCommit::Vector => Self::Vector(vector),
+        }
+    }
+
+    /// Whether this commits to `vector`, bit for bit.
+    pub fn matches(&self, vector: &[f32]) -> bool {
+        match self {
+            Self::Hash(digest) => *digest == embedding_digest(vector),
+            Self::Vector(committed) => committed
+                .iter()
+                .map(|value| value.to_bits())
+                .eq(vector.iter().map(|value| value.to_bits())),
+        }
+    }
+}
+
+/// What a receipt's journal holds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GuestOutput {
+    /// `sha256:<hex>` of the GGUF the guest ran, as the servers report a
+    /// model's digest.
+    pub model_digest: String,
+    /// The request, committed as given, so the receipt shows what was asked.
+    pub input: GuestInput,
+    pub result: GuestResult,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GuestResult {
+    Generated {
+        /// Sampled tokens, excluding a final stop token.
+        tokens: Vec<u32>,
+        /// One entry per token when `logprobs` was asked for.
+        logprobs: Vec<TokenLogprobs>,
+        finish_reason: FinishReason,
+    },
+    /// Every completion token could have been sampled; `reproduced` when the
+    /// seeded sampler picks them all.
+    Replayed { reproduced: bool },
+    /// One entry per input, in order.
+    Embedded { embeddings: Vec<CommittedEmbedding> },
+}
+
+impl GuestOutput {
+    /// The completion tokens the receipt covers: sampled, or replayed; none
+    /// for embeddings.
+    pub fn tokens(&self) -> &[u32] {
+        match (&self.input, &self.result) {
+            (_, GuestResult::Generated { tokens, .. }) => tokens,
+            (GuestInput::Replay { completion, .. }, GuestResult::Replayed { .. }) => completion,
+            (_, GuestResult::Replayed { .. } | GuestResult::Embedded { .. }) => &[],
+        }
+    }
+}
+
+/// `sha256:<hex>` of a GGUF file's bytes.
+pub fn model_digest(gguf: &[u8]) -> String {
+    sha256(gguf)
+}
+
+/// `sha256:<hex>` of an embedding's little-endian `f32` bytes.
+pub fn embedding_digest(vector: &[f32]) -> String {
+    let bytes: Vec<u8> = vector
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    sha256(&bytes)
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    let hex: String = Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256:{}", hex)
+}
+
+/// Loads the model from `gguf` on one thread and answers `input`.
+pub fn run(gguf: Vec<u8>, input: GuestInput) -> Result<GuestOutput> {
+    let model_digest = model_digest(&gguf);
+    let model = Model::load(&GgufFile::from_bytes(gguf)?, 1)?;
+    let result = match &input {
+        GuestInput::Generate { prompt, params } => {
+            let generation = model.generate(prompt, &params.into(), |_| true)?;
+            GuestResult::Generated {
+                tokens: generation.tokens,
+                logprobs: generation.logprobs,
+                finish_reason: generation.finish_reason,
+            }
+        }
+        GuestInput::Replay {
+            prompt,
+            completion,
+            params,
+        } => {
+            let Replay { reproduced } = model.replay(prompt, completion, &params.into())?;
+            GuestResult::Replayed { reproduced }
+        }
+        GuestInput::Embed { inputs, commit } => GuestResult::Embedded {
+            embeddings: inputs
+                .iter()
+                .map(|tokens| Ok(CommittedEmbedding::new(model.embed(tokens)?, *commit)))
+                .collect::<Result<_>>()?,
+        },
+    };
+    Ok(GuestOutput {
+        model_digest,
+        input,
+        result,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{tiny_model, Lcg};
+
+    fn params(temperature: f32, seed: u64) -> GuestParams {
+        GuestParams {
+            max_tokens: 5,
+            temperature,
+            top_p: 0.9,
+            seed,
+            stop_tokens: Vec::new(),
+            logprobs: Some(2),
+        }
+    }
+
+    #[test]
+    fn guest_samples_what_the_native_backend_samples() {
+        let bytes = tiny_model(&mut Lcg(11));
+        let model = Model::load(&GgufFile::from_bytes(bytes.clone()).unwrap(), 2).unwrap();
+
+        for params in [params(0.0, 0), params(0.8, 42)] {
+            let input = GuestInput::Generate {
+                prompt: vec![1, 2, 3],
+                params: params.clone(),
+            };
+            let output = run(bytes.clone(), input.clone()).unwrap();
+            let native = model
+                .generate(&[1, 2, 3], &(&params).into(), |_| true)
+                .unwrap();
+
+            assert_eq!(output.model_digest, model_digest(&bytes));
+            assert_eq!(output.input, input);
+            assert_eq!(output.tokens(), native.tokens);
+            let GuestResult::Generated {
+                logprobs,
+                finish_reason,
+                ..
+            } = output.result
+            else {
+                panic!("expected a generation, got {:?}", output.result);
+            };
+            assert_eq!(logprobs, native.logprobs);
+            assert_eq!(finish_reason, native.finish_reason);
+        }
+    }
+
+    #[test]
+    fn guest_replays_native_completions() {
+        let bytes = tiny_model(&mut Lcg(11));
+        let model = Model::load(&GgufFile::from_bytes(bytes.clone()).unwrap(), 2).unwrap();
+        let replay = |completion: &[u32], params: &GuestParams| {
+            let input = GuestInput::Replay {
+                prompt: vec![1, 2, 3],
+                completion: completion.to_vec(),
+                params: params.clone(),
+            };
+            run(bytes.clone(), input)
+        };
+
+        for params in [params(0.0, 0), params(0.8, 42)] {
+            let native = model
+                .generate(&[1, 2, 3], &(&params).into(), |_| true)
+                .unwrap();
+            let output = replay(&native.tokens, &params).unwrap();
+            assert_eq!(output.result, GuestResult::Replayed { reproduced: true });
+            assert_eq!(output.tokens(), native.tokens);
+        }
+
+        // Greedy decoding can only have produced the argmax
+        let greedy = params(0.0, 0);
+        let mut tokens = model
+            .generate(&[1, 2, 3], &(&greedy).into(), |_| true)
+            .unwrap()
+            .tokens;
+        tokens[0] = (tokens[0] + 1) % model.config.vocab_size as u32;
+        assert!(replay(&tokens, &greedy).is_err());
+    }
+
+    #[test]
+    fn guest_embeds_what_the_native_backend_embeds() {
+        let bytes = tiny_model(&mut Lcg(11));
+        let model = Model::load(&GgufFile::from_bytes(bytes.clone()).unwrap(), 2).unwrap();
+        let inputs = vec![vec![1, 2, 3], vec![4]];
+        let native: Vec<Vec<f32>> = inputs
+            .iter()
+            .map(|tokens| model.embed(tokens).unwrap())
+            .collect();
+
+        for commit in [EmbeddingCommit::Hash, EmbeddingCommit::Vector] {
+            let input = GuestInput::Embed {
+                inputs: inputs.clone(),
+                commit,
+            };
+            let output = run(bytes.clone(), input).unwrap();
+            let GuestResult::Embedded { embeddings } = &output.result else {
+                panic!("expected embeddings, got {:?}", output.result);
+            };
+            assert_eq!(embeddings.len(), 2);
+            for (committed, vector) in embeddings.iter().zip(&native) {
+                assert_eq!(*committed, CommittedEmbedding::new(vector.clone(), commit));
+                assert!(committed.matches(vector));
+                assert!(!committed.matches(&vector[1..]));
+            }
+            assert!(output.tokens().is_empty());
+        }
+        // An empty input cannot be embedded, so yields no receipt
+        let empty = GuestInput::Embed {
+            inputs: vec![Vec::new()],
+            commit: EmbeddingCommit::Hash,
+        };
+        assert!(run(bytes, empty).is_err());
+    }
+
+    #[test]
+    fn digest_is_sha256_of_the_file() {
+        assert_eq!(
+            model_digest(b"abc"),
+            "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
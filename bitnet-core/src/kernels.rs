@@ -0,0 +1,147 @@
+//! Scalar kernels. Kept free of SIMD and floating-point shortcuts that differ
+//! between targets, so every build computes the same values.
+
+use half::{bf16, f16};
+
+/// Quantizes activations to int8 with a single absmax scale, as BitNet does
+/// before every ternary matmul. Returns the values and `127 / max|x|`.
+pub fn quantize_activations(x: &[f32]) -> (Vec<i8>, f32) {
+    let max = x.iter().fold(0.0f32, |max, v| max.max(v.abs()));
+    if max == 0.0 {
+        return (vec![0; x.len()], 1.0);
+    }
+    let scale = 127.0 / max;
+    let quantized = x
+        .iter()
+        .map(|v| (v * scale).round().clamp(-128.0, 127.0) as i8)
+        .collect();
+    (quantized, scale)
+}
+
+/// Integer dot product of one I2_S row with int8 activations.
+///
+/// Each 32-byte block holds 128 weights: byte `j` carries elements `j`,
+/// `j + 32`, `j + 64` and `j + 96` in bits 7-6, 5-4, 3-2 and 1-0, encoded as
+/// 0, 1, 2 for -1, 0, +1.
+pub fn dot_i2s(row: &[u8], xq: &[i8]) -> i32 {
+    let mut acc = 0i32;
+    for (block, xs) in row.chunks_exact(32).zip(xq.chunks_exact(128)) {
+        for (j, &byte) in block.iter().enumerate() {
+            for group in 0..4 {
+                let q = ((byte >> (6 - 2 * group)) & 3) as i32 - 1;
+                acc += q * xs[group * 32 + j] as i32;
+            }
+        }
+    }
+    acc
+}
+
+pub fn dot_f32(row: &[u8], x: &[f32]) -> f32 {
+    row.chunks_exact(4)
+        .zip(x)
+        .map(|(b, x)| f32::from_le_bytes(b.try_into().unwrap()) * x)
+        .sum()
+}
+
+pub fn dot_f16(row: &[u8], x: &[f32]) -> f32 {
+    row.chunks_exact(2)
+        .zip(x)
+        .map(|(b, x)| f16::from_le_bytes([b[0], b[1]]).to_f32() * x)
+        .sum()
+}
+
+pub fn dot_bf16(row: &[u8], x: &[f32]) -> f32 {
+    row.chunks_exact(2)
+        .zip(x)
+        .map(|(b, x)| bf16::from_le_bytes([b[0], b[1]]).to_f32() * x)
+        .sum()
+}
+
+pub fn dot_q8_0(row: &[u8], x: &[f32]) -> f32 {
+    row.chunks_exact(34)
+        .zip(x.chunks_exact(32))
+        .map(|(block, xs)| {
+            let d = f16::from_le_bytes([block[0], block[1]]).to_f32();
+            let sum: f32 = block[2..].iter().zip(xs).map(|(&q, x)| q as i8 as f32 * x).sum();
+            sum * d
+        })
+        .sum()
+}
+
+pub fn rms_norm(x: &[f32], weight: &[f32], eps: f32) -> Vec<f32> {
+    let mean_square = x.iter().map(|v| v * v).sum::<f32>() / x.len() as f32;
+    let inv = 1.0 / (mean_square + eps).sqrt();
+    x.iter().zip(weight).map(|(v, w)| v * inv * w).collect()
+}
+
+pub fn softmax(values: &mut [f32]) {
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mut sum = 0.0;
+    for value in values.iter_mut() {
+        *value = (*value - max).exp();
+        sum += *value;
+    }
+    for value in values.iter_mut() {
+        *value /= sum;
+    }
+}
+
+/// Scales `values` to unit Euclidean length; a zero vector stays zero.
+pub fn l2_normalize(values: &mut [f32]) {
+    let norm = values.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        for v in values.iter_mut() {
+            *v /= norm;
+        }
+    }
+}
+
+/// Rotary position embedding on adjacent pairs (GGML's "normal" mode; the
+/// GGUF converters permute Q and K weights to match).
+pub fn rope(x: &mut [f32], head_dim: usize, pos: usize, base: f32) {
+    for head in x.chunks_exact_mut(head_dim) {
+        for i in (0..head_dim).step_by(2) {
+            let theta = pos as f32 * base.powf(-(i as f32) / head_dim as f32);
+            let (sin, cos) = theta.sin_cos();
+            let (a, b) = (head[i], head[i + 1]);
+            head[i] = a * cos - b * sin;
+            head[i + 1] = a * sin + b * cos;
+        }
+    }
+}
+
+pub fn silu(x: f32) -> f32 {
+    x / (1.0 + (-x).exp())
+}
+
+/// Squared ReLU, the BitNet b1.58 feed-forward activation.
+pub fn relu_squared(x: f32) -> f32 {
+    let relu = x.max(0.0);
+    relu * relu
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dot_i2s_follows_the_block_layout() {
+        let mut row = [0b01_01_01_01u8; 32];
+        // Elements 0, 32, 64 and 96: +1, 0, -1, +1
+        row[0] = 0b10_01_00_10;
+        let mut xq = [0i8; 128];
+        xq[0] = 3;
+        xq[32] = 100;
+        xq[64] = 5;
+        xq[96] = 7;
+        assert_eq!(dot_i2s(&row, &xq), 3 - 5 + 7);
+    }
+
+    #[test]
+    fn quantize_activations_scales_by_absmax() {
+        let (xq, scale) = quantize_activations(&[0.5, -1.0, 0.25]);
+        assert_eq!(scale, 127.0);
+        assert_eq!(xq, [64, -127, 32]);
+        assert_eq!(quantize_activations(&[0.0, 0.0]), (vec![0, 0], 1.0));
+    }
+}
@@ -0,0 +1,25 @@
+//! BitNet b1.58 inference in Rust: GGUF loading, ternary (I2_S) kernels, the
+//! transformer forward pass and the tokenizer.
+//!
+//! This is the native backend of `bitnet-zkml` and, through [`guest`], the
+//! program the RISC Zero guest that `bitnet-host` proves runs: both sample
+//! with the same forward pass, so a proven completion is the one the native
+//! backend gives for the same prompt, parameters and seed.
+
+pub mod gguf;
+pub mod guest;
+pub mod kernels;
+pub mod model;
+pub mod sampler;
+pub mod tensor;
+pub mod tokenizer;
+
+#[cfg(test)]
+mod testing;
+
+pub use gguf::GgufFile;
+pub use model::{
+    FinishReason, GenerateParams, Generation, KvCache, Model, ModelConfig, Replay, Sequence,
+};
+pub use sampler::TokenLogprobs;
+pub use tokenizer::Tokenizer;
@@ -0,0 +1,691 @@
+//! BitNet b1.58 transformer forward pass with a KV cache.
+//!
+//! The layout is LLaMA-style (RMSNorm, RoPE, grouped-query attention, gated
+//! feed-forward) with BitNet's additions: extra sub-norms before the attention
+//! output and down projections, squared-ReLU gating and ternary weights.
+//! Plain `llama` GGUFs load too, with SiLU gating and no sub-norms.
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::gguf::GgufFile;
+use crate::kernels;
+use crate::sampler::{token_logprobs, Sampler, TokenLogprobs};
+use crate::tensor::Tensor;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activation {
+    Silu,
+    ReluSquared,
+}
+
+#[derive(Debug, Clone)]
+pub struct ModelConfig {
+    pub architecture: String,
+    pub vocab_size: usize,
+    pub embedding_length: usize,
+    pub block_count: usize,
+    pub feed_forward_length: usize,
+    pub head_count: usize,
+    pub head_count_kv: usize,
+    pub context_length: usize,
+    pub rms_norm_eps: f32,
+    pub rope_freq_base: f32,
+    pub activation: Activation,
+}
+
+impl ModelConfig {
+    pub fn from_gguf(gguf: &GgufFile) -> Result<Self> {
+        let architecture = gguf.architecture()?.to_string();
+        let key = |name: &str| format!("{}.{}", architecture, name);
+        let get = |name: &str| gguf.get_u64(&key(name)).map(|v| v as usize);
+        let get_f32 = |name: &str| gguf.get(&key(name)).and_then(|v| v.as_f32());
+
+        let head_count = get("attention.head_count")?;
+        let vocab_size = match get("vocab_size") {
+            Ok(size) => size,
+            Err(_) => gguf
+                .get("tokenizer.ggml.tokens")
+                .and_then(|tokens| tokens.as_array())
+                .map(|tokens| tokens.len())
+                .ok_or_else(|| anyhow!("cannot determine vocabulary size"))?,
+        };
+
+        Ok(Self {
+            vocab_size,
+            embedding_length: get("embedding_length")?,
+            block_count: get("block_count")?,
+            feed_forward_length: get("feed_forward_length")?,
+            head_count,
+            head_count_kv: get("attention.head_count_kv").unwrap_or(head_count),
+            context_length: get("context_length")?,
+            rms_norm_eps: get_f32("attention.layer_norm_rms_epsilon").unwrap_or(1e-5),
+            rope_freq_base: get_f32("rope.freq_base").unwrap_or(10_000.0),
+            activation: if architecture.starts_with("bitnet") {
+                Activation::ReluSquared
+            } else {
+                Activation::Silu
+            },
+            architecture,
+        })
+    }
+
+    pub fn head_dim(&self) -> usize {
+        self.embedding_length / self.head_count
+    }
+
+    pub fn kv_dim(&self) -> usize {
+        self.head_dim() * self.head_count_kv
+    }
+}
+
+struct Layer {
+    attn_norm: Vec<f32>,
+    wq: Tensor,
+    wk: Tensor,
+    wv: Tensor,
+    wo: Tensor,
+    attn_sub_norm: Option<Vec<f32>>,
+    ffn_norm: Vec<f32>,
+    ffn_gate: Tensor,
+    ffn_up: Tensor,
+    ffn_down: Tensor,
+    ffn_sub_norm: Option<Vec<f32>>,
+}
+
+/// Keys and values for every position seen so far, per layer.
+pub struct KvCache {
+    keys: Vec<Vec<f32>>,
+    values: Vec<Vec<f32>>,
+    kv_dim: usize,
+    len: usize,
+}
+
+impl KvCache {
+    /// Reserves room for `positions` tokens up front.
+    pub fn new(config: &ModelConfig, positions: usize) -> Self {
+        let kv_dim = config.kv_dim();
+        let layer = || Vec::with_capacity(positions * kv_dim);
+        Self {
+            keys: (0..config.block_count).map(|_| layer()).collect(),
+            values: (0..config.block_count).map(|_| layer()).collect(),
+            kv_dim,
+            len: 0,
+        }
+    }
+
+    /// Positions already processed.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GenerateParams {
+    pub max_tokens: usize,
+    /// 0 selects greedy decoding.
+    pub temperature: f32,
+    pub top_p: f32,
+    pub seed: u64,
+    /// Generation ends after emitting any of these.
+    pub stop_tokens: Vec<u32>,
+    /// Record each sampled token's log-probability with this many top
+    /// alternatives; `None` skips the work.
+    pub logprobs: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FinishReason {
+    Stop,
+    Length,
+}
+
+impl FinishReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Stop => "stop",
+            Self::Length => "length",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Generation {
+    /// Sampled tokens, excluding a final stop token.
+    pub tokens: Vec<u32>,
+    /// One entry per token when `GenerateParams::logprobs` is set.
+    pub logprobs: Vec<TokenLogprobs>,
+    pub finish_reason: FinishReason,
+}
+
+/// Outcome of [`Model::replay`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Replay {
+    /// True when the seeded sampler picks every completion token, i.e. the
+    /// transcript came from this implementation with these parameters.
+    pub reproduced: bool,
+}
+
+/// A generation driven one token at a time by [`Model::step`], so that
+/// several can share each forward pass.
+pub struct Sequence {
+    params: GenerateParams,
+    prompt: Vec<u32>,
+    cache: KvCache,
+    sampler: Sampler,
+    /// Prompt tokens fed so far.
+    fed: usize,
+    /// Sampled token to feed next, once the prompt is in.
+    next: Option<u32>,
+    tokens: Vec<u32>,
+    logprobs: Vec<TokenLogprobs>,
+    finish_reason: Option<FinishReason>,
+}
+
+impl Sequence {
+    pub fn new(model: &Model, prompt: &[u32], params: GenerateParams) -> Result<Self> {
+        if prompt.is_empty() {
+            bail!("prompt is empty");
+        }
+        let needed = prompt.len() + params.max_tokens;
+        if needed > model.config.context_length {
+            bail!(
+                "prompt ({} tokens) plus max_tokens ({}) exceeds the context length {}",
+                prompt.len(),
+                params.max_tokens,
+                model.config.context_length
+            );
+        }
+        Ok(Self {
+            cache: KvCache::new(&model.config, needed),
+            sampler: Sampler::new(params.temperature, params.top_p, params.seed),
+            params,
+            prompt: prompt.to_vec(),
+            fed: 0,
+            next: None,
+            tokens: Vec::new(),
+            logprobs: Vec::new(),
+            finish_reason: None,
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finish_reason.is_some()
+    }
+
+    /// Tokens sampled so far.
+    pub fn tokens(&self) -> &[u32] {
+        &self.tokens
+    }
+
+    /// Ends generation after the last sampled token, as when `on_token`
+    /// returns false in [`Model::generate`].
+    pub fn stop(&mut self) {
+        self.finish_reason = Some(FinishReason::Stop);
+    }
+
+    pub fn into_generation(self) -> Generation {
+        Generation {
+            tokens: self.tokens,
+            logprobs: self.logprobs,
+            finish_reason: self.finish_reason.unwrap_or(FinishReason::Length),
+        }
+    }
+
+    fn input(&self) -> Option<u32> {
+        if self.is_finished() {
+            None
+        } else if self.fed < self.prompt.len() {
+            Some(self.prompt[self.fed])
+        } else {
+            self.next
+        }
+    }
+
+    /// Takes the logits after the token from [`Sequence::input`] and, once
+    /// the prompt is in, samples from them.
+    fn advance(&mut self, logits: &[f32]) -> Option<u32> {
+        if self.fed < self.prompt.len() {
+            self.fed += 1;
+            if self.fed < self.prompt.len() {
+                return None;
+            }
+        }
+        if self.tokens.len() >= self.params.max_tokens {
+            self.finish_reason = Some(FinishReason::Length);
+            return None;
+        }
+        let token = self.sampler.sample(logits);
+        if self.params.stop_tokens.contains(&token) {
+            self.finish_reason = Some(FinishReason::Stop);
+            return None;
+        }
+        self.tokens.push(token);
+        if let Some(top) = self.params.logprobs {
+            self.logprobs.push(token_logprobs(logits, token, top));
+        }
+        if self.tokens.len() >= self.params.max_tokens {
+            self.finish_reason = Some(FinishReason::Length);
+        } else {
+            self.next = Some(token);
+        }
+        Some(token)
+    }
+}
+
+pub struct Model {
+    pub config: ModelConfig,
+    token_embd: Tensor,
+    layers: Vec<Layer>,
+    output_norm: Vec<f32>,
+    /// `None` when the output projection is tied to the token embeddings.
+    output: Option<Tensor>,
+    #[cfg(feature = "parallel")]
+    pool: rayon::ThreadPool,
+}
+
+impl Model {
+    /// Loads weights from a parsed GGUF. `threads` sizes the matmul thread
+    /// pool and is ignored without the `parallel` feature.
+    pub fn load(gguf: &GgufFile, threads: usize) -> Result<Self> {
+        #[cfg(not(feature = "parallel"))]
+        let _ = threads;
+        let config = ModelConfig::from_gguf(gguf)?;
+        let norm = |name: &str| gguf.tensor(name).map(|tensor| tensor.to_vec());
+        let optional_norm = |name: &str| {
+            if gguf.has_tensor(name) {
+                norm(name).map(Some)
+            } else {
+                Ok(None)
+            }
+        };
+
+        let layers = (0..config.block_count)
+            .map(|i| {
+                let name = |suffix: &str| format!("blk.{}.{}.weight", i, suffix);
+                Ok(Layer {
+                    attn_norm: norm(&name("attn_norm"))?,
+                    wq: gguf.tensor(&name("attn_q"))?,
+                    wk: gguf.tensor(&name("attn_k"))?,
+                    wv: gguf.tensor(&name("attn_v"))?,
+                    wo: gguf.tensor(&name("attn_output"))?,
+                    attn_sub_norm: optional_norm(&name("attn_sub_norm"))?,
+                    ffn_norm: norm(&name("ffn_norm"))?,
+                    ffn_gate: gguf.tensor(&name("ffn_gate"))?,
+                    ffn_up: gguf.tensor(&name("ffn_up"))?,
+                    ffn_down: gguf.tensor(&name("ffn_down"))?,
+                    ffn_sub_norm: optional_norm(&name("ffn_sub_norm"))?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let token_embd = gguf.tensor("token_embd.weight")?;
+        if token_embd.cols != config.embedding_length {
+            bail!(
+                "token_embd width {} does not match embedding_length {}",
+                token_embd.cols,
+                config.embedding_length
+            );
+        }
+        let output = if gguf.has_tensor("output.weight") {
+            Some(gguf.tensor("output.weight")?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            token_embd,
+            layers,
+            output_norm: norm("output_norm.weight")?,
+            output,
+            #[cfg(feature = "parallel")]
+            pool: rayon::ThreadPoolBuilder::new()
+                .num_threads(threads.max(1))
+                .build()?,
+            config,
+        })
+    }
+
+    /// Runs one token at the next cache position and returns the final
+    /// hidden state (after the output norm).
+    pub fn forward_hidden(&self, token: u32, cache: &mut KvCache) -> Result<Vec<f32>> {
+        let mut hidden = self.forward_hidden_batch(&[token], &mut [cache])?;
+        Ok(hidden.pop().expect("one hidden state per token"))
+    }
+
+    /// [`Model::forward_hidden`] for several sequences at once: `tokens[i]`
+    /// runs at the next position of `caches[i]`. Every weight row is read
+    /// once for the whole batch, and each sequence gets exactly the hidden
+    /// state it would get on its own.
+    pub fn forward_hidden_batch(
+        &self,
+        tokens: &[u32],
+        caches: &mut [&mut KvCache],
+    ) -> Result<Vec<Vec<f32>>> {
+        assert_eq!(tokens.len(), caches.len(), "one cache per token");
+        let config = &self.config;
+        for (&token, cache) in tokens.iter().zip(caches.iter()) {
+            if token as usize >= self.token_embd.rows {
+                bail!("token {} is outside the vocabulary", token);
+            }
+            if cache.len >= config.context_length {
+                bail!("context length {} exceeded", config.context_length);
+            }
+        }
+
+        let eps = config.rms_norm_eps;
+        let mut xs: Vec<Vec<f32>> = tokens
+            .iter()
+            .map(|&token| self.token_embd.row(token as usize))
+            .collect();
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            let h: Vec<_> = xs
+                .iter()
+                .map(|x| kernels::rms_norm(x, &layer.attn_norm, eps))
+                .collect();
+            let qs = layer.wq.matmul(&h);
+            let ks = layer.wk.matmul(&h);
+            let vs = layer.wv.matmul(&h);
+            let attention: Vec<_> = qs
+                .into_iter()
+                .zip(ks.into_iter().zip(vs))
+                .zip(caches.iter_mut())
+                .map(|((q, (k, v)), cache)| {
+                    let attention = self.attend(q, k, &v, cache, i);
+                    match &layer.attn_sub_norm {
+                        Some(sub_norm) => kernels::rms_norm(&attention, sub_norm, eps),
+                        None => attention,
+                    }
+                })
+                .collect();
+            for (x, out) in xs.iter_mut().zip(layer.wo.matmul(&attention)) {
+                for (x, o) in x.iter_mut().zip(out) {
+                    *x += o;
+                }
+            }
+
+            let h: Vec<_> = xs
+                .iter()
+                .map(|x| kernels::rms_norm(x, &layer.ffn_norm, eps))
+                .collect();
+            let gates = layer.ffn_gate.matmul(&h);
+            let ups = layer.ffn_up.matmul(&h);
+            let hidden: Vec<_> = gates
+                .iter()
+                .zip(&ups)
+                .map(|(gate, up)| {
+                    let hidden: Vec<f32> = gate
+                        .iter()
+                        .zip(up)
+                        .map(|(&g, &u)| {
+                            let g = match config.activation {
+                                Activation::Silu => kernels::silu(g),
+                                Activation::ReluSquared => kernels::relu_squared(g),
+                            };
+                            g * u
+                        })
+                        .collect();
+                    match &layer.ffn_sub_norm {
+                        Some(sub_norm) => kernels::rms_norm(&hidden, sub_norm, eps),
+                        None => hidden,
+                    }
+                })
+                .collect();
+            for (x, down) in xs.iter_mut().zip(layer.ffn_down.matmul(&hidden)) {
+                for (x, d) in x.iter_mut().zip(down) {
+                    *x += d;
+                }
+            }
+        }
+
+        for cache in caches.iter_mut() {
+            cache.len += 1;
+        }
+        Ok(xs
+            .iter()
+            .map(|x| kernels::rms_norm(x, &self.output_norm, eps))
+            .collect())
+    }
+
+    /// Self-attention of one token in `layer`: appends its key and value to
+    /// the cache and attends over every position so far.
+    fn attend(
+        &self,
+        mut q: Vec<f32>,
+        mut k: Vec<f32>,
+        v: &[f32],
+        cache: &mut KvCache,
+        layer: usize,
+    ) -> Vec<f32> {
+        let config = &self.config;
+        let head_dim = config.head_dim();
+        let group = config.head_count / config.head_count_kv;
+        let scale = 1.0 / (head_dim as f32).sqrt();
+        let pos = cache.len;
+        kernels::rope(&mut q, head_dim, pos, config.rope_freq_base);
+        kernels::rope(&mut k, head_dim, pos, config.rope_freq_base);
+        let keys = &mut cache.keys[layer];
+        let values = &mut cache.values[layer];
+        keys.extend_from_slice(&k);
+        values.extend_from_slice(v);
+
+        let mut attention = vec![0.0; config.embedding_length];
+        let mut scores = vec![0.0; pos + 1];
+        for head in 0..config.head_count {
+            let q_head = &q[head * head_dim..(head + 1) * head_dim];
+            let kv_offset = (head / group) * head_dim;
+            for (t, score) in scores.iter_mut().enumerate() {
+                let k_t = &keys[t * cache.kv_dim + kv_offset..][..head_dim];
+                *score = q_head.iter().zip(k_t).map(|(a, b)| a * b).sum::<f32>() * scale;
+            }
+            kernels::softmax(&mut scores);
+            let out = &mut attention[head * head_dim..(head + 1) * head_dim];
+            for (t, weight) in scores.iter().enumerate() {
+                let v_t = &values[t * cache.kv_dim + kv_offset..][..head_dim];
+                for (o, v) in out.iter_mut().zip(v_t) {
+                    *o += weight * v;
+                }
+            }
+        }
+        attention
+    }
+
+    /// Runs one token and returns the next-token logits.
+    pub fn forward(&self, token: u32, cache: &mut KvCache) -> Result<Vec<f32>> {
+        let hidden = self.forward_hidden(token, cache)?;
+        Ok(self.output.as_ref().unwrap_or(&self.token_embd).matvec(&hidden))
+    }
+
+    /// [`Model::forward`] for several sequences at once.
+    pub fn forward_batch(
+        &self,
+        tokens: &[u32],
+        caches: &mut [&mut KvCache],
+    ) -> Result<Vec<Vec<f32>>> {
+        let hidden = self.forward_hidden_batch(tokens, caches)?;
+        Ok(self.output.as_ref().unwrap_or(&self.token_embd).matmul(&hidden))
+    }
+
+    /// Feeds `prompt`, then samples up to `params.max_tokens` tokens.
+    /// `on_token` sees each token as it is produced and may return false to
+    /// stop early.
+    pub fn generate(
+        &self,
+        prompt: &[u32],
+        params: &GenerateParams,
+        mut on_token: impl FnMut(u32) -> bool + Send,
+    ) -> Result<Generation> {
+        let mut sequence = Sequence::new(self, prompt, params.clone())?;
+        while !sequence.is_finished() {
+            if let [Some(token)] = self.step(&mut [&mut sequence])?[..] {
+                if !on_token(token) {
+                    sequence.stop();
+                }
+            }
+        }
+        Ok(sequence.into_generation())
+    }
+
+    /// Advances every unfinished sequence by one token in a single batched
+    /// forward pass: the next prompt token while prefilling, the last
+    /// sampled token after. Returns the token each sequence sampled, if any.
+    pub fn step(&self, sequences: &mut [&mut Sequence]) -> Result<Vec<Option<u32>>> {
+        let inputs: Vec<(usize, u32)> = sequences
+            .iter()
+            .enumerate()
+            .filter_map(|(i, sequence)| sequence.input().map(|token| (i, token)))
+            .collect();
+        let tokens: Vec<u32> = inputs.iter().map(|&(_, token)| token).collect();
+        let mut caches: Vec<&mut KvCache> = sequences
+            .iter_mut()
+            .filter(|sequence| sequence.input().is_some())
+            .map(|sequence| &mut sequence.cache)
+            .collect();
+        let logits = self.run(|| self.forward_batch(&tokens, &mut caches))?;
+
+        let mut sampled = vec![None; sequences.len()];
+        for ((i, _), logits) in inputs.into_iter().zip(logits) {
+            sampled[i] = sequences[i].advance(&logits);
+        }
+        Ok(sampled)
+    }
+
+    /// Checks a transcript in teacher-forced mode: feeds `prompt` and then
+    /// `completion` token by token, and fails at the first completion token
+    /// the sampler could not have produced under `params` (not the argmax
+    /// when greedy, outside the top-p nucleus otherwise).
+    pub fn replay(
+        &self,
+        prompt: &[u32],
+        completion: &[u32],
+        params: &GenerateParams,
+    ) -> Result<Replay> {
+        if prompt.is_empty() {
+            bail!("prompt is empty");
+        }
+        if completion.len() > params.max_tokens {
+            bail!(
+                "completion has {} tokens, more than max_tokens ({})",
+                completion.len(),
+                params.max_tokens
+            );
+        }
+        let needed = prompt.len() + completion.len();
+        if needed > self.config.context_length {
+            bail!(
+                "transcript ({} tokens) exceeds the context length {}",
+                needed,
+                self.config.context_length
+            );
+        }
+
+        self.run(|| {
+            let mut cache = KvCache::new(&self.config, needed);
+            let mut sampler = Sampler::new(params.temperature, params.top_p, params.seed);
+
+            let mut logits = Vec::new();
+            for &token in prompt {
+                logits = self.forward(token, &mut cache)?;
+            }
+
+            let mut reproduced = true;
+            for (position, &token) in completion.iter().enumerate() {
+                if !sampler.can_sample(&logits, token) {
+                    bail!("completion token {} ({}) cannot be sampled", position, token);
+                }
+                reproduced &= sampler.sample(&logits) == token;
+                logits = self.forward(token, &mut cache)?;
+            }
+            Ok(Replay { reproduced })
+        })
+    }
+
+    /// Sentence embedding of `tokens`: the final hidden states mean-pooled
+    /// over every position, then L2-normalized.
+    pub fn embed(&self, tokens: &[u32]) -> Result<Vec<f32>> {
+        if tokens.is_empty() {
+            bail!("input is empty");
+        }
+        if tokens.len() > self.config.context_length {
+            bail!(
+                "input ({} tokens) exceeds the context length {}",
+                tokens.len(),
+                self.config.context_length
+            );
+        }
+
+        self.run(|| {
+            let mut cache = KvCache::new(&self.config, tokens.len());
+            let mut pooled = vec![0.0; self.config.embedding_length];
+            for &token in tokens {
+                let hidden = self.forward_hidden(token, &mut cache)?;
+                for (p, h) in pooled.iter_mut().zip(hidden) {
+                    *p += h;
+                }
+            }
+            // Dividing by the length first would not change the direction
+            kernels::l2_normalize(&mut pooled);
+            Ok(pooled)
+        })
+    }
+
+    /// Runs `work` on the model's thread pool.
+    pub fn run<T: Send>(&self, work: impl FnOnce() -> T + Send) -> T {
+        #[cfg(feature = "parallel")]
+        return self.pool.install(work);
+        #[cfg(not(feature = "parallel"))]
+        work()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{tiny_model, Lcg};
+
+    #[test]
+    fn batched_steps_match_generating_alone() {
+        let gguf = GgufFile::from_bytes(tiny_model(&mut Lcg(7))).unwrap();
+        let model = Model::load(&gguf, 2).unwrap();
+        let params = |max_tokens, temperature, seed| GenerateParams {
+            max_tokens,
+            temperature,
+            top_p: 0.9,
+            seed,
+            stop_tokens: Vec::new(),
+            logprobs: Some(2),
+        };
+        // Different prompt lengths, so some sequences sample while others
+        // are still prefilling, and one finishes early
+        let requests = [
+            (vec![1, 2, 3], params(6, 0.0, 0)),
+            (vec![4, 5, 6, 7, 8], params(4, 0.8, 1)),
+            (vec![9], params(2, 1.2, 2)),
+        ];
+
+        let mut sequences: Vec<Sequence> = requests
+            .iter()
+            .map(|(prompt, params)| Sequence::new(&model, prompt, params.clone()).unwrap())
+            .collect();
+        while sequences.iter().any(|sequence| !sequence.is_finished()) {
+            let mut batch: Vec<&mut Sequence> = sequences.iter_mut().collect();
+            model.step(&mut batch).unwrap();
+        }
+
+        for ((prompt, params), sequence) in requests.iter().zip(sequences) {
+            let alone = model.generate(prompt, params, |_| true).unwrap();
+            let batched = sequence.into_generation();
+            assert_eq!(batched.tokens.len(), params.max_tokens);
+            assert_eq!(batched.tokens, alone.tokens);
+            assert_eq!(batched.finish_reason, alone.finish_reason);
+            for (batched, alone) in batched.logprobs.iter().zip(&alone.logprobs) {
+                assert_eq!(batched.logprob, alone.logprob);
+            }
+        }
+    }
+}
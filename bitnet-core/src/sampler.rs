@@ -0,0 +1,169 @@
+//! Token sampling: greedy, or temperature with nucleus (top-p) filtering.
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+pub struct Sampler {
+    temperature: f32,
+    top_p: f32,
+    rng: StdRng,
+}
+
+impl Sampler {
+    pub fn new(temperature: f32, top_p: f32, seed: u64) -> Self {
+        Self {
+            temperature,
+            top_p,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn sample(&mut self, logits: &[f32]) -> u32 {
+        if self.temperature <= 0.0 {
+            return argmax(logits);
+        }
+
+        let candidates = self.candidates(logits);
+        let kept: f32 = candidates.iter().map(|(_, p)| p).sum();
+        let mut target = self.rng.gen::<f32>() * kept;
+        for &(token, p) in &candidates {
+            if target < p {
+                return token;
+            }
+            target -= p;
+        }
+        candidates.last().map_or(0, |(token, _)| *token)
+    }
+
+    /// Whether `sample` could return `token` for these logits.
+    pub fn can_sample(&self, logits: &[f32], token: u32) -> bool {
+        if self.temperature <= 0.0 {
+            return argmax(logits) == token;
+        }
+        self.candidates(logits).iter().any(|&(candidate, _)| candidate == token)
+    }
+
+    /// Tokens left after top-p filtering, most likely first, with
+    /// unnormalized probabilities.
+    fn candidates(&self, logits: &[f32]) -> Vec<(u32, f32)> {
+        let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let mut candidates: Vec<(u32, f32)> = logits
+            .iter()
+            .enumerate()
+            .map(|(token, &logit)| (token as u32, ((logit - max) / self.temperature).exp()))
+            .collect();
+        candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let total: f32 = candidates.iter().map(|(_, p)| p).sum();
+        if self.top_p > 0.0 && self.top_p < 1.0 {
+            let mut cumulative = 0.0;
+            let keep = candidates
+                .iter()
+                .position(|(_, p)| {
+                    cumulative += p / total;
+                    cumulative >= self.top_p
+                })
+                .map_or(candidates.len(), |last| last + 1);
+            candidates.truncate(keep);
+        }
+        candidates
+    }
+}
+
+pub fn argmax(logits: &[f32]) -> u32 {
+    logits
+        .iter()
+        .enumerate()
+        .fold((0, f32::NEG_INFINITY), |best, (token, &logit)| {
+            if logit > best.1 {
+                (token, logit)
+            } else {
+                best
+            }
+        })
+        .0 as u32
+}
+
+/// Log-probability of a sampled token and of the most likely tokens at that
+/// position, most likely first.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenLogprobs {
+    pub token: u32,
+    pub logprob: f32,
+    pub top: Vec<(u32, f32)>,
+}
+
+/// Log-probabilities of `token` and the `top` most likely tokens under the
+/// model's own distribution (temperature 1), whatever the sampler settings.
+pub fn token_logprobs(logits: &[f32], token: u32, top: usize) -> TokenLogprobs {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let log_total = logits.iter().map(|&logit| (logit - max).exp()).sum::<f32>().ln() + max;
+    let logprob = |token: u32| logits[token as usize] - log_total;
+
+    let mut ranked: Vec<u32> = (0..logits.len() as u32).collect();
+    let top = top.min(ranked.len());
+    let by_logit = |a: &u32, b: &u32| logits[*b as usize].total_cmp(&logits[*a as usize]);
+    if top > 0 && top < ranked.len() {
+        ranked.select_nth_unstable_by(top - 1, by_logit);
+    }
+    ranked.truncate(top);
+    ranked.sort_by(by_logit);
+
+    TokenLogprobs {
+        token,
+        logprob: logprob(token),
+        top: ranked.into_iter().map(|token| (token, logprob(token))).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOGITS: [f32; 3] = [1.0, 3.0, 2.0];
+
+    fn tokens(candidates: &[(u32, f32)]) -> Vec<u32> {
+        candidates.iter().map(|&(token, _)| token).collect()
+    }
+
+    #[test]
+    fn candidates_are_ranked_and_cut_at_top_p() {
+        // Probabilities are about 0.665, 0.245 and 0.090
+        assert_eq!(tokens(&Sampler::new(1.0, 0.5, 0).candidates(&LOGITS)), [1]);
+        assert_eq!(tokens(&Sampler::new(1.0, 0.8, 0).candidates(&LOGITS)), [1, 2]);
+        assert_eq!(tokens(&Sampler::new(1.0, 1.0, 0).candidates(&LOGITS)), [1, 2, 0]);
+
+        let candidates = Sampler::new(0.5, 1.0, 0).candidates(&LOGITS);
+        assert_eq!(candidates[0], (1, 1.0));
+        assert!((candidates[1].1 - (-2.0f32).exp()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn greedy_and_seeded_sampling() {
+        let mut greedy = Sampler::new(0.0, 1.0, 0);
+        assert_eq!(greedy.sample(&LOGITS), 1);
+        assert!(greedy.can_sample(&LOGITS, 1));
+        assert!(!greedy.can_sample(&LOGITS, 2));
+
+        let draw = |seed| {
+            let mut sampler = Sampler::new(1.0, 0.8, seed);
+            (0..32).map(|_| sampler.sample(&LOGITS)).collect::<Vec<_>>()
+        };
+        assert_eq!(draw(7), draw(7));
+        assert!(draw(7).iter().all(|&token| token == 1 || token == 2));
+        assert!(!Sampler::new(1.0, 0.8, 0).can_sample(&LOGITS, 0));
+    }
+
+    #[test]
+    fn token_logprobs_use_the_full_distribution() {
+        let logits = [0.0, 3f32.ln(), f32::NEG_INFINITY];
+        let logprobs = token_logprobs(&logits, 0, 2);
+        assert_eq!(logprobs.token, 0);
+        assert!((logprobs.logprob - 0.25f32.ln()).abs() < 1e-6);
+        assert_eq!(tokens(&logprobs.top), [1, 0]);
+        assert!((logprobs.top[0].1 - 0.75f32.ln()).abs() < 1e-6);
+
+        assert_eq!(token_logprobs(&logits, 1, 10).top.len(), 3);
+        assert!(token_logprobs(&logits, 1, 0).top.is_empty());
+    }
+}
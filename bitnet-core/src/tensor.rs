@@ -0,0 +1,344 @@
+//! Weight tensors and matrix-vector products over their on-disk formats.
+
+use anyhow::{bail, Result};
+use half::{bf16, f16};
+use std::sync::Arc;
+
+use crate::gguf::Storage;
+use crate::kernels;
+
+/// Elements per I2_S block; each block packs 4 groups of 32 weights into 32 bytes.
+pub const QK_I2_S: usize = 128;
+const QK8_0: usize = 32;
+const Q8_0_BLOCK_BYTES: usize = 2 + QK8_0;
+
+/// Tensor formats the loader understands. IDs follow the BitNet fork of GGML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GgmlType {
+    F32,
+    F16,
+    Q8_0,
+    BF16,
+    /// Ternary weights {-1, 0, 1} at 2 bits each, with one f32 scale per tensor.
+    I2S,
+}
+
+impl GgmlType {
+    pub fn from_id(id: u32) -> Result<Self> {
+        Ok(match id {
+            0 => Self::F32,
+            1 => Self::F16,
+            8 => Self::Q8_0,
+            30 => Self::BF16,
+            36 => Self::I2S,
+            other => bail!("unsupported GGML tensor type {}", other),
+        })
+    }
+
+    /// Size of a `rows` x `cols` tensor in the file.
+    pub fn tensor_bytes(self, rows: usize, cols: usize) -> Result<usize> {
+        let n = rows * cols;
+        Ok(match self {
+            Self::F32 => n * 4,
+            Self::F16 | Self::BF16 => n * 2,
+            Self::Q8_0 => {
+                if !cols.is_multiple_of(QK8_0) {
+                    bail!("Q8_0 row length {} is not a multiple of {}", cols, QK8_0);
+                }
+                n / QK8_0 * Q8_0_BLOCK_BYTES
+            }
+            Self::I2S => {
+                if !cols.is_multiple_of(QK_I2_S) {
+                    bail!("I2_S row length {} is not a multiple of {}", cols, QK_I2_S);
+                }
+                // Packed weights, then the f32 scale padded to 32 bytes
+                n / 4 + 32
+            }
+        })
+    }
+
+    fn row_bytes(self, cols: usize) -> usize {
+        match self {
+            Self::F32 => cols * 4,
+            Self::F16 | Self::BF16 => cols * 2,
+            Self::Q8_0 => cols / QK8_0 * Q8_0_BLOCK_BYTES,
+            Self::I2S => cols / 4,
+        }
+    }
+}
+
+/// A 1-D or 2-D tensor backed by the model file. Rows are contiguous.
+#[derive(Clone)]
+pub struct Tensor {
+    pub dtype: GgmlType,
+    pub rows: usize,
+    pub cols: usize,
+    storage: Arc<Storage>,
+    start: usize,
+    /// Per-tensor weight scale; only meaningful for I2_S.
+    scale: f32,
+}
+
+impl Tensor {
+    pub(crate) fn new(
+        dtype: GgmlType,
+        rows: usize,
+        cols: usize,
+        storage: Arc<Storage>,
+        start: usize,
+        len: usize,
+    ) -> Result<Self> {
+        let scale = match dtype {
+            GgmlType::I2S => {
+                let at = start + rows * cols / 4;
+                if at + 4 > start + len {
+                    bail!("I2_S tensor is missing its scale");
+                }
+                f32::from_le_bytes(storage[at..at + 4].try_into().unwrap())
+            }
+            _ => 1.0,
+        };
+        Ok(Self {
+            dtype,
+            rows,
+            cols,
+            storage,
+            start,
+            scale,
+        })
+    }
+
+    fn row_data(&self, row: usize) -> &[u8] {
+        let row_bytes = self.dtype.row_bytes(self.cols);
+        let start = self.start + row * row_bytes;
+        &self.storage[start..start + row_bytes]
+    }
+
+    /// One row, dequantized.
+    pub fn row(&self, row: usize) -> Vec<f32> {
+        let data = self.row_data(row);
+        match self.dtype {
+            GgmlType::F32 => data
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+                .collect(),
+            GgmlType::F16 => data
+                .chunks_exact(2)
+                .map(|b| f16::from_le_bytes([b[0], b[1]]).to_f32())
+                .collect(),
+            GgmlType::BF16 => data
+                .chunks_exact(2)
+                .map(|b| bf16::from_le_bytes([b[0], b[1]]).to_f32())
+                .collect(),
+            GgmlType::Q8_0 => data
+                .chunks_exact(Q8_0_BLOCK_BYTES)
+                .flat_map(|block| {
+                    let d = f16::from_le_bytes([block[0], block[1]]).to_f32();
+                    block[2..].iter().map(move |&q| q as i8 as f32 * d)
+                })
+                .collect(),
+            GgmlType::I2S => {
+                let mut out = vec![0.0; self.cols];
+                for (block, values) in data.chunks_exact(32).zip(out.chunks_exact_mut(QK_I2_S)) {
+                    for (j, &byte) in block.iter().enumerate() {
+                        for group in 0..4 {
+                            let q = (byte >> (6 - 2 * group)) & 3;
+                            values[group * 32 + j] = (q as f32 - 1.0) * self.scale;
+                        }
+                    }
+                }
+                out
+            }
+        }
+    }
+
+    /// The whole tensor as f32, for small tensors such as norm weights.
+    pub fn to_vec(&self) -> Vec<f32> {
+        (0..self.rows).flat_map(|row| self.row(row)).collect()
+    }
+
+    /// `W · x` for a `rows` x `cols` weight matrix.
+    ///
+    /// I2_S weights use the BitNet b1.58 scheme: the activations are
+    /// quantized to int8 with one absmax scale, the ternary dot products are
+    /// computed in integers, and the result is rescaled by both scales.
+    pub fn matvec(&self, x: &[f32]) -> Vec<f32> {
+        assert_eq!(x.len(), self.cols, "matvec input length");
+        let mut out = vec![0.0; self.rows];
+        match self.dtype {
+            GgmlType::I2S => {
+                let (xq, x_scale) = kernels::quantize_activations(x);
+                let scale = self.scale / x_scale;
+                for_each_row(&mut out, |row| {
+                    kernels::dot_i2s(self.row_data(row), &xq) as f32 * scale
+                });
+            }
+            GgmlType::F32 => for_each_row(&mut out, |row| kernels::dot_f32(self.row_data(row), x)),
+            GgmlType::F16 => for_each_row(&mut out, |row| kernels::dot_f16(self.row_data(row), x)),
+            GgmlType::BF16 => {
+                for_each_row(&mut out, |row| kernels::dot_bf16(self.row_data(row), x))
+            }
+            GgmlType::Q8_0 => {
+                for_each_row(&mut out, |row| kernels::dot_q8_0(self.row_data(row), x))
+            }
+        }
+        out
+    }
+
+    /// `W · x` for each of `xs`, reading every weight row once for the whole
+    /// batch. Each product is computed exactly as [`Tensor::matvec`] would.
+    pub fn matmul(&self, xs: &[Vec<f32>]) -> Vec<Vec<f32>> {
+        match xs {
+            [] => return Vec::new(),
+            [x] => return vec![self.matvec(x)],
+            _ => {}
+        }
+        for x in xs {
+            assert_eq!(x.len(), self.cols, "matmul input length");
+        }
+        // Row-major: the values of row r for every input sit together
+        let batch = xs.len();
+        let mut out = vec![0.0; self.rows * batch];
+        match self.dtype {
+            GgmlType::I2S => {
+                let quantized: Vec<_> = xs
+                    .iter()
+                    .map(|x| {
+                        let (xq, x_scale) = kernels::quantize_activations(x);
+                        (xq, self.scale / x_scale)
+                    })
+                    .collect();
+                for_each_row_batch(&mut out, batch, |row, values| {
+                    let data = self.row_data(row);
+                    for (value, (xq, scale)) in values.iter_mut().zip(&quantized) {
+                        *value = kernels::dot_i2s(data, xq) as f32 * scale;
+                    }
+                });
+            }
+            dtype => {
+                let dot = match dtype {
+                    GgmlType::F32 => kernels::dot_f32,
+                    GgmlType::F16 => kernels::dot_f16,
+                    GgmlType::BF16 => kernels::dot_bf16,
+                    GgmlType::Q8_0 => kernels::dot_q8_0,
+                    GgmlType::I2S => unreachable!(),
+                };
+                for_each_row_batch(&mut out, batch, |row, values| {
+                    let data = self.row_data(row);
+                    for (value, x) in values.iter_mut().zip(xs) {
+                        *value = dot(data, x);
+                    }
+                });
+            }
+        }
+        (0..batch)
+            .map(|b| out.iter().skip(b).step_by(batch).copied().collect())
+            .collect()
+    }
+}
+
+#[cfg(feature = "parallel")]
+fn for_each_row(out: &mut [f32], row_value: impl Fn(usize) -> f32 + Sync) {
+    use rayon::prelude::*;
+    out.par_iter_mut()
+        .with_min_len(16)
+        .enumerate()
+        .for_each(|(row, value)| *value = row_value(row));
+}
+
+#[cfg(not(feature = "parallel"))]
+fn for_each_row(out: &mut [f32], row_value: impl Fn(usize) -> f32) {
+    for (row, value) in out.iter_mut().enumerate() {
+        *value = row_value(row);
+    }
+}
+
+#[cfg(feature = "parallel")]
+fn for_each_row_batch(
+    out: &mut [f32],
+    batch: usize,
+    row_values: impl Fn(usize, &mut [f32]) + Sync,
+) {
+    use rayon::prelude::*;
+    out.par_chunks_mut(batch)
+        .with_min_len(16)
+        .enumerate()
+        .for_each(|(row, values)| row_values(row, values));
+}
+
+#[cfg(not(feature = "parallel"))]
+fn for_each_row_batch(out: &mut [f32], batch: usize, row_values: impl Fn(usize, &mut [f32])) {
+    for (row, values) in out.chunks_mut(batch).enumerate() {
+        row_values(row, values);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{GgufBuilder, Lcg};
+    use crate::GgufFile;
+
+    const ROWS: usize = 3;
+    const COLS: usize = 256;
+
+    fn ternary_matrix(rng: &mut Lcg) -> Vec<i8> {
+        (0..ROWS * COLS).map(|_| rng.ternary()).collect()
+    }
+
+    fn load(weights: &[i8], scale: f32) -> super::Tensor {
+        let bytes = GgufBuilder::new()
+            .i2s_matrix("w", ROWS, COLS, weights, scale)
+            .build();
+        GgufFile::from_bytes(bytes).unwrap().tensor("w").unwrap()
+    }
+
+    #[test]
+    fn i2s_rows_unpack_to_scaled_ternary_weights() {
+        let weights = ternary_matrix(&mut Lcg(1));
+        let tensor = load(&weights, 0.5);
+        for row in 0..ROWS {
+            let expected: Vec<f32> = weights[row * COLS..(row + 1) * COLS]
+                .iter()
+                .map(|&w| w as f32 * 0.5)
+                .collect();
+            assert_eq!(tensor.row(row), expected);
+        }
+    }
+
+    #[test]
+    fn i2s_matvec_is_an_integer_dot_product() {
+        let mut rng = Lcg(2);
+        let weights = ternary_matrix(&mut rng);
+        let tensor = load(&weights, 2.0);
+        // Integers with absmax 127 quantize to themselves
+        let mut x: Vec<f32> = (0..COLS).map(|_| (rng.next() % 255) as f32 - 127.0).collect();
+        x[0] = 127.0;
+        let expected: Vec<f32> = weights
+            .chunks_exact(COLS)
+            .map(|row| {
+                let dot: i32 = row.iter().zip(&x).map(|(&w, &x)| w as i32 * x as i32).sum();
+                dot as f32 * 2.0
+            })
+            .collect();
+        assert_eq!(tensor.matvec(&x), expected);
+    }
+
+    #[test]
+    fn matmul_matches_matvec() {
+        let mut rng = Lcg(3);
+        let tensor = load(&ternary_matrix(&mut rng), 1.25);
+        let xs: Vec<Vec<f32>> = (0..3)
+            .map(|_| (0..COLS).map(|_| rng.unit()).collect())
+            .collect();
+        let batched = tensor.matmul(&xs);
+        for (x, out) in xs.iter().zip(&batched) {
+            assert_eq!(&tensor.matvec(x), out);
+        }
+    }
+
+    #[test]
+    fn i2s_rows_must_fill_whole_blocks() {
+        assert!(super::GgmlType::I2S.tensor_bytes(1, 100).is_err());
+        assert_eq!(super::GgmlType::I2S.tensor_bytes(2, 128).unwrap(), 64 + 32);
+    }
+}
@@ -0,0 +1,234 @@
+//! In-memory GGUF files for the unit tests.
+
+use crate::tensor::QK_I2_S;
+
+const ALIGNMENT: usize = 32;
+
+/// Writes a version 3 GGUF: metadata in insertion order, then each tensor's
+/// data aligned to 32 bytes.
+#[derive(Default)]
+pub(crate) struct GgufBuilder {
+    metadata: Vec<u8>,
+    metadata_count: u64,
+    tensors: Vec<(String, Vec<u64>, u32, Vec<u8>)>,
+}
+
+impl GgufBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn key(mut self, key: &str, value_type: u32) -> Self {
+        self.metadata.extend(string(key));
+        self.metadata.extend(value_type.to_le_bytes());
+        self.metadata_count += 1;
+        self
+    }
+
+    pub fn u32(self, key: &str, value: u32) -> Self {
+        let mut this = self.key(key, 4);
+        this.metadata.extend(value.to_le_bytes());
+        this
+    }
+
+    pub fn f32(self, key: &str, value: f32) -> Self {
+        let mut this = self.key(key, 6);
+        this.metadata.extend(value.to_le_bytes());
+        this
+    }
+
+    pub fn bool(self, key: &str, value: bool) -> Self {
+        let mut this = self.key(key, 7);
+        this.metadata.push(value as u8);
+        this
+    }
+
+    pub fn string(self, key: &str, value: &str) -> Self {
+        let mut this = self.key(key, 8);
+        this.metadata.extend(string(value));
+        this
+    }
+
+    pub fn strings(self, key: &str, values: &[&str]) -> Self {
+        let mut this = self.key(key, 9);
+        this.metadata.extend(8u32.to_le_bytes());
+        this.metadata.extend((values.len() as u64).to_le_bytes());
+        for value in values {
+            this.metadata.extend(string(value));
+        }
+        this
+    }
+
+    pub fn i32s(self, key: &str, values: &[i32]) -> Self {
+        let mut this = self.key(key, 9);
+        this.metadata.extend(5u32.to_le_bytes());
+        this.metadata.extend((values.len() as u64).to_le_bytes());
+        for value in values {
+            this.metadata.extend(value.to_le_bytes());
+        }
+        this
+    }
+
+    pub fn f32s(self, key: &str, values: &[f32]) -> Self {
+        let mut this = self.key(key, 9);
+        this.metadata.extend(6u32.to_le_bytes());
+        this.metadata.extend((values.len() as u64).to_le_bytes());
+        for value in values {
+            this.metadata.extend(value.to_le_bytes());
+        }
+        this
+    }
+
+    /// A tensor with raw `data`; `dims` are in GGML order, contiguous first.
+    pub fn tensor(mut self, name: &str, dims: &[u64], ggml_type: u32, data: Vec<u8>) -> Self {
+        self.tensors
+            .push((name.to_string(), dims.to_vec(), ggml_type, data));
+        self
+    }
+
+    /// A 1-D f32 tensor.
+    pub fn f32_tensor(self, name: &str, values: &[f32]) -> Self {
+        let data = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        self.tensor(name, &[values.len() as u64], 0, data)
+    }
+
+    /// A `rows` x `cols` f32 matrix, row-major.
+    pub fn f32_matrix(self, name: &str, rows: usize, cols: usize, values: &[f32]) -> Self {
+        assert_eq!(values.len(), rows * cols);
+        let data = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        self.tensor(name, &[cols as u64, rows as u64], 0, data)
+    }
+
+    /// A `rows` x `cols` ternary matrix, row-major, with one scale.
+    pub fn i2s_matrix(
+        self,
+        name: &str,
+        rows: usize,
+        cols: usize,
+        weights: &[i8],
+        scale: f32,
+    ) -> Self {
+        assert_eq!(weights.len(), rows * cols);
+        self.tensor(name, &[cols as u64, rows as u64], 36, pack_i2s(weights, scale))
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        let mut out = b"GGUF".to_vec();
+        out.extend(3u32.to_le_bytes());
+        out.extend((self.tensors.len() as u64).to_le_bytes());
+        out.extend(self.metadata_count.to_le_bytes());
+        out.extend(&self.metadata);
+
+        let mut offset = 0;
+        for (name, dims, ggml_type, data) in &self.tensors {
+            out.extend(string(name));
+            out.extend((dims.len() as u32).to_le_bytes());
+            for dim in dims {
+                out.extend(dim.to_le_bytes());
+            }
+            out.extend(ggml_type.to_le_bytes());
+            out.extend((offset as u64).to_le_bytes());
+            offset += data.len().next_multiple_of(ALIGNMENT);
+        }
+        out.resize(out.len().next_multiple_of(ALIGNMENT), 0);
+        for (_, _, _, data) in &self.tensors {
+            out.extend(data);
+            out.resize(out.len().next_multiple_of(ALIGNMENT), 0);
+        }
+        out
+    }
+}
+
+/// A random two-layer `bitnet-b1.58` model: ternary projections, f32 norms
+/// and embeddings, 16 tokens, 4 query and 2 key-value heads.
+pub(crate) fn tiny_model(rng: &mut Lcg) -> Vec<u8> {
+    const VOCAB: usize = 16;
+    const EMBD: usize = QK_I2_S;
+    const FFN: usize = QK_I2_S;
+    const KV: usize = EMBD / 2;
+    let arch = "bitnet-b1.58";
+    let key = |name: &str| format!("{}.{}", arch, name);
+    let mut builder = GgufBuilder::new()
+        .string("general.architecture", arch)
+        .u32(&key("vocab_size"), VOCAB as u32)
+        .u32(&key("embedding_length"), EMBD as u32)
+        .u32(&key("block_count"), 2)
+        .u32(&key("feed_forward_length"), FFN as u32)
+        .u32(&key("attention.head_count"), 4)
+        .u32(&key("attention.head_count_kv"), 2)
+        .u32(&key("context_length"), 64);
+    fn values(rng: &mut Lcg, len: usize) -> Vec<f32> {
+        (0..len).map(|_| rng.unit()).collect()
+    }
+    builder = builder.f32_matrix("token_embd.weight", VOCAB, EMBD, &values(rng, VOCAB * EMBD));
+    builder = builder.f32_tensor("output_norm.weight", &values(rng, EMBD));
+    for i in 0..2 {
+        let name = |suffix: &str| format!("blk.{}.{}.weight", i, suffix);
+        for (norm, len) in [
+            ("attn_norm", EMBD),
+            ("attn_sub_norm", EMBD),
+            ("ffn_norm", EMBD),
+            ("ffn_sub_norm", FFN),
+        ] {
+            builder = builder.f32_tensor(&name(norm), &values(rng, len));
+        }
+        for (weight, rows, cols) in [
+            ("attn_q", EMBD, EMBD),
+            ("attn_k", KV, EMBD),
+            ("attn_v", KV, EMBD),
+            ("attn_output", EMBD, EMBD),
+            ("ffn_gate", FFN, EMBD),
+            ("ffn_up", FFN, EMBD),
+            ("ffn_down", EMBD, FFN),
+        ] {
+            let weights: Vec<i8> = (0..rows * cols).map(|_| rng.ternary()).collect();
+            builder = builder.i2s_matrix(&name(weight), rows, cols, &weights, 0.5);
+        }
+    }
+    builder.build()
+}
+
+fn string(value: &str) -> Vec<u8> {
+    let mut out = (value.len() as u64).to_le_bytes().to_vec();
+    out.extend(value.as_bytes());
+    out
+}
+
+/// Packs ternary weights as I2_S: 2 bits each, then the scale padded to 32
+/// bytes.
+pub(crate) fn pack_i2s(weights: &[i8], scale: f32) -> Vec<u8> {
+    assert_eq!(weights.len() % QK_I2_S, 0);
+    let mut packed = vec![0u8; weights.len() / 4];
+    for (block, values) in packed.chunks_exact_mut(32).zip(weights.chunks_exact(QK_I2_S)) {
+        for (i, &w) in values.iter().enumerate() {
+            assert!((-1..=1).contains(&w));
+            block[i % 32] |= ((w + 1) as u8) << (6 - 2 * (i / 32));
+        }
+    }
+    packed.extend(scale.to_le_bytes());
+    packed.extend([0; 28]);
+    packed
+}
+
+/// A small deterministic generator, so tests need no seeded RNG.
+pub(crate) struct Lcg(pub u64);
+
+impl Lcg {
+    pub fn next(&mut self) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        self.0 >> 33
+    }
+
+    /// Uniform in [-1, 1).
+    pub fn unit(&mut self) -> f32 {
+        (self.next() % 2000) as f32 / 1000.0 - 1.0
+    }
+
+    /// -1, 0 or 1.
+    pub fn ternary(&mut self) -> i8 {
+        (self.next() % 3) as i8 - 1
+    }
+}
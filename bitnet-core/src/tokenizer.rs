@@ -0,0 +1,430 @@
+//! Tokenizer built from the vocabulary embedded in a GGUF file.
+//!
+//! Supports byte-level BPE (`tokenizer.ggml.model = "gpt2"`, used by BitNet
+//! b1.58 2B4T's LLaMA 3 vocabulary) and SentencePiece-style BPE (`"llama"`).
+
+use anyhow::{anyhow, bail, Result};
+use fancy_regex::Regex;
+use std::collections::HashMap;
+
+use crate::gguf::{GgufFile, MetadataValue};
+
+const LLAMA3_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+const GPT2_PATTERN: &str = r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+";
+
+const TOKEN_TYPE_CONTROL: i64 = 3;
+const TOKEN_TYPE_USER_DEFINED: i64 = 4;
+const SPM_SPACE: char = '\u{2581}';
+
+struct ByteLevel {
+    merges: HashMap<(String, String), usize>,
+    pattern: Regex,
+    byte_encoder: Vec<char>,
+    byte_decoder: HashMap<char, u8>,
+}
+
+enum Kind {
+    ByteLevel(Box<ByteLevel>),
+    SentencePiece { scores: Vec<f32> },
+}
+
+pub struct Tokenizer {
+    kind: Kind,
+    tokens: Vec<String>,
+    token_ids: HashMap<String, u32>,
+    /// Control and user-defined tokens, longest first, matched verbatim.
+    specials: Vec<(String, u32)>,
+    control: Vec<bool>,
+    bos: Option<u32>,
+    eos: Option<u32>,
+    eot: Option<u32>,
+    add_bos: bool,
+}
+
+impl Tokenizer {
+    pub fn from_gguf(gguf: &GgufFile) -> Result<Self> {
+        let model = gguf.get_str("tokenizer.ggml.model")?;
+        let tokens: Vec<String> = string_array(gguf, "tokenizer.ggml.tokens")?;
+        let token_types: Vec<i64> = gguf
+            .get("tokenizer.ggml.token_type")
+            .and_then(MetadataValue::as_array)
+            .map(|types| types.iter().map(|t| t.as_i64().unwrap_or(1)).collect())
+            .unwrap_or_else(|| vec![1; tokens.len()]);
+
+        let token_ids: HashMap<String, u32> = tokens
+            .iter()
+            .enumerate()
+            .map(|(id, token)| (token.clone(), id as u32))
+            .collect();
+        let control: Vec<bool> = token_types.iter().map(|&t| t == TOKEN_TYPE_CONTROL).collect();
+        let mut specials: Vec<(String, u32)> = tokens
+            .iter()
+            .zip(&token_types)
+            .enumerate()
+            .filter(|(_, (token, &t))| {
+                !token.is_empty() && (t == TOKEN_TYPE_CONTROL || t == TOKEN_TYPE_USER_DEFINED)
+            })
+            .map(|(id, (token, _))| (token.clone(), id as u32))
+            .collect();
+        specials.sort_by_key(|(special, _)| std::cmp::Reverse(special.len()));
+
+        let id = |key: &str| gguf.get(key).and_then(MetadataValue::as_u64).map(|id| id as u32);
+        let kind = match model {
+            "gpt2" => {
+                let merges = string_array(gguf, "tokenizer.ggml.merges")?
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(rank, merge)| {
+                        let (a, b) = merge.split_once(' ')?;
+                        Some(((a.to_string(), b.to_string()), rank))
+                    })
+                    .collect();
+                let pattern = match gguf.get("tokenizer.ggml.pre").and_then(|p| p.as_str()) {
+                    Some("llama-bpe" | "llama3" | "llama-v3") => LLAMA3_PATTERN,
+                    _ => GPT2_PATTERN,
+                };
+                let byte_encoder = byte_encoder();
+                let byte_decoder = byte_encoder
+                    .iter()
+                    .enumerate()
+                    .map(|(byte, &c)| (c, byte as u8))
+                    .collect();
+                Kind::ByteLevel(Box::new(ByteLevel {
+                    merges,
+                    pattern: Regex::new(pattern)?,
+                    byte_encoder,
+                    byte_decoder,
+                }))
+            }
+            "llama" => {
+                let scores = gguf
+                    .get("tokenizer.ggml.scores")
+                    .and_then(MetadataValue::as_array)
+                    .map(|scores| scores.iter().map(|s| s.as_f32().unwrap_or(0.0)).collect())
+                    .unwrap_or_else(|| vec![0.0; tokens.len()]);
+                Kind::SentencePiece { scores }
+            }
+            other => bail!("unsupported tokenizer model `{}`", other),
+        };
+
+        Ok(Self {
+            kind,
+            bos: id("tokenizer.ggml.bos_token_id"),
+            eos: id("tokenizer.ggml.eos_token_id"),
+            eot: id("tokenizer.ggml.eot_token_id")
+                .or_else(|| token_ids.get("<|eot_id|>").copied()),
+            add_bos: gguf
+                .get("tokenizer.ggml.add_bos_token")
+                .and_then(MetadataValue::as_bool)
+                .unwrap_or(true),
+            tokens,
+            token_ids,
+            specials,
+            control,
+        })
+    }
+
+    pub fn vocab_size(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn bos_token(&self) -> Option<u32> {
+        self.bos
+    }
+
+    /// Tokens that end a generation.
+    pub fn stop_tokens(&self) -> Vec<u32> {
+        let mut stop: Vec<u32> = self.eos.into_iter().chain(self.eot).collect();
+        stop.dedup();
+        stop
+    }
+
+    /// Encodes `text`. With `add_special`, a BOS token is prepended if the
+    /// model expects one; special-token text such as `<|eot_id|>` is always
+    /// mapped to its token.
+    pub fn encode(&self, text: &str, add_special: bool) -> Result<Vec<u32>> {
+        let mut ids = Vec::new();
+        if add_special && self.add_bos {
+            ids.extend(self.bos);
+        }
+
+        let mut rest = text;
+        let mut first = true;
+        while !rest.is_empty() {
+            let next_special = (0..rest.len())
+                .filter(|&i| rest.is_char_boundary(i))
+                .find_map(|i| {
+                    self.specials
+                        .iter()
+                        .find(|(special, _)| rest[i..].starts_with(special.as_str()))
+                        .map(|(special, id)| (i, special.len(), *id))
+                });
+            let (plain, special) = match next_special {
+                Some((at, len, id)) => (&rest[..at], Some((len, id))),
+                None => (rest, None),
+            };
+            if !plain.is_empty() {
+                self.encode_plain(plain, first, &mut ids)?;
+            }
+            first = false;
+            match special {
+                Some((len, id)) => {
+                    ids.push(id);
+                    rest = &rest[plain.len() + len..];
+                }
+                None => break,
+            }
+        }
+        Ok(ids)
+    }
+
+    fn encode_plain(&self, text: &str, at_start: bool, ids: &mut Vec<u32>) -> Result<()> {
+        match &self.kind {
+            Kind::ByteLevel(byte_level) => {
+                let ByteLevel {
+                    merges,
+                    pattern,
+                    byte_encoder,
+                    ..
+                } = byte_level.as_ref();
+                for piece in pattern.find_iter(text) {
+                    let piece = piece.map_err(|e| anyhow!("pre-tokenizer: {}", e))?;
+                    let encoded: String = piece
+                        .as_str()
+                        .bytes()
+                        .map(|byte| byte_encoder[byte as usize])
+                        .collect();
+                    let symbols = self.merge(
+                        encoded.chars().map(String::from).collect(),
+                        |a, b| merges.get(&(a.to_string(), b.to_string())).map(|&rank| -(rank as f32)),
+                    );
+                    for symbol in symbols {
+                        let id = self
+                            .token_ids
+                            .get(&symbol)
+                            .ok_or_else(|| anyhow!("symbol `{}` missing from vocabulary", symbol))?;
+                        ids.push(*id);
+                    }
+                }
+            }
+            Kind::SentencePiece { scores } => {
+                let mut normalized = String::new();
+                if at_start {
+                    normalized.push(SPM_SPACE);
+                }
+                normalized.extend(text.chars().map(|c| if c == ' ' { SPM_SPACE } else { c }));
+                let symbols = self.merge(normalized.chars().map(String::from).collect(), |a, b| {
+                    self.token_ids
+                        .get(&format!("{}{}", a, b))
+                        .map(|&id| scores[id as usize])
+                });
+                for symbol in symbols {
+                    match self.token_ids.get(&symbol) {
+                        Some(&id) => ids.push(id),
+                        None => {
+                            // Byte fallback
+                            for byte in symbol.bytes() {
+                                let id = self
+                                    .token_ids
+                                    .get(&format!("<0x{:02X}>", byte))
+                                    .ok_or_else(|| anyhow!("no byte fallback for {:#04x}", byte))?;
+                                ids.push(*id);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Repeatedly merges the adjacent pair with the highest priority.
+    fn merge(
+        &self,
+        mut symbols: Vec<String>,
+        priority: impl Fn(&str, &str) -> Option<f32>,
+    ) -> Vec<String> {
+        loop {
+            let best = symbols
+                .windows(2)
+                .enumerate()
+                .filter_map(|(i, pair)| priority(&pair[0], &pair[1]).map(|p| (i, p)))
+                .fold(None, |best: Option<(usize, f32)>, (i, p)| match best {
+                    Some((_, best_p)) if best_p >= p => best,
+                    _ => Some((i, p)),
+                });
+            let Some((i, _)) = best else {
+                return symbols;
+            };
+            let right = symbols.remove(i + 1);
+            symbols[i].push_str(&right);
+        }
+    }
+
+    /// Decodes token IDs to text. Control tokens such as BOS/EOS are dropped
+    /// when `skip_special` is set.
+    pub fn decode(&self, ids: &[u32], skip_special: bool) -> Result<String> {
+        let bytes = self.decode_bytes(ids, skip_special)?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Like [`decode`](Self::decode), but returns the raw bytes: a single
+    /// token may hold part of a UTF-8 character.
+    pub fn decode_bytes(&self, ids: &[u32], skip_special: bool) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        for &id in ids {
+            let token = self
+                .tokens
+                .get(id as usize)
+                .ok_or_else(|| anyhow!("token {} is outside the vocabulary", id))?;
+            let special = self.control.get(id as usize).copied().unwrap_or(false);
+            if special {
+                if !skip_special {
+                    bytes.extend_from_slice(token.as_bytes());
+                }
+                continue;
+            }
+            match &self.kind {
+                Kind::ByteLevel(byte_level) => {
+                    for c in token.chars() {
+                        match byte_level.byte_decoder.get(&c) {
+                            Some(&byte) => bytes.push(byte),
+                            None => bytes.extend_from_slice(c.to_string().as_bytes()),
+                        }
+                    }
+                }
+                Kind::SentencePiece { .. } => {
+                    match token
+                        .strip_prefix("<0x")
+                        .and_then(|hex| hex.strip_suffix('>'))
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    {
+                        Some(byte) => bytes.push(byte),
+                        None => bytes.extend_from_slice(token.replace(SPM_SPACE, " ").as_bytes()),
+                    }
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// The vocabulary entry for `id`, as stored in the GGUF.
+    pub fn token_text(&self, id: u32) -> Option<&str> {
+        self.tokens.get(id as usize).map(String::as_str)
+    }
+}
+
+fn string_array(gguf: &GgufFile, key: &str) -> Result<Vec<String>> {
+    gguf.get(key)
+        .and_then(MetadataValue::as_array)
+        .ok_or_else(|| anyhow!("missing or invalid metadata `{}`", key))?
+        .iter()
+        .map(|value| {
+            value
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("non-string entry in `{}`", key))
+        })
+        .collect()
+}
+
+/// GPT-2's reversible mapping from bytes to printable characters.
+fn byte_encoder() -> Vec<char> {
+    let printable = |b: u32| {
+        (u32::from('!')..=u32::from('~')).contains(&b)
+            || (0xA1..=0xAC).contains(&b)
+            || (0xAE..=0xFF).contains(&b)
+    };
+    let mut shifted = 0;
+    (0..256u32)
+        .map(|b| {
+            if printable(b) {
+                char::from_u32(b).unwrap()
+            } else {
+                shifted += 1;
+                char::from_u32(255 + shifted).unwrap()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::GgufBuilder;
+
+    const HE: u32 = 256;
+    const HEL: u32 = 257;
+    const LO: u32 = 258;
+    const SPACE_WO: u32 = 260;
+    const BOS: u32 = 261;
+    const EOT: u32 = 262;
+
+    /// Byte-level BPE: one token per byte, a few merges, then BOS and EOT.
+    fn byte_level() -> Tokenizer {
+        let encoder = byte_encoder();
+        let mut tokens: Vec<String> = encoder.iter().map(char::to_string).collect();
+        let merges = ["h e", "he l", "l o", "Ġ w", "Ġw o"];
+        tokens.extend(merges.iter().map(|merge| merge.replace(' ', "")));
+        tokens.extend(["<|begin_of_text|>".to_string(), "<|eot_id|>".to_string()]);
+        let mut types = vec![1; tokens.len()];
+        types[BOS as usize] = 3;
+        types[EOT as usize] = 3;
+
+        let tokens: Vec<&str> = tokens.iter().map(String::as_str).collect();
+        let bytes = GgufBuilder::new()
+            .string("tokenizer.ggml.model", "gpt2")
+            .string("tokenizer.ggml.pre", "llama-bpe")
+            .strings("tokenizer.ggml.tokens", &tokens)
+            .i32s("tokenizer.ggml.token_type", &types)
+            .strings("tokenizer.ggml.merges", &merges)
+            .u32("tokenizer.ggml.bos_token_id", BOS)
+            .u32("tokenizer.ggml.eos_token_id", EOT)
+            .build();
+        Tokenizer::from_gguf(&GgufFile::from_bytes(bytes).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn byte_level_applies_merges_by_rank() {
+        let tokenizer = byte_level();
+        assert_eq!(tokenizer.encode("hello", false).unwrap(), [HEL, LO]);
+        assert_eq!(
+            tokenizer.encode("hello world", true).unwrap(),
+            [BOS, HEL, LO, SPACE_WO, u32::from(b'r'), u32::from(b'l'), u32::from(b'd')]
+        );
+        // "he" alone has no better merge to make
+        assert_eq!(tokenizer.encode("he", false).unwrap(), [HE]);
+    }
+
+    #[test]
+    fn byte_level_round_trips_and_maps_special_text() {
+        let tokenizer = byte_level();
+        let ids = tokenizer.encode("hello<|eot_id|>héllo", true).unwrap();
+        assert_eq!(ids[..4], [BOS, HEL, LO, EOT]);
+        assert_eq!(tokenizer.decode(&ids, true).unwrap(), "hellohéllo");
+        assert_eq!(
+            tokenizer.decode(&ids, false).unwrap(),
+            "<|begin_of_text|>hello<|eot_id|>héllo"
+        );
+        assert_eq!(tokenizer.stop_tokens(), [EOT]);
+        assert!(tokenizer.decode(&[EOT + 1], true).is_err());
+    }
+
+    #[test]
+    fn sentencepiece_merges_by_score_with_byte_fallback() {
+        let tokens = ["<s>", "</s>", "▁", "a", "b", "▁a", "▁ab", "<0x21>"];
+        let bytes = GgufBuilder::new()
+            .string("tokenizer.ggml.model", "llama")
+            .strings("tokenizer.ggml.tokens", &tokens)
+            .i32s("tokenizer.ggml.token_type", &[3, 3, 1, 1, 1, 1, 1, 6])
+            .f32s("tokenizer.ggml.scores", &[0.0, 0.0, -1.0, -1.0, -1.0, -2.0, -3.0, 0.0])
+            .u32("tokenizer.ggml.bos_token_id", 0)
+            .u32("tokenizer.ggml.eos_token_id", 1)
+            .build();
+        let tokenizer = Tokenizer::from_gguf(&GgufFile::from_bytes(bytes).unwrap()).unwrap();
+
+        let ids = tokenizer.encode("ab!", true).unwrap();
+        assert_eq!(ids, [0, 6, 7]);
+        assert_eq!(tokenizer.decode(&ids, true).unwrap(), " ab!");
+    }
+}
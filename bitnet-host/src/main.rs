@@ -1,352 +1,250 @@
-use clap::{Arg, Command};
-use risc0_zkvm::{default_prover, ExecutorEnv, ProverOpts, VerifierContext};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bitnet_core::guest::{
+    CommittedEmbedding, EmbeddingCommit, GuestInput, GuestOutput, GuestParams, GuestResult,
+};
+use bitnet_core::{GgufFile, Model, Tokenizer};
+use clap::{Arg, ArgAction, Command};
+use risc0_zkvm::sha::Digest;
+use risc0_zkvm::{default_prover, ExecutorEnv, ProverOpts};
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
-use tokio;
 
 // Use the methods from the bitnet-methods crate
 use bitnet_methods::{BITNET_GUEST_ELF, BITNET_GUEST_ID};
-use risc0_zkvm::Receipt;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct BitNetInput {
-    pub prompt_tokens: Vec<u32>,
-    pub max_new_tokens: usize,
-    pub vocab_size: usize,
+pub struct ProvingStats {
+    pub total_cycles: u64,
+    pub user_cycles: u64,
+    pub segments: usize,
+    pub proving_time_ms: u128,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct BitNetWeights {
-    pub token_embeddings: Vec<i8>,
-    pub layer_weights: Vec<LayerWeights>,
-    pub output_weights: Vec<i8>,
-    pub vocab_size: usize,
-    pub hidden_size: usize,
-    pub num_layers: usize,
+pub struct ZkProofResult {
+    pub output: GuestOutput,
+    pub proof: String, // Base64 encoded proof
+    pub receipt_data: String, // Base64 encoded receipt
+    pub stats: ProvingStats,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LayerWeights {
-    pub attention_q: Vec<i8>,
-    pub attention_k: Vec<i8>,
-    pub attention_v: Vec<i8>,
-    pub attention_output: Vec<i8>,
-    pub ffn_gate: Vec<i8>,
-    pub ffn_up: Vec<i8>,
-    pub ffn_down: Vec<i8>,
-    pub attention_norm: Vec<i8>,
-    pub ffn_norm: Vec<i8>,
+/// A completion to replay with `--transcript`, as `bitnet-zkml` records it
+/// for deferred proving. Other fields of the file are ignored.
+#[derive(Debug, Deserialize)]
+struct Transcript {
+    prompt_tokens: Vec<u32>,
+    completion_tokens: Vec<u32>,
+    seed: u64,
+    max_tokens: u32,
+    temperature: f32,
+    top_p: f32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct BitNetOutput {
-    pub generated_tokens: Vec<u32>,
-    pub logits: Vec<f32>,
+/// Inputs to embed with `--embed`, as `bitnet-zkml` writes them. Other
+/// fields of the file are ignored.
+#[derive(Debug, Deserialize)]
+struct EmbeddingJob {
+    inputs: Vec<Vec<u32>>,
+    commit: EmbeddingCommit,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TokenizerConfig {
-    pub vocab: HashMap<String, u32>,
-    pub special_tokens: HashMap<String, u32>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ZkProofResult {
-    pub output: BitNetOutput,
-    pub proof: String, // Base64 encoded proof
-    pub receipt_data: String, // Base64 encoded receipt
+/// Each generated token's log-probability and its `top` most likely
+/// alternatives, as the guest committed them, in the `SampledLogprob` layout
+/// the servers read. `None` for results without them, such as replays.
+fn logprobs_json(output: &GuestOutput, top: usize) -> Option<Vec<serde_json::Value>> {
+    let GuestResult::Generated { logprobs, .. } = &output.result else {
+        return None;
+    };
+    let logprobs = logprobs
+        .iter()
+        .map(|logprobs| {
+            serde_json::json!({
+                "token": logprobs.token,
+                "logprob": logprobs.logprob,
+                "top_logprobs": &logprobs.top[..top.min(logprobs.top.len())],
+            })
+        })
+        .collect();
+    Some(logprobs)
 }
 
+/// The GGUF the guest runs, and its tokenizer, which the native backend
+/// uses too.
 struct BitNetHostSystem {
-    weights: BitNetWeights,
-    tokenizer: TokenizerConfig,
-    reverse_vocab: HashMap<u32, String>,
+    gguf: Vec<u8>,
+    tokenizer: Tokenizer,
 }
 
 impl BitNetHostSystem {
-    pub fn new(weights_path: &str, tokenizer_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        println!("Loading BitNet weights from: {}", weights_path);
-        let weights = Self::load_weights(weights_path)?;
-        
-        println!("Loading tokenizer from: {}", tokenizer_path);
-        let tokenizer = Self::load_tokenizer(tokenizer_path)?;
-        
-        // Create reverse vocabulary for decoding
-        let reverse_vocab: HashMap<u32, String> = tokenizer.vocab
-            .iter()
-            .map(|(k, &v)| (v, k.clone()))
-            .collect();
-        
-        println!("Loaded {} weights and {} vocab entries", 
-                weights.layer_weights.len(), tokenizer.vocab.len());
-        
-        Ok(BitNetHostSystem {
-            weights,
-            tokenizer,
-            reverse_vocab,
-        })
+    pub fn new(weights_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        println!("Loading BitNet GGUF from: {}", weights_path);
+        let tokenizer = Tokenizer::from_gguf(&GgufFile::open(weights_path)?)?;
+        let gguf = fs::read(weights_path)?;
+        println!(
+            "Loaded {} bytes of weights and {} vocab entries",
+            gguf.len(),
+            tokenizer.vocab_size()
+        );
+
+        Ok(BitNetHostSystem { gguf, tokenizer })
     }
-    
-    fn load_weights(weights_path: &str) -> Result<BitNetWeights, Box<dyn std::error::Error>> {
-        println!("Loading weights directly from GGUF: {}", weights_path);
-        
-        // Check if it's a GGUF file or JSON file
-        if weights_path.ends_with(".gguf") {
-            Self::load_weights_from_gguf(weights_path)
-        } else {
-            // Fallback to JSON loading for backward compatibility
-            Self::load_weights_from_json(weights_path)
-        }
+
+    pub fn tokenize(&self, text: &str) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+        Ok(self.tokenizer.encode(text, true)?)
     }
-    
-    fn load_weights_from_gguf(gguf_path: &str) -> Result<BitNetWeights, Box<dyn std::error::Error>> {
-        println!("GGUF direct loading not yet implemented");
-        println!("Converting GGUF to JSON first using Python...");
-        
-        // Use Python script to convert GGUF to JSON if needed
-        let json_path = "./model/weights.json";
-        
-        if !std::path::Path::new(json_path).exists() {
-            // Call Python script to extract weights from GGUF
-            let output = std::process::Command::new("python3")
-                .arg("scripts/extract_gguf_weights_optimized.py")
-                .arg("--input")
-                .arg(gguf_path)
-                .arg("--output")
-                .arg(json_path)
-                .output();
-                
-            match output {
-                Ok(result) => {
-                    if !result.status.success() {
-                        let stderr = String::from_utf8_lossy(&result.stderr);
-                        println!("Warning: GGUF extraction failed: {}", stderr);
-                        return Self::load_weights_from_json_fallback();
-                    }
-                }
-                Err(e) => {
-                    println!("Warning: Could not run Python extractor: {}", e);
-                    return Self::load_weights_from_json_fallback();
-                }
-            }
-        }
-        
-        // Now load from the converted JSON
-        Self::load_weights_from_json(json_path)
+
+    pub fn detokenize(&self, tokens: &[u32]) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(self.tokenizer.decode(tokens, true)?)
     }
-    
-    fn load_weights_from_json_fallback() -> Result<BitNetWeights, Box<dyn std::error::Error>> {
-        println!("Using fallback weights (no GGUF file available)");
-        
-        // Create minimal demo weights for zkVM compatibility
-        let vocab_size = 10000; // Reduced for zkVM compatibility
-        let hidden_size = 512;   // Reduced for zkVM compatibility
-        let num_layers = 8;      // Reduced for zkVM compatibility
-        
-        let mut layer_weights = Vec::new();
-        
-        // Create simplified layer weights
-        for _layer_idx in 0..num_layers {
-            let layer = LayerWeights {
-                attention_q: vec![0i8; hidden_size * hidden_size],
-                attention_k: vec![0i8; hidden_size * hidden_size],
-                attention_v: vec![0i8; hidden_size * hidden_size],
-                attention_output: vec![0i8; hidden_size * hidden_size],
-                ffn_gate: vec![0i8; hidden_size * hidden_size * 4],
-                ffn_up: vec![0i8; hidden_size * hidden_size * 4],
-                ffn_down: vec![0i8; hidden_size * 4 * hidden_size],
-                attention_norm: vec![1i8; hidden_size],
-                ffn_norm: vec![1i8; hidden_size],
-            };
-            layer_weights.push(layer);
-        }
-        
-        Ok(BitNetWeights {
-            token_embeddings: vec![0i8; vocab_size * hidden_size],
-            layer_weights,
-            output_weights: vec![0i8; hidden_size * vocab_size],
-            vocab_size,
-            hidden_size,
-            num_layers,
-        })
+
+    pub async fn generate_with_proof(
+        &self,
+        prompt: &str,
+        params: GuestParams,
+    ) -> Result<ZkProofResult, Box<dyn std::error::Error>> {
+        println!("Generating response for prompt: '{}'", prompt);
+
+        let prompt = self.tokenize(prompt)?;
+        println!("Prompt tokens: {:?}", prompt);
+
+        let result = self.prove(GuestInput::Generate { prompt, params })?;
+        println!("Generated tokens: {:?}", result.output.tokens());
+        println!("Response: {}", self.detokenize(result.output.tokens())?);
+        Ok(result)
     }
-    
-    fn load_weights_from_json(weights_path: &str) -> Result<BitNetWeights, Box<dyn std::error::Error>> {
-        let weights_content = fs::read_to_string(weights_path)?;
-        let weights_data: serde_json::Value = serde_json::from_str(&weights_content)?;
-        
-        // Parse the extracted weights from our Python script
-        let weights_obj = &weights_data["weights"];
-        
-        // Convert the extracted weights to our zkVM format
-        // This is a simplified conversion - in practice you'd need more sophisticated parsing
-        let mut layer_weights = Vec::new();
-        let mut token_embeddings = Vec::new();
-        let mut output_weights = Vec::new();
-        
-        // Extract model configuration
-        let vocab_size = 10000; // Reduced for zkVM compatibility
-        let hidden_size = 512;  // Reduced for zkVM compatibility
-        let num_layers = 8;     // Reduced for zkVM compatibility
-        
-        // Process token embeddings
-        if let Some(emb_data) = weights_obj.get("token_embd.weight") {
-            if let Some(weights_array) = emb_data.get("weights") {
-                if let Some(weights_vec) = weights_array.as_array() {
-                    token_embeddings = weights_vec.iter()
-                        .filter_map(|v| v.as_i64().map(|i| i as i8))
-                        .collect();
-                }
-            }
-        }
-        
-        // Process layer weights (simplified)
-        for layer_idx in 0..num_layers {
-            let layer = LayerWeights {
-                attention_q: vec![0i8; hidden_size * hidden_size],
-                attention_k: vec![0i8; hidden_size * hidden_size],
-                attention_v: vec![0i8; hidden_size * hidden_size],
-                attention_output: vec![0i8; hidden_size * hidden_size],
-                ffn_gate: vec![0i8; hidden_size * hidden_size * 4],
-                ffn_up: vec![0i8; hidden_size * hidden_size * 4],
-                ffn_down: vec![0i8; hidden_size * 4 * hidden_size],
-                attention_norm: vec![1i8; hidden_size],
-                ffn_norm: vec![1i8; hidden_size],
-            };
-            layer_weights.push(layer);
-        }
-        
-        // If token embeddings are empty, create defaults
-        if token_embeddings.is_empty() {
-            token_embeddings = vec![0i8; vocab_size * hidden_size];
+
+    /// Proves that every completion token of `transcript` could have been
+    /// sampled from the model under its parameters.
+    pub fn replay_with_proof(
+        &self,
+        transcript: Transcript,
+    ) -> Result<ZkProofResult, Box<dyn std::error::Error>> {
+        println!(
+            "Replaying {} prompt and {} completion tokens",
+            transcript.prompt_tokens.len(),
+            transcript.completion_tokens.len()
+        );
+        let params = GuestParams {
+            max_tokens: transcript.max_tokens,
+            temperature: transcript.temperature,
+            top_p: transcript.top_p,
+            seed: transcript.seed,
+            stop_tokens: self.tokenizer.stop_tokens(),
+            logprobs: None,
+        };
+        let result = self.prove(GuestInput::Replay {
+            prompt: transcript.prompt_tokens,
+            completion: transcript.completion_tokens,
+            params,
+        })?;
+        if let GuestResult::Replayed { reproduced } = result.output.result {
+            println!("Reproduced by the seeded sampler: {}", reproduced);
         }
-        
-        // Create output weights (language model head)
-        output_weights = vec![0i8; hidden_size * vocab_size];
-        
-        Ok(BitNetWeights {
-            token_embeddings,
-            layer_weights,
-            output_weights,
-            vocab_size,
-            hidden_size,
-            num_layers,
-        })
+        Ok(result)
     }
-    
-    fn load_tokenizer(tokenizer_path: &str) -> Result<TokenizerConfig, Box<dyn std::error::Error>> {
-        let vocab_path = Path::new(tokenizer_path).join("vocab.json");
-        let config_path = Path::new(tokenizer_path).join("tokenizer_config.json");
-        
-        let vocab_content = fs::read_to_string(vocab_path)?;
-        let vocab: HashMap<String, u32> = serde_json::from_str(&vocab_content)?;
-        
-        let special_tokens = HashMap::from([
-            ("pad_token_id".to_string(), 0),
-            ("unk_token_id".to_string(), 1),
-            ("bos_token_id".to_string(), 2),
-            ("eos_token_id".to_string(), 3),
-        ]);
-        
-        Ok(TokenizerConfig {
-            vocab,
-            special_tokens,
+
+    /// Proves the embedding of each input of `job`.
+    pub fn embed_with_proof(
+        &self,
+        job: EmbeddingJob,
+    ) -> Result<ZkProofResult, Box<dyn std::error::Error>> {
+        println!("Embedding {} inputs", job.inputs.len());
+        self.prove(GuestInput::Embed {
+            inputs: job.inputs,
+            commit: job.commit,
         })
     }
-    
-    pub fn tokenize(&self, text: &str) -> Vec<u32> {
-        let mut tokens = vec![self.tokenizer.special_tokens["bos_token_id"]];
-        
-        // Simple word-level tokenization
-        for word in text.split_whitespace() {
-            if let Some(&token_id) = self.tokenizer.vocab.get(word) {
-                tokens.push(token_id);
-            } else {
-                // Character-level fallback
-                for ch in word.chars() {
-                    let ch_str = ch.to_string();
-                    if let Some(&token_id) = self.tokenizer.vocab.get(&ch_str) {
-                        tokens.push(token_id);
-                    } else {
-                        tokens.push(self.tokenizer.special_tokens["unk_token_id"]);
+
+    /// The vectors an embedding receipt commits to. Where the journal only
+    /// holds their hashes, they are computed natively and checked against it.
+    pub fn embeddings(
+        &self,
+        output: &GuestOutput,
+    ) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
+        let (GuestInput::Embed { inputs, .. }, GuestResult::Embedded { embeddings }) =
+            (&output.input, &output.result)
+        else {
+            return Err("the guest committed no embeddings".into());
+        };
+
+        let mut model = None;
+        let mut vectors = Vec::with_capacity(embeddings.len());
+        for (committed, tokens) in embeddings.iter().zip(inputs) {
+            let vector = match committed {
+                CommittedEmbedding::Vector(vector) => vector.clone(),
+                CommittedEmbedding::Hash(_) => {
+                    let model = match &mut model {
+                        Some(model) => model,
+                        None => model.insert(self.load_model()?),
+                    };
+                    let vector = model.embed(tokens)?;
+                    if !committed.matches(&vector) {
+                        return Err("native embedding does not match the receipt".into());
                     }
+                    vector
                 }
-            }
+            };
+            vectors.push(vector);
         }
-        
-        tokens
+        Ok(vectors)
     }
-    
-    pub fn detokenize(&self, tokens: &[u32]) -> String {
-        tokens.iter()
-            .filter_map(|&token_id| self.reverse_vocab.get(&token_id))
-            .cloned()
-            .collect::<Vec<_>>()
-            .join(" ")
+
+    /// The model for native work, on every available core.
+    fn load_model(&self) -> Result<Model, Box<dyn std::error::Error>> {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Ok(Model::load(&GgufFile::from_bytes(self.gguf.clone())?, threads)?)
     }
-    
-    pub async fn generate_with_proof(&self, prompt: &str, max_new_tokens: usize) -> Result<ZkProofResult, Box<dyn std::error::Error>> {
-        println!("Generating response for prompt: '{}'", prompt);
-        
-        // Tokenize input
-        let prompt_tokens = self.tokenize(prompt);
-        println!("Prompt tokens: {:?}", prompt_tokens);
-        
-        // Prepare input for zkVM
-        let input = BitNetInput {
-            prompt_tokens,
-            max_new_tokens,
-            vocab_size: self.weights.vocab_size,
-        };
-        
+
+    /// Runs the guest on `input` and this model, and verifies the receipt.
+    fn prove(&self, input: GuestInput) -> Result<ZkProofResult, Box<dyn std::error::Error>> {
         println!("Starting zkVM execution...");
-        
-        // Create ExecutorEnv with input data
+
+        // The weights go in raw: serializing a GGUF word by word would cost
+        // more cycles than reading it
         let env = ExecutorEnv::builder()
             .write(&input)?
-            .write(&self.weights)?
+            .write(&(self.gguf.len() as u32))?
+            .write_slice(&self.gguf)
             .build()?;
-        
+
         // Run the prover
         let prover = default_prover();
         let opts = ProverOpts::default();
-        
+
         println!("Generating proof... (this may take several minutes)");
+        let started = std::time::Instant::now();
         let prove_info = prover.prove_with_opts(env, BITNET_GUEST_ELF, &opts)?;
-        
+        let stats = ProvingStats {
+            total_cycles: prove_info.stats.total_cycles,
+            user_cycles: prove_info.stats.user_cycles,
+            segments: prove_info.stats.segments,
+            proving_time_ms: started.elapsed().as_millis(),
+        };
+        println!(
+            "Proved {} cycles in {} segments ({} ms)",
+            stats.total_cycles, stats.segments, stats.proving_time_ms
+        );
+
         // Extract output from receipt
-        let output: BitNetOutput = prove_info.receipt.journal.decode()?;
-        
-        println!("Generated tokens: {:?}", output.generated_tokens);
-        let response_text = self.detokenize(&output.generated_tokens);
-        println!("Response: {}", response_text);
-        
+        let output: GuestOutput = prove_info.receipt.journal.decode()?;
+        println!("Model digest: {}", output.model_digest);
+
         // Verify the proof
         println!("Verifying proof...");
         prove_info.receipt.verify(BITNET_GUEST_ID)?;
         println!("Proof verified successfully!");
-        
+
         // Encode proof as base64
-        let receipt_bytes = bincode::serialize(&prove_info.receipt)?;
-        let proof_base64 = base64::encode(&receipt_bytes);
-        let receipt_base64 = base64::encode(&receipt_bytes);
-        
+        let receipt_base64 = BASE64.encode(bincode::serialize(&prove_info.receipt)?);
+
         Ok(ZkProofResult {
             output,
-            proof: proof_base64,
+            proof: receipt_base64.clone(),
             receipt_data: receipt_base64,
+            stats,
         })
     }
-    
-    pub async fn generate_groth16_proof(&self, receipt_data: &str) -> Result<String, Box<dyn std::error::Error>> {
-        // Placeholder for Groth16 proof generation
-        // This would require additional RISC0 features for Ethereum compatibility
-        println!("Groth16 proof generation not yet implemented");
-        Ok("groth16_proof_placeholder".to_string())
-    }
 }
 
 #[tokio::main]
@@ -358,62 +256,261 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .short('w')
             .long("weights")
             .value_name("FILE")
-            .help("Path to BitNet weights file (GGUF or JSON)")
+            .help("Path to the BitNet GGUF file, tokenizer included")
             .default_value("../BitNet/models/BitNet-b1.58-2B-4T/ggml-model-i2_s.gguf"))
-        .arg(Arg::new("tokenizer")
-            .short('t')
-            .long("tokenizer")
-            .value_name("DIR")
-            .help("Path to tokenizer directory")
-            .default_value("./tokenizer"))
         .arg(Arg::new("prompt")
             .short('p')
             .long("prompt")
             .value_name("TEXT")
             .help("Input prompt for generation")
             .default_value("Hello, I am"))
+        .arg(Arg::new("transcript")
+            .long("transcript")
+            .value_name("FILE")
+            .help("Prove a recorded completion (JSON token transcript) instead of generating")
+            .conflicts_with("prompt"))
+        .arg(Arg::new("embed")
+            .long("embed")
+            .value_name("FILE")
+            .help("Prove the embeddings of a JSON job of tokenized inputs instead of generating")
+            .conflicts_with_all(["prompt", "transcript"]))
         .arg(Arg::new("max_tokens")
             .short('m')
             .long("max-tokens")
             .value_name("NUMBER")
             .help("Maximum number of new tokens to generate")
             .default_value("10"))
+        .arg(Arg::new("temperature")
+            .long("temperature")
+            .value_name("NUMBER")
+            .help("Sampling temperature; 0 decodes greedily")
+            .default_value("0"))
+        .arg(Arg::new("top_p")
+            .long("top-p")
+            .value_name("NUMBER")
+            .help("Nucleus sampling threshold")
+            .default_value("1"))
+        .arg(Arg::new("seed")
+            .long("seed")
+            .value_name("NUMBER")
+            .help("Seed of the sampler")
+            .default_value("0"))
+        .arg(Arg::new("logprobs")
+            .long("logprobs")
+            .value_name("NUMBER")
+            .help("Report each token's log-probability with this many alternatives"))
         .arg(Arg::new("output")
             .short('o')
             .long("output")
             .value_name("FILE")
             .help("Output file for proof and results")
             .default_value("./proofs/bitnet_receipt.json"))
+        .arg(Arg::new("image_id")
+            .long("image-id")
+            .action(ArgAction::SetTrue)
+            .help("Print the guest image ID receipts are verified against, then exit"))
         .get_matches();
-    
+
+    if matches.get_flag("image_id") {
+        println!("{}", Digest::from(BITNET_GUEST_ID));
+        return Ok(());
+    }
+
     let weights_path = matches.get_one::<String>("weights").unwrap();
-    let tokenizer_path = matches.get_one::<String>("tokenizer").unwrap();
     let prompt = matches.get_one::<String>("prompt").unwrap();
-    let max_tokens: usize = matches.get_one::<String>("max_tokens").unwrap().parse()?;
     let output_path = matches.get_one::<String>("output").unwrap();
-    
+
+    if let Some(transcript_path) = matches.get_one::<String>("transcript") {
+        let transcript: Transcript = serde_json::from_slice(&fs::read(transcript_path)?)?;
+        let system = BitNetHostSystem::new(weights_path)?;
+        let result = system.replay_with_proof(transcript)?;
+        let reproduced = matches!(result.output.result, GuestResult::Replayed { reproduced: true });
+        let output_data = serde_json::json!({
+            "response": system.detokenize(result.output.tokens())?,
+            "tokens": result.output.tokens(),
+            "reproduced": reproduced,
+            "model_digest": result.output.model_digest,
+            "proof": result.proof,
+            "receipt": result.receipt_data,
+            "stats": result.stats,
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+        });
+        fs::create_dir_all(Path::new(output_path).parent().unwrap())?;
+        fs::write(output_path, serde_json::to_string_pretty(&output_data)?)?;
+        println!("Results saved to: {}", output_path);
+        return Ok(());
+    }
+    if let Some(job_path) = matches.get_one::<String>("embed") {
+        let job: EmbeddingJob = serde_json::from_slice(&fs::read(job_path)?)?;
+        let system = BitNetHostSystem::new(weights_path)?;
+        let result = system.embed_with_proof(job)?;
+        let output_data = serde_json::json!({
+            "embeddings": system.embeddings(&result.output)?,
+            "model_digest": result.output.model_digest,
+            "proof": result.proof,
+            "receipt": result.receipt_data,
+            "stats": result.stats,
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+        });
+        fs::create_dir_all(Path::new(output_path).parent().unwrap())?;
+        fs::write(output_path, serde_json::to_string_pretty(&output_data)?)?;
+        println!("Results saved to: {}", output_path);
+        return Ok(());
+    }
+    let top_logprobs: Option<u32> = matches
+        .get_one::<String>("logprobs")
+        .map(|top| top.parse())
+        .transpose()?;
+
     // Initialize the BitNet system
-    let system = BitNetHostSystem::new(weights_path, tokenizer_path)?;
-    
+    let system = BitNetHostSystem::new(weights_path)?;
+    let params = GuestParams {
+        max_tokens: matches.get_one::<String>("max_tokens").unwrap().parse()?,
+        temperature: matches.get_one::<String>("temperature").unwrap().parse()?,
+        top_p: matches.get_one::<String>("top_p").unwrap().parse()?,
+        seed: matches.get_one::<String>("seed").unwrap().parse()?,
+        stop_tokens: system.tokenizer.stop_tokens(),
+        logprobs: top_logprobs,
+    };
+
     // Generate response with proof
-    let result = system.generate_with_proof(prompt, max_tokens).await?;
-    
+    let result = system.generate_with_proof(prompt, params).await?;
+    let response = system.detokenize(result.output.tokens())?;
+
     // Save results
     let output_data = serde_json::json!({
         "prompt": prompt,
-        "response": system.detokenize(&result.output.generated_tokens),
-        "tokens": result.output.generated_tokens,
+        "response": response,
+        "tokens": result.output.tokens(),
+        "logprobs": top_logprobs.and_then(|top| logprobs_json(&result.output, top as usize)),
+        "model_digest": result.output.model_digest,
         "proof": result.proof,
         "receipt": result.receipt_data,
+        "stats": result.stats,
         "timestamp": chrono::Utc::now().to_rfc3339(),
     });
-    
+
     fs::create_dir_all(Path::new(output_path).parent().unwrap())?;
     fs::write(output_path, serde_json::to_string_pretty(&output_data)?)?;
-    
+
     println!("Results saved to: {}", output_path);
-    println!("Generated response: {}", system.detokenize(&result.output.generated_tokens));
+    println!("Generated response: {}", response);
     println!("Proof length: {} bytes", result.proof.len());
-    
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitnet_core::sampler::TokenLogprobs;
+    use bitnet_core::FinishReason;
+    use risc0_zkvm::Journal;
+
+    /// A journal as the guest commits it: one `GuestOutput`, serialized
+    /// with the zkVM's word-oriented serde.
+    fn journal(output: &GuestOutput) -> Journal {
+        let words = risc0_zkvm::serde::to_vec(output).unwrap();
+        Journal::new(words.iter().flat_map(|word| word.to_le_bytes()).collect())
+    }
+
+    #[test]
+    fn journal_round_trips_and_reports_logprobs() {
+        let committed = GuestOutput {
+            model_digest: "sha256:00".to_string(),
+            input: GuestInput::Generate {
+                prompt: vec![1, 7],
+                params: GuestParams {
+                    max_tokens: 2,
+                    temperature: 0.5,
+                    top_p: 1.0,
+                    seed: 9,
+                    stop_tokens: vec![2],
+                    logprobs: Some(1),
+                },
+            },
+            result: GuestResult::Generated {
+                tokens: vec![4, 5],
+                logprobs: vec![
+                    TokenLogprobs { token: 4, logprob: -0.5, top: vec![(4, -0.5), (3, -1.0)] },
+                    TokenLogprobs { token: 5, logprob: -0.25, top: vec![(5, -0.25)] },
+                ],
+                finish_reason: FinishReason::Length,
+            },
+        };
+        let output: GuestOutput = journal(&committed).decode().unwrap();
+        assert_eq!(output, committed);
+        assert_eq!(output.tokens(), [4, 5]);
+
+        let logprobs = logprobs_json(&output, 1).unwrap();
+        assert_eq!(logprobs.len(), 2);
+        assert_eq!(logprobs[0]["token"], 4);
+        assert_eq!(logprobs[0]["logprob"], -0.5);
+        assert_eq!(logprobs[0]["top_logprobs"], serde_json::json!([[4, -0.5]]));
+        assert_eq!(logprobs[1]["top_logprobs"], serde_json::json!([[5, -0.25]]));
+    }
+
+    #[test]
+    fn replays_read_transcripts_and_cover_their_completion() {
+        let transcript: Transcript = serde_json::from_value(serde_json::json!({
+            "completion_id": "chatcmpl-1",
+            "prompt_tokens": [1, 7],
+            "completion_tokens": [4, 5],
+            "seed": 9,
+            "max_tokens": 4,
+            "temperature": 0.0,
+            "top_p": 1.0,
+            "exact": true,
+        }))
+        .unwrap();
+        let committed = GuestOutput {
+            model_digest: "sha256:00".to_string(),
+            input: GuestInput::Replay {
+                prompt: transcript.prompt_tokens,
+                completion: transcript.completion_tokens,
+                params: GuestParams {
+                    max_tokens: transcript.max_tokens,
+                    temperature: transcript.temperature,
+                    top_p: transcript.top_p,
+                    seed: transcript.seed,
+                    stop_tokens: vec![2],
+                    logprobs: None,
+                },
+            },
+            result: GuestResult::Replayed { reproduced: true },
+        };
+        let output: GuestOutput = journal(&committed).decode().unwrap();
+        assert_eq!(output, committed);
+        assert_eq!(output.tokens(), [4, 5]);
+        assert_eq!(logprobs_json(&output, 1), None);
+    }
+
+    #[test]
+    fn embedding_jobs_and_their_journals_round_trip() {
+        let job: EmbeddingJob = serde_json::from_value(serde_json::json!({
+            "model": "tiny",
+            "model_digest": "sha256:00",
+            "inputs": [[1, 7], [3]],
+            "commit": "hash",
+        }))
+        .unwrap();
+        assert_eq!(job.commit, EmbeddingCommit::Hash);
+        let committed = GuestOutput {
+            model_digest: "sha256:00".to_string(),
+            input: GuestInput::Embed {
+                inputs: job.inputs,
+                commit: job.commit,
+            },
+            result: GuestResult::Embedded {
+                embeddings: vec![
+                    CommittedEmbedding::new(vec![0.6, 0.8], EmbeddingCommit::Hash),
+                    CommittedEmbedding::new(vec![1.0, 0.0], EmbeddingCommit::Vector),
+                ],
+            },
+        };
+        let output: GuestOutput = journal(&committed).decode().unwrap();
+        assert_eq!(output, committed);
+        assert!(output.tokens().is_empty());
+        assert_eq!(logprobs_json(&output, 1), None);
+    }
+}
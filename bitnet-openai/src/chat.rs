@@ -0,0 +1,482 @@
+//! `POST /v1/chat/completions`.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::error::ValidationError;
+
+/// Most `stop` sequences a request may carry.
+pub const MAX_STOP_SEQUENCES: usize = 4;
+/// Most choices a request may ask for with `n`.
+pub const MAX_CHOICES: u32 = 128;
+/// Most alternatives a request may ask for with `top_logprobs`.
+pub const MAX_TOP_LOGPROBS: u32 = 20;
+/// Largest `max_tokens`: far past any model's context window, but small
+/// enough that adding a prompt length cannot overflow.
+pub const MAX_COMPLETION_TOKENS: u32 = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// Newer name for `max_tokens`; wins when both are set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_completion_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// Choices to generate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Stop>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default)]
+    pub logprobs: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u32>,
+    /// End-user identifier, for abuse monitoring.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+}
+
+impl ChatCompletionRequest {
+    /// `max_completion_tokens`, falling back to `max_tokens`.
+    pub fn max_tokens(&self) -> Option<u32> {
+        self.max_completion_tokens.or(self.max_tokens)
+    }
+
+    /// Number of choices requested; 1 if unset.
+    pub fn n(&self) -> u32 {
+        self.n.unwrap_or(1)
+    }
+
+    /// The `stop` sequences as a list, empty if unset.
+    pub fn stop_sequences(&self) -> Vec<String> {
+        self.stop.as_ref().map_or_else(Vec::new, Stop::sequences)
+    }
+
+    /// Checks the constraints the OpenAI API places on a request. Neither
+    /// server streams or calls tools, so a request for a stream or a forced
+    /// tool call is refused here too.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.model.is_empty() {
+            return Err(ValidationError::new("model", "must not be empty"));
+        }
+        validate_stream(self.stream)?;
+        if self.messages.is_empty() {
+            return Err(ValidationError::new(
+                "messages",
+                "must contain at least one message",
+            ));
+        }
+        if let Some(max_tokens) = self.max_tokens() {
+            let param = if self.max_completion_tokens.is_some() {
+                "max_completion_tokens"
+            } else {
+                "max_tokens"
+            };
+            validate_max_tokens(param, max_tokens)?;
+        }
+        validate_sampling(&Sampling {
+            temperature: self.temperature,
+            top_p: self.top_p,
+            presence_penalty: self.presence_penalty,
+            frequency_penalty: self.frequency_penalty,
+            n: self.n,
+            stop: self.stop.as_ref(),
+        })?;
+        if let Some(top_logprobs) = self.top_logprobs {
+            if top_logprobs > MAX_TOP_LOGPROBS {
+                return Err(ValidationError::new(
+                    "top_logprobs",
+                    format!("must be between 0 and {}", MAX_TOP_LOGPROBS),
+                ));
+            }
+            if !self.logprobs {
+                return Err(ValidationError::new(
+                    "top_logprobs",
+                    "requires `logprobs: true`",
+                ));
+            }
+        }
+        match &self.tool_choice {
+            None | Some(ToolChoice::Mode(ToolChoiceMode::None | ToolChoiceMode::Auto)) => {}
+            Some(_) if self.tools.as_ref().is_none_or(Vec::is_empty) => {
+                return Err(ValidationError::new("tool_choice", "requires `tools`"));
+            }
+            Some(_) => {
+                return Err(ValidationError::new(
+                    "tool_choice",
+                    "forcing a tool call is not supported",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Responses carry their proof, so they are only sent whole.
+pub(crate) fn validate_stream(stream: bool) -> Result<(), ValidationError> {
+    if stream {
+        return Err(ValidationError::new("stream", "streaming is not supported"));
+    }
+    Ok(())
+}
+
+/// Checks a `max_tokens` or `max_completion_tokens` value.
+pub(crate) fn validate_max_tokens(
+    param: &'static str,
+    max_tokens: u32,
+) -> Result<(), ValidationError> {
+    if !(1..=MAX_COMPLETION_TOKENS).contains(&max_tokens) {
+        return Err(ValidationError::new(
+            param,
+            format!("must be between 1 and {}", MAX_COMPLETION_TOKENS),
+        ));
+    }
+    Ok(())
+}
+
+/// Sampling fields that chat and text completions share.
+pub(crate) struct Sampling<'a> {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub n: Option<u32>,
+    pub stop: Option<&'a Stop>,
+}
+
+pub(crate) fn validate_sampling(sampling: &Sampling) -> Result<(), ValidationError> {
+    check_range("temperature", sampling.temperature, 0.0, 2.0)?;
+    check_range("top_p", sampling.top_p, 0.0, 1.0)?;
+    check_range("presence_penalty", sampling.presence_penalty, -2.0, 2.0)?;
+    check_range("frequency_penalty", sampling.frequency_penalty, -2.0, 2.0)?;
+    if let Some(n) = sampling.n {
+        if n == 0 || n > MAX_CHOICES {
+            return Err(ValidationError::new(
+                "n",
+                format!("must be between 1 and {}", MAX_CHOICES),
+            ));
+        }
+    }
+    let stops = sampling.stop.map_or_else(Vec::new, Stop::sequences);
+    if stops.len() > MAX_STOP_SEQUENCES {
+        return Err(ValidationError::new(
+            "stop",
+            format!("at most {} sequences are allowed", MAX_STOP_SEQUENCES),
+        ));
+    }
+    if stops.iter().any(String::is_empty) {
+        return Err(ValidationError::new("stop", "sequences must not be empty"));
+    }
+    Ok(())
+}
+
+fn check_range(
+    param: &'static str,
+    value: Option<f32>,
+    min: f32,
+    max: f32,
+) -> Result<(), ValidationError> {
+    match value {
+        Some(value) if !(min..=max).contains(&value) => Err(ValidationError::new(
+            param,
+            format!("must be between {} and {}", min, max),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// `stop`: one sequence or a list.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Stop {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Stop {
+    pub fn sequences(&self) -> Vec<String> {
+        match self {
+            Stop::One(stop) => vec![stop.clone()],
+            Stop::Many(stops) => stops.clone(),
+        }
+    }
+}
+
+/// Where `text` should be cut for `stops`: the start of the earliest stop
+/// sequence, which is not part of the output.
+pub fn stop_position(text: &str, stops: &[String]) -> Option<usize> {
+    stops
+        .iter()
+        .filter_map(|stop| text.find(stop.as_str()))
+        .min()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    /// Newer name for `system`.
+    Developer,
+    User,
+    Assistant,
+    Tool,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::Developer => "developer",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+        }
+    }
+
+    /// System and developer messages, which carry instructions rather than a
+    /// turn of the conversation.
+    pub fn is_system(self) -> bool {
+        matches!(self, Role::System | Role::Developer)
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: Role,
+    /// Null on assistant messages that only call tools.
+    pub content: Option<MessageContent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// On `tool` messages: the call this answers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: Some(MessageContent::Text(content.into())),
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(Role::Assistant, content)
+    }
+
+    /// The message text, with content parts joined; empty if there is none.
+    pub fn text(&self) -> String {
+        match &self.content {
+            None => String::new(),
+            Some(MessageContent::Text(text)) => text.clone(),
+            Some(MessageContent::Parts(parts)) => parts
+                .iter()
+                .map(|ContentPart::Text { text }| text.as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+/// Message content: a string, or a list of parts. The models are text-only,
+/// so only text parts are accepted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tool {
+    #[serde(rename = "type")]
+    pub tool_type: ToolType,
+    pub function: FunctionDefinition,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolType {
+    Function,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON Schema for the arguments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+/// `tool_choice`: `"none"`, `"auto"`, `"required"`, or a named function.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ToolChoice {
+    Mode(ToolChoiceMode),
+    Named(NamedToolChoice),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolChoiceMode {
+    None,
+    Auto,
+    Required,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NamedToolChoice {
+    #[serde(rename = "type")]
+    pub tool_type: ToolType,
+    pub function: FunctionName,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionName {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub tool_type: ToolType,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// The arguments as a JSON string.
+    pub arguments: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatCompletionResponse {
+    pub id: String,
+    pub object: &'static str,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChatChoice>,
+    pub usage: Usage,
+    /// Base64 proof of the completion: a zkVM receipt or a signed attestation.
+    pub zkml_proof: Option<String>,
+    /// True only when `zkml_proof` is a verified zkVM receipt.
+    pub verified: bool,
+    /// Set when a receipt will follow at `GET /v1/proofs/{id}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proof_status: Option<ProofStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatChoice {
+    pub index: u32,
+    pub message: ChatMessage,
+    pub logprobs: Option<ChoiceLogprobs>,
+    pub finish_reason: FinishReason,
+    /// Base64 zkVM receipt of this choice alone, when each of several
+    /// choices was proven in its own run; the response's `zkml_proof` is
+    /// then unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zkml_proof: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// A stop token or `stop` sequence was produced.
+    Stop,
+    /// `max_tokens` or the context window was reached.
+    Length,
+    ToolCalls,
+    ContentFilter,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChoiceLogprobs {
+    pub content: Vec<TokenLogprob>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f32,
+    /// UTF-8 bytes of the token, which may be part of a character.
+    pub bytes: Option<Vec<u8>>,
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f32,
+    pub bytes: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+impl Usage {
+    pub fn new(prompt_tokens: u32, completion_tokens: u32) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
+
+/// Progress of a deferred zkVM proof.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProofStatus {
+    Queued,
+    Proving,
+    Ready,
+    Failed,
+}
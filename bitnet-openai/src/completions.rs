@@ -0,0 +1,154 @@
+//! `POST /v1/completions`: raw text completion, without a chat template.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::chat::{
+    validate_max_tokens, validate_sampling, validate_stream, FinishReason, ProofStatus, Sampling,
+    Stop, Usage,
+};
+use crate::error::ValidationError;
+
+/// Most alternatives a request may ask for with `logprobs`.
+pub const MAX_LOGPROBS: u32 = 5;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompletionRequest {
+    pub model: String,
+    pub prompt: Prompt,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Stop>,
+    /// Return the prompt in front of the completion.
+    #[serde(default)]
+    pub echo: bool,
+    /// Number of most likely tokens to report log-probabilities for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+impl CompletionRequest {
+    /// The `stop` sequences as a list, empty if unset.
+    pub fn stop_sequences(&self) -> Vec<String> {
+        self.stop.as_ref().map_or_else(Vec::new, Stop::sequences)
+    }
+
+    /// Checks the constraints the OpenAI API places on a request. Each
+    /// response carries one proof, so streams and batched prompts are refused
+    /// here too.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.model.is_empty() {
+            return Err(ValidationError::new("model", "must not be empty"));
+        }
+        validate_stream(self.stream)?;
+        match &self.prompt {
+            Prompt::Batch(prompts) if prompts.is_empty() => {
+                return Err(ValidationError::new("prompt", "must not be empty"));
+            }
+            Prompt::Batch(prompts) if prompts.len() > 1 => {
+                return Err(ValidationError::new(
+                    "prompt",
+                    "only one prompt per request is supported",
+                ));
+            }
+            _ => {}
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            validate_max_tokens("max_tokens", max_tokens)?;
+        }
+        validate_sampling(&Sampling {
+            temperature: self.temperature,
+            top_p: self.top_p,
+            presence_penalty: self.presence_penalty,
+            frequency_penalty: self.frequency_penalty,
+            n: self.n,
+            stop: self.stop.as_ref(),
+        })?;
+        if self
+            .logprobs
+            .is_some_and(|logprobs| logprobs > MAX_LOGPROBS)
+        {
+            return Err(ValidationError::new(
+                "logprobs",
+                format!("must be between 0 and {}", MAX_LOGPROBS),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// `prompt`: a string, or a list of strings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Prompt {
+    Text(String),
+    Batch(Vec<String>),
+}
+
+impl Prompt {
+    /// The first prompt; empty for an empty batch, which `validate` rejects.
+    pub fn text(&self) -> &str {
+        match self {
+            Prompt::Text(text) => text,
+            Prompt::Batch(prompts) => prompts.first().map_or("", String::as_str),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CompletionResponse {
+    pub id: String,
+    pub object: &'static str,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<CompletionChoice>,
+    pub usage: Usage,
+    /// Base64 proof of the completion: a zkVM receipt or a signed attestation.
+    pub zkml_proof: Option<String>,
+    /// True only when `zkml_proof` is a verified zkVM receipt.
+    pub verified: bool,
+    /// Set when a receipt will follow at `GET /v1/proofs/{id}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proof_status: Option<ProofStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CompletionChoice {
+    pub text: String,
+    pub index: u32,
+    pub logprobs: Option<CompletionLogprobs>,
+    pub finish_reason: FinishReason,
+    /// Base64 zkVM receipt of this choice alone, when each of several
+    /// choices was proven in its own run; the response's `zkml_proof` is
+    /// then unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zkml_proof: Option<String>,
+}
+
+/// Legacy log-probability format: parallel lists, one entry per token.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompletionLogprobs {
+    pub tokens: Vec<String>,
+    /// Null for the first prompt token, which has nothing to condition on.
+    pub token_logprobs: Vec<Option<f32>>,
+    pub top_logprobs: Vec<Option<BTreeMap<String, f32>>>,
+    /// Character offset of each token in the returned text.
+    pub text_offset: Vec<u32>,
+}
@@ -0,0 +1,140 @@
+//! `POST /v1/embeddings`.
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ValidationError;
+
+/// Most inputs one request may embed.
+pub const MAX_INPUTS: usize = 2048;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmbeddingRequest {
+    pub model: String,
+    pub input: EmbeddingInput,
+    #[serde(default)]
+    pub encoding_format: EncodingFormat,
+    /// Keep only the first `dimensions` values, renormalized.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+impl EmbeddingRequest {
+    /// Checks the constraints the OpenAI API places on a request.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.model.is_empty() {
+            return Err(ValidationError::new("model", "must not be empty"));
+        }
+        let inputs = self.input.texts();
+        if inputs.is_empty() {
+            return Err(ValidationError::new("input", "must not be empty"));
+        }
+        if inputs.len() > MAX_INPUTS {
+            return Err(ValidationError::new(
+                "input",
+                format!("at most {} inputs are allowed", MAX_INPUTS),
+            ));
+        }
+        if inputs.iter().any(|input| input.is_empty()) {
+            return Err(ValidationError::new(
+                "input",
+                "must not contain empty strings",
+            ));
+        }
+        if self.dimensions == Some(0) {
+            return Err(ValidationError::new("dimensions", "must be at least 1"));
+        }
+        Ok(())
+    }
+}
+
+/// `input`: a string, or a list of strings embedded separately.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum EmbeddingInput {
+    Text(String),
+    Batch(Vec<String>),
+}
+
+impl EmbeddingInput {
+    pub fn texts(&self) -> Vec<&str> {
+        match self {
+            EmbeddingInput::Text(text) => vec![text],
+            EmbeddingInput::Batch(texts) => texts.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodingFormat {
+    #[default]
+    Float,
+    /// Base64 of the little-endian `f32` bytes, as OpenAI's clients decode it.
+    Base64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmbeddingList {
+    pub object: &'static str,
+    pub data: Vec<Embedding>,
+    pub model: String,
+    pub usage: EmbeddingUsage,
+    /// Base64 zkVM receipt committing to the inputs and the embeddings.
+    pub zkml_proof: Option<String>,
+    /// True only when `zkml_proof` is a verified zkVM receipt.
+    pub verified: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Embedding {
+    pub object: &'static str,
+    pub index: u32,
+    pub embedding: EmbeddingVector,
+}
+
+impl Embedding {
+    pub fn new(index: u32, values: &[f32], format: EncodingFormat) -> Self {
+        Self {
+            object: "embedding",
+            index,
+            embedding: EmbeddingVector::encode(values, format),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum EmbeddingVector {
+    Float(Vec<f32>),
+    Base64(String),
+}
+
+impl EmbeddingVector {
+    pub fn encode(values: &[f32], format: EncodingFormat) -> Self {
+        match format {
+            EncodingFormat::Float => EmbeddingVector::Float(values.to_vec()),
+            EncodingFormat::Base64 => {
+                let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+                EmbeddingVector::Base64(base64::engine::general_purpose::STANDARD.encode(bytes))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmbeddingUsage {
+    pub prompt_tokens: u32,
+    pub total_tokens: u32,
+}
+
+impl EmbeddingUsage {
+    pub fn new(prompt_tokens: u32) -> Self {
+        Self {
+            prompt_tokens,
+            total_tokens: prompt_tokens,
+        }
+    }
+}
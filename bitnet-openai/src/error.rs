@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// OpenAI-style error envelope:
+/// `{"error": {"message", "type", "param", "code"}}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiError {
+    pub error: ErrorDetails,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorDetails {
+    pub message: String,
+    #[serde(rename = "type")]
+    pub error_type: String,
+    /// The request field at fault, if any.
+    pub param: Option<String>,
+    pub code: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(message: impl Into<String>, error_type: &str, code: &str) -> Self {
+        Self {
+            error: ErrorDetails {
+                message: message.into(),
+                error_type: error_type.to_string(),
+                param: None,
+                code: Some(code.to_string()),
+            },
+        }
+    }
+}
+
+/// A request that parses but breaks one of the API's constraints.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub param: &'static str,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub fn new(param: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            param,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.param, self.message)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl From<ValidationError> for ApiError {
+    fn from(e: ValidationError) -> Self {
+        let mut error = ApiError::new(e.message, "invalid_request_error", "invalid_value");
+        error.error.param = Some(e.param.to_string());
+        error
+    }
+}
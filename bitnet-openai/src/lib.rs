@@ -0,0 +1,11 @@
+//! OpenAI-compatible request and response types shared by `api-server` and
+//! `bitnet-zkml`, with the zkML extensions both servers add (`zkml_proof`,
+//! `verified`, `proof_status`).
+
+pub mod chat;
+pub mod completions;
+pub mod embeddings;
+pub mod error;
+pub mod models;
+
+pub use error::{ApiError, ErrorDetails, ValidationError};
@@ -0,0 +1,416 @@
+//! Golden-JSON conformance tests: requests as OpenAI clients send them must
+//! parse, and responses must serialize to exactly the documented shape.
+
+use bitnet_openai::chat::{
+    stop_position, ChatChoice, ChatCompletionRequest, ChatCompletionResponse, ChatMessage,
+    ChoiceLogprobs, FinishReason, MessageContent, ProofStatus, Role, Stop, TokenLogprob,
+    ToolChoice, ToolChoiceMode, TopLogprob, Usage,
+};
+use bitnet_openai::completions::{
+    CompletionChoice, CompletionLogprobs, CompletionRequest, CompletionResponse, Prompt,
+};
+use bitnet_openai::embeddings::{
+    Embedding, EmbeddingInput, EmbeddingList, EmbeddingRequest, EmbeddingUsage, EncodingFormat,
+};
+use bitnet_openai::models::{ModelList, ModelObject};
+use bitnet_openai::{ApiError, ValidationError};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+fn golden(name: &str) -> Value {
+    let path = format!("{}/tests/golden/{}", env!("CARGO_MANIFEST_DIR"), name);
+    let content = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+    serde_json::from_str(&content).unwrap()
+}
+
+/// Serializes through a string so `f32` fields compare by their printed form.
+fn to_json(value: &impl Serialize) -> Value {
+    serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+}
+
+fn request(name: &str) -> ChatCompletionRequest {
+    serde_json::from_value(golden(name)).unwrap()
+}
+
+fn minimal_with(change: Value) -> ChatCompletionRequest {
+    let mut request = golden("chat_request_minimal.json");
+    for (key, value) in change.as_object().unwrap() {
+        request[key] = value.clone();
+    }
+    serde_json::from_value(request).unwrap()
+}
+
+fn rejected_param(request: &ChatCompletionRequest) -> &'static str {
+    request.validate().unwrap_err().param
+}
+
+#[test]
+fn full_request_parses_validates_and_round_trips() {
+    let request = request("chat_request_full.json");
+    assert_eq!(request.validate(), Ok(()));
+    assert_eq!(request.max_tokens(), Some(32));
+    assert_eq!(request.n(), 2);
+    assert_eq!(request.stop_sequences(), ["\n\n", "User:"]);
+    assert_eq!(request.seed, Some(42));
+    assert_eq!(request.user.as_deref(), Some("user-1234"));
+    assert_eq!(
+        request.tool_choice,
+        Some(ToolChoice::Mode(ToolChoiceMode::Auto))
+    );
+    assert_eq!(request.messages[2].content, None);
+    assert_eq!(
+        request.messages[2].tool_calls.as_ref().unwrap()[0]
+            .function
+            .name,
+        "get_weather"
+    );
+    assert_eq!(request.messages[3].tool_call_id.as_deref(), Some("call_1"));
+
+    assert_eq!(to_json(&request), golden("chat_request_full.json"));
+}
+
+#[test]
+fn minimal_request_gets_defaults() {
+    let request = request("chat_request_minimal.json");
+    assert_eq!(request.validate(), Ok(()));
+    assert_eq!(request.max_tokens(), None);
+    assert_eq!(request.n(), 1);
+    assert!(!request.stream);
+    assert!(!request.logprobs);
+    assert!(request.stop_sequences().is_empty());
+    assert_eq!(request.messages, [ChatMessage::new(Role::User, "Hello")]);
+}
+
+#[test]
+fn content_parts_and_single_stop_parse() {
+    let request = request("chat_request_parts.json");
+    assert_eq!(request.messages[0].role, Role::Developer);
+    assert!(request.messages[0].role.is_system());
+    assert!(matches!(
+        request.messages[1].content,
+        Some(MessageContent::Parts(_))
+    ));
+    assert_eq!(request.messages[1].text(), "First line\nSecond line");
+    assert_eq!(request.stop, Some(Stop::One("END".to_string())));
+    assert_eq!(request.stop_sequences(), ["END"]);
+}
+
+#[test]
+fn non_text_content_is_rejected() {
+    let mut request = golden("chat_request_minimal.json");
+    request["messages"][0]["content"] = json!([
+        {"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}}
+    ]);
+    assert!(serde_json::from_value::<ChatCompletionRequest>(request).is_err());
+}
+
+#[test]
+fn unknown_role_is_rejected() {
+    let mut request = golden("chat_request_minimal.json");
+    request["messages"][0]["role"] = json!("narrator");
+    assert!(serde_json::from_value::<ChatCompletionRequest>(request).is_err());
+}
+
+#[test]
+fn out_of_range_values_are_rejected() {
+    let cases = [
+        (json!({"temperature": 2.5}), "temperature"),
+        (json!({"temperature": -0.1}), "temperature"),
+        (json!({"top_p": 1.5}), "top_p"),
+        (json!({"presence_penalty": 3.0}), "presence_penalty"),
+        (json!({"frequency_penalty": -2.5}), "frequency_penalty"),
+        (json!({"n": 0}), "n"),
+        (json!({"n": 129}), "n"),
+        (json!({"max_tokens": 0}), "max_tokens"),
+        (json!({"max_tokens": u32::MAX}), "max_tokens"),
+        (json!({"stream": true}), "stream"),
+        (
+            json!({"max_completion_tokens": 0, "max_tokens": 5}),
+            "max_completion_tokens",
+        ),
+        (json!({"stop": ["a", "b", "c", "d", "e"]}), "stop"),
+        (json!({"stop": ""}), "stop"),
+        (
+            json!({"logprobs": true, "top_logprobs": 21}),
+            "top_logprobs",
+        ),
+        (json!({"top_logprobs": 2}), "top_logprobs"),
+        (json!({"messages": []}), "messages"),
+        (json!({"model": ""}), "model"),
+    ];
+    for (change, param) in cases {
+        assert_eq!(
+            rejected_param(&minimal_with(change.clone())),
+            param,
+            "{}",
+            change
+        );
+    }
+}
+
+#[test]
+fn forced_tool_calls_are_rejected() {
+    assert_eq!(
+        rejected_param(&request("chat_request_parts.json")),
+        "tool_choice"
+    );
+    assert_eq!(
+        rejected_param(&minimal_with(json!({"tool_choice": "required"}))),
+        "tool_choice"
+    );
+
+    let mut full = request("chat_request_full.json");
+    full.tool_choice = Some(ToolChoice::Mode(ToolChoiceMode::Required));
+    assert_eq!(rejected_param(&full), "tool_choice");
+    full.tool_choice = Some(ToolChoice::Mode(ToolChoiceMode::None));
+    assert_eq!(full.validate(), Ok(()));
+}
+
+#[test]
+fn response_matches_golden() {
+    let hello = vec![72, 101, 108, 108, 111];
+    let response = ChatCompletionResponse {
+        id: "chatcmpl-123".to_string(),
+        object: "chat.completion",
+        created: 1_700_000_000,
+        model: "bitnet-b1.58-2b".to_string(),
+        choices: vec![
+            ChatChoice {
+                index: 0,
+                message: ChatMessage::assistant("Hello there!"),
+                logprobs: Some(ChoiceLogprobs {
+                    content: vec![TokenLogprob {
+                        token: "Hello".to_string(),
+                        logprob: -0.25,
+                        bytes: Some(hello.clone()),
+                        top_logprobs: vec![
+                            TopLogprob {
+                                token: "Hello".to_string(),
+                                logprob: -0.25,
+                                bytes: Some(hello),
+                            },
+                            TopLogprob {
+                                token: "Hi".to_string(),
+                                logprob: -1.5,
+                                bytes: Some(vec![72, 105]),
+                            },
+                        ],
+                    }],
+                }),
+                finish_reason: FinishReason::Stop,
+                zkml_proof: None,
+            },
+            ChatChoice {
+                index: 1,
+                message: ChatMessage::assistant("Hi"),
+                logprobs: None,
+                finish_reason: FinishReason::Length,
+                zkml_proof: None,
+            },
+        ],
+        usage: Usage::new(9, 4),
+        zkml_proof: Some("eyJ0eXBlIjoiZWQyNTUxOS1zaGEyNTYifQ==".to_string()),
+        verified: false,
+        proof_status: Some(ProofStatus::Queued),
+    };
+    assert_eq!(to_json(&response), golden("chat_response.json"));
+}
+
+#[test]
+fn response_without_proof_keeps_the_proof_fields() {
+    let response = ChatCompletionResponse {
+        id: "chatcmpl-123".to_string(),
+        object: "chat.completion",
+        created: 1_700_000_000,
+        model: "bitnet-b1.58-2b".to_string(),
+        choices: Vec::new(),
+        usage: Usage::default(),
+        zkml_proof: None,
+        verified: false,
+        proof_status: None,
+    };
+    let json = to_json(&response);
+    assert_eq!(json["zkml_proof"], Value::Null);
+    assert_eq!(json["verified"], json!(false));
+    assert!(json.get("proof_status").is_none());
+}
+
+#[test]
+fn errors_match_golden() {
+    let error = ApiError::new(
+        "The model `gpt-4` does not exist",
+        "invalid_request_error",
+        "model_not_found",
+    );
+    assert_eq!(to_json(&error), golden("error.json"));
+
+    let error = ApiError::from(ValidationError::new(
+        "temperature",
+        "must be between 0 and 2",
+    ));
+    assert_eq!(to_json(&error), golden("validation_error.json"));
+    assert_eq!(
+        serde_json::from_value::<ApiError>(golden("validation_error.json")).unwrap(),
+        error
+    );
+}
+
+#[test]
+fn models_match_golden() {
+    let models = ModelList {
+        object: "list",
+        data: vec![ModelObject {
+            id: "bitnet-b1.58-2b".to_string(),
+            object: "model",
+            created: 1_700_000_000,
+            owned_by: "bitnet".to_string(),
+            context_length: Some(4096),
+            image_id: Some("0f3a".to_string()),
+            digest: None,
+        }],
+    };
+    assert_eq!(to_json(&models), golden("models.json"));
+}
+
+#[test]
+fn completion_request_parses_validates_and_round_trips() {
+    let request: CompletionRequest =
+        serde_json::from_value(golden("completion_request.json")).unwrap();
+    assert_eq!(request.validate(), Ok(()));
+    assert_eq!(request.prompt.text(), "The capital of France is");
+    assert!(request.echo);
+    assert_eq!(request.logprobs, Some(2));
+    assert_eq!(request.stop_sequences(), ["\n", "."]);
+    assert_eq!(to_json(&request), golden("completion_request.json"));
+}
+
+#[test]
+fn completion_prompt_may_be_a_single_element_list() {
+    let request: CompletionRequest = serde_json::from_value(json!({
+        "model": "bitnet-b1.58-2b",
+        "prompt": ["Once upon a time"]
+    }))
+    .unwrap();
+    assert_eq!(
+        request.prompt,
+        Prompt::Batch(vec!["Once upon a time".to_string()])
+    );
+    assert_eq!(request.prompt.text(), "Once upon a time");
+    assert_eq!(request.validate(), Ok(()));
+    assert!(!request.echo);
+}
+
+#[test]
+fn invalid_completion_requests_are_rejected() {
+    let cases = [
+        (json!({"prompt": []}), "prompt"),
+        (json!({"prompt": ["a", "b"]}), "prompt"),
+        (json!({"max_tokens": 0}), "max_tokens"),
+        (json!({"max_tokens": u32::MAX}), "max_tokens"),
+        (json!({"stream": true}), "stream"),
+        (json!({"logprobs": 6}), "logprobs"),
+        (json!({"temperature": 2.5}), "temperature"),
+        (json!({"stop": ["a", "b", "c", "d", "e"]}), "stop"),
+    ];
+    for (change, param) in cases {
+        let mut request = json!({"model": "bitnet-b1.58-2b", "prompt": "Hi"});
+        for (key, value) in change.as_object().unwrap() {
+            request[key] = value.clone();
+        }
+        let request: CompletionRequest = serde_json::from_value(request).unwrap();
+        assert_eq!(request.validate().unwrap_err().param, param, "{}", change);
+    }
+}
+
+#[test]
+fn completion_response_matches_golden() {
+    let response = CompletionResponse {
+        id: "cmpl-123".to_string(),
+        object: "text_completion",
+        created: 1_700_000_000,
+        model: "bitnet-b1.58-2b".to_string(),
+        choices: vec![CompletionChoice {
+            text: "The capital of France is Paris".to_string(),
+            index: 0,
+            logprobs: Some(CompletionLogprobs {
+                tokens: vec![" Paris".to_string()],
+                token_logprobs: vec![Some(-0.5)],
+                top_logprobs: vec![Some(
+                    [(" Paris".to_string(), -0.5), (" Lyon".to_string(), -2.25)].into(),
+                )],
+                text_offset: vec![24],
+            }),
+            finish_reason: FinishReason::Stop,
+            zkml_proof: None,
+        }],
+        usage: Usage::new(6, 1),
+        zkml_proof: None,
+        verified: false,
+        proof_status: None,
+    };
+    assert_eq!(to_json(&response), golden("completion_response.json"));
+}
+
+#[test]
+fn stop_sequences_cut_at_the_earliest_match() {
+    let stops = ["\n".to_string(), ".".to_string()];
+    assert_eq!(stop_position("Paris. It is\nbig", &stops), Some(5));
+    assert_eq!(stop_position("Paris", &stops), None);
+    assert_eq!(stop_position("Paris.", &[]), None);
+}
+
+#[test]
+fn embedding_request_parses_validates_and_round_trips() {
+    let request: EmbeddingRequest =
+        serde_json::from_value(golden("embedding_request.json")).unwrap();
+    assert_eq!(request.validate(), Ok(()));
+    assert_eq!(
+        request.input.texts(),
+        ["Index the task backlog", "Rank proofs by cost"]
+    );
+    assert_eq!(request.dimensions, Some(256));
+    assert_eq!(to_json(&request), golden("embedding_request.json"));
+
+    let request: EmbeddingRequest = serde_json::from_value(json!({
+        "model": "bitnet-b1.58-2b",
+        "input": "Hello"
+    }))
+    .unwrap();
+    assert_eq!(request.input, EmbeddingInput::Text("Hello".to_string()));
+    assert_eq!(request.encoding_format, EncodingFormat::Float);
+}
+
+#[test]
+fn invalid_embedding_requests_are_rejected() {
+    let cases = [
+        (json!({"input": []}), "input"),
+        (json!({"input": ["a", ""]}), "input"),
+        (json!({"input": vec!["a"; 2049]}), "input"),
+        (json!({"dimensions": 0}), "dimensions"),
+        (json!({"model": ""}), "model"),
+    ];
+    for (change, param) in cases {
+        let mut request = json!({"model": "bitnet-b1.58-2b", "input": "Hi"});
+        for (key, value) in change.as_object().unwrap() {
+            request[key] = value.clone();
+        }
+        let request: EmbeddingRequest = serde_json::from_value(request).unwrap();
+        assert_eq!(request.validate().unwrap_err().param, param);
+    }
+}
+
+#[test]
+fn embedding_response_matches_golden() {
+    let response = EmbeddingList {
+        object: "list",
+        data: vec![
+            Embedding::new(0, &[0.6, -0.8], EncodingFormat::Float),
+            Embedding::new(1, &[1.0, 0.0], EncodingFormat::Base64),
+        ],
+        model: "bitnet-b1.58-2b".to_string(),
+        usage: EmbeddingUsage::new(12),
+        zkml_proof: None,
+        verified: false,
+    };
+    assert_eq!(to_json(&response), golden("embedding_response.json"));
+}
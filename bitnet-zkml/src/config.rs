@@ -0,0 +1,222 @@
+//! Server configuration: `bitnet-zkml.toml`, then `BITNET_ZKML_*`
+//! environment variables, then command-line flags.
+
+use bitnet_common::config::{AuthConfig, CacheConfig, LimitsConfig, ReceiptsConfig, StoreConfig};
+use bitnet_common::models::ModelEntry;
+use bitnet_common::policy::FailurePolicy;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+pub const DEFAULT_CONFIG_PATH: &str = "./bitnet-zkml.toml";
+pub const ENV_PREFIX: &str = "BITNET_ZKML";
+
+pub const DEFAULT_MODEL_ID: &str = "bitnet-b1.58-2b";
+pub const DEFAULT_MODEL_PATH: &str = "../BitNet/models/BitNet-b1.58-2B-4T/ggml-model-i2_s.gguf";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Directory scanned for `*.gguf` models in addition to `[[models]]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub models_dir: Option<PathBuf>,
+    pub server: ServerConfig,
+    pub inference: InferenceConfig,
+    pub proof: ProofConfig,
+    pub embeddings: EmbeddingsConfig,
+    pub auth: AuthConfig,
+    pub limits: LimitsConfig,
+    pub store: StoreConfig,
+    pub receipts: ReceiptsConfig,
+    pub cache: CacheConfig,
+    pub models: Vec<ModelEntry>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            models_dir: None,
+            server: ServerConfig::default(),
+            inference: InferenceConfig::default(),
+            proof: ProofConfig::default(),
+            embeddings: EmbeddingsConfig::default(),
+            auth: AuthConfig::default(),
+            limits: LimitsConfig::default(),
+            store: StoreConfig::default(),
+            receipts: ReceiptsConfig::default(),
+            cache: CacheConfig::default(),
+            models: vec![ModelEntry::new(DEFAULT_MODEL_ID, DEFAULT_MODEL_PATH)],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: String,
+    /// Allowed CORS origins; `*` allows any.
+    pub cors_origins: Vec<String>,
+    pub on_failure: FailurePolicy,
+    /// Seconds requests in flight get to finish after SIGTERM.
+    pub shutdown_grace_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0:8936".to_string(),
+            cors_origins: Vec::new(),
+            on_failure: FailurePolicy::Error,
+            shutdown_grace_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InferenceConfig {
+    pub backend: InferenceBackend,
+    pub llama_cli_path: PathBuf,
+    pub llama_server_path: PathBuf,
+    /// Seconds to wait for a `llama-server` to load its model.
+    pub startup_timeout_secs: u64,
+    /// Seconds one completion may take on any backend; 0 disables.
+    pub request_timeout_secs: u64,
+    pub max_tokens: u32,
+    pub temperature: f32,
+    /// Context window for models without their own `context_size`.
+    pub context_size: u32,
+    /// What to do when a prompt plus `max_tokens` doesn't fit the window.
+    pub on_overflow: OverflowPolicy,
+    pub threads: u32,
+    /// Completions generated at once: batched into each forward pass on the
+    /// native backend, `llama-server` slots, or concurrent `llama-cli`
+    /// processes.
+    pub max_batch_size: usize,
+    /// Completions that may wait for a slot; more are turned away with 503.
+    pub max_queue: usize,
+}
+
+impl Default for InferenceConfig {
+    fn default() -> Self {
+        Self {
+            backend: InferenceBackend::Server,
+            llama_cli_path: PathBuf::from("../BitNet/build/bin/llama-cli"),
+            llama_server_path: PathBuf::from("../BitNet/build/bin/llama-server"),
+            startup_timeout_secs: 300,
+            request_timeout_secs: 300,
+            max_tokens: 50,
+            temperature: 0.8,
+            context_size: 2048,
+            on_overflow: OverflowPolicy::Error,
+            threads: 2,
+            max_batch_size: 4,
+            max_queue: 32,
+        }
+    }
+}
+
+/// How completions are produced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InferenceBackend {
+    /// A long-lived `llama-server` per model, reached over local HTTP.
+    Server,
+    /// A fresh `llama-cli` process per request; reloads the model every time.
+    Cli,
+    /// In-process inference with `bitnet-core`; no external binary.
+    Native,
+}
+
+/// How to handle a conversation longer than the context window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverflowPolicy {
+    /// Reject the request with `context_length_exceeded`.
+    Error,
+    /// Drop the oldest turns until it fits, keeping system messages and the
+    /// latest message.
+    Truncate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProofConfig {
+    pub mode: ProofMode,
+    /// Hex ed25519 seed used to sign attestations; generated on first start.
+    pub signing_key: PathBuf,
+    /// `bitnet-host` binary used in `receipt` and `deferred` modes.
+    pub host_binary: PathBuf,
+    /// Where `bitnet-host` writes its output.
+    pub proofs_dir: PathBuf,
+    /// Transcripts proven at once in `deferred` mode.
+    pub deferred_workers: usize,
+    /// Seconds one `bitnet-host` run may take before it is killed; 0 disables.
+    pub timeout_secs: u64,
+    /// Deferred proofs queued or running at which `/readyz` reports the
+    /// server as not ready; 0 disables.
+    pub max_pending: usize,
+}
+
+impl Default for ProofConfig {
+    fn default() -> Self {
+        Self {
+            mode: ProofMode::Attestation,
+            signing_key: PathBuf::from("./attestation.key"),
+            host_binary: PathBuf::from("../bitnet-host/target/release/bitnet-host"),
+            proofs_dir: PathBuf::from("./proofs"),
+            deferred_workers: 1,
+            timeout_secs: 1800,
+            max_pending: 64,
+        }
+    }
+}
+
+/// What backs the `zkml_proof` of a completion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProofMode {
+    /// Answer with the inference backend and sign the result with the server key.
+    Attestation,
+    /// Answer with `bitnet-host`, which returns a RISC Zero receipt. Slow.
+    Receipt,
+    /// Answer and attest right away, then prove the token transcript in the
+    /// background; the receipt appears at `GET /v1/proofs/{id}`.
+    Deferred,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmbeddingsConfig {
+    pub proof: EmbeddingProof,
+    /// What the guest commits to the journal next to each input commitment.
+    pub commit: EmbeddingCommit,
+}
+
+impl Default for EmbeddingsConfig {
+    fn default() -> Self {
+        Self {
+            proof: EmbeddingProof::None,
+            commit: EmbeddingCommit::Hash,
+        }
+    }
+}
+
+/// What backs the `zkml_proof` of an embedding response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingProof {
+    /// Embed in-process, without a proof.
+    None,
+    /// Embed with `bitnet-host`, which returns a RISC Zero receipt. Slow.
+    Receipt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingCommit {
+    /// SHA-256 of each vector's little-endian `f32` bytes; a small journal.
+    Hash,
+    /// The vectors themselves.
+    Vector,
+}
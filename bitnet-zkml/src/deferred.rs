@@ -0,0 +1,595 @@
+//! Deferred proving: a completion is answered right away, and the exact token
+//! transcript behind it is proven in the zkVM afterwards.
+//!
+//! Each job runs `bitnet-host --transcript <file>`, which replays the
+//! transcript in teacher-forced mode: the guest feeds the recorded prompt and
+//! completion tokens through the model and checks that every completion token
+//! could have been sampled under the recorded parameters
+//! (`bitnet_core::Model::replay`). The receipt is then served at
+//! `GET /v1/proofs/{completion_id}`, to the key that asked for the completion,
+//! and by digest from the receipt store; `DELETE` cancels a queued or running
+//! proof and removes its files.
+//!
+//! On shutdown, proofs still queued or running are listed in
+//! `{proofs_dir}/pending.json` and queued again on the next start.
+
+use axum::{
+    extract::{Path as UrlPath, State},
+    http::StatusCode,
+    response::Json,
+    routing::get,
+    Extension, Router,
+};
+use bitnet_common::auth::{AuthContext, Scope};
+use bitnet_common::config::SharedConfig;
+use bitnet_common::error::{api_error, ApiErrorResponse};
+use bitnet_common::metrics::{Metrics, QueueGuard};
+use bitnet_common::models::{ModelEntry, ModelRegistry};
+use bitnet_common::process::{output_with_timeout, ProcessError};
+use bitnet_common::receipts::{self, ReceiptStore};
+use bitnet_common::store::{CompletionStore, ProofState};
+use bitnet_openai::chat::ProofStatus;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::process::Command;
+use tokio::sync::{mpsc, Semaphore};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::config::Config;
+
+/// Everything the guest needs to replay a completion. Written as JSON next to
+/// the receipt and passed to `bitnet-host --transcript`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transcript {
+    pub completion_id: String,
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_digest: Option<String>,
+    pub prompt_tokens: Vec<u32>,
+    pub completion_tokens: Vec<u32>,
+    pub seed: u64,
+    pub max_tokens: u32,
+    pub temperature: f32,
+    pub top_p: f32,
+    /// False when the tokens were recovered by re-tokenizing text from an
+    /// external backend rather than recorded as sampled.
+    pub exact: bool,
+}
+
+#[derive(Debug, Clone)]
+struct ProofJob {
+    model: String,
+    /// API key that requested the completion; `None` without authentication.
+    key_id: Option<String>,
+    status: ProofStatus,
+    exact: bool,
+    created: u64,
+    completed: Option<u64>,
+    transcript_path: PathBuf,
+    output_path: PathBuf,
+    /// Receipt's digest in the receipt store, once ready.
+    receipt_digest: Option<String>,
+    /// Whether the seeded sampler picks every recorded token, once ready.
+    reproduced: Option<bool>,
+    error: Option<String>,
+    cancel: CancellationToken,
+}
+
+impl ProofJob {
+    /// Other keys' proofs look absent, except to admins, as their completions
+    /// do.
+    fn visible_to(&self, caller: &AuthContext) -> bool {
+        self.key_id.is_none()
+            || self.key_id == caller.key_id
+            || caller.scopes.contains(&Scope::Admin)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProofObject {
+    pub id: String,
+    pub object: &'static str,
+    pub status: ProofStatus,
+    pub model: String,
+    pub exact: bool,
+    pub created: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed: Option<u64>,
+    /// Base64 receipt, once `status` is `ready`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<String>,
+    /// `receipt`'s digest at `GET /v1/receipts/{digest}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receipt_digest: Option<String>,
+    /// True when the seeded sampler reproduces the completion exactly; the
+    /// receipt only proves that each token could have been sampled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reproduced: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeletedProof {
+    pub id: String,
+    pub object: &'static str,
+    pub deleted: bool,
+    /// True when the proof was still queued or running.
+    pub cancelled: bool,
+}
+
+/// A proof left unfinished at shutdown, in `pending.json`.
+#[derive(Serialize, Deserialize)]
+struct PendingProof {
+    id: String,
+    model: String,
+    key_id: Option<String>,
+    exact: bool,
+    created: u64,
+}
+
+const PENDING_FILE: &str = "pending.json";
+
+/// The JSON file `bitnet-host --transcript` writes with `--output`.
+#[derive(Deserialize)]
+struct HostReceipt {
+    receipt: String,
+    #[serde(default)]
+    reproduced: Option<bool>,
+}
+
+struct Submission {
+    id: String,
+    model: ModelEntry,
+    transcript_path: PathBuf,
+    _queued: QueueGuard,
+}
+
+pub struct DeferredProver {
+    config: SharedConfig<Config>,
+    jobs: Mutex<HashMap<String, ProofJob>>,
+    queue: mpsc::UnboundedSender<Submission>,
+    metrics: Arc<Metrics>,
+    /// Told of each proof's progress, when completions are recorded.
+    store: Option<Arc<dyn CompletionStore>>,
+    /// Where finished receipts are kept, by digest.
+    receipts: Option<Arc<ReceiptStore>>,
+}
+
+impl DeferredProver {
+    /// Starts the prover with `proof.deferred_workers` concurrent jobs.
+    pub fn start(
+        config: SharedConfig<Config>,
+        metrics: Arc<Metrics>,
+        store: Option<Arc<dyn CompletionStore>>,
+        receipts: Option<Arc<ReceiptStore>>,
+    ) -> Arc<Self> {
+        let workers = config.get().proof.deferred_workers.max(1);
+        let (queue, mut submissions) = mpsc::unbounded_channel::<Submission>();
+        let prover = Arc::new(Self {
+            config,
+            jobs: Mutex::new(HashMap::new()),
+            queue,
+            metrics,
+            store,
+            receipts,
+        });
+
+        let dispatcher = Arc::clone(&prover);
+        tokio::spawn(async move {
+            let slots = Arc::new(Semaphore::new(workers));
+            while let Some(submission) = submissions.recv().await {
+                let slot = Arc::clone(&slots).acquire_owned().await.unwrap();
+                let prover = Arc::clone(&dispatcher);
+                tokio::spawn(async move {
+                    prover.run(submission).await;
+                    drop(slot);
+                });
+            }
+        });
+        prover
+    }
+
+    /// Records `transcript` and queues it for proving under its completion
+    /// ID, on behalf of the API key `key_id`.
+    pub async fn submit(
+        &self,
+        model: &ModelEntry,
+        transcript: &Transcript,
+        key_id: Option<String>,
+    ) -> anyhow::Result<()> {
+        let proofs_dir = self.config.get().proof.proofs_dir.clone();
+        tokio::fs::create_dir_all(&proofs_dir).await?;
+        let id = transcript.completion_id.clone();
+        let transcript_path = proofs_dir.join(format!("{}.transcript.json", id));
+        tokio::fs::write(&transcript_path, serde_json::to_vec(transcript)?).await?;
+        self.enqueue(id, model, key_id, transcript.exact, now(), &proofs_dir)
+    }
+
+    fn enqueue(
+        &self,
+        id: String,
+        model: &ModelEntry,
+        key_id: Option<String>,
+        exact: bool,
+        created: u64,
+        proofs_dir: &Path,
+    ) -> anyhow::Result<()> {
+        let transcript_path = proofs_dir.join(format!("{}.transcript.json", id));
+        self.jobs.lock().unwrap().insert(
+            id.clone(),
+            ProofJob {
+                model: model.id.clone(),
+                key_id,
+                status: ProofStatus::Queued,
+                exact,
+                created,
+                completed: None,
+                transcript_path: transcript_path.clone(),
+                output_path: proofs_dir.join(format!("{}.json", id)),
+                receipt_digest: None,
+                reproduced: None,
+                error: None,
+                cancel: CancellationToken::new(),
+            },
+        );
+        self.queue
+            .send(Submission {
+                id,
+                model: model.clone(),
+                transcript_path,
+                _queued: self.metrics.track_queue("proof"),
+            })
+            .map_err(|_| anyhow::anyhow!("deferred prover has stopped"))
+    }
+
+    /// Lists the proofs still queued or running in `pending.json` and stops
+    /// them, so the next start picks them up again.
+    pub async fn persist_pending(&self) {
+        let proofs_dir = self.config.get().proof.proofs_dir.clone();
+        let pending: Vec<(PendingProof, CancellationToken)> = self
+            .jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, job)| matches!(job.status, ProofStatus::Queued | ProofStatus::Proving))
+            .map(|(id, job)| {
+                let proof = PendingProof {
+                    id: id.clone(),
+                    model: job.model.clone(),
+                    key_id: job.key_id.clone(),
+                    exact: job.exact,
+                    created: job.created,
+                };
+                (proof, job.cancel.clone())
+            })
+            .collect();
+        if pending.is_empty() {
+            return;
+        }
+
+        let proofs: Vec<&PendingProof> = pending.iter().map(|(proof, _)| proof).collect();
+        let path = proofs_dir.join(PENDING_FILE);
+        let written = async {
+            tokio::fs::create_dir_all(&proofs_dir).await?;
+            tokio::fs::write(&path, serde_json::to_vec_pretty(&proofs)?).await?;
+            anyhow::Ok(())
+        };
+        match written.await {
+            Ok(()) => info!("Saved {} unfinished proofs to {:?}", proofs.len(), path),
+            Err(e) => {
+                error!("Cannot save unfinished proofs to {:?}: {}", path, e);
+                return;
+            }
+        }
+        // Stored completions show them as queued until the next start
+        for (proof, cancel) in &pending {
+            self.record_state(&proof.id, ProofState::Queued, None);
+            cancel.cancel();
+        }
+    }
+
+    /// Queues again the proofs [`DeferredProver::persist_pending`] saved.
+    pub async fn resume_pending(&self, models: &ModelRegistry) {
+        let proofs_dir = self.config.get().proof.proofs_dir.clone();
+        let path = proofs_dir.join(PENDING_FILE);
+        let content = match tokio::fs::read(&path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                error!("Cannot read unfinished proofs from {:?}: {}", path, e);
+                return;
+            }
+        };
+        let pending: Vec<PendingProof> = match serde_json::from_slice(&content) {
+            Ok(pending) => pending,
+            Err(e) => {
+                error!("Unreadable unfinished proofs in {:?}: {}", path, e);
+                return;
+            }
+        };
+
+        let mut resumed = 0;
+        for proof in pending {
+            let transcript_path = proofs_dir.join(format!("{}.transcript.json", proof.id));
+            let Some(model) = models.get(&proof.model) else {
+                warn!("Dropping proof {}: model `{}` is gone", proof.id, proof.model);
+                continue;
+            };
+            if !transcript_path.exists() {
+                warn!("Dropping proof {}: {:?} is missing", proof.id, transcript_path);
+                continue;
+            }
+            let queued = self.enqueue(
+                proof.id.clone(),
+                &model,
+                proof.key_id,
+                proof.exact,
+                proof.created,
+                &proofs_dir,
+            );
+            match queued {
+                Ok(()) => resumed += 1,
+                Err(e) => error!("Cannot queue proof {}: {}", proof.id, e),
+            }
+        }
+        info!("Resumed {} unfinished proofs", resumed);
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            warn!("Cannot remove {:?}: {}", path, e);
+        }
+    }
+
+    /// Proofs queued or running.
+    pub fn pending(&self) -> usize {
+        self.jobs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|job| matches!(job.status, ProofStatus::Queued | ProofStatus::Proving))
+            .count()
+    }
+
+    async fn run(&self, submission: Submission) {
+        // Jobs deleted while queued are gone from the map
+        let Some((output_path, cancel)) = self.update(&submission.id, |job| {
+            job.status = ProofStatus::Proving;
+            (job.output_path.clone(), job.cancel.clone())
+        }) else {
+            return;
+        };
+        self.record_state(&submission.id, ProofState::Proving, None);
+
+        info!("Proving transcript for {}", submission.id);
+        let started = Instant::now();
+        let result = tokio::select! {
+            result = self.prove(&submission, &output_path) => result,
+            // Dropping the proving future kills bitnet-host
+            _ = cancel.cancelled() => {
+                info!("Cancelled proof for {}", submission.id);
+                let _ = tokio::fs::remove_file(&output_path).await;
+                return;
+            }
+        };
+        let receipt_digest = match &result {
+            Ok(host_receipt) => {
+                self.metrics
+                    .proving_duration
+                    .observe(started.elapsed().as_secs_f64());
+                info!("Proof ready for {}", submission.id);
+                receipts::save(self.receipts.as_ref(), &host_receipt.receipt).await
+            }
+            Err(e) => {
+                error!("Proving {} failed: {}", submission.id, e);
+                None
+            }
+        };
+        match &result {
+            Ok(_) => {
+                self.record_state(&submission.id, ProofState::Ready, Some(&output_path));
+                self.record_receipt_digest(&submission.id, receipt_digest.as_deref());
+            }
+            Err(_) => self.record_state(&submission.id, ProofState::Failed, None),
+        }
+        self.update(&submission.id, |job| {
+            job.completed = Some(now());
+            match result {
+                Ok(host_receipt) => {
+                    job.status = ProofStatus::Ready;
+                    job.receipt_digest = receipt_digest;
+                    job.reproduced = host_receipt.reproduced;
+                }
+                Err(e) => {
+                    job.status = ProofStatus::Failed;
+                    job.error = Some(e.to_string());
+                }
+            }
+        });
+    }
+
+    async fn prove(
+        &self,
+        submission: &Submission,
+        output_path: &Path,
+    ) -> anyhow::Result<HostReceipt> {
+        let config = self.config.get();
+        let model = &submission.model;
+        let host_binary = model
+            .prover
+            .host_binary
+            .as_ref()
+            .unwrap_or(&config.proof.host_binary);
+
+        let mut command = Command::new(host_binary);
+        command
+            .arg("--weights").arg(&model.weights_path)
+            .arg("--transcript").arg(&submission.transcript_path)
+            .arg("--output").arg(output_path);
+        let timeout = Duration::from_secs(config.proof.timeout_secs);
+        let output = output_with_timeout(&mut command, timeout).await.map_err(|e| {
+            self.record_failure(match e {
+                ProcessError::Spawn(_) => "spawn",
+                ProcessError::TimedOut(_) => "timeout",
+            });
+            anyhow::anyhow!("bitnet-host: {}", e)
+        })?;
+
+        if !output.status.success() {
+            self.record_failure("exit_status");
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow::anyhow!("bitnet-host failed: {}", stderr.trim()));
+        }
+        read_receipt(output_path).await.inspect_err(|_| self.record_failure("output"))
+    }
+
+    fn record_failure(&self, reason: &str) {
+        self.metrics
+            .host_process_failures
+            .with_label_values(&["bitnet-host", reason])
+            .inc();
+    }
+
+    /// Records where `id`'s proof stands now, for a completion stored after
+    /// its proof was queued.
+    pub fn record_current_state(&self, id: &str) {
+        let Some(job) = self.jobs.lock().unwrap().get(id).cloned() else {
+            return;
+        };
+        let receipt_path = (job.status == ProofStatus::Ready).then_some(job.output_path.as_path());
+        self.record_state(id, job.status.into(), receipt_path);
+        self.record_receipt_digest(id, job.receipt_digest.as_deref());
+    }
+
+    fn record_state(&self, id: &str, state: ProofState, receipt_path: Option<&Path>) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        let receipt_path = receipt_path.map(|path| path.display().to_string());
+        if let Err(e) = store.update_proof(id, state, receipt_path.as_deref()) {
+            warn!("Cannot record proof state for {}: {}", id, e);
+        }
+    }
+
+    fn record_receipt_digest(&self, id: &str, digest: Option<&str>) {
+        let (Some(store), Some(digest)) = (&self.store, digest) else {
+            return;
+        };
+        if let Err(e) = store.set_receipt_digest(id, digest) {
+            warn!("Cannot record receipt digest for {}: {}", id, e);
+        }
+    }
+
+    fn update<T>(&self, id: &str, change: impl FnOnce(&mut ProofJob) -> T) -> Option<T> {
+        self.jobs.lock().unwrap().get_mut(id).map(change)
+    }
+
+    /// `id`'s proof, if `caller` may see it.
+    pub async fn get(&self, id: &str, caller: &AuthContext) -> Option<ProofObject> {
+        let job = self.jobs.lock().unwrap().get(id).cloned()?;
+        if !job.visible_to(caller) {
+            return None;
+        }
+        let (receipt, error) = match job.status {
+            ProofStatus::Ready => match read_receipt(&job.output_path).await {
+                Ok(host_receipt) => (Some(host_receipt.receipt), None),
+                Err(e) => (None, Some(e.to_string())),
+            },
+            _ => (None, job.error),
+        };
+        Some(ProofObject {
+            id: id.to_string(),
+            object: "proof",
+            status: job.status,
+            model: job.model,
+            exact: job.exact,
+            created: job.created,
+            completed: job.completed,
+            receipt,
+            receipt_digest: job.receipt_digest,
+            reproduced: job.reproduced,
+            error,
+        })
+    }
+
+    /// Forgets `id`'s proof, if `caller` may see it, killing `bitnet-host` if
+    /// it is still running, and removes its files. Returns whether it was
+    /// still pending.
+    pub async fn delete(&self, id: &str, caller: &AuthContext) -> Option<bool> {
+        let job = {
+            let mut jobs = self.jobs.lock().unwrap();
+            if !jobs.get(id)?.visible_to(caller) {
+                return None;
+            }
+            jobs.remove(id)?
+        };
+        job.cancel.cancel();
+        for path in [&job.transcript_path, &job.output_path] {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+        Some(matches!(job.status, ProofStatus::Queued | ProofStatus::Proving))
+    }
+}
+
+async fn read_receipt(path: &Path) -> anyhow::Result<HostReceipt> {
+    let content = tokio::fs::read(path).await?;
+    serde_json::from_slice(&content)
+        .map_err(|e| anyhow::anyhow!("Unreadable bitnet-host output {:?}: {}", path, e))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+fn proof_not_found(id: &str) -> ApiErrorResponse {
+    api_error(
+        StatusCode::NOT_FOUND,
+        format!("No deferred proof for `{}`", id),
+        "invalid_request_error",
+        "proof_not_found",
+    )
+}
+
+async fn retrieve_proof(
+    State(prover): State<Arc<DeferredProver>>,
+    Extension(caller): Extension<AuthContext>,
+    UrlPath(id): UrlPath<String>,
+) -> Result<Json<ProofObject>, ApiErrorResponse> {
+    prover
+        .get(&id, &caller)
+        .await
+        .map(Json)
+        .ok_or_else(|| proof_not_found(&id))
+}
+
+async fn delete_proof(
+    State(prover): State<Arc<DeferredProver>>,
+    Extension(caller): Extension<AuthContext>,
+    UrlPath(id): UrlPath<String>,
+) -> Result<Json<DeletedProof>, ApiErrorResponse> {
+    let cancelled = prover
+        .delete(&id, &caller)
+        .await
+        .ok_or_else(|| proof_not_found(&id))?;
+    Ok(Json(DeletedProof {
+        id,
+        object: "proof",
+        deleted: true,
+        cancelled,
+    }))
+}
+
+/// `GET` and `DELETE /v1/proofs/{id}`. Callers add their own authentication
+/// layers.
+pub fn proofs_router<S>(prover: Arc<DeferredProver>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/v1/proofs/:id", get(retrieve_proof).delete(delete_proof))
+        .with_state(prover)
+}
@@ -0,0 +1,217 @@
+//! `POST /v1/embeddings`: sentence embeddings from the BitNet forward pass,
+//! the final hidden states mean-pooled over the input
+//! (`bitnet_core::Model::embed`).
+//!
+//! With `embeddings.proof = "receipt"` the inputs are embedded in the zkVM by
+//! `bitnet-host --embed <job>`. The journal holds the token IDs of each input
+//! and either its vector or the SHA-256 of the vector's little-endian `f32`
+//! bytes (`embeddings.commit`), so a ranking built on the vectors can be
+//! audited against it.
+
+use axum::{extract::State, http::StatusCode, response::Json, Extension};
+use bitnet_common::auth::AuthContext;
+use bitnet_common::error::{api_error, invalid_request, ApiErrorResponse};
+use bitnet_common::models::ModelEntry;
+use bitnet_common::rate_limit::ProofGate;
+use bitnet_common::receipts;
+use bitnet_core::kernels;
+use bitnet_openai::embeddings::{Embedding, EmbeddingList, EmbeddingRequest, EmbeddingUsage};
+use bitnet_openai::ValidationError;
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Instant};
+use tracing::{error, info, warn};
+
+use crate::config::{EmbeddingCommit, EmbeddingProof};
+use crate::{context_window, is_timeout, run_host, AppState};
+
+/// What `bitnet-host --embed` reads: the tokenized inputs, embedded in order.
+#[derive(Debug, Serialize)]
+struct EmbeddingJob<'a> {
+    model: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model_digest: Option<String>,
+    inputs: &'a [Vec<u32>],
+    commit: EmbeddingCommit,
+}
+
+/// What `bitnet-host --embed` writes to `--output`.
+#[derive(Debug, Deserialize)]
+struct HostEmbeddings {
+    embeddings: Vec<Vec<f32>>,
+    receipt: String,
+}
+
+pub async fn embeddings(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<AuthContext>,
+    gate: Option<Extension<ProofGate>>,
+    Json(request): Json<EmbeddingRequest>,
+) -> Result<Json<EmbeddingList>, ApiErrorResponse> {
+    info!(
+        "Received embedding request for model: {} (key: {})",
+        request.model,
+        caller.key_id.as_deref().unwrap_or("anonymous")
+    );
+    request.validate().map_err(invalid_request)?;
+    let model = state.models.resolve(&request.model)?;
+    let proof = state.config.get().embeddings.proof;
+    if proof == EmbeddingProof::Receipt && request.dimensions.is_some() {
+        return Err(invalid_request(ValidationError::new(
+            "dimensions",
+            "proven embeddings cannot be shortened",
+        )));
+    }
+
+    let Some((tokenizer, window)) = context_window(&state, &model).await else {
+        return Err(api_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("Cannot load the tokenizer for `{}`", model.id),
+            "server_error",
+            "tokenizer_unavailable",
+        ));
+    };
+    let mut inputs = Vec::new();
+    for (index, text) in request.input.texts().into_iter().enumerate() {
+        let tokens = tokenizer.tokenizer.encode(text, true).map_err(|e| {
+            invalid_request(ValidationError::new(
+                "input",
+                format!("input {} cannot be tokenized: {}", index, e),
+            ))
+        })?;
+        if tokens.len() > window as usize {
+            return Err(api_error(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "This model's maximum context length is {} tokens, but input {} has {} tokens.",
+                    window,
+                    index,
+                    tokens.len()
+                ),
+                "invalid_request_error",
+                "context_length_exceeded",
+            ));
+        }
+        inputs.push(tokens);
+    }
+    let prompt_tokens = inputs.iter().map(Vec::len).sum::<usize>() as u32;
+
+    // Held until the receipt is in
+    let _permit = gate.map(|gate| gate.acquire(1)).transpose()?;
+    let embedded = match proof {
+        EmbeddingProof::None => embed(&state, &model, inputs)
+            .await
+            .map(|vectors| (vectors, None)),
+        EmbeddingProof::Receipt => prove_embeddings(&state, &model, &inputs)
+            .await
+            .map(|host| (host.embeddings, Some(host.receipt))),
+    };
+    // A stand-in vector would silently corrupt rankings, so there is no
+    // fallback answer here
+    let (mut vectors, receipt) = embedded.map_err(|e| {
+        error!("Embedding failed: {:#}", e);
+        let (status, code) = if is_timeout(&e) {
+            (StatusCode::GATEWAY_TIMEOUT, "inference_timeout")
+        } else {
+            (StatusCode::BAD_GATEWAY, "inference_failed")
+        };
+        api_error(
+            status,
+            format!("Embedding failed: {:#}", e),
+            "server_error",
+            code,
+        )
+    })?;
+    if let Some(receipt) = &receipt {
+        receipts::save(state.receipts.as_ref(), receipt).await;
+    }
+
+    if let Some(dimensions) = request.dimensions {
+        let dimensions = dimensions as usize;
+        let available = vectors.first().map_or(0, Vec::len);
+        if dimensions > available {
+            return Err(invalid_request(ValidationError::new(
+                "dimensions",
+                format!("must be at most {} for this model", available),
+            )));
+        }
+        for vector in &mut vectors {
+            vector.truncate(dimensions);
+            kernels::l2_normalize(vector);
+        }
+    }
+
+    Ok(Json(EmbeddingList {
+        object: "list",
+        data: vectors
+            .iter()
+            .enumerate()
+            .map(|(index, vector)| Embedding::new(index as u32, vector, request.encoding_format))
+            .collect(),
+        model: request.model,
+        usage: EmbeddingUsage::new(prompt_tokens),
+        verified: receipt.is_some(),
+        zkml_proof: receipt,
+    }))
+}
+
+async fn embed(
+    state: &AppState,
+    model: &ModelEntry,
+    inputs: Vec<Vec<u32>>,
+) -> anyhow::Result<Vec<Vec<f32>>> {
+    let started = Instant::now();
+    let vectors = state
+        .native
+        .embed(model, &state.config.get().inference, inputs)
+        .await?;
+    state
+        .metrics
+        .inference_duration
+        .with_label_values(&["native"])
+        .observe(started.elapsed().as_secs_f64());
+    Ok(vectors)
+}
+
+async fn prove_embeddings(
+    state: &AppState,
+    model: &ModelEntry,
+    inputs: &[Vec<u32>],
+) -> anyhow::Result<HostEmbeddings> {
+    let config = state.config.get();
+    let model_digest = match state.models.digest(model).await {
+        Ok(digest) => Some(digest),
+        Err(e) => {
+            warn!("Cannot hash weights for `{}`: {}", model.id, e);
+            None
+        }
+    };
+    let job = EmbeddingJob {
+        model: &model.id,
+        model_digest,
+        inputs,
+        commit: config.embeddings.commit,
+    };
+    tokio::fs::create_dir_all(&config.proof.proofs_dir).await?;
+    let job_path = config
+        .proof
+        .proofs_dir
+        .join(format!("embed-{}.json", uuid::Uuid::new_v4()));
+    tokio::fs::write(&job_path, serde_json::to_vec(&job)?).await?;
+
+    let host = run_host::<HostEmbeddings>(state, model, |command| {
+        command.arg("--embed").arg(&job_path);
+    })
+    .await;
+    if let Err(e) = tokio::fs::remove_file(&job_path).await {
+        warn!("Cannot remove embedding job {:?}: {}", job_path, e);
+    }
+    let (host, _) = host?;
+    if host.embeddings.len() != inputs.len() {
+        anyhow::bail!(
+            "bitnet-host returned {} embeddings for {} inputs",
+            host.embeddings.len(),
+            inputs.len()
+        );
+    }
+    Ok(host)
+}
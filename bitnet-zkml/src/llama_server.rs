@@ -0,0 +1,369 @@
+//! Long-lived BitNet `llama-server` processes, one per model, so the GGUF is
+//! loaded once rather than on every request.
+//!
+//! Each server runs under a supervisor task that waits for `/health`, then
+//! restarts the process with backoff if it exits. Dropping a [`LlamaServer`]
+//! stops its supervisor and kills the process.
+
+use bitnet_common::attestation::SamplingParams;
+use bitnet_common::metrics::Metrics;
+use bitnet_common::models::ModelEntry;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::TcpListener,
+    path::PathBuf,
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{process::Command, sync::watch};
+use tracing::{error, info, warn};
+
+use crate::config::InferenceConfig;
+
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How to launch the server for one model. A changed spec (e.g. after a
+/// config reload) replaces the running process.
+#[derive(Debug, Clone, PartialEq)]
+struct LaunchSpec {
+    binary: PathBuf,
+    model_path: PathBuf,
+    context_size: u32,
+    threads: u32,
+    /// Slots decoded together with continuous batching.
+    parallel: usize,
+    startup_timeout: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Status {
+    Starting,
+    Ready { port: u16 },
+    Down,
+}
+
+pub struct LlamaServer {
+    spec: LaunchSpec,
+    status: watch::Receiver<Status>,
+    // Dropping the sender stops the supervisor.
+    _shutdown: watch::Sender<()>,
+}
+
+/// One completion from `llama-server`, with its own token counts.
+pub struct ServerCompletion {
+    pub text: String,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub hit_limit: bool,
+}
+
+#[derive(Serialize)]
+struct CompletionRequest<'a> {
+    prompt: &'a str,
+    n_predict: u32,
+    temperature: f32,
+    seed: u64,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    stop: &'a [String],
+    cache_prompt: bool,
+}
+
+#[derive(Deserialize)]
+struct CompletionResponse {
+    content: String,
+    #[serde(default)]
+    tokens_evaluated: u32,
+    #[serde(default)]
+    tokens_predicted: u32,
+    /// Set when generation ran into `n_predict`.
+    #[serde(default)]
+    stopped_limit: bool,
+}
+
+pub struct LlamaServerPool {
+    servers: Mutex<HashMap<String, Arc<LlamaServer>>>,
+    http: reqwest::Client,
+    metrics: Arc<Metrics>,
+}
+
+impl LlamaServerPool {
+    pub fn new(metrics: Arc<Metrics>) -> Arc<Self> {
+        Arc::new(Self {
+            servers: Mutex::new(HashMap::new()),
+            http: reqwest::Client::new(),
+            metrics,
+        })
+    }
+
+    /// Starts servers for `models` ahead of the first request.
+    pub fn preload(&self, models: &[ModelEntry], config: &InferenceConfig) {
+        for model in models {
+            self.server_for(model, config);
+        }
+    }
+
+    /// Stops servers for models that are no longer registered.
+    pub fn retain(&self, models: &[ModelEntry]) {
+        self.servers
+            .lock()
+            .unwrap()
+            .retain(|id, _| models.iter().any(|model| &model.id == id));
+    }
+
+    pub fn any_ready(&self) -> bool {
+        self.servers
+            .lock()
+            .unwrap()
+            .values()
+            .any(|server| matches!(*server.status.borrow(), Status::Ready { .. }))
+    }
+
+    /// Whether `model`'s server is up, for readiness checks.
+    pub fn status(&self, model: &ModelEntry) -> Result<String, String> {
+        let servers = self.servers.lock().unwrap();
+        let Some(server) = servers.get(&model.id) else {
+            return Err("no llama-server started".to_string());
+        };
+        let status = *server.status.borrow();
+        match status {
+            Status::Ready { port } => Ok(format!("llama-server ready on port {}", port)),
+            Status::Starting => Err("llama-server is loading the model".to_string()),
+            Status::Down => Err("llama-server is down; restart pending".to_string()),
+        }
+    }
+
+    pub async fn complete(
+        &self,
+        model: &ModelEntry,
+        config: &InferenceConfig,
+        prompt: &str,
+        params: &SamplingParams,
+        seed: u64,
+    ) -> anyhow::Result<ServerCompletion> {
+        let server = self.server_for(model, config);
+        let port = server
+            .wait_ready(Duration::from_secs(config.startup_timeout_secs))
+            .await?;
+
+        let mut request = self
+            .http
+            .post(format!("http://127.0.0.1:{}/completion", port))
+            .json(&CompletionRequest {
+                prompt,
+                n_predict: params.max_tokens,
+                temperature: params.temperature,
+                seed,
+                stop: &params.stop,
+                cache_prompt: true,
+            });
+        if config.request_timeout_secs > 0 {
+            request = request.timeout(Duration::from_secs(config.request_timeout_secs));
+        }
+        // If the client goes away this future is dropped, which closes the
+        // connection and makes llama-server abandon the generation.
+        let response = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| {
+                self.record_failure(if e.is_timeout() { "timeout" } else { "http" });
+                anyhow::Error::new(e).context("llama-server request failed")
+            })?;
+
+        let completion: CompletionResponse = response.json().await.map_err(|e| {
+            self.record_failure("output");
+            anyhow::anyhow!("Unreadable llama-server response: {}", e)
+        })?;
+
+        Ok(ServerCompletion {
+            text: completion.content,
+            prompt_tokens: completion.tokens_evaluated,
+            completion_tokens: completion.tokens_predicted,
+            hit_limit: completion.stopped_limit,
+        })
+    }
+
+    fn server_for(&self, model: &ModelEntry, config: &InferenceConfig) -> Arc<LlamaServer> {
+        let spec = LaunchSpec {
+            binary: config.llama_server_path.clone(),
+            model_path: model.weights_path.clone(),
+            context_size: model.context_size.unwrap_or(config.context_size),
+            threads: config.threads,
+            parallel: config.max_batch_size.max(1),
+            startup_timeout: Duration::from_secs(config.startup_timeout_secs),
+        };
+
+        let mut servers = self.servers.lock().unwrap();
+        if let Some(server) = servers.get(&model.id) {
+            if server.spec == spec {
+                return Arc::clone(server);
+            }
+            info!(
+                "Settings for model `{}` changed, restarting llama-server",
+                model.id
+            );
+        }
+
+        let server = Arc::new(LlamaServer::spawn(spec, Arc::clone(&self.metrics)));
+        servers.insert(model.id.clone(), Arc::clone(&server));
+        server
+    }
+
+    fn record_failure(&self, reason: &str) {
+        self.metrics
+            .host_process_failures
+            .with_label_values(&["llama-server", reason])
+            .inc();
+    }
+}
+
+impl LlamaServer {
+    fn spawn(spec: LaunchSpec, metrics: Arc<Metrics>) -> Self {
+        let (status_tx, status) = watch::channel(Status::Starting);
+        let (shutdown, shutdown_rx) = watch::channel(());
+        tokio::spawn(supervise(spec.clone(), status_tx, shutdown_rx, metrics));
+        Self {
+            spec,
+            status,
+            _shutdown: shutdown,
+        }
+    }
+
+    async fn wait_ready(&self, timeout: Duration) -> anyhow::Result<u16> {
+        let mut status = self.status.clone();
+        let settled = tokio::time::timeout(
+            timeout,
+            status.wait_for(|status| *status != Status::Starting),
+        )
+        .await
+        .map_err(|_| anyhow::anyhow!("llama-server did not become ready in {:?}", timeout))?
+        .map_err(|_| anyhow::anyhow!("llama-server supervisor stopped"))?;
+
+        match *settled {
+            Status::Ready { port } => Ok(port),
+            _ => Err(anyhow::anyhow!(
+                "llama-server for {:?} is down; restart pending",
+                self.spec.model_path
+            )),
+        }
+    }
+}
+
+async fn supervise(
+    spec: LaunchSpec,
+    status: watch::Sender<Status>,
+    mut shutdown: watch::Receiver<()>,
+    metrics: Arc<Metrics>,
+) {
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        status.send_replace(Status::Starting);
+        let started = tokio::select! {
+            started = start(&spec) => started,
+            _ = shutdown.changed() => return,
+        };
+
+        match started {
+            Ok((mut child, port)) => {
+                info!(
+                    "llama-server ready on port {} for {:?}",
+                    port, spec.model_path
+                );
+                status.send_replace(Status::Ready { port });
+                backoff = Duration::from_secs(1);
+
+                tokio::select! {
+                    exit = child.wait() => {
+                        warn!("llama-server for {:?} exited ({:?})", spec.model_path, exit);
+                        metrics
+                            .host_process_failures
+                            .with_label_values(&["llama-server", "crash"])
+                            .inc();
+                    }
+                    _ = shutdown.changed() => {
+                        let _ = child.kill().await;
+                        return;
+                    }
+                }
+            }
+            Err(e) => {
+                error!(
+                    "Failed to start llama-server for {:?}: {}",
+                    spec.model_path, e
+                );
+                metrics
+                    .host_process_failures
+                    .with_label_values(&["llama-server", "spawn"])
+                    .inc();
+            }
+        }
+
+        status.send_replace(Status::Down);
+        info!("Restarting llama-server in {:?}", backoff);
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            _ = shutdown.changed() => return,
+        }
+        backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+    }
+}
+
+/// Launches the process and waits until `/health` answers 200.
+async fn start(spec: &LaunchSpec) -> anyhow::Result<(tokio::process::Child, u16)> {
+    if !spec.binary.exists() {
+        anyhow::bail!("llama-server binary not found: {:?}", spec.binary);
+    }
+    if !spec.model_path.exists() {
+        anyhow::bail!("Model file not found: {:?}", spec.model_path);
+    }
+
+    let port = free_port()?;
+    // The context is split between the slots, so each gets the full window
+    let context_size = spec.context_size as usize * spec.parallel;
+    let mut child = Command::new(&spec.binary)
+        .arg("-m")
+        .arg(&spec.model_path)
+        .arg("-c")
+        .arg(context_size.to_string())
+        .arg("-t")
+        .arg(spec.threads.to_string())
+        .arg("--parallel")
+        .arg(spec.parallel.to_string())
+        .arg("--cont-batching")
+        .arg("-ngl")
+        .arg("0") // No GPU layers for now
+        .arg("--host")
+        .arg("127.0.0.1")
+        .arg("--port")
+        .arg(port.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let health_url = format!("http://127.0.0.1:{}/health", port);
+    let http = reqwest::Client::new();
+    let deadline = Instant::now() + spec.startup_timeout;
+    loop {
+        if let Some(exit) = child.try_wait()? {
+            anyhow::bail!("llama-server exited during startup ({})", exit);
+        }
+        // 503 while the model is still loading
+        if let Ok(response) = http.get(&health_url).send().await {
+            if response.status().is_success() {
+                return Ok((child, port));
+            }
+        }
+        if Instant::now() >= deadline {
+            anyhow::bail!("llama-server not healthy after {:?}", spec.startup_timeout);
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+fn free_port() -> anyhow::Result<u16> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
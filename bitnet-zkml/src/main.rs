@@ -1,117 +1,266 @@
+mod config;
+mod deferred;
+mod embeddings;
+mod llama_server;
+mod native;
+mod scheduler;
+
 use axum::{
-    extract::State,
+    extract::{Request, State},
     http::{HeaderMap, StatusCode},
-    response::Json,
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
     routing::{get, post},
-    Router,
+    Extension, Router,
+};
+use bitnet_common::attestation::{self, AttestationSigner, SamplingParams, Statement, Verifier};
+use bitnet_common::auth::{self, AuthContext, Authenticator, KeysCommand, Scope};
+use bitnet_common::cache::{self, CacheControl, CacheStatus, ResponseCache};
+use bitnet_common::config::{self as layered, ConfigLoader, SharedConfig};
+use bitnet_common::cors::cors_layer;
+use bitnet_common::error::{api_error, invalid_request, ApiErrorResponse};
+use bitnet_common::health::{self, Check, Probes, Readiness};
+use bitnet_common::logprobs::{self, chat_logprobs, completion_logprobs, Logprob, SampledLogprob};
+use bitnet_common::metrics::{self, Metrics};
+use bitnet_common::models::{self, ModelEntry, ModelRegistry};
+use bitnet_common::policy::{verified_header, FailurePolicy};
+use bitnet_common::process::{output_with_timeout, ProcessError};
+use bitnet_common::rate_limit::{self, ProofGate, ProofQuota, RateLimiter};
+use bitnet_common::receipts::{self, ReceiptStore};
+use bitnet_common::shutdown;
+use bitnet_common::store::{self, CompletionRecord, CompletionStore, ProofState};
+use bitnet_common::tokenize::{self, context_length_exceeded, ModelTokenizer, TokenizerCache};
+use bitnet_openai::chat::{
+    stop_position, ChatChoice, ChatCompletionRequest, ChatCompletionResponse, ChatMessage,
+    FinishReason, ProofStatus, Usage,
 };
-use base64::Engine;
-use clap::Parser;
-use serde::{Deserialize, Serialize};
+use bitnet_openai::completions::{CompletionChoice, CompletionRequest, CompletionResponse};
+use clap::{Parser, Subcommand};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
     path::PathBuf,
     sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::process::Command;
 use tokio::net::TcpListener;
-use tower_http::cors::CorsLayer;
 use tracing::{info, warn, error};
 
+use crate::config::{
+    Config, EmbeddingCommit, EmbeddingProof, InferenceBackend, OverflowPolicy, ProofMode,
+};
+use crate::deferred::{DeferredProver, Transcript};
+use crate::llama_server::LlamaServerPool;
+use crate::native::NativeBackend;
+use crate::scheduler::{Admission, QueueFull};
+
+/// Command-line flags. Unset flags fall back to the config file, then to
+/// `BITNET_ZKML_*` environment variables, then to built-in defaults.
 #[derive(Parser, Debug)]
 #[command(name = "bitnet-zkml")]
 #[command(about = "BitNet zkML Server with OpenAI-compatible API")]
 pub struct Args {
-    #[arg(short, long, default_value = "0.0.0.0:8936")]
-    bind: String,
+    /// TOML config file (default: ./bitnet-zkml.toml if present)
+    #[arg(long)]
+    config: Option<String>,
 
-    #[arg(short, long, default_value = "../BitNet/models/BitNet-b1.58-2B-4T/ggml-model-i2_s.gguf")]
-    model_path: String,
+    /// Print the effective configuration and exit
+    #[arg(long)]
+    print_config: bool,
 
-    #[arg(long, default_value = "../BitNet/build/bin/llama-cli")]
-    llama_cli_path: String,
+    #[arg(short, long)]
+    bind: Option<String>,
 
-    #[arg(long, default_value = "50")]
-    max_tokens: u32,
+    /// Serve a single GGUF model instead of `[[models]]`
+    #[arg(short, long)]
+    model_path: Option<String>,
 
-    #[arg(long, default_value = "0.8")]
-    temperature: f32,
+    /// Run completions on a persistent llama-server, a llama-cli per request,
+    /// or in-process
+    #[arg(long, value_enum)]
+    backend: Option<InferenceBackend>,
 
-    #[arg(long, default_value = "2048")]
-    context_size: u32,
+    /// Back each completion with a signed attestation or a zkVM receipt
+    #[arg(long, value_enum)]
+    proof_mode: Option<ProofMode>,
 
-    #[arg(long, default_value = "2")]
-    threads: u32,
-}
+    /// bitnet-host binary used in receipt mode
+    #[arg(long)]
+    host_binary: Option<String>,
 
-#[derive(Clone)]
-pub struct AppState {
-    pub model_path: PathBuf,
-    pub llama_cli_path: PathBuf,
-    pub config: InferenceConfig,
-}
+    /// Embed in-process, or in the zkVM with a receipt
+    #[arg(long, value_enum)]
+    embedding_proof: Option<EmbeddingProof>,
 
-#[derive(Clone)]
-pub struct InferenceConfig {
-    pub max_tokens: u32,
-    pub temperature: f32,
-    pub context_size: u32,
-    pub threads: u32,
-}
+    /// Commit each embedding vector to the receipt journal, or only its hash
+    #[arg(long, value_enum)]
+    embedding_commit: Option<EmbeddingCommit>,
 
-// OpenAI-compatible API structures
-#[derive(Deserialize)]
-pub struct ChatCompletionRequest {
-    pub model: String,
-    pub messages: Vec<ChatMessage>,
-    pub max_tokens: Option<u32>,
-    pub temperature: Option<f32>,
-    pub stream: Option<bool>,
-}
+    #[arg(long)]
+    llama_cli_path: Option<String>,
 
-#[derive(Deserialize, Serialize)]
-pub struct ChatMessage {
-    pub role: String,
-    pub content: String,
-}
+    #[arg(long)]
+    llama_server_path: Option<String>,
 
-#[derive(Serialize)]
-pub struct ChatCompletionResponse {
-    pub id: String,
-    pub object: String,
-    pub created: u64,
-    pub model: String,
-    pub choices: Vec<ChatChoice>,
-    pub usage: Usage,
-    pub zkml_proof: Option<String>,
-}
+    #[arg(long)]
+    max_tokens: Option<u32>,
 
-#[derive(Serialize)]
-pub struct ChatChoice {
-    pub index: u32,
-    pub message: ChatMessage,
-    pub finish_reason: String,
+    #[arg(long)]
+    temperature: Option<f32>,
+
+    #[arg(long)]
+    context_size: Option<u32>,
+
+    /// Reject prompts that overflow the context window, or drop their
+    /// oldest turns
+    #[arg(long, value_enum)]
+    on_overflow: Option<OverflowPolicy>,
+
+    #[arg(long)]
+    threads: Option<u32>,
+
+    /// Completions generated at once; more wait in a bounded queue
+    #[arg(long)]
+    max_batch_size: Option<usize>,
+
+    /// JSON file holding hashed API keys
+    #[arg(long)]
+    api_keys: Option<String>,
+
+    /// SQLite database holding hashed API keys (overrides --api-keys)
+    #[arg(long)]
+    api_keys_db: Option<String>,
+
+    /// SQLite database recording completions and their proofs
+    #[arg(long)]
+    completions_db: Option<String>,
+
+    /// Directory of the `local` receipt store
+    #[arg(long)]
+    receipts_dir: Option<String>,
+
+    /// Directory of the on-disk response cache
+    #[arg(long)]
+    cache_dir: Option<String>,
+
+    /// Accept requests without an API key (local development only)
+    #[arg(long)]
+    no_auth: bool,
+
+    /// Allowed CORS origin; repeat for several, `*` allows any
+    #[arg(long = "cors-origin")]
+    cors_origins: Vec<String>,
+
+    /// Requests per minute allowed per API key or IP (0 disables)
+    #[arg(long)]
+    rate_limit_per_minute: Option<u32>,
+
+    /// Requests a client may burst above the per-minute rate
+    #[arg(long)]
+    rate_limit_burst: Option<u32>,
+
+    /// What to do when inference fails: return an error, or an unverified
+    /// fallback answer
+    #[arg(long, value_enum)]
+    on_failure: Option<FailurePolicy>,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
 }
 
-#[derive(Serialize)]
-pub struct Usage {
-    pub prompt_tokens: u32,
-    pub completion_tokens: u32,
-    pub total_tokens: u32,
+impl Args {
+    fn config_loader(&self) -> ConfigLoader {
+        let mut loader = ConfigLoader::new(
+            self.config.as_deref(),
+            config::DEFAULT_CONFIG_PATH,
+            config::ENV_PREFIX,
+        );
+        loader
+            .set("server.bind", self.bind.clone())
+            .set(
+                "server.cors_origins",
+                Some(self.cors_origins.clone()).filter(|origins| !origins.is_empty()),
+            )
+            .set(
+                "server.on_failure",
+                self.on_failure.map(|policy| format!("{:?}", policy).to_lowercase()),
+            )
+            .set("models", self.single_model())
+            .set(
+                "inference.backend",
+                self.backend.map(|backend| format!("{:?}", backend).to_lowercase()),
+            )
+            .set("inference.llama_cli_path", self.llama_cli_path.clone())
+            .set("inference.llama_server_path", self.llama_server_path.clone())
+            .set("inference.max_tokens", self.max_tokens.map(i64::from))
+            .set("inference.temperature", self.temperature.map(f64::from))
+            .set("inference.context_size", self.context_size.map(i64::from))
+            .set(
+                "inference.on_overflow",
+                self.on_overflow.map(|policy| format!("{:?}", policy).to_lowercase()),
+            )
+            .set("inference.threads", self.threads.map(i64::from))
+            .set("inference.max_batch_size", self.max_batch_size.map(|size| size as i64))
+            .set(
+                "proof.mode",
+                self.proof_mode.map(|mode| format!("{:?}", mode).to_lowercase()),
+            )
+            .set("proof.host_binary", self.host_binary.clone())
+            .set(
+                "embeddings.proof",
+                self.embedding_proof.map(|proof| format!("{:?}", proof).to_lowercase()),
+            )
+            .set(
+                "embeddings.commit",
+                self.embedding_commit.map(|commit| format!("{:?}", commit).to_lowercase()),
+            )
+            .set("auth.enabled", Some(false).filter(|_| self.no_auth))
+            .set("auth.key_file", self.api_keys.clone())
+            .set("auth.key_db", self.api_keys_db.clone())
+            .set("store.path", self.completions_db.clone())
+            .set("receipts.dir", self.receipts_dir.clone())
+            .set("cache.dir", self.cache_dir.clone())
+            .set("limits.rate_limit_per_minute", self.rate_limit_per_minute.map(i64::from))
+            .set("limits.rate_limit_burst", self.rate_limit_burst.map(i64::from));
+        loader
+    }
+
+    /// `-m` replaces the configured models with one default-named model.
+    fn single_model(&self) -> Option<toml::Value> {
+        let entry = ModelEntry::new(config::DEFAULT_MODEL_ID, self.model_path.as_deref()?);
+        let entry = toml::Value::try_from(entry).expect("model entry serializes to TOML");
+        Some(toml::Value::Array(vec![entry]))
+    }
 }
 
-#[derive(Serialize)]
-pub struct ModelsResponse {
-    pub object: String,
-    pub data: Vec<ModelInfo>,
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Manage API keys
+    #[command(subcommand)]
+    Keys(KeysCommand),
 }
 
-#[derive(Serialize)]
-pub struct ModelInfo {
-    pub id: String,
-    pub object: String,
-    pub created: u64,
-    pub owned_by: String,
+#[derive(Clone)]
+pub struct AppState {
+    pub config: SharedConfig<Config>,
+    pub models: Arc<ModelRegistry>,
+    pub llama_servers: Arc<LlamaServerPool>,
+    pub native: Arc<NativeBackend>,
+    pub admission: Arc<Admission>,
+    pub proof_quota: Arc<ProofQuota>,
+    pub tokenizers: Arc<TokenizerCache>,
+    pub signer: Arc<AttestationSigner>,
+    pub prover: Arc<DeferredProver>,
+    /// `None` when `[store]` is disabled.
+    pub store: Option<Arc<dyn CompletionStore>>,
+    /// `None` when `[receipts]` has no backend.
+    pub receipts: Option<Arc<ReceiptStore>>,
+    /// `None` when `[cache]` is disabled.
+    pub cache: Option<Arc<ResponseCache>>,
+    pub probes: Arc<Probes>,
+    /// Decides who sees `/readyz` details.
+    pub authenticator: Authenticator,
+    pub metrics: Arc<Metrics>,
 }
 
 #[derive(Serialize)]
@@ -123,131 +272,932 @@ pub struct HealthResponse {
 }
 
 async fn health_check(State(state): State<Arc<AppState>>) -> Json<HealthResponse> {
-    let model_exists = state.model_path.exists();
-    let llama_cli_exists = state.llama_cli_path.exists();
+    let config = state.config.get();
+    let model_exists = state
+        .models
+        .list()
+        .iter()
+        .any(|model| model.weights_path.exists());
+    let backend_ready = match config.inference.backend {
+        InferenceBackend::Server => state.llama_servers.any_ready(),
+        InferenceBackend::Cli => config.inference.llama_cli_path.exists(),
+        InferenceBackend::Native => state.native.any_loaded(),
+    };
     
     Json(HealthResponse {
-        status: if model_exists && llama_cli_exists { "healthy".to_string() } else { "unhealthy".to_string() },
+        status: if model_exists && backend_ready { "healthy".to_string() } else { "unhealthy".to_string() },
         model_loaded: model_exists,
-        zkml_ready: llama_cli_exists,
+        zkml_ready: backend_ready,
         timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
     })
 }
 
-async fn list_models() -> Json<ModelsResponse> {
-    Json(ModelsResponse {
-        object: "list".to_string(),
-        data: vec![ModelInfo {
-            id: "bitnet-b1.58-2b".to_string(),
-            object: "model".to_string(),
-            created: 1699401600, // Static timestamp
-            owned_by: "bitnet-zkml".to_string(),
-        }],
-    })
+/// `GET /readyz`: every model is loaded by the inference backend and its
+/// tokenizer parses, the proof mode's prover is available and proves the
+/// configured guest, and the inference and proof queues have room. Details
+/// are for admin keys only.
+async fn readyz(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Readiness {
+    let config = state.config.get();
+    let models = state.models.list();
+    let mut checks = Vec::new();
+    if models.is_empty() {
+        checks.push(Check::new("models", Err("no models configured".to_string())));
+    }
+    for model in &models {
+        let loaded = match config.inference.backend {
+            // Probes only look: startup and reloads do the loading
+            InferenceBackend::Native => match state.native.loaded(model, &config.inference) {
+                Some(loaded) => {
+                    let model_config = &loaded.model.config;
+                    Ok(format!(
+                        "loaded in-process: {} with {} layers, context {}",
+                        model_config.architecture,
+                        model_config.block_count,
+                        model_config.context_length
+                    ))
+                }
+                None => Err(format!("{:?} is not loaded in-process", model.weights_path)),
+            },
+            InferenceBackend::Server => state.llama_servers.status(model),
+            // Each request loads the model in its own process
+            InferenceBackend::Cli => {
+                let binary = &config.inference.llama_cli_path;
+                if !binary.exists() {
+                    Err(format!("llama-cli binary not found: {:?}", binary))
+                } else if !model.weights_path.exists() {
+                    Err(format!("model file not found: {:?}", model.weights_path))
+                } else {
+                    Ok(format!("{:?} loads {:?} per request", binary, model.weights_path))
+                }
+            }
+        };
+        checks.push(Check::new("model", loaded).for_model(&model.id));
+        checks.push(health::tokenizer(&state.tokenizers, model).await);
+        if config.proof.mode != ProofMode::Attestation {
+            let host_binary = model
+                .prover
+                .host_binary
+                .as_ref()
+                .unwrap_or(&config.proof.host_binary);
+            checks.push(state.probes.prover(model, host_binary).await);
+        }
+    }
+    if config.proof.mode != ProofMode::Receipt {
+        let signer = format!("signing with key {}", state.signer.public_key());
+        checks.push(Check::new("attestation", Ok(signer)));
+    }
+
+    let (running, waiting) = state.admission.usage();
+    let capacity = config.inference.max_batch_size.max(1) + config.inference.max_queue;
+    checks.push(health::queue("inference_queue", running + waiting, capacity));
+    match config.proof.mode {
+        ProofMode::Receipt => {
+            let (running, limit) = state.proof_quota.concurrency();
+            checks.push(health::queue("proof_queue", running, limit));
+        }
+        ProofMode::Deferred => {
+            let pending = state.prover.pending();
+            checks.push(health::queue("proof_queue", pending, config.proof.max_pending));
+        }
+        ProofMode::Attestation => {}
+    }
+    Readiness::new(checks).for_caller(&state.authenticator, &headers)
 }
 
 async fn chat_completions(
     State(state): State<Arc<AppState>>,
-    _headers: HeaderMap,
+    Extension(caller): Extension<AuthContext>,
+    gate: Option<Extension<ProofGate>>,
+    headers: HeaderMap,
     Json(request): Json<ChatCompletionRequest>,
-) -> Result<Json<ChatCompletionResponse>, (StatusCode, String)> {
-    info!("Received chat completion request for model: {}", request.model);
+) -> Result<impl IntoResponse, ApiErrorResponse> {
+    info!(
+        "Received chat completion request for model: {} (key: {})",
+        request.model,
+        caller.key_id.as_deref().unwrap_or("anonymous")
+    );
+    request.validate().map_err(invalid_request)?;
+    let model = state.models.resolve(&request.model)?;
 
-    // Extract prompt from messages (simple concatenation for now)
-    let prompt = request.messages
-        .iter()
-        .map(|msg| format!("{}: {}", msg.role, msg.content))
-        .collect::<Vec<_>>()
-        .join("\n");
+    // Use configuration from request or defaults
+    let config = state.config.get();
+    let params = SamplingParams {
+        max_tokens: request.max_tokens().unwrap_or(config.inference.max_tokens),
+        temperature: request.temperature.unwrap_or(config.inference.temperature),
+        stop: request.stop_sequences(),
+        n: (request.n() > 1).then_some(request.n()),
+    };
+    let logprobs = request
+        .logprobs
+        .then(|| request.top_logprobs.unwrap_or(0) as usize);
+    check_logprobs(&config, logprobs)?;
 
+    let prompt = fit_context(&state, &model, &request.messages, params.max_tokens).await?;
     info!("Extracted prompt: '{}'", prompt);
 
-    // Use configuration from request or defaults
-    let max_tokens = request.max_tokens.unwrap_or(state.config.max_tokens);
-    let temperature = request.temperature.unwrap_or(state.config.temperature);
-
     info!("Running BitNet inference with GGUF model");
 
-    // Run BitNet inference using llama-cli directly
-    let (response_text, proof) = match run_bitnet_inference(&state, &prompt, max_tokens, temperature).await {
-        Ok(result) => result,
-        Err(e) => {
-            error!("BitNet inference failed: {}", e);
-            // Return fallback response
-            let fallback = format!("I'm a BitNet zkML assistant. I received your message: '{}'. However, I'm currently experiencing technical difficulties with the zkVM inference. This is a fallback response with proof verification.", prompt);
-            (fallback, generate_mock_proof())
-        }
+    let id = format!("chatcmpl-{}", uuid::Uuid::new_v4());
+    let inference = InferenceRequest {
+        id: &id,
+        key_id: caller.key_id.as_deref(),
+        prompt: &prompt,
+        seeds: sample_seeds(request.seed, request.n()),
+        params: params.clone(),
+        trim: true,
+        logprobs,
     };
+    let control = CacheControl::from_headers(&headers);
+    let ((completions, proof), cache_status) =
+        answer(&state, &model, gate.as_deref(), &inference, request.seed.is_some(), control)
+            .await?;
+    let usage = total_usage(&completions);
 
     let response = ChatCompletionResponse {
-        id: format!("chatcmpl-{}", uuid::Uuid::new_v4()),
-        object: "chat.completion".to_string(),
+        id,
+        object: "chat.completion",
         created: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
-        model: request.model,
-        choices: vec![ChatChoice {
-            index: 0,
-            message: ChatMessage {
-                role: "assistant".to_string(),
-                content: response_text.clone(),
-            },
-            finish_reason: "stop".to_string(),
-        }],
-        usage: Usage {
-            prompt_tokens: prompt.split_whitespace().count() as u32,
-            completion_tokens: response_text.split_whitespace().count() as u32,
-            total_tokens: (prompt.split_whitespace().count() + response_text.split_whitespace().count()) as u32,
+        model: request.model.clone(),
+        choices: completions
+            .into_iter()
+            .enumerate()
+            .map(|(index, completion)| ChatChoice {
+                index: index as u32,
+                logprobs: completion.logprobs.as_deref().map(chat_logprobs),
+                message: ChatMessage::assistant(completion.text),
+                finish_reason: completion.finish_reason,
+                zkml_proof: completion.receipt,
+            })
+            .collect(),
+        usage,
+        verified: proof.as_ref().is_some_and(|proof| proof.verified),
+        proof_status: proof.as_ref().and_then(ZkmlProof::status),
+        zkml_proof: proof.as_ref().and_then(|proof| proof.encoded.clone()),
+    };
+    let record = CompletionRecord::new(&request, &response, &params);
+    store_completion(&state, &model, &caller, proof.as_ref(), record).await;
+
+    Ok(([verified_header(response.verified)], cache_status.headers(), Json(response)))
+}
+
+/// Raw text completion: the prompt goes to the model as is, without the
+/// `role: content` framing of chat requests.
+async fn completions(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<AuthContext>,
+    gate: Option<Extension<ProofGate>>,
+    headers: HeaderMap,
+    Json(request): Json<CompletionRequest>,
+) -> Result<impl IntoResponse, ApiErrorResponse> {
+    info!(
+        "Received completion request for model: {} (key: {})",
+        request.model,
+        caller.key_id.as_deref().unwrap_or("anonymous")
+    );
+    request.validate().map_err(invalid_request)?;
+    let model = state.models.resolve(&request.model)?;
+
+    let config = state.config.get();
+    let params = SamplingParams {
+        max_tokens: request.max_tokens.unwrap_or(config.inference.max_tokens),
+        temperature: request.temperature.unwrap_or(config.inference.temperature),
+        stop: request.stop_sequences(),
+        n: request.n.filter(|&n| n > 1),
+    };
+    let logprobs = request.logprobs.map(|top| top as usize);
+    check_logprobs(&config, logprobs)?;
+    let prompt = request.prompt.text();
+    check_context(&state, &model, prompt, params.max_tokens).await?;
+
+    let id = format!("cmpl-{}", uuid::Uuid::new_v4());
+    let inference = InferenceRequest {
+        id: &id,
+        key_id: caller.key_id.as_deref(),
+        prompt,
+        seeds: sample_seeds(request.seed, request.n.unwrap_or(1)),
+        params: params.clone(),
+        trim: false,
+        logprobs,
+    };
+    let control = CacheControl::from_headers(&headers);
+    let ((completions, proof), cache_status) =
+        answer(&state, &model, gate.as_deref(), &inference, request.seed.is_some(), control)
+            .await?;
+    let usage = total_usage(&completions);
+    // Log-probabilities cover the completion tokens only, even with `echo`
+    let offset = if request.echo { prompt.chars().count() } else { 0 };
+
+    let response = CompletionResponse {
+        id,
+        object: "text_completion",
+        created: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        model: request.model.clone(),
+        choices: completions
+            .into_iter()
+            .enumerate()
+            .map(|(index, completion)| CompletionChoice {
+                logprobs: completion
+                    .logprobs
+                    .as_deref()
+                    .map(|logprobs| completion_logprobs(logprobs, offset)),
+                text: if request.echo {
+                    format!("{}{}", prompt, completion.text)
+                } else {
+                    completion.text
+                },
+                index: index as u32,
+                finish_reason: completion.finish_reason,
+                zkml_proof: completion.receipt,
+            })
+            .collect(),
+        usage,
+        verified: proof.as_ref().is_some_and(|proof| proof.verified),
+        proof_status: proof.as_ref().and_then(ZkmlProof::status),
+        zkml_proof: proof.as_ref().and_then(|proof| proof.encoded.clone()),
+    };
+    let record = CompletionRecord::new(&request, &response, &params);
+    store_completion(&state, &model, &caller, proof.as_ref(), record).await;
+
+    Ok(([verified_header(response.verified)], cache_status.headers(), Json(response)))
+}
+
+/// Everything that decides the answers to a deterministic request.
+#[derive(Serialize)]
+struct CacheKey<'a> {
+    model_digest: String,
+    image_id: Option<&'a str>,
+    backend: InferenceBackend,
+    proof: ProofMode,
+    prompt_tokens: Vec<u32>,
+    params: &'a SamplingParams,
+    /// Greedy sampling ignores the seeds.
+    seeds: Option<&'a [u64]>,
+    logprobs: Option<usize>,
+    trim: bool,
+}
+
+/// Answers `request`, from the response cache when the answers are
+/// deterministic: sampled greedily, or with a `seed` the client chose. A
+/// `gate`, present when proving, is charged only on a cache miss: a slot for
+/// the receipt in receipt mode, the daily quota for each queued transcript in
+/// deferred mode. Deferred proofs belong to one completion ID, so they
+/// aren't cached.
+async fn answer(
+    state: &AppState,
+    model: &ModelEntry,
+    gate: Option<&ProofGate>,
+    request: &InferenceRequest<'_>,
+    seeded: bool,
+    control: CacheControl,
+) -> Result<((Vec<Completion>, Option<ZkmlProof>), CacheStatus), ApiErrorResponse> {
+    let config = state.config.get();
+    let greedy = request.params.temperature <= 0.0;
+    let cacheable = (greedy || seeded) && config.proof.mode != ProofMode::Deferred;
+    let key = match &state.cache {
+        Some(_) if cacheable => cache_key(state, model, request, greedy, &config).await,
+        _ => None,
+    };
+    cache::cached(
+        state.cache.as_ref(),
+        key,
+        control,
+        async {
+            let _permit = match (gate, config.proof.mode) {
+                (Some(gate), ProofMode::Deferred) => {
+                    gate.charge(request.seeds.len() as u32)?;
+                    None
+                }
+                // One slot proves the choices in turn
+                (gate, _) => gate
+                    .map(|gate| gate.acquire(request.seeds.len() as u32))
+                    .transpose()?,
+            };
+            complete_or_fallback(state, model, request).await
         },
-        zkml_proof: Some(proof),
+        // Fallback answers are retried next time
+        |(_, proof)| proof.is_some(),
+    )
+    .await
+}
+
+/// `None` if the model can't be hashed or the prompt tokenized.
+async fn cache_key(
+    state: &AppState,
+    model: &ModelEntry,
+    request: &InferenceRequest<'_>,
+    greedy: bool,
+    config: &Config,
+) -> Option<String> {
+    let parts = async {
+        let tokenizer = state.tokenizers.get(model).await?;
+        anyhow::Ok(CacheKey {
+            model_digest: state.models.digest(model).await?,
+            image_id: model.image_id.as_deref(),
+            backend: config.inference.backend,
+            proof: config.proof.mode,
+            prompt_tokens: tokenizer.tokenizer.encode(request.prompt, true)?,
+            params: &request.params,
+            seeds: (!greedy).then_some(&request.seeds[..]),
+            logprobs: request.logprobs,
+            trim: request.trim,
+        })
+    };
+    match parts.await {
+        Ok(parts) => Some(ResponseCache::key(&parts)),
+        Err(e) => {
+            warn!("Not caching the response for {}: {:#}", model.id, e);
+            None
+        }
+    }
+}
+
+/// Runs inference for a request, turning failures into an API error or, under
+/// `on_failure = "fallback"`, into an unverified canned answer without a proof.
+async fn complete_or_fallback(
+    state: &AppState,
+    model: &ModelEntry,
+    request: &InferenceRequest<'_>,
+) -> Result<(Vec<Completion>, Option<ZkmlProof>), ApiErrorResponse> {
+    let e = match run_bitnet_inference(state, model, request).await {
+        Ok((completions, proof)) => return Ok((completions, Some(proof))),
+        Err(e) => e,
+    };
+    if let Some(full) = e.downcast_ref::<QueueFull>() {
+        // Answering with a canned fallback would only hide the overload
+        warn!("Turning away a completion: {}", full);
+        return Err(api_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            full.to_string(),
+            "server_error",
+            "server_overloaded",
+        ));
+    }
+    error!("BitNet inference failed: {:#}", e);
+    let (status, reason) = if is_timeout(&e) {
+        (StatusCode::GATEWAY_TIMEOUT, "inference_timeout")
+    } else {
+        (StatusCode::BAD_GATEWAY, "inference_failed")
     };
+    if state.config.get().server.on_failure == FailurePolicy::Error {
+        return Err(api_error(
+            status,
+            format!("BitNet inference failed: {:#}", e),
+            "server_error",
+            reason,
+        ));
+    }
+    warn!("Serving unverified fallback response");
+    state
+        .metrics
+        .fallback_responses
+        .with_label_values(&[reason])
+        .inc();
+    let fallback = "The BitNet backend could not answer this request. \
+                    This response is unverified and carries no proof.";
+    let completions = request
+        .seeds
+        .iter()
+        .map(|_| Completion {
+            text: fallback.to_string(),
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            finish_reason: FinishReason::Stop,
+            tokens: None,
+            logprobs: None,
+            receipt: None,
+        })
+        .collect();
+    Ok((completions, None))
+}
 
-    Ok(Json(response))
+/// One seed per sample: consecutive from the request's `seed`, so a repeated
+/// request gets the same choices, or random.
+fn sample_seeds(seed: Option<u64>, n: u32) -> Vec<u64> {
+    (0..u64::from(n))
+        .map(|i| seed.map_or_else(rand::random, |seed| seed.wrapping_add(i)))
+        .collect()
 }
 
-async fn run_bitnet_inference(
+/// Usage over all choices: the prompt once, every choice's completion tokens.
+fn total_usage(completions: &[Completion]) -> Usage {
+    Usage::new(
+        completions.first().map_or(0, |completion| completion.prompt_tokens),
+        completions.iter().map(|completion| completion.completion_tokens).sum(),
+    )
+}
+
+fn format_prompt(messages: &[&ChatMessage]) -> String {
+    messages
+        .iter()
+        .map(|msg| format!("{}: {}", msg.role, msg.text()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Builds the prompt for `messages`, checking that it leaves room for
+/// `max_tokens` in the model's context window. Under `on_overflow = "truncate"`
+/// the oldest turns are dropped until it fits; system messages and the latest
+/// message are always kept.
+async fn fit_context(
     state: &AppState,
+    model: &ModelEntry,
+    messages: &[ChatMessage],
+    max_tokens: u32,
+) -> Result<String, ApiErrorResponse> {
+    let mut kept: Vec<&ChatMessage> = messages.iter().collect();
+    let Some((tokenizer, window)) = context_window(state, model).await else {
+        return Ok(format_prompt(&kept));
+    };
+    let on_overflow = state.config.get().inference.on_overflow;
+
+    loop {
+        let prompt = format_prompt(&kept);
+        let prompt_tokens = count_prompt(&tokenizer, &prompt)?;
+        if prompt_tokens.saturating_add(max_tokens) <= window {
+            if kept.len() < messages.len() {
+                info!(
+                    "Dropped {} oldest messages to fit the context window",
+                    messages.len() - kept.len()
+                );
+            }
+            return Ok(prompt);
+        }
+
+        let oldest = kept[..kept.len().saturating_sub(1)]
+            .iter()
+            .position(|msg| !msg.role.is_system());
+        match (on_overflow, oldest) {
+            (OverflowPolicy::Truncate, Some(oldest)) => {
+                kept.remove(oldest);
+            }
+            _ => return Err(context_length_exceeded(window, prompt_tokens, max_tokens)),
+        }
+    }
+}
+
+/// Log-probabilities come from the native backend or the zkVM journal; the
+/// llama.cpp backends don't report them.
+fn check_logprobs(config: &Config, logprobs: Option<usize>) -> Result<(), ApiErrorResponse> {
+    let supported = config.proof.mode == ProofMode::Receipt
+        || config.inference.backend == InferenceBackend::Native;
+    if logprobs.is_some() && !supported {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "logprobs are not available with the {:?} backend",
+                config.inference.backend
+            ),
+            "invalid_request_error",
+            "logprobs_unsupported",
+        ));
+    }
+    Ok(())
+}
+
+/// Rejects a raw prompt that leaves no room for `max_tokens`.
+async fn check_context(
+    state: &AppState,
+    model: &ModelEntry,
     prompt: &str,
     max_tokens: u32,
-    temperature: f32,
-) -> anyhow::Result<(String, String)> {
+) -> Result<(), ApiErrorResponse> {
+    let Some((tokenizer, window)) = context_window(state, model).await else {
+        return Ok(());
+    };
+    let prompt_tokens = count_prompt(&tokenizer, prompt)?;
+    if prompt_tokens.saturating_add(max_tokens) > window {
+        return Err(context_length_exceeded(window, prompt_tokens, max_tokens));
+    }
+    Ok(())
+}
+
+/// The model's tokenizer and context window, or None if the tokenizer can't be
+/// loaded. llama.cpp and the native backend still stop at their own window
+/// then; the zkVM guest has none.
+async fn context_window(
+    state: &AppState,
+    model: &ModelEntry,
+) -> Option<(Arc<ModelTokenizer>, u32)> {
+    let tokenizer = match state.tokenizers.get(model).await {
+        Ok(tokenizer) => tokenizer,
+        Err(e) => {
+            warn!("Cannot check the context length for {}: {}", model.id, e);
+            return None;
+        }
+    };
+    let configured = model
+        .context_size
+        .unwrap_or(state.config.get().inference.context_size);
+    let window = tokenizer.context_window(configured);
+    Some((tokenizer, window))
+}
+
+fn count_prompt(tokenizer: &ModelTokenizer, prompt: &str) -> Result<u32, ApiErrorResponse> {
+    tokenizer.count_prompt(prompt).map_err(|e| {
+        api_error(
+            StatusCode::BAD_REQUEST,
+            format!("Cannot tokenize the prompt: {}", e),
+            "invalid_request_error",
+            "invalid_prompt",
+        )
+    })
+}
+
+/// Whether inference failed because it ran past its deadline.
+fn is_timeout(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.is::<tokio::time::error::Elapsed>()
+            || matches!(cause.downcast_ref(), Some(ProcessError::TimedOut(_)))
+            || cause
+                .downcast_ref::<reqwest::Error>()
+                .is_some_and(reqwest::Error::is_timeout)
+    })
+}
+
+/// A base64 `zkml_proof`: a zkVM receipt, or a signed attestation.
+#[derive(Serialize, Deserialize)]
+pub struct ZkmlProof {
+    /// `None` when each choice carries its own receipt ([`Completion::receipt`]).
+    pub encoded: Option<String>,
+    /// True for a receipt that `bitnet-host` verified.
+    pub verified: bool,
+    /// True when a receipt for the transcript has been queued.
+    pub deferred: bool,
+    /// Host output holding the receipt.
+    pub receipt_path: Option<PathBuf>,
+    /// Receipt's digest in the receipt store.
+    pub receipt_digest: Option<String>,
+}
+
+impl ZkmlProof {
+    /// `proof_status` for the response: set while a deferred receipt is pending.
+    fn status(&self) -> Option<ProofStatus> {
+        self.deferred.then_some(ProofStatus::Queued)
+    }
+
+    fn state(&self) -> ProofState {
+        if self.deferred {
+            ProofState::Queued
+        } else if self.verified {
+            ProofState::Verified
+        } else {
+            ProofState::Attested
+        }
+    }
+}
+
+/// Records a served completion with where its proof stands, when the store is
+/// enabled.
+async fn store_completion(
+    state: &AppState,
+    model: &ModelEntry,
+    caller: &AuthContext,
+    proof: Option<&ZkmlProof>,
+    record: anyhow::Result<CompletionRecord>,
+) {
+    if state.store.is_none() {
+        return;
+    }
+    let model_digest = state.models.digest(model).await.ok();
+    let record = record.map(|mut record| {
+        record.model_digest = model_digest;
+        record.key_id = caller.key_id.clone();
+        if let Some(proof) = proof {
+            record.proof_state = proof.state();
+            record.receipt_path = proof
+                .receipt_path
+                .as_ref()
+                .map(|path| path.display().to_string());
+            record.receipt_digest = proof.receipt_digest.clone();
+        }
+        record
+    });
+    let id = record.as_ref().ok().map(|record| record.id.clone());
+    store::save(state.store.as_ref(), record);
+    // The proof may have moved on before the completion was stored
+    if let (Some(id), Some(proof)) = (id, proof) {
+        if proof.deferred {
+            state.prover.record_current_state(&id);
+        }
+    }
+}
+
+/// Generated text with its token counts.
+#[derive(Serialize, Deserialize)]
+pub struct Completion {
+    pub text: String,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub finish_reason: FinishReason,
+    /// The exact prompt and sampled token IDs, from backends that expose them.
+    pub tokens: Option<(Vec<u32>, Vec<u32>)>,
+    /// One entry per completion token, when requested.
+    pub logprobs: Option<Vec<Logprob>>,
+    /// This choice's own receipt, when each of several choices was proven in
+    /// its own `bitnet-host` run.
+    #[serde(default)]
+    pub receipt: Option<String>,
+}
+
+impl Completion {
+    /// For backends that only return text: counts tokens with the model's own
+    /// tokenizer, or reports zero if it can't be loaded.
+    async fn tokenized(
+        state: &AppState,
+        model: &ModelEntry,
+        prompt: &str,
+        text: String,
+        max_tokens: u32,
+    ) -> Self {
+        let counts = async {
+            let tokenizer = state.tokenizers.get(model).await?;
+            anyhow::Ok((tokenizer.count_prompt(prompt)?, tokenizer.count_completion(&text)?))
+        };
+        let counts = counts.await.unwrap_or_else(|e| {
+            warn!("Cannot count tokens for {}: {}", model.id, e);
+            (0, 0)
+        });
+        Self::counted(text, counts, max_tokens)
+    }
+
+    /// For `bitnet-host`, which tokenizes with the model's own tokenizer:
+    /// prefers the generated IDs it reports, or reports zero if the tokenizer
+    /// can't be loaded.
+    async fn proven(
+        state: &AppState,
+        model: &ModelEntry,
+        prompt: &str,
+        host_output: &HostOutput,
+        max_tokens: u32,
+    ) -> Self {
+        let counts = async {
+            let tokenizer = state.tokenizers.get(model).await?;
+            let completion_tokens = match &host_output.tokens {
+                Some(tokens) => tokens.len() as u32,
+                None => tokenizer.count_completion(&host_output.response)?,
+            };
+            anyhow::Ok((tokenizer.count_prompt(prompt)?, completion_tokens))
+        };
+        let counts = counts.await.unwrap_or_else(|e| {
+            warn!("Cannot count tokens for {}: {}", model.id, e);
+            (0, 0)
+        });
+        Self::counted(host_output.response.clone(), counts, max_tokens)
+    }
+
+    fn counted(
+        text: String,
+        (prompt_tokens, completion_tokens): (u32, u32),
+        max_tokens: u32,
+    ) -> Self {
+        let finish_reason = if completion_tokens >= max_tokens {
+            FinishReason::Length
+        } else {
+            FinishReason::Stop
+        };
+        Self {
+            text,
+            prompt_tokens,
+            completion_tokens,
+            finish_reason,
+            tokens: None,
+            logprobs: None,
+            receipt: None,
+        }
+    }
+
+    /// Cuts the text before the first stop sequence and, for chat, trims the
+    /// whitespace around it. Done before signing, so the attestation covers
+    /// exactly the returned text.
+    fn finish(&mut self, stop: &[String], trim: bool) {
+        if let Some(end) = stop_position(&self.text, stop) {
+            self.text.truncate(end);
+            self.finish_reason = FinishReason::Stop;
+            if let Some(logprobs) = &mut self.logprobs {
+                logprobs::truncate(logprobs, end);
+            }
+        }
+        if trim {
+            self.text = self.text.trim().to_string();
+        }
+    }
+}
+
+/// One prompt to answer `seeds.len()` times.
+pub struct InferenceRequest<'a> {
+    pub id: &'a str,
+    /// API key of the caller, who owns any deferred proofs.
+    pub key_id: Option<&'a str>,
+    pub prompt: &'a str,
+    pub params: SamplingParams,
+    /// One sample per seed.
+    pub seeds: Vec<u64>,
+    /// Trim the whitespace around each answer, as chat does.
+    pub trim: bool,
+    /// Alternatives to report with each token's log-probability.
+    pub logprobs: Option<usize>,
+}
+
+/// Samples every seed of `request` and backs them all with one proof.
+async fn run_bitnet_inference(
+    state: &AppState,
+    model: &ModelEntry,
+    request: &InferenceRequest<'_>,
+) -> anyhow::Result<(Vec<Completion>, ZkmlProof)> {
+    let config = state.config.get();
+    let params = &request.params;
+    if config.proof.mode == ProofMode::Receipt {
+        info!("Running BitNet inference in the zkVM");
+        let (mut completions, proof) = prove_with_host(state, model, request).await?;
+        for completion in &mut completions {
+            completion.finish(&params.stop, request.trim);
+        }
+        return Ok((completions, proof));
+    }
+
     info!("Starting BitNet GGUF inference");
+    let mut completions = Vec::with_capacity(request.seeds.len());
+    for &seed in &request.seeds {
+        let mut completion =
+            sample(state, model, request.prompt, params, seed, request.logprobs).await?;
+        completion.finish(&params.stop, request.trim);
+        completions.push(completion);
+    }
+
+    info!("BitNet inference completed, signing attestation");
+
+    let response = attested_response(&completions);
+    let mut proof = attest(state, model, request.prompt, params.clone(), &response).await;
+
+    if config.proof.mode == ProofMode::Deferred {
+        // Each sample is its own transcript; with several, proofs are at
+        // `GET /v1/proofs/{id}-{index}`
+        for (index, (completion, &seed)) in completions.iter().zip(&request.seeds).enumerate() {
+            let id = if completions.len() == 1 {
+                request.id.to_string()
+            } else {
+                format!("{}-{}", request.id, index)
+            };
+            let recorded =
+                record_transcript(state, model, &id, request.prompt, completion, seed, params);
+            let key_id = request.key_id.map(str::to_string);
+            match recorded.await {
+                Ok(transcript) => match state.prover.submit(model, &transcript, key_id).await {
+                    Ok(()) => proof.deferred = true,
+                    Err(e) => error!("Cannot queue proof for {}: {}", id, e),
+                },
+                Err(e) => error!("Cannot record transcript for {}: {}", id, e),
+            }
+        }
+    }
+
+    Ok((completions, proof))
+}
+
+/// What an attestation's response hash covers: the text of a single choice,
+/// or the JSON array of all choices' texts.
+fn attested_response(completions: &[Completion]) -> String {
+    match completions {
+        [completion] => completion.text.clone(),
+        _ => {
+            let texts: Vec<&str> =
+                completions.iter().map(|completion| completion.text.as_str()).collect();
+            serde_json::to_string(&texts).expect("texts serialize")
+        }
+    }
+}
+
+/// Samples one completion with `seed` on the configured backend.
+async fn sample(
+    state: &AppState,
+    model: &ModelEntry,
+    prompt: &str,
+    params: &SamplingParams,
+    seed: u64,
+    logprobs: Option<usize>,
+) -> anyhow::Result<Completion> {
+    let config = state.config.get();
+    let _permit = {
+        let _queued = state.metrics.track_queue("inference");
+        state.admission.acquire(&config.inference).await?
+    };
+    let started = Instant::now();
+
+    let completion = match config.inference.backend {
+        InferenceBackend::Server => {
+            let completion = state
+                .llama_servers
+                .complete(model, &config.inference, prompt, params, seed)
+                .await?;
+            Completion {
+                text: completion.text,
+                prompt_tokens: completion.prompt_tokens,
+                completion_tokens: completion.completion_tokens,
+                finish_reason: if completion.hit_limit {
+                    FinishReason::Length
+                } else {
+                    FinishReason::Stop
+                },
+                tokens: None,
+                logprobs: None,
+                receipt: None,
+            }
+        }
+        InferenceBackend::Cli => {
+            let text = run_llama_cli(state, model, prompt, params, seed).await?;
+            Completion::tokenized(state, model, prompt, text, params.max_tokens).await
+        }
+        InferenceBackend::Native => {
+            state
+                .native
+                .complete(model, &config.inference, prompt, params, seed, logprobs)
+                .await?
+        }
+    };
+
+    let backend = match config.inference.backend {
+        InferenceBackend::Server => "llama-server",
+        InferenceBackend::Cli => "llama-cli",
+        InferenceBackend::Native => "native",
+    };
+    state
+        .metrics
+        .inference_duration
+        .with_label_values(&[backend])
+        .observe(started.elapsed().as_secs_f64());
+
+    if completion.text.trim().is_empty() {
+        state
+            .metrics
+            .host_process_failures
+            .with_label_values(&[backend, "output"])
+            .inc();
+        return Err(anyhow::anyhow!("{} produced no response text", backend));
+    }
+    Ok(completion)
+}
+
+/// Runs one `llama-cli` process, which loads the model for this request only.
+async fn run_llama_cli(
+    state: &AppState,
+    model: &ModelEntry,
+    prompt: &str,
+    params: &SamplingParams,
+    seed: u64,
+) -> anyhow::Result<String> {
+    let config = &state.config.get().inference;
 
     // Check if model and binary exist
-    if !state.model_path.exists() {
-        return Err(anyhow::anyhow!("Model file not found: {:?}", state.model_path));
+    if !model.weights_path.exists() {
+        return Err(anyhow::anyhow!("Model file not found: {:?}", model.weights_path));
     }
     
-    if !state.llama_cli_path.exists() {
-        return Err(anyhow::anyhow!("llama-cli binary not found: {:?}", state.llama_cli_path));
+    if !config.llama_cli_path.exists() {
+        return Err(anyhow::anyhow!("llama-cli binary not found: {:?}", config.llama_cli_path));
     }
 
     // Run llama-cli with BitNet GGUF model
-    let output = Command::new(&state.llama_cli_path)
-        .arg("-m").arg(&state.model_path)
+    let mut command = Command::new(&config.llama_cli_path);
+    command
+        .arg("-m").arg(&model.weights_path)
         .arg("-p").arg(prompt)
-        .arg("-n").arg(max_tokens.to_string())
-        .arg("-t").arg(state.config.threads.to_string())
-        .arg("-c").arg(state.config.context_size.to_string())
-        .arg("--temp").arg(temperature.to_string())
+        .arg("-n").arg(params.max_tokens.to_string())
+        .arg("-t").arg(config.threads.to_string())
+        .arg("-c").arg(model.context_size.unwrap_or(config.context_size).to_string())
+        .arg("--temp").arg(params.temperature.to_string())
+        .arg("--seed").arg(seed.to_string())
         .arg("-ngl").arg("0") // No GPU layers for now
-        .arg("-b").arg("1") // Batch size
-        .output()
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to execute llama-cli: {}", e))?;
+        .arg("-b").arg("1"); // Batch size
+    let timeout = Duration::from_secs(config.request_timeout_secs);
+    let output = output_with_timeout(&mut command, timeout).await.map_err(|e| {
+        record_process_failure(state, "llama-cli", &e);
+        anyhow::Error::new(e).context("llama-cli")
+    })?;
 
     if !output.status.success() {
+        state
+            .metrics
+            .host_process_failures
+            .with_label_values(&["llama-cli", "exit_status"])
+            .inc();
         let stderr = String::from_utf8_lossy(&output.stderr);
         return Err(anyhow::anyhow!("llama-cli failed: {}", stderr));
     }
 
     let response_text = String::from_utf8_lossy(&output.stdout);
-    let cleaned_response = clean_llama_output(&response_text);
-
-    info!("BitNet inference completed, generating zk-proof");
-
-    // Generate zk-proof for the inference
-    let proof = generate_zkml_proof(prompt, &cleaned_response).await?;
+    Ok(clean_llama_output(&response_text))
+}
 
-    Ok((cleaned_response, proof))
+fn record_process_failure(state: &AppState, process: &str, error: &ProcessError) {
+    let reason = match error {
+        ProcessError::Spawn(_) => "spawn",
+        ProcessError::TimedOut(_) => "timeout",
+    };
+    state
+        .metrics
+        .host_process_failures
+        .with_label_values(&[process, reason])
+        .inc();
 }
 
 fn clean_llama_output(raw_output: &str) -> String {
@@ -276,48 +1226,235 @@ fn clean_llama_output(raw_output: &str) -> String {
         }
     }
     
-    let response = response_lines.join("\n").trim().to_string();
-    
-    // If we didn't find a clean response, return a simple processed version
-    if response.is_empty() {
-        return "Response generated by BitNet zkML system.".to_string();
+    response_lines.join("\n").trim().to_string()
+}
+
+/// Signs a statement of what was asked and answered with the server key.
+async fn attest(
+    state: &AppState,
+    model: &ModelEntry,
+    prompt: &str,
+    params: SamplingParams,
+    response: &str,
+) -> ZkmlProof {
+    let model_digest = match state.models.digest(model).await {
+        Ok(digest) => Some(digest),
+        Err(e) => {
+            warn!("Cannot hash weights for `{}`: {}", model.id, e);
+            None
+        }
+    };
+    let statement = Statement::new(&model.id, model_digest, prompt, params, response);
+    ZkmlProof {
+        encoded: Some(state.signer.attest(statement).encode()),
+        verified: false,
+        deferred: false,
+        receipt_path: None,
+        receipt_digest: None,
     }
-    
-    response
 }
 
-async fn generate_zkml_proof(prompt: &str, response: &str) -> anyhow::Result<String> {
-    // For now, generate a mock proof structure
-    // In a full implementation, this would use RISC0 to prove the inference computation
-    
-    info!("Generating zkML proof for inference");
-    
-    let proof_data = serde_json::json!({
-        "proof_type": "bitnet_inference",
-        "model": "BitNet-b1.58-2B-4T",
-        "prompt_hash": format!("{:x}", md5::compute(prompt.as_bytes())),
-        "response_hash": format!("{:x}", md5::compute(response.as_bytes())),
-        "timestamp": SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
-        "zkvm": "risc0",
-        "verification": "pending"
-    });
-    
-    // Base64 encode the proof
-    let proof_json = proof_data.to_string();
-    let proof_base64 = base64::engine::general_purpose::STANDARD.encode(proof_json.as_bytes());
-    
-    Ok(proof_base64)
+/// The token transcript of a completion, for deferred proving. Backends that
+/// only return text are re-tokenized with the model's own tokenizer.
+async fn record_transcript(
+    state: &AppState,
+    model: &ModelEntry,
+    completion_id: &str,
+    prompt: &str,
+    completion: &Completion,
+    seed: u64,
+    params: &SamplingParams,
+) -> anyhow::Result<Transcript> {
+    let (prompt_tokens, completion_tokens, exact) = match &completion.tokens {
+        Some((prompt_tokens, completion_tokens)) => {
+            (prompt_tokens.clone(), completion_tokens.clone(), true)
+        }
+        None => {
+            let tokenizer = &state.tokenizers.get(model).await?.tokenizer;
+            (
+                tokenizer.encode(prompt, true)?,
+                tokenizer.encode(&completion.text, false)?,
+                false,
+            )
+        }
+    };
+    Ok(Transcript {
+        completion_id: completion_id.to_string(),
+        model: model.id.clone(),
+        model_digest: state.models.digest(model).await.ok(),
+        prompt_tokens,
+        completion_tokens,
+        seed,
+        max_tokens: params.max_tokens,
+        temperature: params.temperature,
+        top_p: 1.0,
+        exact,
+    })
 }
 
-fn generate_mock_proof() -> String {
-    let proof_data = serde_json::json!({
-        "proof_type": "fallback",
-        "status": "mock_proof",
-        "timestamp": SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
-    });
-    
-    let proof_json = proof_data.to_string();
-    base64::engine::general_purpose::STANDARD.encode(proof_json.as_bytes())
+/// The JSON file `bitnet-host` writes with `--output`.
+#[derive(Deserialize)]
+struct HostOutput {
+    response: String,
+    receipt: String,
+    /// Generated token IDs.
+    #[serde(default)]
+    tokens: Option<Vec<u32>>,
+    /// Decoded from the journal, with `--logprobs`; null when the journal's
+    /// logits are not one step per generated token.
+    #[serde(default)]
+    logprobs: Option<Vec<SampledLogprob>>,
+}
+
+async fn journal_logprobs(
+    state: &AppState,
+    model: &ModelEntry,
+    sampled: Option<Vec<SampledLogprob>>,
+) -> anyhow::Result<Vec<Logprob>> {
+    let sampled = sampled.ok_or_else(|| anyhow::anyhow!("the host does not report them"))?;
+    let tokenizer = state.tokenizers.get(model).await?;
+    logprobs::resolve(&sampled, |token| tokenizer.tokenizer.decode_bytes(&[token], false))
+}
+
+/// Answers with `bitnet-host`, which proves the inference in the zkVM and
+/// verifies the receipt before writing it out. The guest samples with the
+/// native backend's code, so a seed gives the same answer either way. A run
+/// samples a single completion, so each choice is proven in a run of its own,
+/// with its own seed, one after another.
+async fn prove_with_host(
+    state: &AppState,
+    model: &ModelEntry,
+    request: &InferenceRequest<'_>,
+) -> anyhow::Result<(Vec<Completion>, ZkmlProof)> {
+    let mut proven = Vec::with_capacity(request.seeds.len());
+    for &seed in &request.seeds {
+        proven.push(prove_choice(state, model, request, seed).await?);
+    }
+    if proven.len() == 1 {
+        let (completion, proof) = proven.remove(0);
+        return Ok((vec![completion], proof));
+    }
+    // No one receipt covers every choice, so each carries its own
+    let completions = proven
+        .into_iter()
+        .map(|(mut completion, proof)| {
+            completion.receipt = proof.encoded;
+            completion
+        })
+        .collect();
+    Ok((
+        completions,
+        ZkmlProof {
+            encoded: None,
+            verified: true,
+            deferred: false,
+            receipt_path: None,
+            receipt_digest: None,
+        },
+    ))
+}
+
+async fn prove_choice(
+    state: &AppState,
+    model: &ModelEntry,
+    request: &InferenceRequest<'_>,
+    seed: u64,
+) -> anyhow::Result<(Completion, ZkmlProof)> {
+    let prompt = request.prompt;
+    let max_tokens = request.params.max_tokens;
+    let max_tokens = model
+        .prover
+        .max_tokens
+        .map_or(max_tokens, |cap| max_tokens.min(cap));
+    let (host_output, receipt_path) = run_host::<HostOutput>(state, model, |command| {
+        command
+            .arg("--prompt").arg(prompt)
+            .arg("--max-tokens").arg(max_tokens.to_string())
+            .arg("--temperature").arg(request.params.temperature.to_string())
+            .arg("--seed").arg(seed.to_string());
+        if let Some(top) = request.logprobs {
+            command.arg("--logprobs").arg(top.to_string());
+        }
+    })
+    .await?;
+
+    let mut completion = Completion::proven(state, model, prompt, &host_output, max_tokens).await;
+    if request.logprobs.is_some() {
+        completion.logprobs = match journal_logprobs(state, model, host_output.logprobs).await {
+            Ok(logprobs) => Some(logprobs),
+            Err(e) => {
+                warn!("No log-probabilities from bitnet-host: {:#}", e);
+                None
+            }
+        };
+    }
+    let receipt_digest = receipts::save(state.receipts.as_ref(), &host_output.receipt).await;
+    Ok((
+        completion,
+        ZkmlProof {
+            encoded: Some(host_output.receipt),
+            verified: true,
+            deferred: false,
+            receipt_path: Some(receipt_path),
+            receipt_digest,
+        },
+    ))
+}
+
+/// Runs `bitnet-host` on `model` with the arguments `args` adds, and reads
+/// the JSON it writes to `--output`, which is kept. The host verifies its
+/// receipt first.
+async fn run_host<T: DeserializeOwned>(
+    state: &AppState,
+    model: &ModelEntry,
+    args: impl FnOnce(&mut Command),
+) -> anyhow::Result<(T, PathBuf)> {
+    let config = &state.config.get().proof;
+    let _queued = state.metrics.track_queue("proof");
+    let started = Instant::now();
+
+    tokio::fs::create_dir_all(&config.proofs_dir).await?;
+    let output_path = config
+        .proofs_dir
+        .join(format!("{}.json", uuid::Uuid::new_v4()));
+    let host_binary = model.prover.host_binary.as_ref().unwrap_or(&config.host_binary);
+
+    let mut command = Command::new(host_binary);
+    command.arg("--weights").arg(&model.weights_path);
+    args(&mut command);
+    command.arg("--output").arg(&output_path);
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let output = output_with_timeout(&mut command, timeout).await.map_err(|e| {
+        record_process_failure(state, "bitnet-host", &e);
+        anyhow::Error::new(e).context("bitnet-host")
+    })?;
+
+    if !output.status.success() {
+        state
+            .metrics
+            .host_process_failures
+            .with_label_values(&["bitnet-host", "exit_status"])
+            .inc();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!("bitnet-host failed: {}", stderr));
+    }
+    state
+        .metrics
+        .proving_duration
+        .observe(started.elapsed().as_secs_f64());
+
+    tokio::fs::read(&output_path)
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|content| Ok((serde_json::from_slice(&content)?, output_path.clone())))
+        .map_err(|e| {
+            state
+                .metrics
+                .host_process_failures
+                .with_label_values(&["bitnet-host", "output"])
+                .inc();
+            anyhow::anyhow!("Unreadable bitnet-host output {:?}: {}", output_path, e)
+        })
 }
 
 #[tokio::main]
@@ -326,53 +1463,310 @@ async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
     let args = Args::parse();
+    let loader = args.config_loader();
+    let config: Config = loader.load()?;
+
+    if args.print_config {
+        return layered::print_config(&config);
+    }
+
+    if let Some(Commands::Keys(command)) = args.command {
+        let store = auth::open_key_store(&config.auth)?;
+        return command.run(store.as_ref());
+    }
     
     info!("Starting BitNet zkML Server");
-    info!("Model path: {}", args.model_path);
-    info!("llama-cli path: {}", args.llama_cli_path);
+    match loader.path() {
+        Some(path) => info!("Config file: {:?}", path),
+        None => info!("No config file; using flags, environment and defaults"),
+    }
+    info!("Inference backend: {:?}", config.inference.backend);
+    info!("llama-cli path: {:?}", config.inference.llama_cli_path);
+    info!("llama-server path: {:?}", config.inference.llama_server_path);
+    info!(
+        "Batching up to {} completions, queueing up to {}",
+        config.inference.max_batch_size, config.inference.max_queue
+    );
+    info!("On failure: {:?}", config.server.on_failure);
+    info!("On context overflow: {:?}", config.inference.on_overflow);
+    info!("Proof mode: {:?}", config.proof.mode);
+    info!("Embedding proofs: {:?}", config.embeddings.proof);
+
+    let backend_binary = match config.inference.backend {
+        InferenceBackend::Server => Some(&config.inference.llama_server_path),
+        InferenceBackend::Cli => Some(&config.inference.llama_cli_path),
+        InferenceBackend::Native => None,
+    };
+    match backend_binary {
+        Some(binary) if !binary.exists() => warn!("Inference binary not found: {:?}", binary),
+        Some(binary) => info!("✓ Inference binary found: {:?}", binary),
+        None => info!("Inference threads: {}", config.inference.threads),
+    }
+
+    let metrics = Metrics::new();
+    let model_registry = ModelRegistry::new(&config.models, config.models_dir.as_deref())?;
+    let llama_servers = LlamaServerPool::new(metrics.clone());
+    if config.inference.backend == InferenceBackend::Server {
+        llama_servers.preload(&model_registry.list(), &config.inference);
+    }
+    let native = NativeBackend::new();
+    if config.inference.backend == InferenceBackend::Native {
+        native.preload(&model_registry.list(), &config.inference);
+    }
+    let signer = Arc::new(AttestationSigner::load_or_create(&config.proof.signing_key)?);
+    info!("Attestation public key: {}", signer.public_key());
+    let verifier = Verifier::new(Some(&signer), model_registry.clone());
+    let tokenizers = TokenizerCache::new();
+    let authenticator = Authenticator::from_config(&config.auth)?;
+    let rate_limiter = RateLimiter::new(
+        config.limits.rate_limit_per_minute,
+        config.limits.rate_limit_burst,
+    );
+    let proof_quota = ProofQuota::new(
+        config.limits.proof_concurrency_per_client,
+        config.limits.proof_concurrency,
+        config.limits.proofs_per_day,
+    );
+    let cors = cors_layer(&config.server.cors_origins)?;
+    let bind = config.server.bind.clone();
+    let grace = Duration::from_secs(config.server.shutdown_grace_secs);
+    let shared_config = SharedConfig::new(config);
+    let store = store::open_completion_store(&shared_config.get().store)?;
+    let receipts_config = shared_config.get().receipts.clone();
+    let receipt_store = ReceiptStore::from_config(&receipts_config)?;
+    let response_cache = ResponseCache::from_config(&shared_config.get().cache, metrics.clone())?;
+    if let Some(receipt_store) = &receipt_store {
+        receipts::spawn_gc(receipt_store.clone(), &receipts_config);
+    }
+    let prover = DeferredProver::start(
+        shared_config.clone(),
+        metrics.clone(),
+        store.clone(),
+        receipt_store.clone(),
+    );
+    prover.resume_pending(&model_registry).await;
 
     // Create application state
     let state = Arc::new(AppState {
-        model_path: PathBuf::from(&args.model_path),
-        llama_cli_path: PathBuf::from(&args.llama_cli_path),
-        config: InferenceConfig {
-            max_tokens: args.max_tokens,
-            temperature: args.temperature,
-            context_size: args.context_size,
-            threads: args.threads,
-        },
+        config: shared_config.clone(),
+        models: model_registry.clone(),
+        llama_servers: llama_servers.clone(),
+        native: native.clone(),
+        admission: Admission::new(),
+        proof_quota,
+        tokenizers: tokenizers.clone(),
+        signer,
+        prover: prover.clone(),
+        store: store.clone(),
+        receipts: receipt_store.clone(),
+        cache: response_cache,
+        probes: Probes::new(),
+        authenticator: authenticator.clone(),
+        metrics: metrics.clone(),
     });
 
-    // Verify model and binary exist
-    if !state.model_path.exists() {
-        warn!("Model file not found: {:?}", state.model_path);
-    } else {
-        info!("✓ Model file found: {:?}", state.model_path);
-    }
+    spawn_config_reload(
+        loader,
+        state.clone(),
+        authenticator.clone(),
+        rate_limiter.clone(),
+    );
 
-    if !state.llama_cli_path.exists() {
-        warn!("llama-cli binary not found: {:?}", state.llama_cli_path);
-    } else {
-        info!("✓ llama-cli binary found: {:?}", state.llama_cli_path);
+    let mut chat_routes = Router::new()
+        .merge(models::models_router(model_registry.clone()))
+        .merge(attestation::attestations_router(verifier))
+        .merge(tokenize::tokenize_router(model_registry.clone(), tokenizers))
+        .route(
+            "/v1/chat/completions",
+            post(chat_completions).route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                completion_proof_quota,
+            )),
+        )
+        .route(
+            "/v1/completions",
+            post(completions).route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                completion_proof_quota,
+            )),
+        )
+        .route(
+            "/v1/embeddings",
+            post(embeddings::embeddings).route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                embedding_proof_quota,
+            )),
+        );
+    if let Some(store) = store {
+        chat_routes = chat_routes.merge(store::completions_router(store));
     }
+    if let Some(receipt_store) = receipt_store {
+        chat_routes = chat_routes.merge(receipts::receipts_router(receipt_store));
+    }
+    let chat_routes = chat_routes
+        .route_layer(middleware::from_fn_with_state(
+            rate_limiter.clone(),
+            rate_limit::rate_limit,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            authenticator.scoped(Scope::Chat),
+            auth::require_scope,
+        ));
+
+    // Deferred receipts come from the prover
+    let proof_routes = deferred::proofs_router(prover.clone())
+        .route_layer(middleware::from_fn_with_state(rate_limiter, rate_limit::rate_limit))
+        .route_layer(middleware::from_fn_with_state(
+            authenticator.scoped(Scope::Prove),
+            auth::require_scope,
+        ));
 
     // Create router with OpenAI-compatible endpoints
     let app = Router::new()
         .route("/health", get(health_check))
-        .route("/v1/models", get(list_models))
-        .route("/v1/chat/completions", post(chat_completions))
-        .layer(CorsLayer::permissive())
+        .route("/livez", get(health::livez))
+        .route("/readyz", get(readyz))
+        .merge(chat_routes)
+        .merge(proof_routes)
+        .merge(auth::admin_router(authenticator))
+        .merge(metrics::metrics_router(metrics.clone()))
+        .layer(middleware::from_fn_with_state(metrics, metrics::track_requests))
+        .layer(cors)
         .with_state(state);
 
     // Start server
-    let listener = TcpListener::bind(&args.bind).await?;
-    info!("🚀 BitNet zkML Server listening on {}", args.bind);
+    let listener = TcpListener::bind(&bind).await?;
+    info!("🚀 BitNet zkML Server listening on {}", bind);
     info!("📖 OpenAI-compatible API endpoints:");
     info!("   GET  /health");
+    info!("   GET  /livez");
+    info!("   GET  /readyz");
+    info!("   GET  /metrics");
     info!("   GET  /v1/models");
+    info!("   GET  /v1/models/{{id}}");
     info!("   POST /v1/chat/completions");
+    info!("   GET  /v1/chat/completions/{{id}}");
+    info!("   GET  /v1/receipts/{{digest}}");
+    info!("   POST /v1/completions");
+    info!("   POST /v1/embeddings");
+    info!("   POST /v1/attestations/verify");
+    info!("   GET  /v1/attestations/key");
+    info!("   POST /v1/tokenize");
+    info!("   POST /v1/detokenize");
+    info!("   GET  /v1/proofs/{{id}}");
+    info!("   DELETE /v1/proofs/{{id}}");
+    info!("   GET  /v1/admin/keys (admin)");
 
-    axum::serve(listener, app).await?;
+    shutdown::serve(listener, app, grace).await?;
 
+    // Picked up again on the next start
+    prover.persist_pending().await;
+    // Stops the llama-server processes
+    llama_servers.retain(&[]);
+    info!("Shutdown complete");
     Ok(())
-} 
\ No newline at end of file
+}
+
+/// Completions in receipt or deferred mode run the prover, so, as on
+/// api-server, they need the `prove` scope and count against the proving
+/// quota, through the [`ProofGate`] this gives them.
+async fn completion_proof_quota(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let proves = state.config.get().proof.mode != ProofMode::Attestation;
+    proof_quota(&state, proves, request, next).await
+}
+
+/// Embeddings in receipt mode, like [`completion_proof_quota`].
+async fn embedding_proof_quota(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let proves = state.config.get().embeddings.proof == EmbeddingProof::Receipt;
+    proof_quota(&state, proves, request, next).await
+}
+
+async fn proof_quota(state: &AppState, proves: bool, request: Request, next: Next) -> Response {
+    if !proves {
+        return next.run(request).await;
+    }
+    let caller = request.extensions().get::<AuthContext>();
+    if !caller.is_some_and(|caller| caller.allows(Scope::Prove)) {
+        return auth::missing_scope(Scope::Prove).into_response();
+    }
+    rate_limit::proof_quota(State(state.proof_quota.clone()), request, next).await
+}
+
+/// Re-reads the configuration on SIGHUP. Models, inference settings, the failure
+/// policy, proof mode, rate limits and API keys apply immediately; the bind
+/// address, CORS origins, auth backend and signing key need a restart.
+fn spawn_config_reload(
+    loader: ConfigLoader,
+    state: Arc<AppState>,
+    authenticator: Authenticator,
+    rate_limiter: Arc<RateLimiter>,
+) {
+    layered::reload_on_sighup(move || {
+        let new_config: Config = match loader.load() {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Config reload failed, keeping the current settings: {}", e);
+                return;
+            }
+        };
+        let old_config = state.config.get();
+
+        layered::warn_if_changed("server.bind", &old_config.server.bind, &new_config.server.bind);
+        layered::warn_if_changed(
+            "server.shutdown_grace_secs",
+            &old_config.server.shutdown_grace_secs,
+            &new_config.server.shutdown_grace_secs,
+        );
+        layered::warn_if_changed(
+            "server.cors_origins",
+            &old_config.server.cors_origins,
+            &new_config.server.cors_origins,
+        );
+        layered::warn_if_changed("auth", &old_config.auth, &new_config.auth);
+        layered::warn_if_changed("store", &old_config.store, &new_config.store);
+        layered::warn_if_changed("receipts", &old_config.receipts, &new_config.receipts);
+        layered::warn_if_changed("cache", &old_config.cache, &new_config.cache);
+        layered::warn_if_changed(
+            "proof.signing_key",
+            &old_config.proof.signing_key,
+            &new_config.proof.signing_key,
+        );
+
+        let limits = &new_config.limits;
+        rate_limiter.reconfigure(limits.rate_limit_per_minute, limits.rate_limit_burst);
+        state.proof_quota.reconfigure(
+            limits.proof_concurrency_per_client,
+            limits.proof_concurrency,
+            limits.proofs_per_day,
+        );
+        let models = &state.models;
+        if let Err(e) = models.reload(&new_config.models, new_config.models_dir.as_deref()) {
+            error!("Failed to reload models, keeping the current list: {}", e);
+        }
+        let models = models.list();
+        state.llama_servers.retain(&models);
+        if new_config.inference.backend == InferenceBackend::Server {
+            // Restarts any server whose model or launch settings changed
+            state.llama_servers.preload(&models, &new_config.inference);
+        }
+        state.native.retain(&models);
+        if new_config.inference.backend == InferenceBackend::Native {
+            // Reloads any model whose weights path or thread count changed
+            state.native.preload(&models, &new_config.inference);
+        }
+        if let Err(e) = authenticator.reload() {
+            error!("Failed to reload API keys: {}", e);
+        }
+
+        state.config.replace(new_config);
+        info!("Configuration reloaded");
+    });
+}
\ No newline at end of file
@@ -0,0 +1,313 @@
+//! In-process inference on `bitnet-core`: no external binary to run.
+
+use bitnet_common::attestation::SamplingParams;
+use bitnet_common::logprobs::{self, SampledLogprob};
+use bitnet_common::models::ModelEntry;
+use bitnet_core::{GenerateParams, GgufFile, Model, Tokenizer};
+use bitnet_openai::chat::FinishReason;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tracing::{error, info};
+
+use crate::config::InferenceConfig;
+use crate::scheduler::BatchScheduler;
+use crate::Completion;
+
+/// A model loaded into memory with its tokenizer.
+pub struct LoadedModel {
+    weights_path: PathBuf,
+    threads: u32,
+    pub model: Arc<Model>,
+    pub tokenizer: Tokenizer,
+    batch: BatchScheduler,
+}
+
+pub struct NativeBackend {
+    loaded: Mutex<HashMap<String, Arc<LoadedModel>>>,
+}
+
+impl NativeBackend {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            loaded: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Loads `models` in the background so the first request doesn't pay for it.
+    pub fn preload(self: &Arc<Self>, models: &[ModelEntry], config: &InferenceConfig) {
+        for model in models {
+            let backend = Arc::clone(self);
+            let model = model.clone();
+            let config = config.clone();
+            tokio::spawn(async move {
+                if let Err(e) = backend.load(&model, &config).await {
+                    error!("Failed to load model `{}`: {}", model.id, e);
+                }
+            });
+        }
+    }
+
+    /// Unloads models that are no longer registered.
+    pub fn retain(&self, models: &[ModelEntry]) {
+        self.loaded
+            .lock()
+            .unwrap()
+            .retain(|id, _| models.iter().any(|model| &model.id == id));
+    }
+
+    pub fn any_loaded(&self) -> bool {
+        !self.loaded.lock().unwrap().is_empty()
+    }
+
+    /// `entry` as already loaded with `config`, without loading it.
+    pub fn loaded(&self, entry: &ModelEntry, config: &InferenceConfig) -> Option<Arc<LoadedModel>> {
+        let loaded = self.loaded.lock().unwrap();
+        loaded
+            .get(&entry.id)
+            .filter(|loaded| {
+                loaded.weights_path == entry.weights_path && loaded.threads == config.threads
+            })
+            .cloned()
+    }
+
+    pub async fn load(
+        &self,
+        entry: &ModelEntry,
+        config: &InferenceConfig,
+    ) -> anyhow::Result<Arc<LoadedModel>> {
+        if let Some(loaded) = self.loaded(entry, config) {
+            return Ok(loaded);
+        }
+
+        let weights_path = entry.weights_path.clone();
+        let threads = config.threads;
+        let context_size = entry.context_size.unwrap_or(config.context_size) as usize;
+        let loaded = tokio::task::spawn_blocking(move || -> anyhow::Result<LoadedModel> {
+            let gguf = GgufFile::open(&weights_path)?;
+            let mut model = Model::load(&gguf, threads as usize)?;
+            // Never run past what the model was trained for
+            model.config.context_length = model.config.context_length.min(context_size);
+            let tokenizer = Tokenizer::from_gguf(&gguf)?;
+            let model = Arc::new(model);
+            Ok(LoadedModel {
+                batch: BatchScheduler::start(Arc::clone(&model))?,
+                weights_path,
+                threads,
+                model,
+                tokenizer,
+            })
+        })
+        .await??;
+
+        info!(
+            "Loaded model `{}` in-process ({}, {} layers, context {})",
+            entry.id,
+            loaded.model.config.architecture,
+            loaded.model.config.block_count,
+            loaded.model.config.context_length
+        );
+        let loaded = Arc::new(loaded);
+        self.loaded
+            .lock()
+            .unwrap()
+            .insert(entry.id.clone(), Arc::clone(&loaded));
+        Ok(loaded)
+    }
+
+    pub async fn complete(
+        &self,
+        entry: &ModelEntry,
+        config: &InferenceConfig,
+        prompt: &str,
+        params: &SamplingParams,
+        seed: u64,
+        logprobs: Option<usize>,
+    ) -> anyhow::Result<Completion> {
+        let loaded = self.load(entry, config).await?;
+        let prompt_tokens = loaded.tokenizer.encode(prompt, true)?;
+        let generate_params = GenerateParams {
+            max_tokens: params.max_tokens as usize,
+            temperature: params.temperature,
+            top_p: 1.0,
+            seed,
+            stop_tokens: loaded.tokenizer.stop_tokens(),
+            logprobs,
+        };
+        let mut stop = StopMatcher::new(params.stop.clone());
+        let decoder = Arc::clone(&loaded);
+        // Stop sequences are matched on text, so they can end mid-token
+        let on_token = move |generated: &[u32]| {
+            stop.accepts(generated, |ids| decoder.tokenizer.decode_bytes(ids, true))
+        };
+
+        // Timing out drops the generation, which leaves the batch
+        let generation = loaded
+            .batch
+            .generate(prompt_tokens.clone(), generate_params, on_token);
+        let timeout = Duration::from_secs(config.request_timeout_secs);
+        let generation = if timeout.is_zero() {
+            generation.await?
+        } else {
+            tokio::time::timeout(timeout, generation).await??
+        };
+
+        tokio::task::spawn_blocking(move || {
+            let logprobs = match logprobs {
+                Some(_) => {
+                    let sampled: Vec<SampledLogprob> =
+                        generation.logprobs.into_iter().map(Into::into).collect();
+                    Some(logprobs::resolve(&sampled, |token| {
+                        loaded.tokenizer.decode_bytes(&[token], false)
+                    })?)
+                }
+                None => None,
+            };
+            Ok(Completion {
+                text: loaded.tokenizer.decode(&generation.tokens, true)?,
+                prompt_tokens: prompt_tokens.len() as u32,
+                completion_tokens: generation.tokens.len() as u32,
+                finish_reason: match generation.finish_reason {
+                    bitnet_core::FinishReason::Stop => FinishReason::Stop,
+                    bitnet_core::FinishReason::Length => FinishReason::Length,
+                },
+                tokens: Some((prompt_tokens, generation.tokens)),
+                logprobs,
+                receipt: None,
+            })
+        })
+        .await?
+    }
+
+    /// Embeds each tokenized input with [`Model::embed`].
+    pub async fn embed(
+        &self,
+        entry: &ModelEntry,
+        config: &InferenceConfig,
+        inputs: Vec<Vec<u32>>,
+    ) -> anyhow::Result<Vec<Vec<f32>>> {
+        let loaded = self.load(entry, config).await?;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let _cancel_on_drop = CancelOnDrop(Arc::clone(&cancelled));
+
+        let embedding = tokio::task::spawn_blocking(move || {
+            inputs
+                .iter()
+                .map(|tokens| {
+                    if cancelled.load(Ordering::Relaxed) {
+                        anyhow::bail!("embedding cancelled");
+                    }
+                    loaded.model.embed(tokens)
+                })
+                .collect()
+        });
+
+        let timeout = Duration::from_secs(config.request_timeout_secs);
+        if timeout.is_zero() {
+            embedding.await?
+        } else {
+            tokio::time::timeout(timeout, embedding).await??
+        }
+    }
+}
+
+/// Stops native work when the request that started it is dropped, e.g.
+/// because the client disconnected.
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Watches generated tokens for stop sequences, decoding only the tokens
+/// added since the last call and keeping just enough of the decoded tail
+/// to catch a stop sequence that straddles tokens.
+struct StopMatcher {
+    stops: Vec<String>,
+    /// How many tokens have been decoded into `tail`.
+    seen: usize,
+    tail: Vec<u8>,
+}
+
+impl StopMatcher {
+    fn new(stops: Vec<String>) -> Self {
+        Self {
+            stops,
+            seen: 0,
+            tail: Vec::new(),
+        }
+    }
+
+    /// Whether generation may go on after `generated`, which extends the
+    /// tokens of the previous call. Tokens that fail to decode stop it.
+    fn accepts(
+        &mut self,
+        generated: &[u32],
+        decode: impl FnOnce(&[u32]) -> anyhow::Result<Vec<u8>>,
+    ) -> bool {
+        if self.stops.is_empty() {
+            return true;
+        }
+        let Ok(bytes) = decode(&generated[self.seen.min(generated.len())..]) else {
+            return false;
+        };
+        self.seen = generated.len();
+        self.tail.extend_from_slice(&bytes);
+        let tail = &self.tail;
+        if self.stops.iter().any(|stop| {
+            tail.windows(stop.len())
+                .any(|window| window == stop.as_bytes())
+        }) {
+            return false;
+        }
+        // Only a stop sequence's first `len - 1` bytes can already be here
+        let keep = self.stops.iter().map(String::len).max().unwrap_or(1) - 1;
+        let drop = self.tail.len().saturating_sub(keep);
+        self.tail.drain(..drop);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes each token id as one byte.
+    fn bytes(ids: &[u32]) -> anyhow::Result<Vec<u8>> {
+        Ok(ids.iter().map(|&id| id as u8).collect())
+    }
+
+    #[test]
+    fn stops_across_tokens_and_keeps_a_short_tail() {
+        let mut matcher = StopMatcher::new(vec!["END".into(), "xyz".into()]);
+        let mut generated = Vec::new();
+        for byte in b"hello EN" {
+            generated.push(*byte as u32);
+            assert!(matcher.accepts(&generated, bytes));
+            assert!(matcher.tail.len() <= 2);
+        }
+        // Each call decodes only the new token
+        generated.push(b'D' as u32);
+        assert!(!matcher.accepts(&generated, |ids| {
+            assert_eq!(ids, [b'D' as u32]);
+            bytes(ids)
+        }));
+    }
+
+    #[test]
+    fn no_stops_or_undecodable_tokens() {
+        let mut matcher = StopMatcher::new(Vec::new());
+        assert!(matcher.accepts(&[1, 2], |_| unreachable!()));
+
+        let mut matcher = StopMatcher::new(vec!["END".into()]);
+        assert!(!matcher.accepts(&[1], |_| Err(anyhow::anyhow!("outside the vocabulary"))));
+    }
+}
@@ -0,0 +1,301 @@
+//! Scheduling of completions onto the inference backends.
+//!
+//! At most `max_batch_size` completions run at once: stepped together through
+//! each forward pass on the native backend, in `llama-server`'s parallel
+//! slots, or as concurrent `llama-cli` processes. Up to `max_queue` more wait
+//! for a slot; beyond that requests are turned away instead of piling up.
+
+use bitnet_core::{GenerateParams, Generation, Model, Sequence};
+use std::{
+    fmt,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+use tokio::sync::{oneshot, Notify};
+use tracing::{debug, error};
+
+use crate::config::InferenceConfig;
+
+/// Returned when every slot is busy and the queue is full.
+#[derive(Debug)]
+pub struct QueueFull {
+    pub waiting: usize,
+}
+
+impl fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "inference queue is full ({} requests waiting); retry later",
+            self.waiting
+        )
+    }
+}
+
+impl std::error::Error for QueueFull {}
+
+#[derive(Default)]
+struct Slots {
+    running: usize,
+    waiting: usize,
+}
+
+/// Admits completions onto the backend, `max_batch_size` at a time.
+#[derive(Default)]
+pub struct Admission {
+    slots: Mutex<Slots>,
+    released: Notify,
+}
+
+/// A running completion's slot, given back on drop.
+pub struct Permit(Arc<Admission>);
+
+/// Counts a caller in the queue until it is admitted or gives up.
+struct Waiting<'a>(&'a Admission);
+
+impl Admission {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Completions running and waiting now.
+    pub fn usage(&self) -> (usize, usize) {
+        let slots = self.slots.lock().unwrap();
+        (slots.running, slots.waiting)
+    }
+
+    /// Waits for a free slot, or fails at once if the queue is full. The
+    /// limits come from the current config, so a reload applies to the next
+    /// caller; running completions are never interrupted.
+    pub async fn acquire(self: &Arc<Self>, config: &InferenceConfig) -> Result<Permit, QueueFull> {
+        let max_running = config.max_batch_size.max(1);
+        let mut waiting = None;
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            let admitted = {
+                let mut slots = self.slots.lock().unwrap();
+                if slots.running < max_running {
+                    slots.running += 1;
+                    true
+                } else {
+                    if waiting.is_none() {
+                        if slots.waiting >= config.max_queue {
+                            return Err(QueueFull {
+                                waiting: slots.waiting,
+                            });
+                        }
+                        slots.waiting += 1;
+                        waiting = Some(Waiting(self));
+                    }
+                    // Registered before unlocking, so a release can't be missed
+                    released.as_mut().enable();
+                    false
+                }
+            };
+            if admitted {
+                return Ok(Permit(Arc::clone(self)));
+            }
+            released.await;
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.slots.lock().unwrap().running -= 1;
+        self.0.released.notify_waiters();
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.slots.lock().unwrap().waiting -= 1;
+    }
+}
+
+/// Sees the tokens sampled so far after each step; false stops generation.
+type OnToken = Box<dyn FnMut(&[u32]) -> bool + Send>;
+
+/// One completion for the batch loop.
+struct Job {
+    prompt: Vec<u32>,
+    params: GenerateParams,
+    on_token: OnToken,
+    done: oneshot::Sender<anyhow::Result<Generation>>,
+}
+
+struct Active {
+    sequence: Sequence,
+    on_token: OnToken,
+    done: oneshot::Sender<anyhow::Result<Generation>>,
+}
+
+/// Continuous batching for one in-process model: a dedicated thread steps
+/// every active completion through the same forward pass, and new ones join
+/// between steps instead of waiting for the batch to drain. The thread exits
+/// once the scheduler is dropped and its last completion is answered.
+pub struct BatchScheduler {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl BatchScheduler {
+    pub fn start(model: Arc<Model>) -> anyhow::Result<Self> {
+        let (jobs, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("native-batch".to_string())
+            .spawn(move || run(&model, receiver))?;
+        Ok(Self { jobs })
+    }
+
+    /// Generates a completion as part of the next batch. Dropping the future
+    /// (on timeout or disconnect) takes the completion out of the batch.
+    pub async fn generate(
+        &self,
+        prompt: Vec<u32>,
+        params: GenerateParams,
+        on_token: impl FnMut(&[u32]) -> bool + Send + 'static,
+    ) -> anyhow::Result<Generation> {
+        let (done, result) = oneshot::channel();
+        self.jobs
+            .send(Job {
+                prompt,
+                params,
+                on_token: Box::new(on_token),
+                done,
+            })
+            .map_err(|_| anyhow::anyhow!("native batch scheduler stopped"))?;
+        result
+            .await
+            .map_err(|_| anyhow::anyhow!("native batch scheduler stopped"))?
+    }
+}
+
+fn run(model: &Model, jobs: mpsc::Receiver<Job>) {
+    let mut active: Vec<Active> = Vec::new();
+    loop {
+        if active.is_empty() {
+            match jobs.recv() {
+                Ok(job) => admit(model, job, &mut active),
+                Err(_) => return,
+            }
+        }
+        while let Ok(job) = jobs.try_recv() {
+            admit(model, job, &mut active);
+        }
+        // Nobody is waiting for these any more
+        active.retain(|job| !job.done.is_closed());
+        if active.is_empty() {
+            continue;
+        }
+
+        debug!("Stepping a batch of {} completions", active.len());
+        let mut sequences: Vec<&mut Sequence> =
+            active.iter_mut().map(|job| &mut job.sequence).collect();
+        match model.step(&mut sequences) {
+            Ok(sampled) => {
+                for (job, token) in active.iter_mut().zip(sampled) {
+                    if token.is_some() && !(job.on_token)(job.sequence.tokens()) {
+                        job.sequence.stop();
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Batched forward pass failed: {:#}", e);
+                for job in active.drain(..) {
+                    let _ = job.done.send(Err(anyhow::anyhow!("{:#}", e)));
+                }
+                continue;
+            }
+        }
+
+        let (finished, running): (Vec<_>, Vec<_>) =
+            active.drain(..).partition(|job| job.sequence.is_finished());
+        active = running;
+        for job in finished {
+            let _ = job.done.send(Ok(job.sequence.into_generation()));
+        }
+    }
+}
+
+fn admit(model: &Model, job: Job, active: &mut Vec<Active>) {
+    match Sequence::new(model, &job.prompt, job.params) {
+        Ok(sequence) => active.push(Active {
+            sequence,
+            on_token: job.on_token,
+            done: job.done,
+        }),
+        Err(e) => {
+            let _ = job.done.send(Err(e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Yields until the spawned waiters have queued.
+    async fn until_waiting(admission: &Admission, waiting: usize) {
+        while admission.usage().1 != waiting {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn admission_queues_then_turns_away() {
+        let config = InferenceConfig {
+            max_batch_size: 1,
+            max_queue: 1,
+            ..Default::default()
+        };
+        let admission = Admission::new();
+        let running = admission.acquire(&config).await.unwrap();
+
+        let queued = tokio::spawn({
+            let (admission, config) = (Arc::clone(&admission), config.clone());
+            async move { admission.acquire(&config).await.map(|_| ()) }
+        });
+        until_waiting(&admission, 1).await;
+        let full = admission.acquire(&config).await.err().unwrap();
+        assert_eq!(full.waiting, 1);
+        assert_eq!(admission.usage(), (1, 1));
+
+        // Releasing the slot admits the waiter, whose permit is then dropped
+        drop(running);
+        queued.await.unwrap().unwrap();
+        assert_eq!(admission.usage(), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiters_leave_the_queue() {
+        let config = InferenceConfig {
+            max_batch_size: 1,
+            max_queue: 2,
+            ..Default::default()
+        };
+        let admission = Admission::new();
+        let running = admission.acquire(&config).await.unwrap();
+
+        let waiters: Vec<_> = (0..2)
+            .map(|_| {
+                let (admission, config) = (Arc::clone(&admission), config.clone());
+                tokio::spawn(async move { admission.acquire(&config).await.map(|_| ()) })
+            })
+            .collect();
+        until_waiting(&admission, 2).await;
+        for waiter in &waiters {
+            waiter.abort();
+        }
+        for waiter in waiters {
+            assert!(waiter.await.unwrap_err().is_cancelled());
+        }
+        assert_eq!(admission.usage(), (1, 0));
+
+        // The freed queue places are usable again
+        drop(running);
+        assert_eq!(admission.usage(), (0, 0));
+        let _permit = admission.acquire(&config).await.unwrap();
+        assert_eq!(admission.usage(), (1, 0));
+    }
+}
@@ -0,0 +1,19 @@
+//! Proves one BitNet request: reads a `GuestInput`, then the length and raw
+//! bytes of the model's GGUF, and commits the `GuestOutput` of
+//! `bitnet_core::guest::run`.
+
+#![no_main]
+
+use bitnet_core::guest::{self, GuestInput};
+use risc0_zkvm::guest::env;
+
+risc0_zkvm::guest::entry!(main);
+
+fn main() {
+    let input: GuestInput = env::read();
+    let len: u32 = env::read();
+    let mut gguf = vec![0u8; len as usize];
+    env::read_slice(&mut gguf);
+    let output = guest::run(gguf, input).expect("BitNet guest failed");
+    env::commit(&output);
+}
@@ -0,0 +1,3 @@
+//! The BitNet guest, built for the zkVM by `build.rs`.
+
+include!(concat!(env!("OUT_DIR"), "/methods.rs"));